@@ -5,6 +5,7 @@ use cubesql::{
     config::{Config, ConfigObj, CubeServices},
     mysql::SqlAuthService,
     schema::SchemaService,
+    CubeError,
 };
 
 #[derive(Clone)]
@@ -34,9 +35,9 @@ impl NodeConfig {
         &self,
         transport: Arc<NodeBridgeTransport>,
         auth: Arc<NodeBridgeAuthService>,
-    ) -> CubeServices {
+    ) -> Result<CubeServices, CubeError> {
         let injector = self.config.injector();
-        self.config.configure_injector().await;
+        self.config.configure_injector().await?;
 
         injector
             .register_typed::<dyn SchemaService, _, _, _>(async move |_| transport)
@@ -46,6 +47,6 @@ impl NodeConfig {
             .register_typed::<dyn SqlAuthService, _, _, _>(async move |_| auth)
             .await;
 
-        self.config.cube_services().await
+        Ok(self.config.cube_services().await)
     }
 }
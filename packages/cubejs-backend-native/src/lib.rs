@@ -93,9 +93,16 @@ fn register_interface(mut cx: FunctionContext) -> JsResult<JsPromise> {
         channel.settle_with(deferred, move |cx| Ok(cx.undefined()));
 
         runtime.block_on(async move {
-            let services = config
+            let services = match config
                 .configure(Arc::new(transport_service), Arc::new(auth_service))
-                .await;
+                .await
+            {
+                Ok(services) => services,
+                Err(e) => {
+                    log::error!("Unable to start Cube SQL: {}", e.message);
+                    return;
+                }
+            };
             track_event("Cube SQL Start".to_string(), HashMap::new()).await;
             services.wait_processing_loops().await.unwrap();
         });
@@ -4,10 +4,15 @@ use neon::prelude::*;
 use async_trait::async_trait;
 use cubeclient::models::{V1LoadConinueWait, V1LoadRequestQuery, V1LoadResponse, V1MetaResponse};
 use cubesql::{
-    compile::TenantContext, di_service, mysql::AuthContext, schema::SchemaService, CubeError,
+    compile::TenantContext,
+    di_service,
+    mysql::AuthContext,
+    schema::{cancelled, SchemaService},
+    CubeError,
 };
 use serde_derive::Serialize;
 use std::sync::Arc;
+use tokio::sync::watch;
 use uuid::Uuid;
 
 use crate::channel::call_js_with_channel_as_callback;
@@ -43,7 +48,7 @@ struct MetaRequest {
 
 #[async_trait]
 impl SchemaService for NodeBridgeTransport {
-    async fn get_ctx_for_tenant(&self, ctx: &AuthContext) -> Result<TenantContext, CubeError> {
+    async fn get_ctx_for_tenant(&self, ctx: &AuthContext) -> Result<Arc<TenantContext>, CubeError> {
         trace!("[transport] Meta ->");
 
         let extra = serde_json::to_string(&MetaRequest {
@@ -57,15 +62,21 @@ impl SchemaService for NodeBridgeTransport {
         .await?;
         trace!("[transport] Meta <- {:?}", response);
 
-        Ok(TenantContext {
+        Ok(Arc::new(TenantContext {
             cubes: response.cubes.unwrap_or_default(),
-        })
+        }))
+    }
+
+    // Schema is not cached by the bridge, Cube.js is asked for it on every query
+    async fn flush_cache(&self, _ctx: &AuthContext) -> Result<(), CubeError> {
+        Ok(())
     }
 
     async fn request(
         &self,
         query: V1LoadRequestQuery,
         ctx: &AuthContext,
+        mut cancel: watch::Receiver<bool>,
     ) -> Result<V1LoadResponse, CubeError> {
         trace!("[transport] Request ->");
 
@@ -78,12 +89,16 @@ impl SchemaService for NodeBridgeTransport {
                 request_id: format!("{}-span-{}", request_id, span_counter),
                 query: query.clone(),
             })?;
-            let response: serde_json::Value = call_js_with_channel_as_callback(
-                self.channel.clone(),
-                self.on_load.clone(),
-                Some(extra),
-            )
-            .await?;
+            let response: serde_json::Value = tokio::select! {
+                _ = cancelled(&mut cancel) => {
+                    return Err(CubeError::user("Query execution was cancelled".to_string()));
+                }
+                res = call_js_with_channel_as_callback(
+                    self.channel.clone(),
+                    self.on_load.clone(),
+                    Some(extra),
+                ) => res?,
+            };
             trace!("[transport] Request <- {:?}", response);
 
             let load_err = match serde_json::from_value::<V1LoadResponse>(response.clone()) {
@@ -20,88 +20,109 @@ pub enum MetaV1Error {
     UnknownValue(serde_json::Value),
 }
 
+/// Result of a single `/v1/load` request. Cube.js answers with `Continue wait`
+/// while the query is still being processed and the request must be retried.
+#[derive(Debug, Clone)]
+pub enum LoadV1Poll {
+    Ready(crate::models::V1LoadResponse),
+    ContinueWait,
+}
+
 pub async fn load_v1(
     configuration: &configuration::Configuration,
     v1_load_request: Option<crate::models::V1LoadRequest>,
 ) -> Result<crate::models::V1LoadResponse, Error<LoadV1Error>> {
-    let local_var_client = &configuration.client;
-
     let request_id = Uuid::new_v4().to_string();
     let mut span_counter: u32 = 1;
 
     loop {
-        let local_var_uri_str = format!("{}/v1/load", configuration.base_path);
-        let mut local_var_req_builder =
-            local_var_client.request(reqwest::Method::POST, local_var_uri_str.as_str());
-
-        if let Some(ref local_var_user_agent) = configuration.user_agent {
-            local_var_req_builder = local_var_req_builder
-                .header(reqwest::header::USER_AGENT, local_var_user_agent.clone());
+        match load_v1_span(configuration, &v1_load_request, &request_id, span_counter).await? {
+            LoadV1Poll::Ready(response) => return Ok(response),
+            LoadV1Poll::ContinueWait => {
+                span_counter += 1;
+            }
         }
+    }
+}
 
-        if let Some(ref local_var_token) = configuration.bearer_access_token {
-            local_var_req_builder = local_var_req_builder.bearer_auth(local_var_token.to_owned());
-        };
-        local_var_req_builder = local_var_req_builder.json(&v1_load_request);
-
-        local_var_req_builder = local_var_req_builder.header(
-            "x-request-id",
-            format!("{}-span-{}", request_id, span_counter),
-        );
-
-        let local_var_req = local_var_req_builder.build()?;
-        let local_var_resp = local_var_client.execute(local_var_req).await?;
-
-        let local_var_status = local_var_resp.status();
-        let local_var_content = local_var_resp.text().await?;
-
-        if !local_var_status.is_client_error() && !local_var_status.is_server_error() {
-            let response_ok =
-                serde_json::from_str::<crate::models::V1LoadResponse>(&local_var_content)
-                    .map_err(Error::from);
-            if response_ok.is_ok() {
-                return response_ok;
-            };
-
-            let response_err =
-                serde_json::from_str::<crate::models::V1LoadConinueWait>(&local_var_content);
-            if let Ok(res) = response_err {
-                if res.error.to_lowercase() == *"continue wait" {
-                    debug!(
-                        "[client] load - retrying request (continue wait) requestId: {}, span: {}",
-                        request_id, span_counter
-                    );
-
-                    span_counter += 1;
-
-                    continue;
-                } else {
-                    error!(
-                        "[client] load - strange response, success which contains error: {:?}",
-                        res
-                    );
-
-                    let local_var_error = ResponseContent {
-                        status: local_var_status,
-                        content: local_var_content,
-                        entity: None,
-                    };
-
-                    return Err(Error::ResponseError(local_var_error));
-                }
-            };
-
-            return response_ok;
+/// Sends a single `/v1/load` request without retrying on `Continue wait`,
+/// so the caller is able to control backoff and timeout of polling.
+pub async fn load_v1_span(
+    configuration: &configuration::Configuration,
+    v1_load_request: &Option<crate::models::V1LoadRequest>,
+    request_id: &str,
+    span_counter: u32,
+) -> Result<LoadV1Poll, Error<LoadV1Error>> {
+    let local_var_client = &configuration.client;
+
+    let local_var_uri_str = format!("{}/v1/load", configuration.base_path);
+    let mut local_var_req_builder =
+        local_var_client.request(reqwest::Method::POST, local_var_uri_str.as_str());
+
+    if let Some(ref local_var_user_agent) = configuration.user_agent {
+        local_var_req_builder =
+            local_var_req_builder.header(reqwest::header::USER_AGENT, local_var_user_agent.clone());
+    }
+
+    if let Some(ref local_var_token) = configuration.bearer_access_token {
+        local_var_req_builder = local_var_req_builder.bearer_auth(local_var_token.to_owned());
+    };
+    local_var_req_builder = local_var_req_builder.json(v1_load_request);
+
+    local_var_req_builder = local_var_req_builder.header(
+        "x-request-id",
+        format!("{}-span-{}", request_id, span_counter),
+    );
+
+    let local_var_req = local_var_req_builder.build()?;
+    let local_var_resp = local_var_client.execute(local_var_req).await?;
+
+    let local_var_status = local_var_resp.status();
+    let local_var_content = local_var_resp.text().await?;
+
+    if !local_var_status.is_client_error() && !local_var_status.is_server_error() {
+        let response_ok = serde_json::from_str::<crate::models::V1LoadResponse>(&local_var_content)
+            .map_err(Error::from);
+        if response_ok.is_ok() {
+            return response_ok.map(LoadV1Poll::Ready);
         };
 
-        let local_var_entity: Option<LoadV1Error> = serde_json::from_str(&local_var_content).ok();
-        let local_var_error = ResponseContent {
-            status: local_var_status,
-            content: local_var_content,
-            entity: local_var_entity,
+        let response_err =
+            serde_json::from_str::<crate::models::V1LoadConinueWait>(&local_var_content);
+        if let Ok(res) = response_err {
+            if res.error.to_lowercase() == *"continue wait" {
+                debug!(
+                    "[client] load - retrying request (continue wait) requestId: {}, span: {}",
+                    request_id, span_counter
+                );
+
+                return Ok(LoadV1Poll::ContinueWait);
+            } else {
+                error!(
+                    "[client] load - strange response, success which contains error: {:?}",
+                    res
+                );
+
+                let local_var_error = ResponseContent {
+                    status: local_var_status,
+                    content: local_var_content,
+                    entity: None,
+                };
+
+                return Err(Error::ResponseError(local_var_error));
+            }
         };
-        return Err(Error::ResponseError(local_var_error));
-    }
+
+        return response_ok.map(LoadV1Poll::Ready);
+    };
+
+    let local_var_entity: Option<LoadV1Error> = serde_json::from_str(&local_var_content).ok();
+    let local_var_error = ResponseContent {
+        status: local_var_status,
+        content: local_var_content,
+        entity: local_var_entity,
+    };
+    Err(Error::ResponseError(local_var_error))
 }
 
 pub async fn meta_v1(
@@ -230,4 +251,31 @@ mod tests {
             Err(e) => panic!("must be successful, {:?}", e),
         };
     }
+
+    #[tokio::test]
+    async fn test_continue_wait_single_span() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/load"))
+            .respond_with(TestResponder::new(3, std::time::Duration::from_millis(0)))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let reqwest_client = Client::builder().build().unwrap();
+        let client = ClientBuilder::new(reqwest_client).build();
+
+        let mut configuration = Configuration::new(client);
+        configuration.base_path = server.uri();
+
+        match load_v1_span(&configuration, &None, "test", 1).await {
+            Ok(LoadV1Poll::ContinueWait) => {}
+            r => panic!("must be continue wait, {:?}", r),
+        };
+
+        match load_v1_span(&configuration, &None, "test", 2).await {
+            Ok(LoadV1Poll::Ready(_)) => {}
+            r => panic!("must be successful, {:?}", r),
+        };
+    }
 }
@@ -17,7 +17,7 @@ use crate::error::Result;
 use crate::instance::HllInstance::{Dense, Sparse};
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use itertools::Itertools;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use std::cmp::{max, min};
 use std::collections::HashSet;
 use std::convert::TryInto;
@@ -67,6 +67,17 @@ impl HllInstance {
         };
     }
 
+    pub fn insert_hash(&mut self, hash: u64) {
+        match self {
+            Sparse(s) => s.insert_hash(hash),
+            Dense(d) => {
+                d.insert_hash(hash);
+                return;
+            }
+        }
+        self.make_dense_if_necessary();
+    }
+
     /// Returns true iff `self.make_dense_if_necessary` has to be run.
     /// See comments inside the function for explanation on why we need this.
     fn merge_with_prepare(&mut self, o: &HllInstance) -> bool {
@@ -112,10 +123,6 @@ impl HllInstance {
                 v
             )));
         }
-        const ENC_EMPTY: u8 = 1;
-        const ENC_EXPLICIT: u8 = 2;
-        const ENC_SPARSE: u8 = 3;
-        const ENC_FULL: u8 = 4;
         let encoding = match data[0] & 0x0F {
             0 => {
                 return Err(HllError::new(
@@ -270,6 +277,119 @@ impl HllInstance {
         }
     }
 
+    /// Writes v1 of https://github.com/aggregateknowledge/hll-storage-spec, readable by the
+    /// `hll` extension of Postgres. Uses the default register width of 5 bits, so bucket values
+    /// above 31 are truncated. Produces SPARSE or FULL encoding, whichever is smaller.
+    pub fn write_hll_storage_spec(&self) -> Vec<u8> {
+        const REG_WIDTH: u8 = 5;
+        const MAX_VALUE: u8 = (1 << REG_WIDTH) - 1;
+        // Sparse encoding enabled, explicit cutoff is chosen automatically.
+        const CUTOFF: u8 = 0x7F;
+
+        let log_num_buckets = self.index_bit_len();
+        let values = self.bucket_values();
+        let num_set = values.iter().filter(|v| **v != 0).count();
+        let encoding = if num_set == 0 {
+            ENC_EMPTY
+        } else if num_set * ((log_num_buckets + REG_WIDTH) as usize)
+            < values.len() * REG_WIDTH as usize
+        {
+            ENC_SPARSE
+        } else {
+            ENC_FULL
+        };
+
+        let mut r = BitWriter::new();
+        r.write_bits((1 << 4) | encoding as u64, 8);
+        r.write_bits((((REG_WIDTH - 1) << 5) | log_num_buckets) as u64, 8);
+        r.write_bits(CUTOFF as u64, 8);
+        match encoding {
+            ENC_EMPTY => {}
+            ENC_SPARSE => {
+                let entry_len = (log_num_buckets + REG_WIDTH) as usize;
+                for (i, v) in values.iter().enumerate() {
+                    if *v != 0 {
+                        let v = min(*v, MAX_VALUE);
+                        r.write_bits(((i as u64) << REG_WIDTH) | v as u64, entry_len);
+                    }
+                }
+            }
+            ENC_FULL => {
+                for v in values {
+                    r.write_bits(min(v, MAX_VALUE) as u64, REG_WIDTH as usize);
+                }
+            }
+            enc => panic!("Unhandled encoding ordinal {}", enc),
+        }
+        return r.finish();
+    }
+
+    /// Writes the JSON format of Snowflake, i.e. the result of `HLL_EXPORT`.
+    /// Sparse sketches are written in the sparse format, dense ones in the dense format.
+    pub fn write_snowflake(&self) -> String {
+        #[derive(Serialize)]
+        struct SerializedHll {
+            precision: u8,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            sparse: Option<SparseEntries>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            dense: Option<Vec<u8>>,
+            version: u8,
+        }
+        #[derive(Serialize)]
+        #[allow(non_snake_case)]
+        struct SparseEntries {
+            indices: Vec<u32>,
+            maxLzCounts: Vec<u8>,
+        }
+
+        let values = self.bucket_values();
+        let (sparse, dense) = match self {
+            Sparse(_) => {
+                let mut indices = Vec::new();
+                let mut max_lz_counts = Vec::new();
+                for (i, v) in values.into_iter().enumerate() {
+                    if v != 0 {
+                        indices.push(i as u32);
+                        max_lz_counts.push(v);
+                    }
+                }
+                let entries = SparseEntries {
+                    indices,
+                    maxLzCounts: max_lz_counts,
+                };
+                (Some(entries), None)
+            }
+            Dense(_) => (None, Some(values)),
+        };
+        let ser = SerializedHll {
+            precision: self.index_bit_len(),
+            sparse,
+            dense,
+            version: 4,
+        };
+        return serde_json::to_string(&ser).unwrap();
+    }
+
+    /// Values of all buckets, as if the sketch was dense. Zero marks empty buckets.
+    fn bucket_values(&self) -> Vec<u8> {
+        match self {
+            Sparse(s) => {
+                let mut values = vec![0; number_of_buckets(s.index_bit_len) as usize];
+                s.each_bucket(|bucket, value| {
+                    let v = &mut values[bucket as usize];
+                    *v = max(*v, value);
+                });
+                return values;
+            }
+            Dense(d) => {
+                return (0..number_of_buckets(d.index_bit_len))
+                    .map(|b| d.get_value(b) as u8)
+                    .collect();
+            }
+        }
+    }
+
     pub fn read(data: &[u8]) -> Result<HllInstance> {
         if data.is_empty() {
             return Err(HllError::new("hll input data is empty"));
@@ -355,16 +475,30 @@ impl SparseHll {
             return Err(HllError::new("values and indices are or different lengths"));
         }
 
-        // Turn indices into the entries array inplace.
+        // Turn indices into the entries array inplace, skipping empty buckets.
         let mut entries = indices;
+        let mut len = 0;
         for i in 0..entries.len() {
             // TODO: validate range of index values.
-            // High bits are bucket index, followed by zeros and the actual value.
-            // Airlift stores actual bits of the hash in the available bits, but inputs of this
-            // function do not have this information, so we set those bits to 0.
+            if values[i] == 0 {
+                continue;
+            }
+            // High bits are bucket index, followed by the bits of the hash and the number of
+            // leading zeros after them. Inputs of this function do not have the original hash,
+            // so we produce the smallest hash that has the required number of leading zeros.
+            // This ensures [each_bucket] reports the same value back.
             let bucket = entries[i];
-            entries[i] = (bucket << (32 - index_bit_len)) | (values[i] as u32);
+            let zeros = values[i] - 1;
+            let bits = SparseHll::EXTENDED_PREFIX_BITS - index_bit_len;
+            let rest = if zeros < bits {
+                1 << (31 - index_bit_len - zeros)
+            } else {
+                (zeros - bits) as u32
+            };
+            entries[len] = (bucket << (32 - index_bit_len)) | rest;
+            len += 1;
         }
+        entries.truncate(len);
 
         // Sort by bucket index.
         entries
@@ -423,6 +557,30 @@ impl SparseHll {
         self.entries = self.merge_entries(o);
     }
 
+    pub fn insert_hash(&mut self, hash: u64) {
+        let bucket = compute_index(hash, SparseHll::EXTENDED_PREFIX_BITS);
+        let value = number_of_leading_zeros(hash, SparseHll::EXTENDED_PREFIX_BITS);
+
+        match self
+            .entries
+            .binary_search_by_key(&bucket, |e| SparseHll::decode_bucket_index(*e))
+        {
+            Ok(position) => {
+                if SparseHll::decode_bucket_value(self.entries[position]) < value {
+                    self.entries[position] = SparseHll::encode_entry(bucket, value);
+                }
+            }
+            Err(insertion_point) => {
+                // Grow the same way Airlift does, the capacity decides when we switch to dense.
+                if self.entries.len() == self.entries.capacity() {
+                    self.entries.reserve_exact(10);
+                }
+                self.entries
+                    .insert(insertion_point, SparseHll::encode_entry(bucket, value));
+            }
+        }
+    }
+
     pub fn to_dense(&self) -> DenseHll {
         // TODO: this can panic if Sparse HLL had too much precision.
         let mut d = DenseHll::new(self.index_bit_len);
@@ -827,8 +985,7 @@ impl DenseHll {
         }
     }
 
-    #[allow(dead_code)]
-    fn insert_hash(&mut self, hash: u64) {
+    pub fn insert_hash(&mut self, hash: u64) {
         let index = compute_index(hash, self.index_bit_len);
         let value = compute_value(hash, self.index_bit_len);
 
@@ -1127,7 +1284,6 @@ fn index_bit_length(n: u32) -> Result<u8> {
     }
 }
 
-#[allow(dead_code)]
 fn compute_index(hash: u64, index_bit_len: u8) -> u32 {
     return (hash >> (64 - index_bit_len)) as u32;
 }
@@ -1136,7 +1292,6 @@ fn compute_value(hash: u64, index_bit_len: u8) -> u8 {
     return number_of_leading_zeros(hash, index_bit_len) + 1;
 }
 
-#[allow(dead_code)]
 fn number_of_leading_zeros(hash: u64, index_bit_len: u8) -> u8 {
     // place a 1 in the LSB to preserve the original number of leading zeros if the hash happens to be 0.
     let value = (hash << index_bit_len) | (1 << (index_bit_len - 1));
@@ -1161,6 +1316,12 @@ fn linear_counting(zero_buckets: u32, total_buckets: u32) -> f64 {
     return total_f * (total_f / (zero_buckets as f64)).ln();
 }
 
+// Encodings of the HLL storage spec.
+const ENC_EMPTY: u8 = 1;
+const ENC_EXPLICIT: u8 = 2;
+const ENC_SPARSE: u8 = 3;
+const ENC_FULL: u8 = 4;
+
 // const TAG_SPARSE_V1: u8 = 0; // Unsupported.
 const TAG_DENSE_V1: u8 = 1;
 const TAG_SPARSE_V2: u8 = 2;
@@ -1207,6 +1368,42 @@ impl BitCursor<'_> {
     }
 }
 
+struct BitWriter {
+    output: Vec<u8>,
+    bit_pos: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter {
+            output: Vec::new(),
+            bit_pos: 0,
+        }
+    }
+
+    /// Writes [num_bits] lowest bits of [value], most significant bits first. This is the order
+    /// expected by [BitCursor].
+    pub fn write_bits(&mut self, value: u64, mut num_bits: usize) {
+        debug_assert!(num_bits <= 64);
+        while num_bits != 0 {
+            if self.bit_pos == 0 {
+                self.output.push(0);
+            }
+            let written_bits = min(num_bits, 8 - self.bit_pos);
+            let b = (value >> (num_bits - written_bits)) & ((1u64 << written_bits) - 1);
+            *self.output.last_mut().unwrap() |= (b << (8 - self.bit_pos - written_bits)) as u8;
+            num_bits -= written_bits;
+
+            self.bit_pos = (self.bit_pos + written_bits) % 8;
+        }
+    }
+
+    /// Returns the written bytes. The last byte is padded with zero bits.
+    pub fn finish(self) -> Vec<u8> {
+        return self.output;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::instance::{compute_index, compute_value, number_of_buckets};
@@ -1234,12 +1431,18 @@ mod tests {
             assert_eq!(
                 &sparse.entries,
                 &[
-                    233832449, 771751938, 1023410177, 1091567620, 1317011458, 1638924290,
-                    1898971139, 2335178753, 2440036353, 2552233986, 2647654404, 2785017858,
-                    3089104897, 3118465025, 3414163458, 3926917123, 3954180098, 4263510017
+                    234356736, 772014080, 1023934464, 1091633152, 1317273600, 1639186432,
+                    1899102208, 2335703040, 2440560640, 2552496128, 2647719936, 2785280000,
+                    3089629184, 3118989312, 3414425600, 3927048192, 3954442240, 4264034304
                 ]
             );
-            assert_eq!(sparse.to_dense().cardinality(), 18);
+            let dense = sparse.to_dense();
+            assert_eq!(dense.cardinality(), 18);
+            // Converting to dense must preserve the values.
+            assert_eq!(dense.get_value(223), 1);
+            assert_eq!(dense.get_value(1041), 4);
+            assert_eq!(dense.get_value(3745), 3);
+            assert_eq!(dense.get_value(224), 0);
 
             let dense = HllInstance::read_snowflake(
                 r#"{
@@ -1305,6 +1508,55 @@ mod tests {
             HllInstance::read_snowflake(r#"{ "precision": 1, "version": 4 }"#).unwrap_err();
         }
 
+        #[test]
+        fn test_write_snowflake() {
+            let sparse = r#"{"precision":12,"sparse":{"indices":[223,736,976,1041],"maxLzCounts":[1,2,1,4]},"version":4}"#;
+            let h = HllInstance::read_snowflake(sparse).unwrap();
+            assert_eq!(h.write_snowflake(), sparse);
+
+            let mut values = vec![0; 16];
+            values[1] = 3;
+            values[7] = 40; // overflows the delta.
+            let dense = format!(
+                r#"{{"precision":4,"dense":{},"version":4}}"#,
+                serde_json::to_string(&values).unwrap()
+            );
+            let h = HllInstance::read_snowflake(&dense).unwrap();
+            assert_eq!(h.write_snowflake(), dense);
+
+            let empty = HllInstance::new(4096).unwrap();
+            assert_eq!(
+                empty.write_snowflake(),
+                r#"{"precision":12,"sparse":{"indices":[],"maxLzCounts":[]},"version":4}"#
+            );
+        }
+
+        #[test]
+        fn test_write_hll_storage_spec() {
+            let read = |s: &str| HllInstance::read_hll_storage_spec(&hex::decode(s).unwrap());
+
+            // Empty.
+            let h = read("118b7f").unwrap();
+            assert_eq!(hex::encode(h.write_hll_storage_spec()), "118b7f");
+
+            // Sparse, same bytes after the round trip.
+            let sparse = "138b7f04a10642078507c308e309230a420ac10c2510a2114511611363138116811848188218a119411a821ae11f0122e223a125a126632685276327a328e2296129e52b812fe23081320132c133e335a53641368236a23721374237e1382138e13a813c243e6140e341854304434148a24a034f8150c1520152e254e155a1564157e158e35ac25b265b615c615fc1620166a368226a416a626c016c816d677163728275817a637a817ac37b617c247c427d677f6180e18101826382e1846184e18541858287e1880189218a418b818bc38e018ea290a19244938295e4988198c299e29b239b419c419ce49da1a1e1a321a381a4c1aa61acc2ae01b0a1b101b142b161b443b801bd02bd61bf61c263c4a3c501c7a1caa1cb03cd03cf03cf42d123d4c3d662d744d901dd01df81e001e0a2e641e7e3edc1f0a2f1c1f203f484f5c4f763fc84fdc1fe02fea1";
+            let h = read(sparse).unwrap();
+            assert_eq!(hex::encode(h.write_hll_storage_spec()), sparse);
+
+            // Dense sketches are written as FULL.
+            let mut h = HllInstance::new(2048).unwrap();
+            for i in 0..10_000 {
+                h.insert_hash(crate::murmur3::hash64_long(i));
+            }
+            let written = h.write_hll_storage_spec();
+            assert_eq!(&hex::encode(&written)[..6], "148b7f");
+            assert_eq!(written.len(), 3 + 2048 * 5 / 8);
+            let h2 = HllInstance::read_hll_storage_spec(&written).unwrap();
+            assert_eq!(h2.bucket_values(), h.bucket_values());
+            assert_eq!(h2.cardinality(), h.cardinality());
+        }
+
         #[test]
         fn test_hll_storage_spec() {
             let read = |s: &str| HllInstance::read_hll_storage_spec(&hex::decode(s).unwrap());
@@ -1559,7 +1811,91 @@ mod tests {
             assert_eq!(hll.cardinality(), 655);
         }
     }
-    // TODO: port tests for Sparse HLLs and HLLInstance.
+
+    mod insert {
+        use crate::instance::tests::TestingHll;
+        use crate::instance::{HllInstance, SparseHll};
+        use crate::murmur3::hash64_long;
+        use hex::FromHex;
+
+        #[test]
+        fn test_sparse_insert() {
+            let mut testing_hll = TestingHll::new(12);
+            let mut hll = SparseHll::new(12).unwrap();
+            for i in 0..100 {
+                let h = hash64_long(i);
+                testing_hll.insert_hash(h);
+                hll.insert_hash(h);
+                // Duplicates must not change anything.
+                hll.insert_hash(h);
+            }
+            assert_eq!(hll.entries.len(), 100);
+            assert!(hll.entries.windows(2).all(|w| w[0] < w[1]));
+
+            let dense = hll.to_dense();
+            for i in 0..testing_hll.buckets().len() {
+                assert_eq!(dense.get_value(i as u32), testing_hll.buckets()[i]);
+            }
+        }
+
+        #[test]
+        fn test_switch_to_dense() {
+            let mut testing_hll = TestingHll::new(12);
+            let mut hll = HllInstance::new(4096).unwrap();
+            for i in 0..10_000 {
+                let h = hash64_long(i);
+                testing_hll.insert_hash(h);
+                hll.insert_hash(h);
+            }
+            let dense = match &hll {
+                HllInstance::Dense(d) => d,
+                HllInstance::Sparse(_) => panic!("expected to switch to dense"),
+            };
+            for i in 0..testing_hll.buckets().len() {
+                assert_eq!(dense.get_value(i as u32), testing_hll.buckets()[i]);
+            }
+            let c = hll.cardinality();
+            assert!(9_700 <= c && c <= 10_300, "cardinality is {}", c);
+        }
+
+        /// Inserting hashes that land into the same buckets as in sketches produced by Presto
+        /// must produce identical bytes.
+        #[test]
+        fn test_presto_compatibility() {
+            // Sparse sketch with 2 elements, see `hyperloglog` in cubestore-sql-tests.
+            let presto = Vec::from_hex("020c0200c02ff58941d5f0c6").unwrap();
+            let mut hll = HllInstance::new(4096).unwrap();
+            for e in &[0x89f52fc0u32, 0xc6f0d541] {
+                hll.insert_hash(entry_to_hash(*e));
+            }
+            assert_eq!(hll.write(), presto);
+            assert_eq!(hll.cardinality(), 2);
+
+            // Dense sketch from `test_dense_linear_counting`.
+            let presto = match HllInstance::read(&Vec::from_hex(DENSE_SKETCH).unwrap()).unwrap() {
+                HllInstance::Dense(d) => d,
+                HllInstance::Sparse(_) => panic!("expected dense hll"),
+            };
+            let mut hll = HllInstance::new(4096).unwrap();
+            for bucket in 0..4096 {
+                let value = presto.get_value(bucket);
+                if value != 0 {
+                    hll.insert_hash(((bucket as u64) << 52) | (1 << (52 - value)));
+                }
+            }
+            assert_eq!(hll.write(), presto.write());
+            assert_eq!(hll.cardinality(), 655);
+        }
+
+        /// Produces a hash that is encoded as `entry` by the sparse representation.
+        fn entry_to_hash(entry: u32) -> u64 {
+            let bucket = (entry >> SparseHll::VALUE_BITS) as u64;
+            let zeros = entry & SparseHll::VALUE_MASK;
+            return (bucket << 38) | (1 << (37 - zeros));
+        }
+
+        const DENSE_SKETCH: &str = "030c004020000001000000000000000000000000000000000000050020000001030100000410000000004102100000000000000051000020000020003220000003102000000000001200042000000001000200000002000000100000030040000000010040003010000000000100002000000000000000000031000020000000000000000000100000200302000000000000000000001002000000000002204000000001000001000200400000000000001000020031100000000080000000002003000000100000000100110000000000000000000010000000000000000000000020000001320205000100000612000000000004100020100000000000000000001000000002200000100000001000001020000000000020000000000000001000010300060000010000000000070100003000000000000020000000000001000010000104000000000000000000101000100000001401000000000000000000000000000100010000000000000000000000000400020000000002002300010000000000040000041000200005100000000000001000000000100000203010000000000000000000000000001006000100000000000000300100001000100254200000000000101100040000000020000010000050000000501000000000101020000000010000000003000000000200000102100000000204007000000200010000033000000000061000000000000000000000000000000000100001000001000000013000000003000000000002000000000000010001000000000000000000020010000020000000100001000000000000001000103000000000000000000020020000001000000000100001000000000000000020220200200000001001000010100000000200000000000001000002000000011000000000101200000000000000000000000000000000000000100130000000000000000000100000120000300040000000002000000000000000000000100000000070000100000000301000000401200002020000000000601030001510000000000000110100000000000000000050000000010000100000000000000000100022000100000101054010001000000000000001000001000000002000000000100000000000021000001000002000000000100000000000000000000951000000100000000000000000000000000102000200000000000000010000010000000000100002000000000000000000010000000000000010000000010000000102010000000010520100000021010100000030000000000000000100000001000000022000330051000000100000000000040003020000010000020000100000013000000102020000000050000000020010000000000000000101200c000100000001200400000000010000001000000000100010000000001000001000000100000000010000000004000000002000013102000100000000000000000000000600000010000000000000020000000000001000000000030000000000000020000000001000001000000000010000003002000003000200070001001003030010000000003000000000000020000006000000000000000011000000010000200000000000500000000000000020500000000003000000000000000004000030000100000000103000001000000000000200002004200000020000000030000000000000000000000002000100000000000000002000000000000000010020101000000005250000010000000000023010000001000000000000500002001000123100030011000020001310600000000000021000023000003000000000000000001000000000000220200000000004040000020201000000010201000000000020000400010000050000000000000000000000010000020000000000000000000000000000000000102000010000000000000000000000002010000200200000000000000000000000000100000000000000000200400000000010000000000000000000000000000000010000200300000000000100110000000000000000000000000010000030000001000000000010000010200013000000000000200000001000001200010000000010000000000001000000000000100000000410000040000001000100010000100000002001010000000000000000001000000000000010000000000000000000000002000000000001100001000000001010000000000000002200000000004000000000000100010000000000600000000100300000000000000000000010000003000000000000000000310000010100006000010001000000000000001010101000100000000000000000000000000000201000000000000000700010000030000000000000021000000000000000001020000000030000100001000000000000000000000004010100000000000000000000004000000040100000040100100001000000000300000100000000010010000300000200000000000001302000000000000000000100100000400030000001001000100100002300000004030000002010000220100000000000002000000010010000000003010500000000300000000005020102000200000000000000020100000000000000000000000011000000023000000000010000101000000000000010020040200040000020000004000020000000001000000000100000200000010000000000030100010001000000100000000000600400000000002000000000000132000000900010000000030021400000000004100006000304000000000000010000106000001300020000";
+    }
 
     struct TestingHll {
         index_bit_length: u8,
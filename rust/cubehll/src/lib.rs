@@ -15,6 +15,7 @@
 mod bias_correction;
 mod error;
 mod instance;
+pub mod murmur3;
 mod sketch;
 
 pub use error::HllError;
@@ -0,0 +1,118 @@
+/*
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Port of `Murmur3Hash128` from Airlift, which is used to hash values added to its HyperLogLog.
+const C1: u64 = 0x87c37b91114253d5;
+const C2: u64 = 0x4cf5ad432745937f;
+const DEFAULT_SEED: u64 = 0;
+
+/// Returns the 64 most significant bits of the Murmur3 128-bit hash of `data`.
+/// Matches `Murmur3Hash128.hash64(Slice)` in Airlift.
+pub fn hash64(data: &[u8]) -> u64 {
+    let mut h1 = DEFAULT_SEED;
+    let mut h2 = DEFAULT_SEED;
+
+    let fast_limit = data.len() - data.len() % 16;
+    let mut i = 0;
+    while i < fast_limit {
+        let k1 = LittleEndian::read_u64(&data[i..]);
+        let k2 = LittleEndian::read_u64(&data[i + 8..]);
+
+        h1 ^= mix_k1(k1);
+        h1 = h1.rotate_left(27);
+        h1 = h1.wrapping_add(h2);
+        h1 = h1.wrapping_mul(5).wrapping_add(0x52dce729);
+
+        h2 ^= mix_k2(k2);
+        h2 = h2.rotate_left(31);
+        h2 = h2.wrapping_add(h1);
+        h2 = h2.wrapping_mul(5).wrapping_add(0x38495ab5);
+
+        i += 16;
+    }
+
+    let tail = &data[fast_limit..];
+    if tail.len() > 8 {
+        h2 ^= mix_k2(read_partial_u64(&tail[8..]));
+    }
+    if !tail.is_empty() {
+        h1 ^= mix_k1(read_partial_u64(&tail[..tail.len().min(8)]));
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+
+    return h1.wrapping_add(h2);
+}
+
+/// Hash of a single 64-bit value, same as `Murmur3Hash128.hash64(long)` in Airlift.
+/// Presto uses this for `BIGINT` and, after `Double.doubleToLongBits`, for `DOUBLE` values.
+pub fn hash64_long(value: i64) -> u64 {
+    return hash64(&value.to_le_bytes());
+}
+
+fn read_partial_u64(bytes: &[u8]) -> u64 {
+    debug_assert!(bytes.len() <= 8);
+    let mut r = 0;
+    for i in 0..bytes.len() {
+        r |= (bytes[i] as u64) << (8 * i);
+    }
+    return r;
+}
+
+fn mix_k1(k1: u64) -> u64 {
+    return k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+}
+
+fn mix_k2(k2: u64) -> u64 {
+    return k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+}
+
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51afd7ed558ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ceb9fe1a85ec53);
+    k ^= k >> 33;
+    return k;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::murmur3::{hash64, hash64_long};
+
+    #[test]
+    fn test_hash64() {
+        assert_eq!(hash64(b""), 0);
+        assert_eq!(hash64(b"hello"), 0xcbd8a7b341bd9b02);
+        assert_eq!(
+            hash64(b"The quick brown fox jumps over the lazy dog"),
+            0xe34bbc7bbc071b6c
+        );
+    }
+
+    #[test]
+    fn test_hash64_long() {
+        for v in &[0, 1, -1, 42, i64::MIN, i64::MAX] {
+            assert_eq!(hash64_long(*v), hash64(&v.to_le_bytes()));
+        }
+    }
+}
@@ -13,15 +13,15 @@
  */
 
 use crate::error::Result;
-use crate::instance::HllInstance;
+use crate::instance::{DenseHll, HllInstance};
 
 /// HyperLogLog sketch estimates a size of a set (i.e. the number of unique elements in it) without
 /// storing all the elements in the set.
 ///
 /// Port of the HyperLogLog from Airlift.
-/// You can deserialize sketches produced by Airlift by using `read()`.
-/// In fact, the code to add new elements has not been ported yet, so reading existing sketches is
-/// the only way to produce non-empty sets at this point.
+/// You can deserialize sketches produced by Airlift by using `read()` or build new ones with
+/// `insert_hash()`. Values must be hashed with the functions from `murmur3` to produce sketches
+/// that are compatible with Airlift.
 #[derive(Debug, Clone)]
 pub struct HllSketch {
     instance: HllInstance,
@@ -64,15 +64,40 @@ impl HllSketch {
         });
     }
 
+    /// Create a dense sketch from the values of all buckets, e.g. to convert sketches produced by
+    /// other HyperLogLog implementations. There must be exactly `2^index_bit_len` values.
+    pub fn from_dense_values(index_bit_len: u8, values: Vec<u8>) -> Result<HllSketch> {
+        return Ok(HllSketch {
+            instance: HllInstance::Dense(DenseHll::new_from_entries(index_bit_len, values)?),
+        });
+    }
+
     pub fn write(&self) -> Vec<u8> {
         return self.instance.write();
     }
 
+    /// Write in the format of https://github.com/aggregateknowledge/hll-storage-spec, e.g. to be
+    /// read by the `hll` extension of Postgres.
+    pub fn write_hll_storage_spec(&self) -> Vec<u8> {
+        return self.instance.write_hll_storage_spec();
+    }
+
+    /// Write in the snowflake JSON format, i.e. the same as produced by HLL_EXPORT.
+    pub fn write_snowflake(&self) -> String {
+        return self.instance.write_snowflake();
+    }
+
     /// Produces an estimate of the current set size.
     pub fn cardinality(&self) -> u64 {
         return self.instance.cardinality();
     }
 
+    /// Adds an element with the specified hash to the set.
+    /// Use `murmur3::hash64` or `murmur3::hash64_long` to get the same results as Airlift.
+    pub fn insert_hash(&mut self, hash: u64) {
+        self.instance.insert_hash(hash);
+    }
+
     /// Merges elements from `o` into the current sketch.
     /// Afterwards the current sketch estimates the size of the union.
     ///
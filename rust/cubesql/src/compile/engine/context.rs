@@ -1,66 +1,124 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use datafusion::error::Result;
 use datafusion::{scalar::ScalarValue, variable::VarProvider};
 
+/// System variables of the session. They're read by `@@name` and `@@session.name` expressions
+/// and listed by `SHOW VARIABLES` via `information_schema.session_variables`.
 pub struct SystemVar {
-    variables: HashMap<String, ScalarValue>,
+    variables: BTreeMap<String, ScalarValue>,
 }
 
 impl SystemVar {
     /// new system variable
     pub fn new() -> Self {
-        let mut variables = HashMap::new();
+        let mut variables = BTreeMap::new();
         variables.insert(
-            "@@version_comment".to_string(),
+            "version_comment".to_string(),
             ScalarValue::Utf8(Some("mysql".to_string())),
         );
         variables.insert(
-            "@@sessionauto_increment_increment".to_string(),
+            "auto_increment_increment".to_string(),
             ScalarValue::Int64(Some(1)),
         );
         variables.insert(
-            "@@character_set_client".to_string(),
+            "character_set_client".to_string(),
             ScalarValue::Utf8(Some("utf8mb4".to_string())),
         );
         variables.insert(
-            "@@character_set_connection".to_string(),
+            "character_set_connection".to_string(),
             ScalarValue::Utf8(Some("utf8mb4".to_string())),
         );
         variables.insert(
-            "@@character_set_results".to_string(),
+            "character_set_results".to_string(),
             ScalarValue::Utf8(Some("utf8mb4".to_string())),
         );
         variables.insert(
-            "@@character_set_server".to_string(),
+            "character_set_server".to_string(),
             ScalarValue::Utf8(Some("utf8mb4".to_string())),
         );
         variables.insert(
-            "@@collation_connection".to_string(),
+            "collation_connection".to_string(),
             ScalarValue::Utf8(Some("utf8mb4_general_ci".to_string())),
         );
         variables.insert(
-            "@@system_time_zone".to_string(),
+            "system_time_zone".to_string(),
             ScalarValue::Utf8(Some("UTC".to_string())),
         );
         variables.insert(
-            "@@time_zone".to_string(),
+            "time_zone".to_string(),
             ScalarValue::Utf8(Some("SYSTEM".to_string())),
         );
+        variables.insert(
+            "sql_mode".to_string(),
+            ScalarValue::Utf8(Some(
+                "ONLY_FULL_GROUP_BY,STRICT_TRANS_TABLES,NO_ZERO_IN_DATE,NO_ZERO_DATE,ERROR_FOR_DIVISION_BY_ZERO,NO_ENGINE_SUBSTITUTION".to_string(),
+            )),
+        );
+        variables.insert(
+            "lower_case_table_names".to_string(),
+            ScalarValue::Int64(Some(0)),
+        );
+        variables.insert(
+            "transaction_isolation".to_string(),
+            ScalarValue::Utf8(Some("REPEATABLE-READ".to_string())),
+        );
 
         Self { variables }
     }
+
+    /// Variables sorted by name, names are without `@@` prefix.
+    pub fn all(&self) -> impl Iterator<Item = (&String, &ScalarValue)> {
+        self.variables.iter()
+    }
 }
 
 impl VarProvider for SystemVar {
     /// get system variable value
     fn get_value(&self, var_names: Vec<String>) -> Result<ScalarValue> {
-        let key = var_names.concat();
+        // `@@name` comes as a single part, `@@session.name` and `@@global.name` as two parts
+        let name = match var_names.as_slice() {
+            [scope, name]
+                if scope.eq_ignore_ascii_case("@@session")
+                    || scope.eq_ignore_ascii_case("@@global") =>
+            {
+                name.to_lowercase()
+            }
+            _ => var_names.concat().trim_start_matches('@').to_lowercase(),
+        };
 
-        if let Some(value) = self.variables.get(&key) {
+        if let Some(value) = self.variables.get(&name) {
             Ok(value.clone())
         } else {
             Ok(ScalarValue::Utf8(None))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_value() {
+        let vars = SystemVar::new();
+
+        assert_eq!(
+            vars.get_value(vec!["@@transaction_isolation".to_string()])
+                .unwrap(),
+            ScalarValue::Utf8(Some("REPEATABLE-READ".to_string()))
+        );
+        assert_eq!(
+            vars.get_value(vec![
+                "@@SESSION".to_string(),
+                "auto_increment_increment".to_string()
+            ])
+            .unwrap(),
+            ScalarValue::Int64(Some(1))
+        );
+        assert_eq!(
+            vars.get_value(vec!["@@unknown".to_string()]).unwrap(),
+            ScalarValue::Utf8(None)
+        );
+    }
+}
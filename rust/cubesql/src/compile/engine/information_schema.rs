@@ -0,0 +1,368 @@
+use std::sync::Arc;
+
+use datafusion::arrow::array::{ArrayRef, StringArray, UInt64Array};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::{MemTable, TableProvider};
+use datafusion::error::Result;
+
+use crate::compile::TenantContext;
+use crate::schema::{CubeColumn, V1CubeMetaExt};
+
+use super::context::SystemVar;
+
+/// Tables of `information_schema` which are emulated from the meta of cubes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InfoSchemaTable {
+    Tables,
+    Columns,
+    Schemata,
+    KeyColumnUsage,
+    SessionVariables,
+    Collations,
+}
+
+impl InfoSchemaTable {
+    pub fn all() -> Vec<InfoSchemaTable> {
+        vec![
+            InfoSchemaTable::Tables,
+            InfoSchemaTable::Columns,
+            InfoSchemaTable::Schemata,
+            InfoSchemaTable::KeyColumnUsage,
+            InfoSchemaTable::SessionVariables,
+            InfoSchemaTable::Collations,
+        ]
+    }
+
+    pub fn from_name(name: &str) -> Option<InfoSchemaTable> {
+        InfoSchemaTable::all()
+            .into_iter()
+            .find(|t| t.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InfoSchemaTable::Tables => "tables",
+            InfoSchemaTable::Columns => "columns",
+            InfoSchemaTable::Schemata => "schemata",
+            InfoSchemaTable::KeyColumnUsage => "key_column_usage",
+            InfoSchemaTable::SessionVariables => "session_variables",
+            InfoSchemaTable::Collations => "collations",
+        }
+    }
+
+    fn schema(&self) -> SchemaRef {
+        match self {
+            InfoSchemaTable::Tables => Arc::new(Schema::new(vec![
+                Field::new("table_catalog", DataType::Utf8, false),
+                Field::new("table_schema", DataType::Utf8, false),
+                Field::new("table_name", DataType::Utf8, false),
+                Field::new("table_type", DataType::Utf8, false),
+                Field::new("engine", DataType::Utf8, true),
+                Field::new("table_comment", DataType::Utf8, false),
+            ])),
+            InfoSchemaTable::Columns => Arc::new(Schema::new(vec![
+                Field::new("table_catalog", DataType::Utf8, false),
+                Field::new("table_schema", DataType::Utf8, false),
+                Field::new("table_name", DataType::Utf8, false),
+                Field::new("column_name", DataType::Utf8, false),
+                Field::new("ordinal_position", DataType::UInt64, false),
+                Field::new("column_default", DataType::Utf8, true),
+                Field::new("is_nullable", DataType::Utf8, false),
+                Field::new("data_type", DataType::Utf8, false),
+                Field::new("column_type", DataType::Utf8, false),
+                Field::new("collation_name", DataType::Utf8, true),
+                Field::new("column_key", DataType::Utf8, false),
+                Field::new("extra", DataType::Utf8, false),
+                Field::new("privileges", DataType::Utf8, false),
+                Field::new("column_comment", DataType::Utf8, false),
+            ])),
+            InfoSchemaTable::Schemata => Arc::new(Schema::new(vec![
+                Field::new("catalog_name", DataType::Utf8, false),
+                Field::new("schema_name", DataType::Utf8, false),
+                Field::new("default_character_set_name", DataType::Utf8, false),
+                Field::new("default_collation_name", DataType::Utf8, false),
+            ])),
+            InfoSchemaTable::KeyColumnUsage => Arc::new(Schema::new(vec![
+                Field::new("constraint_catalog", DataType::Utf8, false),
+                Field::new("constraint_schema", DataType::Utf8, false),
+                Field::new("constraint_name", DataType::Utf8, false),
+                Field::new("table_catalog", DataType::Utf8, false),
+                Field::new("table_schema", DataType::Utf8, false),
+                Field::new("table_name", DataType::Utf8, false),
+                Field::new("column_name", DataType::Utf8, false),
+                Field::new("ordinal_position", DataType::UInt64, false),
+                Field::new("referenced_table_schema", DataType::Utf8, true),
+                Field::new("referenced_table_name", DataType::Utf8, true),
+                Field::new("referenced_column_name", DataType::Utf8, true),
+            ])),
+            InfoSchemaTable::SessionVariables => Arc::new(Schema::new(vec![
+                Field::new("variable_name", DataType::Utf8, false),
+                Field::new("variable_value", DataType::Utf8, false),
+            ])),
+            InfoSchemaTable::Collations => Arc::new(Schema::new(vec![
+                Field::new("collation_name", DataType::Utf8, false),
+                Field::new("character_set_name", DataType::Utf8, false),
+                Field::new("id", DataType::UInt64, false),
+                Field::new("is_default", DataType::Utf8, false),
+                Field::new("is_compiled", DataType::Utf8, false),
+                Field::new("sortlen", DataType::UInt64, false),
+                Field::new("pad_attribute", DataType::Utf8, false),
+            ])),
+        }
+    }
+
+    fn batch(&self, ctx: &TenantContext) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = match self {
+            InfoSchemaTable::Tables => {
+                let mut rows: Vec<(&str, String, &str, Option<&str>, String)> = ctx
+                    .cubes
+                    .iter()
+                    .map(|cube| {
+                        (
+                            "db",
+                            cube.name.clone(),
+                            "BASE TABLE",
+                            Some("InnoDB"),
+                            cube.title.clone().unwrap_or_default(),
+                        )
+                    })
+                    .collect();
+
+                for table in InfoSchemaTable::all() {
+                    rows.push((
+                        "information_schema",
+                        table.name().to_string(),
+                        "SYSTEM VIEW",
+                        None,
+                        "".to_string(),
+                    ));
+                }
+
+                vec![
+                    Arc::new(StringArray::from(vec!["def"; rows.len()])),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.1.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.2).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.3).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.4.as_str()).collect::<Vec<_>>(),
+                    )),
+                ]
+            }
+            InfoSchemaTable::Columns => {
+                let mut rows: Vec<(String, u64, CubeColumn)> = vec![];
+
+                for cube in ctx.cubes.iter() {
+                    for (i, column) in cube.get_columns().into_iter().enumerate() {
+                        rows.push((cube.name.clone(), i as u64 + 1, column));
+                    }
+                }
+
+                vec![
+                    Arc::new(StringArray::from(vec!["def"; rows.len()])),
+                    Arc::new(StringArray::from(vec!["db"; rows.len()])),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.0.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter()
+                            .map(|r| r.2.get_name().as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        rows.iter().map(|r| r.1).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(vec![None as Option<&str>; rows.len()])),
+                    Arc::new(StringArray::from(
+                        rows.iter()
+                            .map(|r| if r.2.mysql_can_be_null() { "YES" } else { "NO" })
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter()
+                            .map(|r| r.2.mysql_data_type())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter()
+                            .map(|r| r.2.mysql_type_as_str().as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter()
+                            .map(|r| r.2.mysql_collation())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(vec![""; rows.len()])),
+                    Arc::new(StringArray::from(vec![""; rows.len()])),
+                    Arc::new(StringArray::from(vec!["select"; rows.len()])),
+                    Arc::new(StringArray::from(vec![""; rows.len()])),
+                ]
+            }
+            InfoSchemaTable::Schemata => {
+                let schemas = vec!["db", "information_schema"];
+
+                vec![
+                    Arc::new(StringArray::from(vec!["def"; schemas.len()])),
+                    Arc::new(StringArray::from(schemas.clone())),
+                    Arc::new(StringArray::from(vec!["utf8mb4"; schemas.len()])),
+                    Arc::new(StringArray::from(vec!["utf8mb4_0900_ai_ci"; schemas.len()])),
+                ]
+            }
+            // Cubes don't have keys, but BI tools expect this table to exist
+            InfoSchemaTable::KeyColumnUsage => {
+                let mut columns: Vec<ArrayRef> = vec![];
+
+                for field in self.schema().fields() {
+                    columns.push(match field.data_type() {
+                        DataType::UInt64 => Arc::new(UInt64Array::from(Vec::<u64>::new())),
+                        _ => Arc::new(StringArray::from(Vec::<&str>::new())),
+                    });
+                }
+
+                columns
+            }
+            InfoSchemaTable::SessionVariables => {
+                let variables = SystemVar::new();
+                let rows = variables
+                    .all()
+                    .map(|(name, value)| (name.as_str(), value.to_string()))
+                    .collect::<Vec<_>>();
+
+                vec![
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.1.as_str()).collect::<Vec<_>>(),
+                    )),
+                ]
+            }
+            // Everything is stored and returned in UTF-8
+            InfoSchemaTable::Collations => {
+                let rows: Vec<(&str, u64, &str, u64, &str)> = vec![
+                    ("utf8mb4_general_ci", 45, "", 1, "PAD SPACE"),
+                    ("utf8mb4_bin", 46, "", 1, "PAD SPACE"),
+                    ("utf8mb4_0900_ai_ci", 255, "Yes", 0, "NO PAD"),
+                ];
+
+                vec![
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(vec!["utf8mb4"; rows.len()])),
+                    Arc::new(UInt64Array::from(
+                        rows.iter().map(|r| r.1).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.2).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(vec!["Yes"; rows.len()])),
+                    Arc::new(UInt64Array::from(
+                        rows.iter().map(|r| r.3).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        rows.iter().map(|r| r.4).collect::<Vec<_>>(),
+                    )),
+                ]
+            }
+        };
+
+        Ok(RecordBatch::try_new(self.schema(), columns)?)
+    }
+
+    pub fn to_provider(&self, ctx: &TenantContext) -> Result<Arc<dyn TableProvider>> {
+        let batch = self.batch(ctx)?;
+
+        Ok(Arc::new(MemTable::try_new(
+            self.schema(),
+            vec![vec![batch]],
+        )?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cubeclient::models::{V1CubeMeta, V1CubeMetaDimension, V1CubeMetaMeasure};
+
+    use super::*;
+
+    fn get_test_tenant_ctx() -> TenantContext {
+        TenantContext {
+            cubes: vec![V1CubeMeta {
+                name: "Orders".to_string(),
+                title: Some("Orders".to_string()),
+                dimensions: vec![V1CubeMetaDimension {
+                    name: "Orders.createdAt".to_string(),
+                    _type: "time".to_string(),
+                }],
+                measures: vec![V1CubeMetaMeasure {
+                    name: "Orders.count".to_string(),
+                    title: None,
+                    _type: "number".to_string(),
+                    agg_type: Some("count".to_string()),
+                }],
+                segments: vec![],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_info_schema_batches() {
+        let ctx = get_test_tenant_ctx();
+
+        let tables = InfoSchemaTable::Tables.batch(&ctx).unwrap();
+        assert_eq!(tables.num_rows(), 1 + InfoSchemaTable::all().len());
+
+        let columns = InfoSchemaTable::Columns.batch(&ctx).unwrap();
+        assert_eq!(columns.num_rows(), 2);
+
+        let column_types = columns
+            .column(8)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(column_types.value(0), "int");
+        assert_eq!(column_types.value(1), "datetime");
+
+        assert_eq!(InfoSchemaTable::Schemata.batch(&ctx).unwrap().num_rows(), 2);
+        assert_eq!(
+            InfoSchemaTable::KeyColumnUsage
+                .batch(&ctx)
+                .unwrap()
+                .num_rows(),
+            0
+        );
+
+        let variables = InfoSchemaTable::SessionVariables.batch(&ctx).unwrap();
+        let names = variables
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert!((0..names.len()).any(|i| names.value(i) == "sql_mode"));
+
+        assert_eq!(
+            InfoSchemaTable::Collations.batch(&ctx).unwrap().num_rows(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_info_schema_from_name() {
+        assert_eq!(
+            InfoSchemaTable::from_name("TABLES"),
+            Some(InfoSchemaTable::Tables)
+        );
+        assert_eq!(InfoSchemaTable::from_name("routines"), None);
+    }
+}
@@ -1,2 +1,4 @@
 pub mod context;
+pub mod information_schema;
+pub mod provider;
 pub mod udf;
@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use datafusion::catalog::TableReference;
+use datafusion::datasource::TableProvider;
+use datafusion::execution::context::ExecutionContextState;
+use datafusion::physical_plan::udaf::AggregateUDF;
+use datafusion::physical_plan::udf::ScalarUDF;
+use datafusion::sql::planner::ContextProvider;
+
+use crate::compile::TenantContext;
+
+use super::information_schema::InfoSchemaTable;
+
+/// Resolves `information_schema` tables from the meta of cubes, everything else
+/// is delegated to the state of DataFusion execution context.
+pub struct CubeContext<'a> {
+    state: &'a ExecutionContextState,
+    meta: Arc<TenantContext>,
+}
+
+impl<'a> CubeContext<'a> {
+    pub fn new(state: &'a ExecutionContextState, meta: Arc<TenantContext>) -> Self {
+        Self { state, meta }
+    }
+}
+
+impl ContextProvider for CubeContext<'_> {
+    fn get_table_provider(&self, name: TableReference) -> Option<Arc<dyn TableProvider>> {
+        match name {
+            TableReference::Partial { schema, table }
+                if schema.eq_ignore_ascii_case("information_schema") =>
+            {
+                InfoSchemaTable::from_name(table).and_then(|t| t.to_provider(&self.meta).ok())
+            }
+            _ => self.state.get_table_provider(name),
+        }
+    }
+
+    fn get_function_meta(&self, name: &str) -> Option<Arc<ScalarUDF>> {
+        self.state.get_function_meta(name)
+    }
+
+    fn get_aggregate_meta(&self, name: &str) -> Option<Arc<AggregateUDF>> {
+        self.state.get_aggregate_meta(name)
+    }
+}
@@ -8,6 +8,7 @@ use datafusion::sql::planner::SqlToRel;
 use datafusion::variable::VarType;
 use datafusion::{logical_plan::LogicalPlan, prelude::*};
 use log::{debug, trace};
+use regex::Regex;
 use serde::Serialize;
 use serde_json::json;
 use sqlparser::ast::{self, Ident, ObjectName};
@@ -23,13 +24,16 @@ pub use crate::schema::ctx::*;
 use crate::CubeError;
 use crate::{
     compile::builder::QueryBuilder,
-    schema::{ctx, V1CubeMetaDimensionExt, V1CubeMetaMeasureExt, V1CubeMetaSegmentExt},
+    schema::{
+        ctx, V1CubeMetaDimensionExt, V1CubeMetaExt, V1CubeMetaMeasureExt, V1CubeMetaSegmentExt,
+    },
 };
 use msql_srv::{AsyncMysqlShim, ColumnType};
 
 use self::builder::*;
 use self::context::*;
 use self::engine::context::SystemVar;
+use self::engine::provider::CubeContext;
 use self::engine::udf::{create_connection_id_udf, create_db_udf, create_version_udf};
 
 pub mod builder;
@@ -1085,7 +1089,11 @@ impl QueryPlanner {
                     }
                 }
             }
-            ast::Statement::SetVariable { .. } => {
+            // Transactions are not supported, every statement works in autocommit mode
+            ast::Statement::SetVariable { .. }
+            | ast::Statement::StartTransaction { .. }
+            | ast::Statement::Commit { .. }
+            | ast::Statement::Rollback { .. } => {
                 return Ok(QueryPlan::Meta(Arc::new(dataframe::DataFrame::new(
                     vec![],
                     vec![],
@@ -1094,9 +1102,22 @@ impl QueryPlanner {
             ast::Statement::ShowVariable { variable } => {
                 return self.show_variable_to_plan(variable, props);
             }
-            // Proxy some queries to DF
-            ast::Statement::ShowColumns { .. } | ast::Statement::ShowVariable { .. } => {
-                return self.create_df_logical_plan(stmt.clone(), props);
+            ast::Statement::ShowColumns {
+                full,
+                table_name,
+                filter,
+                ..
+            } => {
+                return self.show_columns_to_plan(*full, table_name, filter, props);
+            }
+            ast::Statement::ExplainTable { table_name, .. } => {
+                return self.show_columns_to_plan(false, table_name, &None, props);
+            }
+            ast::Statement::ShowCreate { obj_type, obj_name } => {
+                return self.show_create_to_plan(obj_type, obj_name);
+            }
+            ast::Statement::Explain { statement, .. } => {
+                return self.explain_to_plan(statement, props);
             }
             _ => {
                 return Err(CompilationError::Unsupported(
@@ -1105,6 +1126,11 @@ impl QueryPlanner {
             }
         };
 
+        // Virtual catalog is queried via DF
+        if Self::is_information_schema_select(select) {
+            return self.create_df_logical_plan(stmt.clone(), props);
+        }
+
         if !select.cluster_by.is_empty() {
             return Err(CompilationError::Unsupported(
                 "Query with CLUSTER BY instruction(s)".to_string(),
@@ -1243,6 +1269,181 @@ impl QueryPlanner {
         }
     }
 
+    fn is_information_schema_select(select: &ast::Select) -> bool {
+        select.from.iter().any(|from| {
+            std::iter::once(&from.relation)
+                .chain(from.joins.iter().map(|join| &join.relation))
+                .any(|relation| match relation {
+                    ast::TableFactor::Table {
+                        name: ast::ObjectName(identifiers),
+                        ..
+                    } => {
+                        identifiers.len() == 2
+                            && identifiers[0]
+                                .value
+                                .eq_ignore_ascii_case("information_schema")
+                    }
+                    // Rewritten SHOW statements select from a subquery over information_schema
+                    ast::TableFactor::Derived { subquery, .. } => match &subquery.body {
+                        ast::SetExpr::Select(select) => Self::is_information_schema_select(select),
+                        _ => false,
+                    },
+                    _ => false,
+                })
+        })
+    }
+
+    fn find_cube_for_table_name(
+        &self,
+        table_name: &ObjectName,
+    ) -> CompilationResult<cubeclient::models::V1CubeMeta> {
+        let name = match table_name.0.last() {
+            Some(ident) => ident.value.clone(),
+            None => {
+                return Err(CompilationError::User(
+                    "Table name is not specified".to_string(),
+                ))
+            }
+        };
+
+        self.context
+            .find_cube_with_name(name.clone())
+            .ok_or_else(|| CompilationError::User(format!("Unknown table: {}", name)))
+    }
+
+    fn show_columns_to_plan(
+        &self,
+        full: bool,
+        table_name: &ObjectName,
+        filter: &Option<ast::ShowStatementFilter>,
+        props: &QueryPlannerExecutionProps,
+    ) -> CompilationResult<QueryPlan> {
+        let cube = self.find_cube_for_table_name(table_name)?;
+
+        let projection = if full {
+            "column_name AS `Field`, column_type AS `Type`, collation_name AS `Collation`, \
+            is_nullable AS `Null`, column_key AS `Key`, column_default AS `Default`, \
+            extra AS `Extra`, privileges AS `Privileges`, column_comment AS `Comment`"
+        } else {
+            "column_name AS `Field`, column_type AS `Type`, is_nullable AS `Null`, \
+            column_key AS `Key`, column_default AS `Default`, extra AS `Extra`"
+        };
+
+        let query = format!(
+            "SELECT {} FROM information_schema.columns WHERE table_schema = 'db' AND table_name = {}",
+            projection,
+            quote_string_literal(&cube.name)
+        );
+
+        self.introspection_query_to_plan(query, "Field", filter, props)
+    }
+
+    fn show_create_to_plan(
+        &self,
+        obj_type: &ast::ShowCreateObject,
+        obj_name: &ObjectName,
+    ) -> CompilationResult<QueryPlan> {
+        match obj_type {
+            ast::ShowCreateObject::Table => {}
+            _ => {
+                return Err(CompilationError::Unsupported(format!(
+                    "SHOW CREATE {}",
+                    obj_type
+                )))
+            }
+        };
+
+        let cube = self.find_cube_for_table_name(obj_name)?;
+
+        let fields = cube
+            .get_columns()
+            .iter()
+            .map(|column| {
+                format!(
+                    "`{}` {}{}",
+                    column.get_name(),
+                    column.mysql_type_as_str(),
+                    if column.mysql_can_be_null() {
+                        ""
+                    } else {
+                        " NOT NULL"
+                    }
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Ok(QueryPlan::Meta(Arc::new(dataframe::DataFrame::new(
+            vec![
+                dataframe::Column::new("Table".to_string(), ColumnType::MYSQL_TYPE_STRING),
+                dataframe::Column::new("Create Table".to_string(), ColumnType::MYSQL_TYPE_STRING),
+            ],
+            vec![dataframe::Row::new(vec![
+                dataframe::TableValue::String(cube.name.clone()),
+                dataframe::TableValue::String(format!(
+                    "CREATE TABLE `{}` (\r\n  {}\r\n) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4",
+                    cube.name,
+                    fields.join(",\r\n  ")
+                )),
+            ])],
+        ))))
+    }
+
+    fn explain_to_plan(
+        &self,
+        statement: &ast::Statement,
+        props: &QueryPlannerExecutionProps,
+    ) -> CompilationResult<QueryPlan> {
+        let plan = self.plan(statement, props)?;
+
+        Ok(QueryPlan::Meta(Arc::new(dataframe::DataFrame::new(
+            vec![dataframe::Column::new(
+                "Execution Plan".to_string(),
+                ColumnType::MYSQL_TYPE_STRING,
+            )],
+            vec![dataframe::Row::new(vec![dataframe::TableValue::String(
+                plan.print(true)
+                    .map_err(|e| CompilationError::Internal(e.message))?,
+            )])],
+        ))))
+    }
+
+    /// Plans query to the virtual catalog, `filter` of SHOW statement is applied on top of it.
+    /// LIKE is compared with `like_column`, WHERE can reference any column of the result.
+    fn introspection_query_to_plan(
+        &self,
+        query: String,
+        like_column: &str,
+        filter: &Option<ast::ShowStatementFilter>,
+        props: &QueryPlannerExecutionProps,
+    ) -> CompilationResult<QueryPlan> {
+        let query = match filter {
+            None => query,
+            Some(ast::ShowStatementFilter::Like(pattern)) => format!(
+                "SELECT * FROM ({}) AS t WHERE `{}` LIKE {}",
+                query,
+                like_column,
+                quote_string_literal(pattern)
+            ),
+            Some(ast::ShowStatementFilter::Where(expr)) => {
+                format!("SELECT * FROM ({}) AS t WHERE {}", query, expr)
+            }
+            #[allow(unreachable_patterns)]
+            Some(filter) => {
+                return Err(CompilationError::Unsupported(format!(
+                    "SHOW filter: {}",
+                    filter
+                )))
+            }
+        };
+
+        let dialect = MySqlDialectWithBackTicks {};
+        let stmts = Parser::parse_sql(&dialect, &query).map_err(|e| {
+            CompilationError::Internal(format!("Unable to parse introspection query: {:?}", e))
+        })?;
+
+        self.create_df_logical_plan(stmts[0].clone(), props)
+    }
+
     fn create_df_logical_plan(
         &self,
         stmt: ast::Statement,
@@ -1258,7 +1459,8 @@ impl QueryPlanner {
         ctx.register_udf(create_connection_id_udf(props));
 
         let state = ctx.state.lock().unwrap().clone();
-        let df_query_planner = SqlToRel::new(&state);
+        let cube_ctx = CubeContext::new(&state, self.context.clone());
+        let df_query_planner = SqlToRel::new(&cube_ctx);
 
         let plan = df_query_planner
             .statement_to_plan(&DFStatement::Statement(stmt))
@@ -1323,24 +1525,133 @@ impl QueryPlan {
     }
 }
 
+fn quote_string_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// SHOW [FULL] TABLES is not supported by the parser, it's rewritten to the query
+/// over `information_schema.tables`.
+fn rewrite_show_tables(query: &str) -> CompilationResult<Option<String>> {
+    let show_tables_regexp = Regex::new(
+        r"(?i)^\s*SHOW\s+(?P<full>FULL\s+)?TABLES(\s+(FROM|IN)\s+`?(?P<db>[a-zA-Z0-9_]+)`?)?(\s+(?P<filter>(LIKE|WHERE)\s.+?))?\s*;?\s*$",
+    )?;
+
+    let captures = match show_tables_regexp.captures(query) {
+        Some(captures) => captures,
+        None => return Ok(None),
+    };
+
+    let db = captures.name("db").map(|m| m.as_str()).unwrap_or("db");
+    let column = format!("Tables_in_{}", db);
+
+    let mut projection = format!("table_name AS `{}`", column);
+    if captures.name("full").is_some() {
+        projection.push_str(", table_type AS `Table_type`");
+    }
+
+    let query = format!(
+        "SELECT {} FROM information_schema.tables WHERE table_schema = {}",
+        projection,
+        quote_string_literal(db)
+    );
+
+    let query = match captures.name("filter").map(|m| m.as_str()) {
+        None => query,
+        Some(filter) if filter[..4].eq_ignore_ascii_case("like") => {
+            format!(
+                "SELECT * FROM ({}) AS t WHERE `{}` {}",
+                query, column, filter
+            )
+        }
+        Some(filter) => format!("SELECT * FROM ({}) AS t {}", query, filter),
+    };
+
+    Ok(Some(query))
+}
+
+/// SHOW [SESSION | GLOBAL] VARIABLES is rewritten to the query over
+/// `information_schema.session_variables`.
+fn rewrite_show_variables(query: &str) -> CompilationResult<Option<String>> {
+    let show_variables_regexp = Regex::new(
+        r"(?i)^\s*SHOW\s+((SESSION|GLOBAL)\s+)?VARIABLES(\s+(?P<filter>(LIKE|WHERE)\s.+?))?\s*;?\s*$",
+    )?;
+
+    let captures = match show_variables_regexp.captures(query) {
+        Some(captures) => captures,
+        None => return Ok(None),
+    };
+
+    let query = "SELECT variable_name AS `Variable_name`, variable_value AS `Value` FROM information_schema.session_variables".to_string();
+
+    let query = match captures.name("filter").map(|m| m.as_str()) {
+        None => query,
+        Some(filter) if filter[..4].eq_ignore_ascii_case("like") => {
+            format!(
+                "SELECT * FROM ({}) AS t WHERE `Variable_name` {}",
+                query, filter
+            )
+        }
+        Some(filter) => format!("SELECT * FROM ({}) AS t {}", query, filter),
+    };
+
+    Ok(Some(query))
+}
+
+/// SHOW COLLATION is rewritten to the query over `information_schema.collations`. Columns are
+/// renamed after filtering, because filters refer to them in lower case (`charset`, `collation`).
+fn rewrite_show_collation(query: &str) -> CompilationResult<Option<String>> {
+    let show_collation_regexp =
+        Regex::new(r"(?i)^\s*SHOW\s+COLLATION(\s+(?P<filter>(LIKE|WHERE)\s.+?))?\s*;?\s*$")?;
+
+    let captures = match show_collation_regexp.captures(query) {
+        Some(captures) => captures,
+        None => return Ok(None),
+    };
+
+    let collations = "SELECT collation_name AS collation, character_set_name AS charset, id, is_default, is_compiled, sortlen, pad_attribute FROM information_schema.collations";
+    let filter = match captures.name("filter").map(|m| m.as_str()) {
+        None => "".to_string(),
+        Some(filter) if filter[..4].eq_ignore_ascii_case("like") => {
+            format!(" WHERE collation {}", filter)
+        }
+        Some(filter) => format!(" {}", filter),
+    };
+
+    Ok(Some(format!(
+        "SELECT collation AS `Collation`, charset AS `Charset`, id AS `Id`, is_default AS `Default`, is_compiled AS `Compiled`, sortlen AS `Sortlen`, pad_attribute AS `Pad_attribute` FROM ({}) AS t{}",
+        collations, filter
+    )))
+}
+
+/// SHOW statements which are not supported by the parser are rewritten to queries over
+/// `information_schema`.
+pub fn rewrite_show_statements(query: &str) -> CompilationResult<Option<String>> {
+    if let Some(query) = rewrite_show_tables(query)? {
+        return Ok(Some(query));
+    }
+
+    if let Some(query) = rewrite_show_variables(query)? {
+        return Ok(Some(query));
+    }
+
+    rewrite_show_collation(query)
+}
+
 pub fn convert_sql_to_cube_query(
     query: &String,
     tenant: Arc<ctx::TenantContext>,
     props: &QueryPlannerExecutionProps,
 ) -> CompilationResult<QueryPlan> {
-    let dialect = MySqlDialectWithBackTicks {};
-    let parse_result = Parser::parse_sql(&dialect, query);
+    let query = rewrite_show_statements(query)?.unwrap_or_else(|| query.clone());
+
+    let parse_result = parser::tokenize(&query).and_then(parser::parse_tokens);
 
     match parse_result {
         Err(error) => Err(CompilationError::User(format!(
             "Unable to parse: {:?}",
             error
         ))),
-        Ok(stmts) => {
-            let stmt = &stmts[0];
-
-            convert_statement_to_cube_query(stmt, tenant, props)
-        }
+        Ok(stmt) => convert_statement_to_cube_query(&stmt, tenant, props),
     }
 }
 
@@ -1351,6 +1662,8 @@ mod tests {
     };
 
     use super::*;
+    use crate::mysql::dataframe::batch_to_dataframe;
+    use datafusion::execution::dataframe_impl::DataFrameImpl;
     use pretty_assertions::assert_eq;
 
     fn get_test_meta() -> Vec<V1CubeMeta> {
@@ -2496,4 +2809,203 @@ mod tests {
             _ => panic!("Must be DateLiteral"),
         };
     }
+
+    #[test]
+    fn test_rewrite_show_tables() {
+        assert_eq!(rewrite_show_tables("SELECT 1").unwrap(), None);
+        assert_eq!(
+            rewrite_show_tables("SHOW TABLES").unwrap(),
+            Some(
+                "SELECT table_name AS `Tables_in_db` FROM information_schema.tables WHERE table_schema = 'db'"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            rewrite_show_tables("show full tables from `db` like 'Kibana%'").unwrap(),
+            Some(
+                "SELECT * FROM (SELECT table_name AS `Tables_in_db`, table_type AS `Table_type` FROM information_schema.tables WHERE table_schema = 'db') AS t WHERE `Tables_in_db` like 'Kibana%'"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_rewrite_show_statements() {
+        assert_eq!(rewrite_show_statements("SELECT 1").unwrap(), None);
+        assert_eq!(
+            rewrite_show_statements("show variables like 'sql_mode'").unwrap(),
+            Some(
+                "SELECT * FROM (SELECT variable_name AS `Variable_name`, variable_value AS `Value` FROM information_schema.session_variables) AS t WHERE `Variable_name` like 'sql_mode'"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            rewrite_show_statements("SHOW COLLATION").unwrap(),
+            Some(
+                "SELECT collation AS `Collation`, charset AS `Charset`, id AS `Id`, is_default AS `Default`, is_compiled AS `Compiled`, sortlen AS `Sortlen`, pad_attribute AS `Pad_attribute` FROM (SELECT collation_name AS collation, character_set_name AS charset, id, is_default, is_compiled, sortlen, pad_attribute FROM information_schema.collations) AS t"
+                    .to_string()
+            )
+        );
+    }
+
+    async fn execute_df_query(query: &str) -> dataframe::DataFrame {
+        let plan = convert_sql_to_cube_query(
+            &query.to_string(),
+            get_test_tenant_ctx(),
+            &QueryPlannerExecutionProps {
+                connection_id: 8,
+                database: None,
+            },
+        )
+        .unwrap();
+
+        match plan {
+            QueryPlan::DataFushionSelect(plan, ctx) => {
+                let df = DataFrameImpl::new(ctx.state, &plan);
+                let batches = df.collect().await.unwrap();
+
+                batch_to_dataframe(&batches).unwrap()
+            }
+            _ => panic!("Must return DF plan for {}", query),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_queries() {
+        let frame = execute_df_query("SHOW VARIABLES LIKE 'sql_mode'").await;
+        assert_eq!(frame.get_rows().len(), 1);
+        assert_eq!(
+            frame.get_rows()[0].values()[0],
+            dataframe::TableValue::String("sql_mode".to_string())
+        );
+
+        let frame = execute_df_query("show variables like 'lower_case_table_names'").await;
+        assert_eq!(
+            frame.get_rows()[0].values()[1],
+            dataframe::TableValue::String("0".to_string())
+        );
+
+        let frame = execute_df_query("SHOW COLLATION WHERE charset = 'utf8mb4'").await;
+        assert!(!frame.get_rows().is_empty());
+        for row in frame.get_rows() {
+            assert_eq!(
+                row.values()[1],
+                dataframe::TableValue::String("utf8mb4".to_string())
+            );
+        }
+
+        let frame = execute_df_query("SHOW TABLES LIKE 'Kibana%'").await;
+        assert_eq!(
+            frame.get_columns()[0].get_name(),
+            "Tables_in_db".to_string()
+        );
+        assert_eq!(
+            frame.get_rows()[0].values()[0],
+            dataframe::TableValue::String("KibanaSampleDataEcommerce".to_string())
+        );
+
+        let frame = execute_df_query(
+            "show collation where charset = 'utf8mb4' and collation = 'utf8mb4_bin'",
+        )
+        .await;
+        assert_eq!(frame.get_rows().len(), 1);
+        assert_eq!(frame.get_columns()[0].get_name(), "Collation".to_string());
+        assert_eq!(
+            frame.get_rows()[0].values()[2],
+            dataframe::TableValue::Int64(46)
+        );
+
+        let frame = execute_df_query("SELECT @@transaction_isolation").await;
+        assert_eq!(
+            frame.get_rows()[0].values()[0],
+            dataframe::TableValue::String("REPEATABLE-READ".to_string())
+        );
+
+        let frame = execute_df_query(
+            "SELECT CAST('test collated returns' AS CHAR CHARACTER SET utf8mb4) COLLATE utf8mb4_bin AS anon_1",
+        )
+        .await;
+        assert_eq!(frame.get_columns()[0].get_name(), "anon_1".to_string());
+        assert_eq!(
+            frame.get_rows()[0].values()[0],
+            dataframe::TableValue::String("test collated returns".to_string())
+        );
+
+        for query in [
+            "SET sql_mode='STRICT_TRANS_TABLES'",
+            "SET autocommit=1",
+            "COMMIT",
+        ] {
+            let plan = convert_sql_to_cube_query(
+                &query.to_string(),
+                get_test_tenant_ctx(),
+                &QueryPlannerExecutionProps {
+                    connection_id: 8,
+                    database: None,
+                },
+            )
+            .unwrap();
+
+            match plan {
+                QueryPlan::Meta(frame) => assert_eq!(frame.get_rows().len(), 0),
+                _ => panic!("Must return Meta plan for {}", query),
+            }
+        }
+    }
+
+    #[test]
+    fn test_introspection_queries_use_virtual_catalog() {
+        let queries = vec![
+            "SHOW FULL TABLES FROM db",
+            "SHOW TABLES LIKE 'Kibana%'",
+            "SHOW VARIABLES LIKE 'sql_mode'",
+            "SHOW COLLATION WHERE charset = 'utf8mb4'",
+            "SHOW FULL COLUMNS FROM KibanaSampleDataEcommerce",
+            "SHOW COLUMNS FROM db.KibanaSampleDataEcommerce WHERE `Type` = 'int'",
+            "DESCRIBE KibanaSampleDataEcommerce",
+            "SELECT table_name FROM information_schema.tables WHERE table_schema = 'db' ORDER BY table_name",
+            "SELECT column_name, data_type FROM information_schema.columns WHERE table_name = 'Logs'",
+        ];
+
+        for query in queries {
+            let plan = convert_sql_to_cube_query(
+                &query.to_string(),
+                get_test_tenant_ctx(),
+                &QueryPlannerExecutionProps {
+                    connection_id: 8,
+                    database: None,
+                },
+            )
+            .unwrap();
+
+            match plan {
+                QueryPlan::DataFushionSelect(_, _) => {}
+                _ => panic!("Must return DF plan for {}", query),
+            }
+        }
+    }
+
+    #[test]
+    fn test_show_create_table() {
+        let plan = convert_sql_to_cube_query(
+            &"SHOW CREATE TABLE `db`.`Logs`".to_string(),
+            get_test_tenant_ctx(),
+            &QueryPlannerExecutionProps {
+                connection_id: 8,
+                database: None,
+            },
+        )
+        .unwrap();
+
+        match plan {
+            QueryPlan::Meta(frame) => assert_eq!(
+                frame.get_rows()[0].values()[1],
+                dataframe::TableValue::String(
+                    "CREATE TABLE `Logs` (\r\n  `agentCount` int NOT NULL,\r\n  `agentCountApprox` int NOT NULL\r\n) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4"
+                        .to_string()
+                )
+            ),
+            _ => panic!("Must return Meta plan"),
+        }
+    }
 }
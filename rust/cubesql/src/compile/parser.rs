@@ -1,4 +1,7 @@
+use sqlparser::ast::Statement;
 use sqlparser::dialect::Dialect;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Token, Tokenizer};
 
 #[derive(Debug)]
 pub struct MySqlDialectWithBackTicks {}
@@ -24,3 +27,93 @@ impl Dialect for MySqlDialectWithBackTicks {
         self.is_identifier_start(ch) || ('0'..='9').contains(&ch)
     }
 }
+
+pub fn tokenize(query: &str) -> Result<Vec<Token>, ParserError> {
+    let dialect = MySqlDialectWithBackTicks {};
+    let tokens = Tokenizer::new(&dialect, query).tokenize()?;
+
+    Ok(strip_charset_and_collation(tokens))
+}
+
+pub fn parse_tokens(tokens: Vec<Token>) -> Result<Statement, ParserError> {
+    let dialect = MySqlDialectWithBackTicks {};
+
+    Parser::new(tokens, &dialect).parse_statement()
+}
+
+fn is_word(token: Option<&Token>, value: &str) -> bool {
+    match token {
+        Some(Token::Word(w)) => w.quote_style.is_none() && w.value.eq_ignore_ascii_case(value),
+        _ => false,
+    }
+}
+
+fn next_significant(tokens: &[Token], from: usize) -> Option<usize> {
+    (from..tokens.len()).find(|i| !matches!(tokens[*i], Token::Whitespace(_)))
+}
+
+/// MySQL allows to specify character set and collation of string expressions, e.g.
+/// `CAST(x AS CHAR CHARACTER SET utf8mb4) COLLATE utf8mb4_bin`, it's not supported by the parser.
+/// Strings are always UTF-8 here, so these clauses are removed.
+fn strip_charset_and_collation(tokens: Vec<Token>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let name = if is_word(tokens.get(i), "COLLATE") {
+            next_significant(&tokens, i + 1)
+        } else if is_word(tokens.get(i), "CHARACTER")
+            // SET CHARACTER SET statement
+            && !is_word(result.iter().rev().find(|t| !matches!(t, Token::Whitespace(_))), "SET")
+        {
+            next_significant(&tokens, i + 1)
+                .filter(|set| is_word(tokens.get(*set), "SET"))
+                .and_then(|set| next_significant(&tokens, set + 1))
+        } else {
+            None
+        };
+
+        match name {
+            Some(name) if matches!(tokens[name], Token::Word(_) | Token::SingleQuotedString(_)) => {
+                i = name + 1;
+            }
+            _ => {
+                result.push(tokens[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> Statement {
+        parse_tokens(tokenize(query).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_strip_charset_and_collation() {
+        assert_eq!(
+            parse(
+                "SELECT CAST('test collated returns' AS CHAR CHARACTER SET utf8mb4) COLLATE utf8mb4_bin AS anon_1"
+            ),
+            parse("SELECT CAST('test collated returns' AS CHAR) AS anon_1")
+        );
+        assert_eq!(
+            parse("SELECT a FROM t WHERE b = 'x' COLLATE 'utf8mb4_bin'"),
+            parse("SELECT a FROM t WHERE b = 'x'")
+        );
+        // Literals and quoted identifiers are kept as is
+        let query = "SELECT 'COLLATE utf8mb4_bin' AS `collate`";
+        assert_eq!(
+            parse(query),
+            Parser::parse_sql(&MySqlDialectWithBackTicks {}, query)
+                .unwrap()
+                .remove(0)
+        );
+    }
+}
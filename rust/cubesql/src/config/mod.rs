@@ -3,7 +3,9 @@ pub mod processing_loop;
 
 use crate::config::injection::{DIService, Injector, InjectorRef};
 use crate::config::processing_loop::ProcessingLoop;
-use crate::mysql::{MySqlServer, SqlAuthDefaultImpl, SqlAuthService};
+use crate::mysql::{
+    MySqlServer, SqlAuthConfigFileImpl, SqlAuthDefaultImpl, SqlAuthJwtImpl, SqlAuthService,
+};
 use crate::schema::{SchemaService, SchemaServiceDefaultImpl};
 use crate::telemetry::{start_track_event_loop, stop_track_event_loop};
 use crate::CubeError;
@@ -59,6 +61,19 @@ impl CubeServices {
                 Ok(())
             }));
         }
+        if self
+            .injector
+            .has_service_typed::<SchemaServiceDefaultImpl>()
+            .await
+        {
+            let schema_service = self
+                .injector
+                .get_service_typed::<SchemaServiceDefaultImpl>()
+                .await;
+            futures.push(tokio::spawn(async move {
+                schema_service.processing_loop().await
+            }));
+        }
         futures.push(tokio::spawn(async move {
             start_track_event_loop().await;
             Ok(())
@@ -74,6 +89,17 @@ impl CubeServices {
                 .stop_processing()
                 .await?;
         }
+        if self
+            .injector
+            .has_service_typed::<SchemaServiceDefaultImpl>()
+            .await
+        {
+            self.injector
+                .get_service_typed::<SchemaServiceDefaultImpl>()
+                .await
+                .stop_processing()
+                .await?;
+        }
         stop_track_event_loop().await;
         Ok(())
     }
@@ -90,12 +116,24 @@ pub trait ConfigObj: DIService {
     fn bind_address(&self) -> &Option<String>;
 
     fn query_timeout(&self) -> u64;
+
+    fn schema_cache_ttl(&self) -> u64;
+
+    fn auth_config_path(&self) -> &Option<String>;
+
+    fn jwt_secret(&self) -> &Option<String>;
+
+    fn jwt_password_auth(&self) -> bool;
 }
 
 #[derive(Debug, Clone)]
 pub struct ConfigObjImpl {
     pub bind_address: Option<String>,
     pub query_timeout: u64,
+    pub schema_cache_ttl: u64,
+    pub auth_config_path: Option<String>,
+    pub jwt_secret: Option<String>,
+    pub jwt_password_auth: bool,
 }
 
 crate::di_service!(ConfigObjImpl, [ConfigObj]);
@@ -109,6 +147,22 @@ impl ConfigObj for ConfigObjImpl {
     fn query_timeout(&self) -> u64 {
         self.query_timeout
     }
+
+    fn schema_cache_ttl(&self) -> u64 {
+        self.schema_cache_ttl
+    }
+
+    fn auth_config_path(&self) -> &Option<String> {
+        &self.auth_config_path
+    }
+
+    fn jwt_secret(&self) -> &Option<String> {
+        &self.jwt_secret
+    }
+
+    fn jwt_password_auth(&self) -> bool {
+        self.jwt_password_auth
+    }
 }
 
 lazy_static! {
@@ -122,6 +176,10 @@ impl Config {
             .ok()
             .map(|v| v.parse::<u64>().unwrap())
             .unwrap_or(120);
+        let schema_cache_ttl = env::var("CUBESQL_SCHEMA_CACHE_TTL")
+            .ok()
+            .map(|v| v.parse::<u64>().unwrap())
+            .unwrap_or(60);
         Config {
             injector: Injector::new(),
             config_obj: Arc::new(ConfigObjImpl {
@@ -132,6 +190,15 @@ impl Config {
                             .unwrap_or(3306u16)),
                 )),
                 query_timeout,
+                schema_cache_ttl,
+                auth_config_path: env::var("CUBESQL_AUTH_CONFIG").ok(),
+                jwt_secret: env::var("CUBESQL_JWT_SECRET")
+                    .or_else(|_| env::var("CUBEJS_API_SECRET"))
+                    .ok(),
+                jwt_password_auth: env::var("CUBESQL_JWT_PASSWORD_AUTH")
+                    .ok()
+                    .map(|v| v.to_lowercase() == "true")
+                    .unwrap_or(false),
             }),
         }
     }
@@ -143,6 +210,10 @@ impl Config {
             config_obj: Arc::new(ConfigObjImpl {
                 bind_address: None,
                 query_timeout,
+                schema_cache_ttl: 0,
+                auth_config_path: None,
+                jwt_secret: None,
+                jwt_password_auth: false,
             }),
         }
     }
@@ -166,24 +237,55 @@ impl Config {
         self.injector.clone()
     }
 
-    pub async fn configure_injector(&self) {
+    pub async fn configure_injector(&self) -> Result<(), CubeError> {
         let config_obj_to_register = self.config_obj.clone();
         self.injector
             .register_typed::<dyn ConfigObj, _, _, _>(async move |_| config_obj_to_register)
             .await;
 
         self.injector
-            .register_typed::<dyn SchemaService, _, _, _>(async move |_| {
-                Arc::new(SchemaServiceDefaultImpl)
+            .register_typed_with_default::<dyn SchemaService, _, _, _>(async move |i| {
+                SchemaServiceDefaultImpl::new(i.get_service_typed().await)
             })
             .await;
 
         if self.config_obj.bind_address().is_some() {
-            self.injector
-                .register_typed::<dyn SqlAuthService, _, _, _>(async move |_| {
-                    Arc::new(SqlAuthDefaultImpl)
-                })
-                .await;
+            if self.config_obj.jwt_password_auth() {
+                if self.config_obj.auth_config_path().is_some() {
+                    return Err(CubeError::user(
+                        "CUBESQL_JWT_PASSWORD_AUTH can't be used with CUBESQL_AUTH_CONFIG"
+                            .to_string(),
+                    ));
+                }
+
+                let auth = Arc::new(SqlAuthJwtImpl::try_new(
+                    self.config_obj.jwt_secret().clone(),
+                    env::var("CUBESQL_CUBE_URL").ok(),
+                )?);
+                self.injector
+                    .register_typed::<dyn SqlAuthService, _, _, _>(async move |_| auth)
+                    .await;
+            } else if let Some(path) = self.config_obj.auth_config_path() {
+                // Errors of the file are reported on startup instead of the first connection
+                let auth = Arc::new(
+                    SqlAuthConfigFileImpl::load(path, self.config_obj.jwt_secret().clone())
+                        .map_err(|e| {
+                            CubeError::user(format!(
+                                "Unable to load CUBESQL_AUTH_CONFIG: {}",
+                                e.message
+                            ))
+                        })?,
+                );
+                self.injector
+                    .register_typed::<dyn SqlAuthService, _, _, _>(async move |_| auth)
+                    .await;
+            } else {
+                self.injector
+                    .register_typed::<dyn SqlAuthService, _, _, _>(async move |_| {
+                        Arc::new(SqlAuthDefaultImpl)
+                    })
+                    .await;
+            }
             self.injector
                 .register_typed::<MySqlServer, _, _, _>(async move |i| {
                     MySqlServer::new(
@@ -199,6 +301,8 @@ impl Config {
                 })
                 .await;
         }
+
+        Ok(())
     }
 
     pub async fn cube_services(&self) -> CubeServices {
@@ -207,9 +311,9 @@ impl Config {
         }
     }
 
-    pub async fn configure(&self) -> CubeServices {
-        self.configure_injector().await;
-        self.cube_services().await
+    pub async fn configure(&self) -> Result<CubeServices, CubeError> {
+        self.configure_injector().await?;
+        Ok(self.cube_services().await)
     }
 }
 
@@ -44,6 +44,7 @@ impl std::error::Error for CubeError {}
 pub enum CubeErrorCauseType {
     User,
     Internal,
+    Timeout,
 }
 
 impl CubeError {
@@ -61,6 +62,13 @@ impl CubeError {
         }
     }
 
+    pub fn timeout(message: String) -> CubeError {
+        CubeError {
+            message,
+            cause: CubeErrorCauseType::Timeout,
+        }
+    }
+
     pub fn from_error<E: fmt::Display>(error: E) -> CubeError {
         CubeError {
             message: format!("{}\n{}", error, Backtrace::capture()),
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use log::debug;
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+use crate::mysql::{AuthContext, SqlAuthService};
+use crate::CubeError;
+
+/// Lifetime of tokens signed from `security_context`. Token is used by the connection until
+/// it's closed.
+const SIGNED_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Signed token is reused by new connections of the user while it's valid for at least this long,
+/// so connections share the schema cached per access token.
+const SIGNED_TOKEN_MIN_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+struct SignedToken {
+    token: String,
+    expires_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SqlUserConfig {
+    /// Password which should be used by MySQL client, it's required.
+    pub password: Option<String>,
+    /// Cube API token which is forwarded to Cube.js as is.
+    pub token: Option<String>,
+    /// Security context which is signed into a Cube API token with JWT secret.
+    pub security_context: Option<Value>,
+    pub base_path: Option<String>,
+}
+
+/// Content of `CUBESQL_AUTH_CONFIG` file:
+///
+/// ```json
+/// {
+///   "base_path": "http://localhost:4000/cubejs-api",
+///   "users": {
+///     "alice": { "password": "secret", "token": "eyJhbGciOiJIUzI1NiJ9..." },
+///     "bob": { "password": "secret", "security_context": { "tenant_id": 2 } }
+///   }
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct SqlAuthConfig {
+    pub base_path: Option<String>,
+    pub users: HashMap<String, SqlUserConfig>,
+}
+
+/// Maps SQL users to Cube API tokens and base paths, so every user queries Cube.js within its
+/// own security context.
+///
+/// When JWT secret is configured, tokens are validated before they are forwarded to Cube.js and
+/// users can be described by `security_context` instead of a token. To use Cube API tokens as
+/// passwords instead of the file, see [SqlAuthJwtImpl].
+pub struct SqlAuthConfigFileImpl {
+    config: SqlAuthConfig,
+    jwt_secret: Option<String>,
+    signed_tokens: Mutex<HashMap<String, SignedToken>>,
+}
+
+crate::di_service!(SqlAuthConfigFileImpl, [SqlAuthService]);
+
+impl SqlAuthConfigFileImpl {
+    pub fn load(path: &str, jwt_secret: Option<String>) -> Result<Self, CubeError> {
+        let content = std::fs::read_to_string(path)?;
+
+        Self::try_new(serde_json::from_str(&content)?, jwt_secret)
+    }
+
+    pub fn try_new(config: SqlAuthConfig, jwt_secret: Option<String>) -> Result<Self, CubeError> {
+        for (name, user) in config.users.iter() {
+            if user.password.is_none() {
+                return Err(CubeError::user(format!(
+                    "User '{}' must have a password",
+                    name
+                )));
+            }
+
+            match (&user.token, &user.security_context) {
+                (Some(_), None) => {}
+                (None, Some(Value::Object(_))) => {
+                    if jwt_secret.is_none() {
+                        return Err(CubeError::user(format!(
+                            "User '{}' is described by security_context, but CUBESQL_JWT_SECRET is not set",
+                            name
+                        )));
+                    }
+                }
+                (None, Some(_)) => {
+                    return Err(CubeError::user(format!(
+                        "security_context of user '{}' must be an object",
+                        name
+                    )))
+                }
+                _ => {
+                    return Err(CubeError::user(format!(
+                        "User '{}' must have either token or security_context",
+                        name
+                    )))
+                }
+            }
+        }
+
+        Ok(Self {
+            config,
+            jwt_secret,
+            signed_tokens: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn access_token(&self, name: &str, user: &SqlUserConfig) -> Result<String, CubeError> {
+        match (&user.token, &user.security_context, &self.jwt_secret) {
+            (Some(token), _, Some(secret)) => {
+                validate_token(token, secret)?;
+
+                Ok(token.clone())
+            }
+            (Some(token), _, None) => Ok(token.clone()),
+            (None, Some(security_context), Some(secret)) => {
+                self.signed_token(name, security_context, secret)
+            }
+            _ => Err(CubeError::internal(
+                "Unable to get access token for user".to_string(),
+            )),
+        }
+    }
+
+    fn signed_token(
+        &self,
+        name: &str,
+        security_context: &Value,
+        secret: &str,
+    ) -> Result<String, CubeError> {
+        let mut signed_tokens = self.signed_tokens.lock().unwrap();
+        let now = now_secs();
+
+        if let Some(signed) = signed_tokens.get(name) {
+            if signed.expires_at >= now + SIGNED_TOKEN_MIN_TTL.as_secs() {
+                return Ok(signed.token.clone());
+            }
+        }
+
+        let token = sign_token(security_context, secret, SIGNED_TOKEN_TTL)?;
+        signed_tokens.insert(
+            name.to_string(),
+            SignedToken {
+                token: token.clone(),
+                expires_at: now + SIGNED_TOKEN_TTL.as_secs(),
+            },
+        );
+
+        Ok(token)
+    }
+
+    fn base_path(&self, user: &SqlUserConfig) -> Result<String, CubeError> {
+        user.base_path
+            .clone()
+            .or_else(|| self.config.base_path.clone())
+            .or_else(|| env::var("CUBESQL_CUBE_URL").ok())
+            .ok_or_else(|| {
+                CubeError::user(
+                    "base_path is not set for user and CUBESQL_CUBE_URL is not defined".to_string(),
+                )
+            })
+    }
+}
+
+#[async_trait]
+impl SqlAuthService for SqlAuthConfigFileImpl {
+    async fn authenticate(&self, user: Option<String>) -> Result<AuthContext, CubeError> {
+        let (name, user_config) = user
+            .as_ref()
+            .and_then(|user| self.config.users.get_key_value(user))
+            .ok_or_else(|| CubeError::user("Incorrect user name or password".to_string()))?;
+
+        Ok(AuthContext {
+            password: user_config.password.clone(),
+            access_token: self.access_token(name, user_config)?,
+            base_path: self.base_path(user_config)?,
+        })
+    }
+}
+
+/// Takes Cube API token from the password of MySQL client, which is enabled by
+/// `CUBESQL_JWT_PASSWORD_AUTH`. Clients are asked for `mysql_clear_password` authentication, so
+/// the token is sent without encryption and clients have to allow it explicitly, e.g. with
+/// `--enable-cleartext-plugin` of `mysql` CLI. The token is validated with JWT secret, user
+/// name is not checked.
+pub struct SqlAuthJwtImpl {
+    jwt_secret: String,
+    base_path: String,
+}
+
+crate::di_service!(SqlAuthJwtImpl, [SqlAuthService]);
+
+impl SqlAuthJwtImpl {
+    pub fn try_new(
+        jwt_secret: Option<String>,
+        base_path: Option<String>,
+    ) -> Result<Self, CubeError> {
+        let jwt_secret = jwt_secret.ok_or_else(|| {
+            CubeError::user(
+                "CUBESQL_JWT_PASSWORD_AUTH requires CUBESQL_JWT_SECRET to be set".to_string(),
+            )
+        })?;
+        let base_path = base_path.ok_or_else(|| {
+            CubeError::user(
+                "CUBESQL_JWT_PASSWORD_AUTH requires CUBESQL_CUBE_URL to be set".to_string(),
+            )
+        })?;
+
+        Ok(Self {
+            jwt_secret,
+            base_path,
+        })
+    }
+}
+
+#[async_trait]
+impl SqlAuthService for SqlAuthJwtImpl {
+    async fn authenticate(&self, _user: Option<String>) -> Result<AuthContext, CubeError> {
+        Err(CubeError::user(
+            "Cube API token must be sent as password with mysql_clear_password authentication"
+                .to_string(),
+        ))
+    }
+
+    fn accepts_token_password(&self) -> bool {
+        true
+    }
+
+    async fn authenticate_with_token(
+        &self,
+        _user: Option<String>,
+        token: String,
+    ) -> Result<AuthContext, CubeError> {
+        if let Err(e) = validate_token(&token, &self.jwt_secret) {
+            debug!("Rejected Cube API token: {}", e);
+
+            return Err(CubeError::user(
+                "Incorrect user name or password".to_string(),
+            ));
+        }
+
+        Ok(AuthContext {
+            password: Some(token.clone()),
+            access_token: token,
+            base_path: self.base_path.clone(),
+        })
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Checks signature of the Cube API token and its expiration, if it's set. Tokens without `exp`
+/// claim are accepted the same way as Cube.js does.
+pub fn validate_token(token: &str, secret: &str) -> Result<(), CubeError> {
+    let mut validation = Validation::default();
+    validation.validate_exp = false;
+
+    let data = decode::<Value>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|e| CubeError::user(format!("Invalid Cube API token: {}", e)))?;
+
+    if let Some(exp) = data.claims.get("exp").and_then(|exp| exp.as_u64()) {
+        if exp < now_secs() {
+            return Err(CubeError::user("Cube API token has expired".to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn sign_token(
+    security_context: &Value,
+    secret: &str,
+    ttl: Duration,
+) -> Result<String, CubeError> {
+    let mut claims = match security_context {
+        Value::Object(claims) => claims.clone(),
+        _ => {
+            return Err(CubeError::user(
+                "Security context must be an object".to_string(),
+            ))
+        }
+    };
+
+    let now = now_secs();
+    claims.insert("iat".to_string(), Value::from(now));
+    claims.insert("exp".to_string(), Value::from(now + ttl.as_secs()));
+
+    encode(
+        &Header::default(),
+        &Value::Object(claims),
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .map_err(|e| CubeError::internal(format!("Unable to sign Cube API token: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::compile::TenantContext;
+    use crate::schema::cache::{SchemaCache, SchemaCacheKey};
+
+    fn auth_service(jwt_secret: Option<String>) -> Result<SqlAuthConfigFileImpl, CubeError> {
+        let config = serde_json::from_value(json!({
+            "base_path": "http://localhost:4000/cubejs-api",
+            "users": {
+                "alice": { "password": "alice_pwd", "token": "static_token" },
+                "bob": {
+                    "password": "bob_pwd",
+                    "security_context": { "tenant_id": 2 },
+                    "base_path": "http://tenant2:4000/cubejs-api"
+                }
+            }
+        }))?;
+
+        SqlAuthConfigFileImpl::try_new(config, jwt_secret)
+    }
+
+    #[tokio::test]
+    async fn test_config_file_users() -> Result<(), CubeError> {
+        assert!(auth_service(None).is_err());
+
+        let auth = auth_service(Some("secret".to_string()))?;
+
+        let bob = auth.authenticate(Some("bob".to_string())).await?;
+        assert_eq!(bob.password, Some("bob_pwd".to_string()));
+        assert_eq!(bob.base_path, "http://tenant2:4000/cubejs-api".to_string());
+        validate_token(&bob.access_token, "secret")?;
+        assert!(validate_token(&bob.access_token, "other_secret").is_err());
+
+        // Static token is not signed with the secret
+        assert!(auth.authenticate(Some("alice".to_string())).await.is_err());
+
+        assert!(auth
+            .authenticate(Some("unknown".to_string()))
+            .await
+            .is_err());
+        assert!(auth.authenticate(None).await.is_err());
+
+        Ok(())
+    }
+
+    async fn load_empty() -> Result<TenantContext, ()> {
+        Ok(TenantContext { cubes: vec![] })
+    }
+
+    async fn load_unexpected() -> Result<TenantContext, ()> {
+        panic!("Must be served from cache")
+    }
+
+    #[tokio::test]
+    async fn test_signed_token_is_shared_by_connections() -> Result<(), CubeError> {
+        let auth = auth_service(Some("secret".to_string()))?;
+        let cache = SchemaCache::new(Duration::from_secs(60));
+
+        let first = auth.authenticate(Some("bob".to_string())).await?;
+        let first_ctx = cache
+            .get_or_load(&SchemaCacheKey::from_auth_ctx(&first), load_empty)
+            .await
+            .unwrap();
+
+        let second = auth.authenticate(Some("bob".to_string())).await?;
+        assert_eq!(first.access_token, second.access_token);
+        let second_ctx = cache
+            .get_or_load(&SchemaCacheKey::from_auth_ctx(&second), load_unexpected)
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&first_ctx, &second_ctx));
+
+        // Token which is close to expiry is signed again
+        auth.signed_tokens.lock().unwrap().insert(
+            "bob".to_string(),
+            SignedToken {
+                token: "stale_token".to_string(),
+                expires_at: now_secs() + 60,
+            },
+        );
+        let third = auth.authenticate(Some("bob".to_string())).await?;
+        assert_ne!(third.access_token, "stale_token".to_string());
+        validate_token(&third.access_token, "secret")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_is_required() {
+        let config = serde_json::from_value(json!({
+            "users": { "alice": { "token": "static_token" } }
+        }))
+        .unwrap();
+
+        let e = SqlAuthConfigFileImpl::try_new(config, None).err().unwrap();
+        assert_eq!(e.message, "User 'alice' must have a password".to_string());
+    }
+
+    #[tokio::test]
+    async fn test_jwt_password() -> Result<(), CubeError> {
+        assert!(SqlAuthJwtImpl::try_new(None, Some("http://localhost".to_string())).is_err());
+
+        let auth = SqlAuthJwtImpl::try_new(
+            Some("secret".to_string()),
+            Some("http://localhost:4000/cubejs-api".to_string()),
+        )?;
+        let token = sign_token(&json!({ "tenant_id": 1 }), "secret", SIGNED_TOKEN_TTL)?;
+
+        let ctx = auth
+            .authenticate_with_token(Some("alice".to_string()), token.clone())
+            .await?;
+        assert_eq!(ctx.password, Some(token.clone()));
+        assert_eq!(ctx.access_token, token);
+
+        let other = sign_token(&json!({ "tenant_id": 1 }), "other", SIGNED_TOKEN_TTL)?;
+        assert!(auth.authenticate_with_token(None, other).await.is_err());
+        assert!(auth.authenticate(Some("alice".to_string())).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_token() -> Result<(), CubeError> {
+        let context = json!({ "user_id": 1 });
+
+        validate_token(&sign_token(&context, "secret", SIGNED_TOKEN_TTL)?, "secret")?;
+
+        let mut expired = serde_json::Map::new();
+        expired.insert("exp".to_string(), Value::from(now_secs() - 60));
+        let token = encode(
+            &Header::default(),
+            &Value::Object(expired),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(validate_token(&token, "secret").is_err());
+
+        assert!(sign_token(&json!([1]), "secret", SIGNED_TOKEN_TTL).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use mysql_common::scramble::scramble_native;
+use tokio::io::{duplex, split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpStream;
+
+use crate::CubeError;
+
+const CLIENT_CONNECT_WITH_DB: u32 = 0x0000_0008;
+const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x0008_0000;
+const CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA: u32 = 0x0020_0000;
+
+const NATIVE_PASSWORD: &[u8] = b"mysql_native_password";
+const CLEAR_PASSWORD: &[u8] = b"mysql_clear_password";
+
+/// Password which was sent by the client in clear text. It's taken by `on_auth` of the connection.
+pub type ClearPasswordSlot = Arc<Mutex<Option<String>>>;
+
+/// MySQL server implementation supports only `mysql_native_password` authentication, which never
+/// sends the password itself. To accept Cube API tokens as passwords, the handshake is proxied:
+/// the client is asked for `mysql_clear_password`, the received password is put into the slot
+/// and is replaced by the native scramble of the same password, which the server then checks
+/// against the password returned by `on_auth`. Clients which don't support clear text passwords
+/// answer with native scramble and are passed as is.
+///
+/// Everything after the handshake is copied without changes.
+pub fn proxy_clear_password(socket: TcpStream) -> (DuplexStream, ClearPasswordSlot) {
+    let (server_side, proxy_side) = duplex(64 * 1024);
+    let slot = ClearPasswordSlot::default();
+
+    let proxy_slot = slot.clone();
+    tokio::spawn(async move {
+        if let Err(e) = proxy(socket, proxy_side, proxy_slot).await {
+            error!("Error during proxying MySQL handshake: {}", e);
+        }
+    });
+
+    (server_side, slot)
+}
+
+async fn proxy(
+    client: TcpStream,
+    server: DuplexStream,
+    slot: ClearPasswordSlot,
+) -> Result<(), CubeError> {
+    let (mut client_read, mut client_write) = split(client);
+    let (mut server_read, mut server_write) = split(server);
+
+    let (seq, greeting) = read_packet(&mut server_read).await?;
+    let (greeting, salt) = request_clear_password(greeting)?;
+    write_packet(&mut client_write, seq, &greeting).await?;
+
+    let (seq, response) = read_packet(&mut client_read).await?;
+    let response = match replace_clear_password(&response, &salt)? {
+        Some((password, response)) => {
+            *slot.lock().unwrap() = Some(password);
+            response
+        }
+        None => response,
+    };
+    write_packet(&mut server_write, seq, &response).await?;
+
+    let to_server = async {
+        tokio::io::copy(&mut client_read, &mut server_write).await?;
+        server_write.shutdown().await
+    };
+    let to_client = async {
+        tokio::io::copy(&mut server_read, &mut client_write).await?;
+        client_write.shutdown().await
+    };
+    tokio::try_join!(to_server, to_client)?;
+
+    Ok(())
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(u8, Vec<u8>), CubeError> {
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header).await?;
+
+    let length = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload).await?;
+
+    Ok((header[3], payload))
+}
+
+async fn write_packet<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    seq: u8,
+    payload: &[u8],
+) -> Result<(), CubeError> {
+    let length = (payload.len() as u32).to_le_bytes();
+    writer
+        .write_all(&[length[0], length[1], length[2], seq])
+        .await?;
+    writer.write_all(payload).await?;
+    writer.flush().await?;
+
+    Ok(())
+}
+
+fn malformed(packet: &str) -> CubeError {
+    CubeError::internal(format!("Malformed {} packet", packet))
+}
+
+fn null_terminated(payload: &[u8], from: usize, packet: &str) -> Result<usize, CubeError> {
+    payload[from.min(payload.len())..]
+        .iter()
+        .position(|b| *b == 0)
+        .map(|p| from + p)
+        .ok_or_else(|| malformed(packet))
+}
+
+/// Replaces authentication plugin of the server greeting with `mysql_clear_password`.
+/// Returns the new greeting and the salt for native scramble.
+fn request_clear_password(greeting: Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), CubeError> {
+    // protocol version, server version, connection id
+    let salt_start = null_terminated(&greeting, 1, "greeting")? + 1 + 4;
+    let salt_part_1 = greeting
+        .get(salt_start..salt_start + 8)
+        .ok_or_else(|| malformed("greeting"))?;
+    // filler, capabilities, character set, status, upper capabilities, salt length, reserved
+    let salt_part_2_start = salt_start + 8 + 1 + 2 + 1 + 2 + 2 + 1 + 10;
+    let plugin_start = null_terminated(&greeting, salt_part_2_start, "greeting")? + 1;
+    let salt = [salt_part_1, &greeting[salt_part_2_start..plugin_start - 1]].concat();
+
+    let capabilities = u32::from_le_bytes([
+        greeting[salt_start + 9],
+        greeting[salt_start + 10],
+        greeting[salt_start + 14],
+        greeting[salt_start + 15],
+    ]);
+    if capabilities & CLIENT_PLUGIN_AUTH == 0 {
+        return Ok((greeting, salt));
+    }
+
+    let mut result = greeting[..plugin_start].to_vec();
+    result.extend_from_slice(CLEAR_PASSWORD);
+    result.push(0);
+
+    Ok((result, salt))
+}
+
+fn read_lenenc(payload: &[u8], pos: usize) -> Result<(usize, usize), CubeError> {
+    let bytes = |n: usize| {
+        payload
+            .get(pos + 1..pos + 1 + n)
+            .ok_or_else(|| malformed("handshake response"))
+    };
+
+    match payload.get(pos) {
+        Some(v) if *v < 0xfb => Ok((*v as usize, 1)),
+        Some(0xfc) => Ok((
+            u16::from_le_bytes(bytes(2)?.try_into().unwrap()) as usize,
+            3,
+        )),
+        Some(0xfd) => {
+            let b = bytes(3)?;
+            Ok((u32::from_le_bytes([b[0], b[1], b[2], 0]) as usize, 4))
+        }
+        Some(0xfe) => Ok((
+            u64::from_le_bytes(bytes(8)?.try_into().unwrap()) as usize,
+            9,
+        )),
+        _ => Err(malformed("handshake response")),
+    }
+}
+
+/// Takes the clear text password from the handshake response of the client and replaces it
+/// with native scramble of the same password. Returns `None` for responses of other plugins.
+fn replace_clear_password(
+    response: &[u8],
+    salt: &[u8],
+) -> Result<Option<(String, Vec<u8>)>, CubeError> {
+    let packet = "handshake response";
+    // SSL request is sent without user name, TLS is not supported
+    if response.len() <= 32 {
+        return Ok(None);
+    }
+
+    let capabilities = u32::from_le_bytes(response[0..4].try_into().unwrap());
+    if capabilities & CLIENT_PROTOCOL_41 == 0 || capabilities & CLIENT_PLUGIN_AUTH == 0 {
+        return Ok(None);
+    }
+
+    // capabilities, max packet size, character set, reserved, user name
+    let auth_start = null_terminated(response, 32, packet)? + 1;
+    let lenenc_auth = capabilities & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA != 0;
+    let (auth_length, prefix_length) = if lenenc_auth {
+        read_lenenc(response, auth_start)?
+    } else if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+        (
+            *response.get(auth_start).ok_or_else(|| malformed(packet))? as usize,
+            1,
+        )
+    } else {
+        return Ok(None);
+    };
+    let auth_end = auth_start + prefix_length + auth_length;
+    let auth_data = response
+        .get(auth_start + prefix_length..auth_end)
+        .ok_or_else(|| malformed(packet))?;
+
+    let plugin_start = if capabilities & CLIENT_CONNECT_WITH_DB != 0 {
+        null_terminated(response, auth_end, packet)? + 1
+    } else {
+        auth_end
+    };
+    let plugin_end = null_terminated(response, plugin_start, packet)?;
+    if &response[plugin_start..plugin_end] != CLEAR_PASSWORD {
+        return Ok(None);
+    }
+
+    let password = auth_data.strip_suffix(&[0]).unwrap_or(auth_data);
+    let password = String::from_utf8(password.to_vec())?;
+    let scramble = scramble_native(salt, password.as_bytes())
+        .map(|s| s.to_vec())
+        .unwrap_or_default();
+
+    let mut result = response[..auth_start].to_vec();
+    result.push(scramble.len() as u8);
+    result.extend_from_slice(&scramble);
+    result.extend_from_slice(&response[auth_end..plugin_start]);
+    result.extend_from_slice(NATIVE_PASSWORD);
+    result.extend_from_slice(&response[plugin_end..]);
+
+    Ok(Some((password, result)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn greeting(plugin: &[u8]) -> Vec<u8> {
+        let mut greeting = vec![10];
+        greeting.extend_from_slice(b"8.0.25\0");
+        greeting.extend_from_slice(&[1, 0, 0, 0]);
+        greeting.extend_from_slice(b"abcdefgh");
+        greeting.push(0);
+        // capabilities with CLIENT_PLUGIN_AUTH, character set, status, upper capabilities
+        greeting.extend_from_slice(&[0x00, 0x82, 33, 2, 0, 0x08, 0x00]);
+        greeting.push(21);
+        greeting.extend_from_slice(&[0; 10]);
+        greeting.extend_from_slice(b"ijklmnopqrst\0");
+        greeting.extend_from_slice(plugin);
+        greeting.push(0);
+        greeting
+    }
+
+    fn response(capabilities: u32, auth: &[u8], plugin: &[u8]) -> Vec<u8> {
+        let mut response = capabilities.to_le_bytes().to_vec();
+        response.extend_from_slice(&[0, 0, 0, 1, 33]);
+        response.extend_from_slice(&[0; 23]);
+        response.extend_from_slice(b"alice\0");
+        response.push(auth.len() as u8);
+        response.extend_from_slice(auth);
+        response.extend_from_slice(b"db\0");
+        response.extend_from_slice(plugin);
+        response.push(0);
+        response
+    }
+
+    #[test]
+    fn test_request_clear_password() {
+        let (result, salt) = request_clear_password(greeting(NATIVE_PASSWORD)).unwrap();
+
+        assert_eq!(salt, b"abcdefghijklmnopqrst".to_vec());
+        assert_eq!(result, greeting(CLEAR_PASSWORD));
+    }
+
+    #[test]
+    fn test_replace_clear_password() {
+        let salt = b"abcdefghijklmnopqrst";
+        let capabilities = CLIENT_PROTOCOL_41
+            | CLIENT_SECURE_CONNECTION
+            | CLIENT_PLUGIN_AUTH
+            | CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA
+            | CLIENT_CONNECT_WITH_DB;
+
+        let (password, result) =
+            replace_clear_password(&response(capabilities, b"token\0", CLEAR_PASSWORD), salt)
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(password, "token".to_string());
+        assert_eq!(
+            result,
+            response(
+                capabilities,
+                &scramble_native(salt, b"token").unwrap(),
+                NATIVE_PASSWORD
+            )
+        );
+
+        // Native scramble is passed as is
+        assert!(
+            replace_clear_password(&response(capabilities, &[1; 20], NATIVE_PASSWORD), salt)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
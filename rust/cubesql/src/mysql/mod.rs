@@ -1,8 +1,9 @@
+use std::collections::HashMap;
 use std::env;
 use std::io;
 
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 
@@ -16,22 +17,31 @@ use log::trace;
 use msql_srv::*;
 
 use serde_json::json;
-use sqlparser::parser::Parser;
-use tokio::net::TcpListener;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
 
 use crate::compile::convert_sql_to_cube_query;
 use crate::compile::convert_statement_to_cube_query;
-use crate::compile::parser::MySqlDialectWithBackTicks;
 use crate::compile::QueryPlannerExecutionProps;
 use crate::config::processing_loop::ProcessingLoop;
+use crate::mysql::clear_password::{proxy_clear_password, ClearPasswordSlot};
 use crate::mysql::dataframe::batch_to_dataframe;
+use crate::mysql::statement::PreparedStatement;
 use crate::schema::SchemaService;
-use crate::schema::V1CubeMetaExt;
-use crate::CubeError;
-use sqlparser::ast::{ShowCreateObject, Statement};
+use crate::{CubeError, CubeErrorCauseType};
 
+pub mod auth;
+mod clear_password;
 pub mod dataframe;
+pub mod statement;
+
+pub use auth::{SqlAuthConfigFileImpl, SqlAuthJwtImpl};
+
+trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> AsyncStream for T {}
 
 struct Backend {
     auth: Arc<dyn SqlAuthService>,
@@ -39,9 +49,147 @@ struct Backend {
     props: QueryPlannerExecutionProps,
     // From Auth Service
     context: Option<AuthContext>,
+    // Password sent in clear text during the handshake, see SqlAuthService::accepts_token_password
+    clear_password: Option<ClearPasswordSlot>,
+    // Server side prepared statements
+    statements: HashMap<u32, PreparedStatement>,
+    statement_id_incr: u32,
+    // Duplicate of client socket, it's used only to detect disconnect
+    connection: Option<std::net::TcpStream>,
+    close_socket_rx: watch::Receiver<bool>,
+}
+
+/// Notifies when the client has closed the connection or the server is shutting down,
+/// so long running requests to Cube.js can be cancelled. Stops watching on drop.
+struct DisconnectWatcher {
+    receiver: watch::Receiver<bool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl DisconnectWatcher {
+    const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+    fn new(
+        connection: &Option<std::net::TcpStream>,
+        mut close_socket_rx: watch::Receiver<bool>,
+    ) -> Result<Self, CubeError> {
+        let (sender, receiver) = watch::channel(false);
+        let connection = match connection {
+            Some(connection) => connection.try_clone()?,
+            None => {
+                return Ok(Self {
+                    receiver,
+                    handle: None,
+                })
+            }
+        };
+
+        let handle = tokio::spawn(async move {
+            let mut buf = [0u8; 1];
+
+            loop {
+                tokio::select! {
+                    res = close_socket_rx.changed() => {
+                        if res.is_err() || *close_socket_rx.borrow() {
+                            break;
+                        }
+                    }
+                    _ = tokio::time::sleep(Self::CHECK_INTERVAL) => {
+                        // Socket is in non-blocking mode, 0 bytes means that peer has closed the connection
+                        match connection.peek(&mut buf) {
+                            Ok(0) => break,
+                            Ok(_) => {}
+                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                            Err(_) => break,
+                        }
+                    }
+                }
+            }
+
+            trace!("Connection was closed, cancelling query");
+            sender.send(true).ok();
+        });
+
+        Ok(Self {
+            receiver,
+            handle: Some(handle),
+        })
+    }
+
+    fn receiver(&self) -> watch::Receiver<bool> {
+        self.receiver.clone()
+    }
+}
+
+impl Drop for DisconnectWatcher {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
 }
 
 impl Backend {
+    /// Compiles prepared statement with NULL in place of placeholders to get meta of the columns
+    /// and infers types of the parameters. Only queries which go directly to Cube.js have known
+    /// columns before execution.
+    async fn prepared_statement_meta(
+        &self,
+        statement: &PreparedStatement,
+    ) -> Result<(Vec<ColumnType>, Vec<dataframe::Column>), CubeError> {
+        let auth_ctx = if self.context.is_some() {
+            self.context.as_ref().unwrap()
+        } else {
+            return Err(CubeError::user("must be auth".to_string()));
+        };
+
+        let ctx = self.schema.get_ctx_for_tenant(auth_ctx).await?;
+        let param_types = statement.param_types(&ctx);
+
+        let plan = statement
+            .bind_nulls()
+            .ok()
+            .and_then(|stmt| convert_statement_to_cube_query(&stmt, ctx, &self.props).ok());
+        let columns = match plan {
+            Some(crate::compile::QueryPlan::CubeSelect(plan)) => plan
+                .meta
+                .iter()
+                .map(|column_meta| {
+                    dataframe::Column::new(column_meta.column_to.clone(), column_meta.column_type)
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        Ok((param_types, columns))
+    }
+
+    /// Executes bound prepared statement. Statements without parameters go through the same
+    /// path as plain text queries.
+    async fn execute_statement(
+        &mut self,
+        statement: &PreparedStatement,
+        params: ParamParser<'_>,
+    ) -> Result<Arc<dataframe::DataFrame>, CubeError> {
+        let bound = statement.bind(params)?;
+        if statement.params_count() == 0 {
+            return self.execute_query(statement.query()).await;
+        }
+
+        debug!("STATEMENT: {}", bound);
+
+        let auth_ctx = if self.context.is_some() {
+            self.context.as_ref().unwrap()
+        } else {
+            return Err(CubeError::user("must be auth".to_string()));
+        };
+
+        let ctx = self.schema.get_ctx_for_tenant(auth_ctx).await?;
+        let plan = convert_statement_to_cube_query(&bound, ctx, &self.props)?;
+
+        self.execute_plan(plan, auth_ctx).await
+    }
+
     async fn execute_query<'a>(
         &'a mut self,
         query: &'a str,
@@ -58,431 +206,94 @@ impl Backend {
         let query_lower = query_lower.replace("`", "");
 
         let ignore = match query_lower.as_str() {
+            // SET NAMES is not supported by the parser, strings are always UTF-8
             "set names utf8mb4" => true,
             "set names latin1" => true,
-            "rollback" => true,
-            "commit" => true,
-            // DataGrip workaround
-            "set character_set_results = utf8" => true,
-            "set character_set_results = latin1" => true,
-            "set autocommit=1" => true,
-            "set sql_mode='strict_trans_tables'" => true,
-            "set sql_select_limit=501" => true,
             _ => false,
         };
 
-        if query_lower.eq("show variables like 'sql_mode'") {
-            return Ok(
-                Arc::new(
-                    dataframe::DataFrame::new(
-                        vec![dataframe::Column::new(
-                            "Variable_name".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        ), dataframe::Column::new(
-                            "Value".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        )],
-                        vec![dataframe::Row::new(vec![
-                            dataframe::TableValue::String("sql_mode".to_string()),
-                            dataframe::TableValue::String("ONLY_FULL_GROUP_BY,STRICT_TRANS_TABLES,NO_ZERO_IN_DATE,NO_ZERO_DATE,ERROR_FOR_DIVISION_BY_ZERO,NO_ENGINE_SUBSTITUTION".to_string())
-                        ])]
-                    )
-                ),
-            )
-        } else if query_lower.eq("show variables like 'lower_case_table_names'") {
-            return Ok(
-                Arc::new(
-                    dataframe::DataFrame::new(
-                        vec![dataframe::Column::new(
-                            "Variable_name".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        ), dataframe::Column::new(
-                            "Value".to_string(),
-                            ColumnType::MYSQL_TYPE_LONGLONG,
-                        )],
-                        vec![dataframe::Row::new(vec![
-                            dataframe::TableValue::String("lower_case_table_names".to_string()),
-                            dataframe::TableValue::Int64(0)
-                        ])]
-                    )
-                ),
-            )
-        } else if query_lower.eq("show collation where charset = 'utf8mb4' and collation = 'utf8mb4_bin'") {
-            return Ok(
-                Arc::new(
-                    dataframe::DataFrame::new(
-                        vec![dataframe::Column::new(
-                            "Collation".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        ), dataframe::Column::new(
-                            "Charset".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        ), dataframe::Column::new(
-                            "Id".to_string(),
-                            ColumnType::MYSQL_TYPE_LONGLONG,
-                        ), dataframe::Column::new(
-                            "Default".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        ), dataframe::Column::new(
-                            "Compiled".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        ), dataframe::Column::new(
-                            "Sortlen".to_string(),
-                            ColumnType::MYSQL_TYPE_LONGLONG,
-                        ), dataframe::Column::new(
-                            "Pad_attribute".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        )],
-                        vec![dataframe::Row::new(vec![
-                            dataframe::TableValue::String("utf8mb4_bin".to_string()),
-                            dataframe::TableValue::String("utf8mb4".to_string()),
-                            dataframe::TableValue::Int64(46),
-                            dataframe::TableValue::String("".to_string()),
-                            dataframe::TableValue::String("YES".to_string()),
-                            dataframe::TableValue::Int64(1),
-                            dataframe::TableValue::String("PAD SPACE".to_string()),
-                        ])]
-                    )
-                ),
-            )
-        }else if query_lower.eq("select cast('test plain returns' as char(60)) as anon_1") {
-            return Ok(
-                Arc::new(
-                    dataframe::DataFrame::new(
-                        vec![dataframe::Column::new(
-                            "anon_1".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        )],
-                        vec![dataframe::Row::new(vec![
-                            dataframe::TableValue::String("test plain returns".to_string())
-                        ])]
-                    )
-                ),
-            )
-        } else if query_lower.eq("select cast('test unicode returns' as char(60)) as anon_1") {
-            return Ok(
-                Arc::new(
-                    dataframe::DataFrame::new(
-                        vec![dataframe::Column::new(
-                            "anon_1".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        )],
-                        vec![dataframe::Row::new(vec![
-                            dataframe::TableValue::String("test plain returns".to_string())
-                        ])]
-                    )
-                ),
-            )
-        } else if query_lower.eq("select cast('test collated returns' as char character set utf8mb4) collate utf8mb4_bin as anon_1") {
-            return Ok(
-                Arc::new(
-                    dataframe::DataFrame::new(
-                        vec![dataframe::Column::new(
-                            "anon_1".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        )],
-                        vec![dataframe::Row::new(vec![
-                            dataframe::TableValue::String("test collated returns".to_string())
-                        ])]
-                    )
-                ),
-            )
-        } else if query_lower.eq("select @@transaction_isolation") {
-            return Ok(
-                Arc::new(
-                    dataframe::DataFrame::new(
-                        vec![dataframe::Column::new(
-                            "@@transaction_isolation".to_string(),
-                            ColumnType::MYSQL_TYPE_STRING,
-                        )],
-                        vec![dataframe::Row::new(vec![
-                            dataframe::TableValue::String("REPEATABLE-READ".to_string())
-                        ])]
-                    )
-                ),
-            )
-        } else if query_lower.starts_with("describe") || query_lower.starts_with("explain") {
-            let dialect = MySqlDialectWithBackTicks {};
-            let parse_result = Parser::parse_sql(&dialect, &query)?;
-
-            let query = &parse_result[0];
-
-            match query {
-                Statement::ExplainTable { table_name, .. } => {
-                    let table_name_filter = if table_name.0.len() == 2 {
-                        &table_name.0[1].value
-                    } else {
-                        &table_name.0[0].value
-                    };
+        if ignore {
+            return Ok(Arc::new(dataframe::DataFrame::new(vec![], vec![])));
+        }
 
-                    let ctx = if self.context.is_some() {
-                        self.context.as_ref().unwrap()
-                    } else {
-                        return Err(CubeError::user("must be auth".to_string()))
-                    };
-
-                    let ctx = self.schema
-                        .get_ctx_for_tenant(ctx)
-                        .await?;
-
-                    if let Some(cube) = ctx.cubes.iter().find(|c| c.name.eq(table_name_filter)) {
-                        let rows = cube.get_columns().iter().map(|column| dataframe::Row::new(
-                            vec![
-                                dataframe::TableValue::String(column.get_name().clone()),
-                                dataframe::TableValue::String(column.mysql_type_as_str().clone()),
-                                dataframe::TableValue::String(if column.mysql_can_be_null() { "Yes".to_string() } else { "No".to_string() }),
-                                dataframe::TableValue::String("".to_string()),
-                                dataframe::TableValue::Null,
-                                dataframe::TableValue::String("".to_string()),
-                            ]
-                        )).collect();
-
-
-                        return Ok(Arc::new(dataframe::DataFrame::new(
-                            vec![
-                                dataframe::Column::new(
-                                    "Field".to_string(),
-                                    ColumnType::MYSQL_TYPE_STRING
-                                ),
-                                dataframe::Column::new(
-                                    "Type".to_string(),
-                                    ColumnType::MYSQL_TYPE_STRING
-                                ),
-                                dataframe::Column::new(
-                                    "Null".to_string(),
-                                    ColumnType::MYSQL_TYPE_STRING
-                                ),
-                                dataframe::Column::new(
-                                    "Key".to_string(),
-                                    ColumnType::MYSQL_TYPE_STRING
-                                ),
-                                dataframe::Column::new(
-                                    "Default".to_string(),
-                                    ColumnType::MYSQL_TYPE_STRING
-                                ),
-                                dataframe::Column::new(
-                                    "Extra".to_string(),
-                                    ColumnType::MYSQL_TYPE_STRING
-                                )
-                            ],
-                            rows
-                        )))
-                    } else {
-                        return Err(CubeError::internal("Unknown table".to_string()))
-                    }
-                },
-                Statement::Explain { statement, .. } => {
-                    let auth_ctx = if self.context.is_some() {
-                        self.context.as_ref().unwrap()
-                    } else {
-                        return Err(CubeError::user("must be auth".to_string()))
-                    };
+        let auth_ctx = if self.context.is_some() {
+            self.context.as_ref().unwrap()
+        } else {
+            return Err(CubeError::user("must be auth".to_string()));
+        };
 
-                    let ctx = self.schema
-                        .get_ctx_for_tenant(auth_ctx)
-                    .await?;
+        if query_lower.eq("flush schema cache") {
+            self.schema.flush_cache(auth_ctx).await?;
 
-                    let plan = convert_statement_to_cube_query(statement, Arc::new(ctx), &self.props)?;
-
-                    return Ok(Arc::new(dataframe::DataFrame::new(
-                        vec![
-                            dataframe::Column::new(
-                                "Execution Plan".to_string(),
-                                ColumnType::MYSQL_TYPE_STRING
-                            ),
-                        ],
-                        vec![dataframe::Row::new(vec![
-                            dataframe::TableValue::String(
-                                plan.print(true)?
-                            )
-                        ])]
-                    )))
-                },
-                _ => {
-                    return Err(CubeError::internal("Unexpected type in ExplainTable".to_string()))
-                }
-            }
-        } else if query_lower.starts_with("show create table") {
-            let dialect = MySqlDialectWithBackTicks {};
-            let parse_result = Parser::parse_sql(&dialect, &query)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-
-            let query = &parse_result[0];
-
-            match query {
-                Statement::ShowCreate { obj_type, obj_name } => {
-                    match obj_type {
-                        ShowCreateObject::Table => {
-                            let table_name_filter = if obj_name.0.len() == 2 {
-                                &obj_name.0[1].value
-                            } else {
-                                &obj_name.0[0].value
-                            };
-
-                            let ctx = if self.context.is_some() {
-                                self.context.as_ref().unwrap()
-                            } else {
-                                return Err(CubeError::user("must be auth".to_string()))
-                            };
-
-                            let ctx = self.schema
-                                .get_ctx_for_tenant(ctx)
-                                .await?;
-
-                            if let Some(cube) = ctx.cubes.iter().find(|c| c.name.eq(table_name_filter)) {
-                                let mut fields: Vec<String> = vec![];
-
-                                for column in &cube.get_columns() {
-                                    fields.push(format!(
-                                        "`{}` {}{}",
-                                        column.get_name(),
-                                        column.mysql_type_as_str(),
-                                        if column.mysql_can_be_null() { " NOT NULL" } else { "" }
-                                    ));
-                                }
-
-                                return Ok(Arc::new(dataframe::DataFrame::new(
-                                    vec![
-                                        dataframe::Column::new(
-                                            "Table".to_string(),
-                                            ColumnType::MYSQL_TYPE_STRING
-                                        ),
-                                        dataframe::Column::new(
-                                            "Create Table".to_string(),
-                                            ColumnType::MYSQL_TYPE_STRING
-                                        )
-                                    ],
-                                    vec![dataframe::Row::new(vec![
-                                        dataframe::TableValue::String(cube.name.clone()),
-                                        dataframe::TableValue::String(
-                                            format!("CREATE TABLE `{}` (\r\n  {}\r\n) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4", cube.name, fields.join(",\r\n  "))
-                                        ),
-                                    ])]
-                                )))
-                            } else {
-                                return Err(CubeError::internal("Unknown table".to_string()));
-                            }
-                        }
-                        _ => {
-                            return Err(CubeError::internal("Unexpected type in ShowCreate".to_string()))
-                        }
-                    }
-                },
-                _ => {
-                    return Err(CubeError::internal("Unexpected AST in ShowCreate method".to_string()))
-                }
-            }
-        } else if query_lower.starts_with("show full tables from") {
-            let auth_ctx = if self.context.is_some() {
-                self.context.as_ref().unwrap()
-            } else {
-                return Err(CubeError::user("must be auth".to_string()))
-            };
+            return Ok(Arc::new(dataframe::DataFrame::new(vec![], vec![])));
+        }
 
-            let ctx = self.schema
-                .get_ctx_for_tenant(auth_ctx)
-                .await?;
-
-            let values = ctx.cubes.iter()
-                .map(|cube| dataframe::Row::new(vec![
-                    dataframe::TableValue::String(cube.name.clone()),
-                    dataframe::TableValue::String("BASE TABLE".to_string()),
-                ])).collect();
-
-            return Ok(Arc::new(dataframe::DataFrame::new(
-                vec![
-                    dataframe::Column::new(
-                        "Tables_in_db".to_string(),
-                        ColumnType::MYSQL_TYPE_STRING
-                    ),
-                    dataframe::Column::new(
-                        "Table_type".to_string(),
-                        ColumnType::MYSQL_TYPE_STRING
-                    )
-                ],
-                values
-            )))
-        } else if !ignore {
-            trace!("query was not detected");
-
-            let auth_ctx = if self.context.is_some() {
-                self.context.as_ref().unwrap()
-            } else {
-                return Err(CubeError::user("must be auth".to_string()))
-            };
+        let ctx = self.schema.get_ctx_for_tenant(auth_ctx).await?;
+        let plan = convert_sql_to_cube_query(&query, ctx, &self.props)?;
+
+        self.execute_plan(plan, auth_ctx).await
+    }
+
+    async fn execute_plan(
+        &self,
+        plan: crate::compile::QueryPlan,
+        auth_ctx: &AuthContext,
+    ) -> Result<Arc<dataframe::DataFrame>, CubeError> {
+        match plan {
+            crate::compile::QueryPlan::Meta(data_frame) => Ok(data_frame),
+            crate::compile::QueryPlan::DataFushionSelect(plan, ctx) => {
+                let df = DataFrameImpl::new(ctx.state, &plan);
+                let batches = df.collect().await?;
+                let response = batch_to_dataframe(&batches)?;
+
+                Ok(Arc::new(response))
+            }
+            crate::compile::QueryPlan::CubeSelect(plan) => {
+                debug!("Request {}", json!(plan.request).to_string());
+                debug!("Meta {:?}", plan.meta);
+
+                let watcher =
+                    DisconnectWatcher::new(&self.connection, self.close_socket_rx.clone())?;
+                let response = self
+                    .schema
+                    .request(plan.request, auth_ctx, watcher.receiver())
+                    .await?;
 
-            let ctx = self.schema
-                .get_ctx_for_tenant(auth_ctx)
-                .await?;
-
-            let plan = convert_sql_to_cube_query(&query, Arc::new(ctx), &self.props)?;
-            match plan {
-                crate::compile::QueryPlan::Meta(data_frame) => {
-                    return Ok(data_frame);
-                },
-                crate::compile::QueryPlan::DataFushionSelect(plan, ctx) => {
-                    let df = DataFrameImpl::new(
-                        ctx.state,
-                        &plan,
-                    );
-                    let batches = df.collect().await?;
-                    let response =  batch_to_dataframe(&batches)?;
-
-                    return Ok(Arc::new(response))
-                },
-                crate::compile::QueryPlan::CubeSelect(plan) => {
-                    debug!("Request {}", json!(plan.request).to_string());
-                    debug!("Meta {:?}", plan.meta);
-
-                    let response = self.schema
-                        .request(plan.request, auth_ctx)
-                        .await?;
-
-                    let mut columns: Vec<dataframe::Column> = vec![];
-
-                    for column_meta in &plan.meta {
-                        columns.push(dataframe::Column::new(
+                let columns = plan
+                    .meta
+                    .iter()
+                    .map(|column_meta| {
+                        dataframe::Column::new(
                             column_meta.column_to.clone(),
-                            column_meta.column_type
-                        ));
-                    }
+                            column_meta.column_type,
+                        )
+                    })
+                    .collect::<Vec<_>>();
 
-                    let mut rows: Vec<dataframe::Row> = vec![];
-
-                    if let Some(result) = response.results.first() {
-                        debug!("Columns {:?}", columns);
-                        debug!("Hydration mapping {:?}", plan.meta);
-                        trace!("Response from Cube.js {:?}", result.data);
-
-                        for row in result.data.iter() {
-                            if let Some(record) = row.as_object() {
-                                rows.push(
-                                    dataframe::Row::hydrate_from_response(&plan.meta, record)
-                                );
-                            } else {
-                                error!(
-                                    "Unable to map row to DataFrame::Row: {:?}, skipping row",
-                                    row
-                                );
-                            }
+                let mut rows: Vec<dataframe::Row> = vec![];
+
+                if let Some(result) = response.results.first() {
+                    debug!("Columns {:?}", columns);
+                    debug!("Hydration mapping {:?}", plan.meta);
+                    trace!("Response from Cube.js {:?}", result.data);
+
+                    for row in result.data.iter() {
+                        if let Some(record) = row.as_object() {
+                            rows.push(dataframe::Row::hydrate_from_response(&plan.meta, record));
+                        } else {
+                            error!(
+                                "Unable to map row to DataFrame::Row: {:?}, skipping row",
+                                row
+                            );
                         }
-
-                        return Ok(Arc::new(dataframe::DataFrame::new(
-                            columns,
-                            rows
-                        )));
-                    } else {
-                        return Ok(Arc::new(dataframe::DataFrame::new(vec![], vec![])));
                     }
+
+                    Ok(Arc::new(dataframe::DataFrame::new(columns, rows)))
+                } else {
+                    Ok(Arc::new(dataframe::DataFrame::new(vec![], vec![])))
                 }
             }
         }
-
-        if ignore {
-            Ok(Arc::new(dataframe::DataFrame::new(vec![], vec![])))
-        } else {
-            Err(CubeError::internal("Unsupported query".to_string()))
-        }
     }
 }
 
@@ -500,25 +311,96 @@ impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
 
     async fn on_prepare<'a>(
         &'a mut self,
-        _query: &'a str,
+        query: &'a str,
         info: StatementMetaWriter<'a, W>,
     ) -> Result<(), Self::Error> {
-        info.reply(42, &[], &[])
+        let statement = match PreparedStatement::new(query) {
+            Err(e) => {
+                error!("Error during preparing {}: {}", query, e.to_string());
+                info.error(ErrorKind::ER_PARSE_ERROR, e.message.as_bytes())?;
+
+                return Ok(());
+            }
+            Ok(statement) => statement,
+        };
+
+        let (param_types, columns) = match self.prepared_statement_meta(&statement).await {
+            Err(e) => {
+                error!("Error during preparing {}: {}", query, e.to_string());
+                info.error(ErrorKind::ER_INTERNAL_ERROR, e.message.as_bytes())?;
+
+                return Ok(());
+            }
+            Ok(meta) => meta,
+        };
+
+        let columns = columns.iter().map(to_mysql_column).collect::<Vec<_>>();
+        let params = param_types
+            .into_iter()
+            .map(|coltype| Column {
+                table: "".to_string(),
+                column: "?".to_string(),
+                coltype,
+                colflags: ColumnFlags::empty(),
+            })
+            .collect::<Vec<_>>();
+
+        self.statement_id_incr = if self.statement_id_incr == u32::MAX {
+            1
+        } else {
+            self.statement_id_incr + 1
+        };
+
+        let id = self.statement_id_incr;
+        self.statements.insert(id, statement);
+
+        info.reply(id, &params, &columns)
     }
 
     async fn on_execute<'a>(
         &'a mut self,
-        _id: u32,
-        _params: ParamParser<'a>,
+        id: u32,
+        params: ParamParser<'a>,
         results: QueryResultWriter<'a, W>,
     ) -> Result<(), Self::Error> {
-        results.completed(0, 0)
+        // Statement is taken out of the map during execution, because execution borrows
+        // the backend mutably
+        let statement = match self.statements.remove(&id) {
+            None => {
+                results.error(
+                    ErrorKind::ER_UNKNOWN_STMT_HANDLER,
+                    format!(
+                        "Unknown prepared statement handler ({}) given to EXECUTE",
+                        id
+                    )
+                    .as_bytes(),
+                )?;
+
+                return Ok(());
+            }
+            Some(statement) => statement,
+        };
+
+        let result = self.execute_statement(&statement, params).await;
+        let query = statement.query().to_string();
+        self.statements.insert(id, statement);
+
+        match result {
+            Err(e) => {
+                error!("Error during processing {}: {}", query, e.to_string());
+                results.error(error_kind(&e), e.message.as_bytes())?;
+
+                Ok(())
+            }
+            Ok(data_frame) => write_data_frame(results, &data_frame, true),
+        }
     }
 
-    async fn on_close<'a>(&'a mut self, _stmt: u32)
+    async fn on_close<'a>(&'a mut self, stmt: u32)
     where
         W: 'async_trait,
     {
+        self.statements.remove(&stmt);
     }
 
     async fn on_query<'a>(
@@ -529,43 +411,11 @@ impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
         match self.execute_query(query).await {
             Err(e) => {
                 error!("Error during processing {}: {}", query, e.to_string());
-                results.error(ErrorKind::ER_INTERNAL_ERROR, e.message.as_bytes())?;
-
-                Ok(())
-            }
-            Ok(data_frame) => {
-                let columns = data_frame
-                    .get_columns()
-                    .iter()
-                    .map(|c| Column {
-                        table: "result".to_string(), // TODO
-                        column: c.get_name(),
-                        coltype: c.get_type(),
-                        colflags: ColumnFlags::empty(),
-                    })
-                    .collect::<Vec<_>>();
-
-                let mut rw = results.start(&columns)?;
-
-                for row in data_frame.get_rows().iter() {
-                    for (_i, value) in row.values().iter().enumerate() {
-                        match value {
-                            dataframe::TableValue::String(s) => rw.write_col(s)?,
-                            dataframe::TableValue::Timestamp(s) => rw.write_col(s.to_string())?,
-                            dataframe::TableValue::Boolean(s) => rw.write_col(s.to_string())?,
-                            dataframe::TableValue::Float64(s) => rw.write_col(s)?,
-                            dataframe::TableValue::Int64(s) => rw.write_col(s)?,
-                            dataframe::TableValue::Null => rw.write_col(Option::<String>::None)?,
-                        }
-                    }
-
-                    rw.end_row()?;
-                }
-
-                rw.finish()?;
+                results.error(error_kind(&e), e.message.as_bytes())?;
 
                 Ok(())
             }
+            Ok(data_frame) => write_data_frame(results, &data_frame, false),
         }
     }
 
@@ -579,7 +429,15 @@ impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
             None
         };
 
-        let ctx = self.auth.authenticate(user.clone()).await.map_err(|e| {
+        let token = self
+            .clear_password
+            .as_ref()
+            .and_then(|slot| slot.lock().unwrap().take());
+        let ctx = match token {
+            Some(token) => self.auth.authenticate_with_token(user, token).await,
+            None => self.auth.authenticate(user).await,
+        };
+        let ctx = ctx.map_err(|e| {
             if e.message != *"Incorrect user name or password" {
                 error!("Error during authentication MySQL connection: {}", e);
             };
@@ -595,6 +453,65 @@ impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
     }
 }
 
+fn error_kind(e: &CubeError) -> ErrorKind {
+    match e.cause {
+        CubeErrorCauseType::Timeout => ErrorKind::ER_QUERY_INTERRUPTED,
+        _ => ErrorKind::ER_INTERNAL_ERROR,
+    }
+}
+
+/// Result columns are computed by the query and don't belong to a table of the schema, so the table
+/// name is left empty, the same way MySQL does for expressions.
+fn to_mysql_column(column: &dataframe::Column) -> Column {
+    Column {
+        table: "".to_string(),
+        column: column.get_name(),
+        coltype: column.get_type(),
+        colflags: ColumnFlags::empty(),
+    }
+}
+
+/// Writes data frame to the client. Binary row protocol (used for prepared statements) requires
+/// the value to be encoded exactly as the declared column type.
+fn write_data_frame<W: io::Write>(
+    results: QueryResultWriter<W>,
+    data_frame: &dataframe::DataFrame,
+    binary: bool,
+) -> Result<(), io::Error> {
+    let columns = data_frame
+        .get_columns()
+        .iter()
+        .map(to_mysql_column)
+        .collect::<Vec<_>>();
+
+    let mut rw = results.start(&columns)?;
+
+    for row in data_frame.get_rows().iter() {
+        for (i, value) in row.values().iter().enumerate() {
+            match value {
+                dataframe::TableValue::String(s) => rw.write_col(s)?,
+                dataframe::TableValue::Timestamp(s) => rw.write_col(s.to_string())?,
+                dataframe::TableValue::Boolean(s) => {
+                    if binary && columns[i].coltype == ColumnType::MYSQL_TYPE_TINY {
+                        rw.write_col(*s as i8)?
+                    } else {
+                        rw.write_col(s.to_string())?
+                    }
+                }
+                dataframe::TableValue::Float64(s) => rw.write_col(s)?,
+                dataframe::TableValue::Int64(s) => rw.write_col(s)?,
+                dataframe::TableValue::Null => rw.write_col(Option::<String>::None)?,
+            }
+        }
+
+        rw.end_row()?;
+    }
+
+    rw.finish()?;
+
+    Ok(())
+}
+
 pub struct MySqlServer {
     address: String,
     auth: Arc<dyn SqlAuthService>,
@@ -637,6 +554,7 @@ impl ProcessingLoop for MySqlServer {
 
             let auth = self.auth.clone();
             let schema = self.schema.clone();
+            let close_socket_rx = stop_receiver.clone();
 
             let connection_id = if connection_id_incr > 100_000_u32 {
                 connection_id_incr = 1;
@@ -648,6 +566,22 @@ impl ProcessingLoop for MySqlServer {
                 connection_id_incr
             };
 
+            let (socket, connection) = match Self::duplicate_socket(socket) {
+                Ok(res) => res,
+                Err(e) => {
+                    error!("Unable to prepare MySQL connection: {}", e);
+                    continue;
+                }
+            };
+
+            let (socket, clear_password): (Box<dyn AsyncStream>, _) =
+                if auth.accepts_token_password() {
+                    let (socket, slot) = proxy_clear_password(socket);
+                    (Box::new(socket), Some(slot))
+                } else {
+                    (Box::new(socket), None)
+                };
+
             tokio::spawn(async move {
                 if let Err(e) = AsyncMysqlIntermediary::run_on(
                     Backend {
@@ -655,6 +589,11 @@ impl ProcessingLoop for MySqlServer {
                         schema,
                         props: QueryPlannerExecutionProps::new(connection_id, None),
                         context: None,
+                        clear_password,
+                        statements: HashMap::new(),
+                        statement_id_incr: 0,
+                        connection: Some(connection),
+                        close_socket_rx,
                     },
                     socket,
                 )
@@ -673,6 +612,13 @@ impl ProcessingLoop for MySqlServer {
 }
 
 impl MySqlServer {
+    fn duplicate_socket(socket: TcpStream) -> Result<(TcpStream, std::net::TcpStream), CubeError> {
+        let socket = socket.into_std()?;
+        let duplicate = socket.try_clone()?;
+
+        Ok((TcpStream::from_std(socket)?, duplicate))
+    }
+
     pub fn new(
         address: String,
         auth: Arc<dyn SqlAuthService>,
@@ -699,6 +645,22 @@ pub struct AuthContext {
 #[async_trait]
 pub trait SqlAuthService: Send + Sync {
     async fn authenticate(&self, user: Option<String>) -> Result<AuthContext, CubeError>;
+
+    /// Clients are asked to send the password in clear text with `mysql_clear_password`
+    /// authentication and it's passed to `authenticate_with_token`.
+    fn accepts_token_password(&self) -> bool {
+        false
+    }
+
+    async fn authenticate_with_token(
+        &self,
+        _user: Option<String>,
+        _token: String,
+    ) -> Result<AuthContext, CubeError> {
+        Err(CubeError::internal(
+            "Authentication by token is not supported".to_string(),
+        ))
+    }
 }
 
 pub struct SqlAuthDefaultImpl;
@@ -0,0 +1,400 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use msql_srv::{ColumnType, ParamParser, ValueInner};
+use sqlparser::ast;
+use sqlparser::dialect::keywords::Keyword;
+use sqlparser::tokenizer::Token;
+
+use crate::compile::parser::{parse_tokens, tokenize};
+use crate::compile::{rewrite_show_statements, TenantContext};
+use crate::schema::{V1CubeMetaDimensionExt, V1CubeMetaMeasureExt};
+use crate::CubeError;
+
+/// Server side prepared statement.
+///
+/// The query is tokenized once on prepare. On execute every `?` placeholder token is replaced
+/// by a literal token of the bound value's type and the statement is parsed from tokens, so
+/// values never become a part of the SQL text and don't need escaping.
+#[derive(Debug)]
+pub struct PreparedStatement {
+    query: String,
+    tokens: Vec<Token>,
+    placeholders: Vec<usize>,
+}
+
+impl PreparedStatement {
+    pub fn new(query: &str) -> Result<PreparedStatement, CubeError> {
+        let query = rewrite_show_statements(query)?.unwrap_or_else(|| query.to_string());
+        let tokens =
+            tokenize(&query).map_err(|e| CubeError::user(format!("Unable to parse: {:?}", e)))?;
+
+        let placeholders = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| **token == Token::Char('?'))
+            .map(|(i, _)| i)
+            .collect();
+
+        Ok(PreparedStatement {
+            query,
+            tokens,
+            placeholders,
+        })
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn params_count(&self) -> usize {
+        self.placeholders.len()
+    }
+
+    /// Types of the parameters reported to the client on prepare. They are inferred from
+    /// the context of the placeholder: `LIMIT ?`/`OFFSET ?` and comparisons with a measure or
+    /// a dimension of the tenant's cubes. Other parameters are declared as strings.
+    pub fn param_types(&self, ctx: &TenantContext) -> Vec<ColumnType> {
+        self.placeholders
+            .iter()
+            .map(|i| self.param_type(*i, ctx))
+            .collect()
+    }
+
+    /// Statement with NULL in place of every placeholder. It's used to compile query on prepare,
+    /// when actual values are not known yet.
+    pub fn bind_nulls(&self) -> Result<ast::Statement, CubeError> {
+        self.bind_tokens(vec![vec![Token::make_keyword("NULL")]; self.params_count()])
+    }
+
+    pub fn bind(&self, params: ParamParser) -> Result<ast::Statement, CubeError> {
+        let mut values = Vec::with_capacity(self.params_count());
+
+        for param in params {
+            values.push(value_to_tokens(param.value.into_inner())?);
+        }
+
+        self.bind_tokens(values)
+    }
+
+    fn bind_tokens(&self, values: Vec<Vec<Token>>) -> Result<ast::Statement, CubeError> {
+        if values.len() != self.params_count() {
+            return Err(CubeError::user(format!(
+                "Incorrect arguments to execute: expected {} parameter(s), actual: {}",
+                self.params_count(),
+                values.len()
+            )));
+        }
+
+        let mut tokens = self.tokens.clone();
+        // Going from the end keeps positions of the remaining placeholders.
+        for (i, value) in self.placeholders.iter().zip(values.into_iter()).rev() {
+            tokens.splice(*i..*i + 1, value);
+        }
+
+        Ok(parse_tokens(tokens)?)
+    }
+
+    fn param_type(&self, index: usize, ctx: &TenantContext) -> ColumnType {
+        let before = self.tokens[..index]
+            .iter()
+            .rev()
+            .filter(|t| !matches!(t, Token::Whitespace(_)))
+            .collect::<Vec<_>>();
+        let after = self.tokens[index + 1..]
+            .iter()
+            .filter(|t| !matches!(t, Token::Whitespace(_)))
+            .collect::<Vec<_>>();
+
+        let member = match (before.as_slice(), after.as_slice()) {
+            ([Token::Word(w), ..], _) if w.keyword == Keyword::LIMIT => {
+                return ColumnType::MYSQL_TYPE_LONGLONG
+            }
+            ([Token::Word(w), ..], _) if w.keyword == Keyword::OFFSET => {
+                return ColumnType::MYSQL_TYPE_LONGLONG
+            }
+            ([op, Token::Word(w), ..], _) if is_comparison(op) => Some(&w.value),
+            (_, [op, Token::Word(w), ..]) if is_comparison(op) => Some(&w.value),
+            _ => None,
+        };
+
+        member
+            .and_then(|name| member_type(ctx, name))
+            .unwrap_or(ColumnType::MYSQL_TYPE_VAR_STRING)
+    }
+}
+
+fn is_comparison(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Eq | Token::Neq | Token::Lt | Token::Gt | Token::LtEq | Token::GtEq
+    )
+}
+
+fn member_type(ctx: &TenantContext, name: &str) -> Option<ColumnType> {
+    for cube in ctx.cubes.iter() {
+        if let Some(measure) = cube.measures.iter().find(|m| m.get_real_name() == name) {
+            return Some(measure.get_mysql_type());
+        }
+
+        if let Some(dimension) = cube.dimensions.iter().find(|d| d.get_real_name() == name) {
+            return Some(match dimension._type.as_str() {
+                "number" => ColumnType::MYSQL_TYPE_DOUBLE,
+                "time" => ColumnType::MYSQL_TYPE_DATETIME,
+                _ => ColumnType::MYSQL_TYPE_VAR_STRING,
+            });
+        }
+    }
+
+    None
+}
+
+/// Negative numbers are a minus followed by the absolute value, the same as the parser sees them
+/// in the query text.
+fn number_tokens(negative: bool, abs: String) -> Vec<Token> {
+    let number = Token::Number(abs, false);
+    if negative {
+        vec![Token::Minus, number]
+    } else {
+        vec![number]
+    }
+}
+
+fn value_to_tokens(value: ValueInner) -> Result<Vec<Token>, CubeError> {
+    let token = match value {
+        ValueInner::NULL => Token::make_keyword("NULL"),
+        ValueInner::Int(v) => return Ok(number_tokens(v < 0, v.unsigned_abs().to_string())),
+        ValueInner::UInt(v) => Token::Number(v.to_string(), false),
+        ValueInner::Double(v) if v.is_finite() => {
+            return Ok(number_tokens(v.is_sign_negative(), v.abs().to_string()))
+        }
+        ValueInner::Double(v) => {
+            return Err(CubeError::user(format!(
+                "Unable to bind {} as a parameter",
+                v
+            )))
+        }
+        ValueInner::Bytes(v) => Token::SingleQuotedString(String::from_utf8_lossy(v).to_string()),
+        ValueInner::Date(mut v) | ValueInner::Datetime(mut v) => {
+            let length = v.len();
+            let year = if length >= 4 {
+                v.read_u16::<LittleEndian>()?
+            } else {
+                0
+            };
+            let (month, day) = if length >= 4 {
+                (v.read_u8()?, v.read_u8()?)
+            } else {
+                (0, 0)
+            };
+            let (hour, minute, second) = if length >= 7 {
+                (v.read_u8()?, v.read_u8()?, v.read_u8()?)
+            } else {
+                (0, 0, 0)
+            };
+            let micros = if length >= 11 {
+                v.read_u32::<LittleEndian>()?
+            } else {
+                0
+            };
+
+            Token::SingleQuotedString(format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}",
+                year, month, day, hour, minute, second, micros
+            ))
+        }
+        ValueInner::Time(mut v) => {
+            if v.is_empty() {
+                Token::SingleQuotedString("00:00:00".to_string())
+            } else {
+                let is_negative = v.read_u8()? == 1;
+                let days = v.read_u32::<LittleEndian>()?;
+                let (hour, minute, second) = (v.read_u8()?, v.read_u8()?, v.read_u8()?);
+                let micros = if !v.is_empty() {
+                    v.read_u32::<LittleEndian>()?
+                } else {
+                    0
+                };
+
+                Token::SingleQuotedString(format!(
+                    "{}{:02}:{:02}:{:02}.{:06}",
+                    if is_negative { "-" } else { "" },
+                    days * 24 + hour as u32,
+                    minute,
+                    second,
+                    micros
+                ))
+            }
+        }
+    };
+
+    Ok(vec![token])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cubeclient::models::{V1CubeMeta, V1CubeMetaDimension, V1CubeMetaMeasure};
+
+    fn parse(query: &str) -> ast::Statement {
+        parse_tokens(tokenize(query).unwrap()).unwrap()
+    }
+
+    fn selection(statement: ast::Statement) -> ast::Expr {
+        match statement {
+            ast::Statement::Query(query) => match query.body {
+                ast::SetExpr::Select(select) => select.selection.unwrap(),
+                _ => panic!("Expected SELECT"),
+            },
+            _ => panic!("Expected query"),
+        }
+    }
+
+    fn get_test_tenant_ctx() -> TenantContext {
+        TenantContext {
+            cubes: vec![V1CubeMeta {
+                name: "Orders".to_string(),
+                title: None,
+                dimensions: vec![
+                    V1CubeMetaDimension {
+                        name: "Orders.status".to_string(),
+                        _type: "string".to_string(),
+                    },
+                    V1CubeMetaDimension {
+                        name: "Orders.createdAt".to_string(),
+                        _type: "time".to_string(),
+                    },
+                    V1CubeMetaDimension {
+                        name: "Orders.amount".to_string(),
+                        _type: "number".to_string(),
+                    },
+                ],
+                measures: vec![V1CubeMetaMeasure {
+                    name: "Orders.count".to_string(),
+                    title: None,
+                    _type: "number".to_string(),
+                    agg_type: Some("count".to_string()),
+                }],
+                segments: vec![],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let stmt = PreparedStatement::new(
+            "SELECT COUNT(*), 'why?' FROM `Why?` WHERE a = ? /* ? */ AND b = ? -- ?\n LIMIT ?",
+        )
+        .unwrap();
+        assert_eq!(stmt.params_count(), 3);
+        assert_eq!(
+            stmt.bind_nulls().unwrap(),
+            parse("SELECT COUNT(*), 'why?' FROM `Why?` WHERE a = NULL AND b = NULL LIMIT NULL")
+        );
+
+        let stmt = PreparedStatement::new("SELECT 'it''s?' FROM t WHERE a = ?").unwrap();
+        assert_eq!(stmt.params_count(), 1);
+
+        let stmt = PreparedStatement::new("SHOW TABLES LIKE ?").unwrap();
+        assert_eq!(stmt.params_count(), 1);
+    }
+
+    #[test]
+    fn test_bind_values_as_literals() {
+        let stmt = PreparedStatement::new("SELECT a FROM t WHERE b = ?").unwrap();
+
+        // Neither quotes nor backslashes in the value can change the statement.
+        for value in ["x' OR '1'='1", "x\\' OR 1=1 -- ", "'; DROP TABLE t"] {
+            let tokens = value_to_tokens(ValueInner::Bytes(value.as_bytes())).unwrap();
+            assert_eq!(
+                selection(stmt.bind_tokens(vec![tokens]).unwrap()),
+                ast::Expr::BinaryOp {
+                    left: Box::new(ast::Expr::Identifier(ast::Ident::new("b"))),
+                    op: ast::BinaryOperator::Eq,
+                    right: Box::new(ast::Expr::Value(ast::Value::SingleQuotedString(
+                        value.to_string()
+                    ))),
+                }
+            );
+        }
+
+        for (value, literal) in [
+            (ValueInner::Int(-5), "-5"),
+            (ValueInner::Int(i64::MIN), "-9223372036854775808"),
+            (ValueInner::Double(-1.5), "-1.5"),
+            (ValueInner::UInt(5), "5"),
+        ] {
+            assert_eq!(
+                stmt.bind_tokens(vec![value_to_tokens(value).unwrap()])
+                    .unwrap(),
+                parse(&format!("SELECT a FROM t WHERE b = {}", literal))
+            );
+        }
+
+        let stmt = PreparedStatement::new("SELECT a FROM t WHERE b = ? AND c = ? - ?").unwrap();
+        let values = vec![ValueInner::Int(-1), ValueInner::Int(2), ValueInner::Int(-3)]
+            .into_iter()
+            .map(|v| value_to_tokens(v).unwrap())
+            .collect();
+        assert_eq!(
+            stmt.bind_tokens(values).unwrap(),
+            parse("SELECT a FROM t WHERE b = -1 AND c = 2 - -3")
+        );
+
+        assert!(stmt.bind_tokens(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_value_to_token() {
+        assert_eq!(
+            value_to_tokens(ValueInner::NULL).unwrap(),
+            vec![Token::make_keyword("NULL")]
+        );
+        assert_eq!(
+            value_to_tokens(ValueInner::UInt(5)).unwrap(),
+            vec![Token::Number("5".to_string(), false)]
+        );
+        assert_eq!(
+            value_to_tokens(ValueInner::Double(1.5)).unwrap(),
+            vec![Token::Number("1.5".to_string(), false)]
+        );
+        assert_eq!(
+            value_to_tokens(ValueInner::Int(-5)).unwrap(),
+            vec![Token::Minus, Token::Number("5".to_string(), false)]
+        );
+        assert!(value_to_tokens(ValueInner::Double(f64::NAN)).is_err());
+        assert_eq!(
+            value_to_tokens(ValueInner::Datetime(&[0xe5, 0x07, 10, 5, 13, 20, 1])).unwrap(),
+            vec![Token::SingleQuotedString(
+                "2021-10-05 13:20:01.000000".to_string()
+            )]
+        );
+        assert_eq!(
+            value_to_tokens(ValueInner::Date(&[0xe5, 0x07, 10, 5])).unwrap(),
+            vec![Token::SingleQuotedString(
+                "2021-10-05 00:00:00.000000".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_param_types() {
+        let ctx = get_test_tenant_ctx();
+        let stmt = PreparedStatement::new(
+            "SELECT count FROM Orders WHERE status = ? AND ? < createdAt AND amount >= ? \
+             AND count > ? AND unknown = ? LIMIT ? OFFSET ?",
+        )
+        .unwrap();
+
+        assert_eq!(
+            stmt.param_types(&ctx),
+            vec![
+                ColumnType::MYSQL_TYPE_VAR_STRING,
+                ColumnType::MYSQL_TYPE_DATETIME,
+                ColumnType::MYSQL_TYPE_DOUBLE,
+                ColumnType::MYSQL_TYPE_LONGLONG,
+                ColumnType::MYSQL_TYPE_VAR_STRING,
+                ColumnType::MYSQL_TYPE_LONGLONG,
+                ColumnType::MYSQL_TYPE_LONGLONG,
+            ]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::Instant;
+
+use crate::compile::TenantContext;
+use crate::mysql::AuthContext;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SchemaCacheKey {
+    pub access_token: String,
+    pub base_path: String,
+}
+
+impl SchemaCacheKey {
+    pub fn from_auth_ctx(ctx: &AuthContext) -> Self {
+        Self {
+            access_token: ctx.access_token.clone(),
+            base_path: ctx.base_path.clone(),
+        }
+    }
+}
+
+struct SchemaCacheEntry {
+    ctx: Arc<TenantContext>,
+    loaded_at: Instant,
+    last_accessed: Instant,
+}
+
+impl SchemaCacheEntry {
+    fn new(ctx: Arc<TenantContext>) -> Self {
+        let now = Instant::now();
+
+        Self {
+            ctx,
+            loaded_at: now,
+            last_accessed: now,
+        }
+    }
+
+    fn used_since_load(&self) -> bool {
+        self.last_accessed > self.loaded_at
+    }
+}
+
+type SchemaCacheSlot = Arc<Mutex<Option<SchemaCacheEntry>>>;
+
+/// Cache of `TenantContext` per access token and Cube.js base path.
+///
+/// Every slot has its own lock, so concurrent connections of the same tenant wait for
+/// the single meta request instead of sending their own.
+pub struct SchemaCache {
+    ttl: Duration,
+    slots: RwLock<HashMap<SchemaCacheKey, SchemaCacheSlot>>,
+}
+
+impl SchemaCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            slots: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ttl != Duration::from_secs(0)
+    }
+
+    /// Interval of background refresh, active entries are reloaded when a half of TTL has passed.
+    pub fn refresh_interval(&self) -> Duration {
+        std::cmp::max(self.ttl / 2, Duration::from_secs(1))
+    }
+
+    async fn slot(&self, key: &SchemaCacheKey) -> SchemaCacheSlot {
+        if let Some(slot) = self.slots.read().await.get(key) {
+            return slot.clone();
+        }
+
+        self.slots
+            .write()
+            .await
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(None)))
+            .clone()
+    }
+
+    pub async fn get_or_load<F, Fut, E>(
+        &self,
+        key: &SchemaCacheKey,
+        load: F,
+    ) -> Result<Arc<TenantContext>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<TenantContext, E>>,
+    {
+        let slot = self.slot(key).await;
+        let mut entry = slot.lock().await;
+
+        if let Some(entry) = entry.as_mut() {
+            if entry.loaded_at.elapsed() < self.ttl {
+                entry.last_accessed = Instant::now();
+
+                return Ok(entry.ctx.clone());
+            }
+        }
+
+        let ctx = Arc::new(load().await?);
+        *entry = Some(SchemaCacheEntry::new(ctx.clone()));
+
+        Ok(ctx)
+    }
+
+    /// Returns keys of the entries which should be reloaded in background: entries which were used
+    /// after the last load and are older than refresh interval. Expired entries without usage
+    /// are evicted.
+    pub async fn keys_to_refresh(&self) -> Vec<SchemaCacheKey> {
+        let mut to_refresh = Vec::new();
+        let mut to_evict = Vec::new();
+
+        for (key, slot) in self.slots.read().await.iter() {
+            // Slot is locked by a foreground load, nothing to do
+            let entry = match slot.try_lock() {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+
+            match entry.as_ref() {
+                None => to_evict.push(key.clone()),
+                Some(entry) => {
+                    let age = entry.loaded_at.elapsed();
+
+                    if entry.used_since_load() && age >= self.refresh_interval() {
+                        to_refresh.push(key.clone());
+                    } else if !entry.used_since_load() && age >= self.ttl {
+                        to_evict.push(key.clone());
+                    }
+                }
+            }
+        }
+
+        if !to_evict.is_empty() {
+            let mut slots = self.slots.write().await;
+            for key in to_evict.iter() {
+                slots.remove(key);
+            }
+        }
+
+        to_refresh
+    }
+
+    pub async fn update(&self, key: &SchemaCacheKey, ctx: TenantContext) {
+        let slot = self.slot(key).await;
+        let mut entry = slot.lock().await;
+
+        *entry = Some(SchemaCacheEntry::new(Arc::new(ctx)));
+    }
+
+    /// Drops the entry of a single tenant, other tenants keep their schemas.
+    pub async fn flush(&self, key: &SchemaCacheKey) {
+        self.slots.write().await.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(token: &str) -> SchemaCacheKey {
+        SchemaCacheKey {
+            access_token: token.to_string(),
+            base_path: "http://localhost:4000/cubejs-api".to_string(),
+        }
+    }
+
+    async fn load_empty() -> Result<TenantContext, ()> {
+        Ok(TenantContext { cubes: vec![] })
+    }
+
+    async fn load_unexpected() -> Result<TenantContext, ()> {
+        panic!("Must be served from cache")
+    }
+
+    #[tokio::test]
+    async fn test_get_or_load_caches_per_key() {
+        let cache = SchemaCache::new(Duration::from_secs(60));
+
+        let first = cache.get_or_load(&key("a"), load_empty).await.unwrap();
+        let second = cache.get_or_load(&key("a"), load_unexpected).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let other = cache.get_or_load(&key("b"), load_empty).await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &other));
+
+        cache.flush(&key("a")).await;
+
+        let reloaded = cache.get_or_load(&key("a"), load_empty).await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &reloaded));
+
+        // Flush of one tenant doesn't affect others
+        let other_cached = cache.get_or_load(&key("b"), load_unexpected).await.unwrap();
+        assert!(Arc::ptr_eq(&other, &other_cached));
+    }
+
+    #[tokio::test]
+    async fn test_keys_to_refresh() {
+        let cache = SchemaCache::new(Duration::from_millis(0));
+        cache
+            .update(&key("a"), TenantContext { cubes: vec![] })
+            .await;
+
+        // Expired and never used after load
+        assert_eq!(cache.keys_to_refresh().await, vec![]);
+        assert!(cache.slots.read().await.is_empty());
+    }
+}
@@ -1,49 +1,105 @@
+use std::cmp::min;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 
-use cubeclient::apis::{configuration::Configuration, default_api as cube_api};
+use cubeclient::apis::{
+    configuration::Configuration,
+    default_api::{self as cube_api, LoadV1Poll},
+};
 use cubeclient::models::{
     V1CubeMeta, V1CubeMetaDimension, V1CubeMetaMeasure, V1CubeMetaSegment, V1LoadRequest,
     V1LoadRequestQuery, V1LoadResponse,
 };
+use log::{debug, error};
 use msql_srv::ColumnType;
+use tokio::sync::{watch, RwLock};
+use tokio::time::Instant;
+use uuid::Uuid;
 
 use crate::compile::TenantContext;
+use crate::config::processing_loop::ProcessingLoop;
+use crate::config::ConfigObj;
 use crate::mysql::AuthContext;
+use crate::schema::cache::{SchemaCache, SchemaCacheKey};
 use crate::CubeError;
 
+pub mod cache;
 pub mod ctx;
 
 #[async_trait]
 pub trait SchemaService: Send + Sync {
-    async fn get_ctx_for_tenant(&self, ctx: &AuthContext) -> Result<TenantContext, CubeError>;
+    /// Returns schema of the tenant, it's cached per access token and base path
+    /// for `ConfigObj::schema_cache_ttl` seconds.
+    async fn get_ctx_for_tenant(&self, ctx: &AuthContext) -> Result<Arc<TenantContext>, CubeError>;
+
+    /// Drops cached schema of the tenant, next access will load it from Cube.js.
+    async fn flush_cache(&self, ctx: &AuthContext) -> Result<(), CubeError>;
 
+    /// Loads data from Cube.js, re-polling while it answers with `Continue wait`.
+    /// Polling stops with timeout error after `ConfigObj::query_timeout` and can be
+    /// interrupted by sending `true` to `cancel` (for example, when the client has disconnected).
     async fn request(
         &self,
         query: V1LoadRequestQuery,
         ctx: &AuthContext,
+        cancel: watch::Receiver<bool>,
     ) -> Result<V1LoadResponse, CubeError>;
 }
 
-pub struct SchemaServiceDefaultImpl;
+/// Resolves when cancellation was requested through the `cancel` receiver of
+/// `SchemaService::request`. Never resolves if sender was dropped without it.
+pub async fn cancelled(cancel: &mut watch::Receiver<bool>) {
+    loop {
+        if *cancel.borrow() {
+            return;
+        }
+
+        if cancel.changed().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+pub struct SchemaServiceDefaultImpl {
+    config_obj: Arc<dyn ConfigObj>,
+    cache: SchemaCache,
+    stop_rx: RwLock<watch::Receiver<bool>>,
+    stop_tx: watch::Sender<bool>,
+}
 
 impl SchemaServiceDefaultImpl {
-    fn get_client_config_for_ctx(&self, ctx: &AuthContext) -> Configuration {
+    const POLL_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+    const POLL_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+    pub fn new(config_obj: Arc<dyn ConfigObj>) -> Arc<Self> {
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let cache = SchemaCache::new(Duration::from_secs(config_obj.schema_cache_ttl()));
+
+        Arc::new(Self {
+            config_obj,
+            cache,
+            stop_rx: RwLock::new(stop_rx),
+            stop_tx,
+        })
+    }
+
+    fn get_client_config(&self, access_token: &String, base_path: &String) -> Configuration {
         let mut cube_config = Configuration::default();
-        cube_config.bearer_access_token = Some(ctx.access_token.clone());
-        cube_config.base_path = ctx.base_path.clone();
+        cube_config.bearer_access_token = Some(access_token.clone());
+        cube_config.base_path = base_path.clone();
 
         cube_config
     }
-}
 
-crate::di_service!(SchemaServiceDefaultImpl, [SchemaService]);
+    fn get_client_config_for_ctx(&self, ctx: &AuthContext) -> Configuration {
+        self.get_client_config(&ctx.access_token, &ctx.base_path)
+    }
 
-#[async_trait]
-impl SchemaService for SchemaServiceDefaultImpl {
-    async fn get_ctx_for_tenant(&self, ctx: &AuthContext) -> Result<TenantContext, CubeError> {
-        let response = cube_api::meta_v1(&self.get_client_config_for_ctx(ctx)).await?;
+    async fn load_ctx(&self, key: &SchemaCacheKey) -> Result<TenantContext, CubeError> {
+        let response =
+            cube_api::meta_v1(&self.get_client_config(&key.access_token, &key.base_path)).await?;
 
         let ctx = if let Some(cubes) = response.cubes {
             TenantContext { cubes }
@@ -54,19 +110,124 @@ impl SchemaService for SchemaServiceDefaultImpl {
         Ok(ctx)
     }
 
+    async fn refresh_cache(&self) {
+        for key in self.cache.keys_to_refresh().await {
+            match self.load_ctx(&key).await {
+                Ok(ctx) => self.cache.update(&key, ctx).await,
+                // Entry will be reloaded on the next access after TTL
+                Err(e) => error!("Error during refreshing schema cache: {}", e),
+            }
+        }
+    }
+
+    fn timeout_error(&self) -> CubeError {
+        CubeError::timeout(format!(
+            "Query execution was interrupted, maximum execution time ({} sec) exceeded",
+            self.config_obj.query_timeout()
+        ))
+    }
+}
+
+crate::di_service!(SchemaServiceDefaultImpl, [SchemaService]);
+
+#[async_trait]
+impl SchemaService for SchemaServiceDefaultImpl {
+    async fn get_ctx_for_tenant(&self, ctx: &AuthContext) -> Result<Arc<TenantContext>, CubeError> {
+        let key = SchemaCacheKey::from_auth_ctx(ctx);
+
+        if self.cache.is_enabled() {
+            self.cache.get_or_load(&key, || self.load_ctx(&key)).await
+        } else {
+            Ok(Arc::new(self.load_ctx(&key).await?))
+        }
+    }
+
+    async fn flush_cache(&self, ctx: &AuthContext) -> Result<(), CubeError> {
+        self.cache.flush(&SchemaCacheKey::from_auth_ctx(ctx)).await;
+
+        Ok(())
+    }
+
     async fn request(
         &self,
         query: V1LoadRequestQuery,
         ctx: &AuthContext,
+        mut cancel: watch::Receiver<bool>,
     ) -> Result<V1LoadResponse, CubeError> {
-        let request = V1LoadRequest {
+        let request = Some(V1LoadRequest {
             query: Some(query),
             query_type: Some("multi".to_string()),
-        };
-        let response =
-            cube_api::load_v1(&self.get_client_config_for_ctx(ctx), Some(request)).await?;
+        });
+        let cube_config = self.get_client_config_for_ctx(ctx);
+
+        let request_id = Uuid::new_v4().to_string();
+        let deadline = Instant::now() + Duration::from_secs(self.config_obj.query_timeout());
+        let mut backoff = Self::POLL_INITIAL_BACKOFF;
+        let mut span_counter: u32 = 1;
+
+        loop {
+            let response = tokio::select! {
+                _ = cancelled(&mut cancel) => {
+                    return Err(CubeError::user("Query execution was cancelled".to_string()));
+                }
+                res = tokio::time::timeout_at(
+                    deadline,
+                    cube_api::load_v1_span(&cube_config, &request, &request_id, span_counter),
+                ) => res.map_err(|_| self.timeout_error())??,
+            };
+
+            match response {
+                LoadV1Poll::Ready(response) => return Ok(response),
+                LoadV1Poll::ContinueWait => {
+                    debug!(
+                        "Continue wait for request {}, span: {}, next poll in {:?}",
+                        request_id, span_counter, backoff
+                    );
+                }
+            }
 
-        Ok(response)
+            tokio::select! {
+                _ = cancelled(&mut cancel) => {
+                    return Err(CubeError::user("Query execution was cancelled".to_string()));
+                }
+                _ = tokio::time::sleep_until(min(Instant::now() + backoff, deadline)) => {}
+            }
+
+            if Instant::now() >= deadline {
+                return Err(self.timeout_error());
+            }
+
+            span_counter += 1;
+            backoff = min(backoff * 2, Self::POLL_MAX_BACKOFF);
+        }
+    }
+}
+
+#[async_trait]
+impl ProcessingLoop for SchemaServiceDefaultImpl {
+    async fn processing_loop(&self) -> Result<(), CubeError> {
+        if !self.cache.is_enabled() {
+            return Ok(());
+        }
+
+        loop {
+            let mut stop_receiver = self.stop_rx.write().await;
+            tokio::select! {
+                res = stop_receiver.changed() => {
+                    if res.is_err() || *stop_receiver.borrow() {
+                        return Ok(());
+                    }
+                }
+                _ = tokio::time::sleep(self.cache.refresh_interval()) => {
+                    self.refresh_cache().await;
+                }
+            }
+        }
+    }
+
+    async fn stop_processing(&self) -> Result<(), CubeError> {
+        self.stop_tx.send(true)?;
+        Ok(())
     }
 }
 
@@ -184,6 +345,21 @@ impl CubeColumn {
     pub fn mysql_can_be_null(&self) -> bool {
         self.can_be_null
     }
+
+    /// Type without length, as in `DATA_TYPE` of `information_schema.columns`
+    pub fn mysql_data_type(&self) -> &str {
+        match self.ty.split_once('(') {
+            Some((data_type, _)) => data_type,
+            None => self.ty.as_str(),
+        }
+    }
+
+    pub fn mysql_collation(&self) -> Option<&str> {
+        match self.mysql_data_type() {
+            "varchar" | "char" | "text" => Some("utf8mb4_0900_ai_ci"),
+            _ => None,
+        }
+    }
 }
 
 pub trait V1CubeMetaExt {
@@ -221,3 +397,154 @@ impl V1CubeMetaExt for V1CubeMeta {
         columns
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, Respond, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::config::Config;
+    use crate::CubeErrorCauseType;
+
+    const READY: &str = r#"{
+        "queryType": "regularQuery",
+        "results": [
+            {
+                "annotation": {
+                    "measures": {},
+                    "dimensions": {},
+                    "segments": {},
+                    "timeDimensions": {}
+                },
+                "data": []
+            }
+        ]
+    }"#;
+
+    /// Answers `Continue wait` until `ready_after` requests were made, every answer is delayed.
+    struct ContinueWaitResponder {
+        requests: AtomicU32,
+        ready_after: u32,
+        delay: Duration,
+    }
+
+    impl Respond for ContinueWaitResponder {
+        fn respond(&self, _request: &wiremock::Request) -> ResponseTemplate {
+            let requests = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+
+            if requests >= self.ready_after {
+                ResponseTemplate::new(200)
+                    .set_delay(self.delay)
+                    .set_body_string(READY)
+            } else {
+                ResponseTemplate::new(200)
+                    .set_delay(self.delay)
+                    .set_body_string(r#"{"error":"Continue wait"}"#)
+            }
+        }
+    }
+
+    async fn start_server(ready_after: u32, delay: Duration) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/load"))
+            .respond_with(ContinueWaitResponder {
+                requests: AtomicU32::new(0),
+                ready_after,
+                delay,
+            })
+            .mount(&server)
+            .await;
+
+        server
+    }
+
+    async fn request(
+        server: &MockServer,
+        cancel: watch::Receiver<bool>,
+    ) -> Result<V1LoadResponse, CubeError> {
+        let config = Config::test("schema_request").update_config(|mut c| {
+            c.query_timeout = 1;
+            c
+        });
+        let service = SchemaServiceDefaultImpl::new(config.config_obj());
+        let ctx = AuthContext {
+            password: None,
+            access_token: "token".to_string(),
+            base_path: server.uri(),
+        };
+
+        service
+            .request(V1LoadRequestQuery::new(), &ctx, cancel)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_request_continue_wait() {
+        let server = start_server(3, Duration::from_millis(0)).await;
+        let (_cancel_tx, cancel_rx) = watch::channel(false);
+
+        request(&server, cancel_rx).await.unwrap();
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let (_cancel_tx, cancel_rx) = watch::channel(false);
+
+        // Cube.js keeps answering `Continue wait`
+        let server = start_server(u32::MAX, Duration::from_millis(0)).await;
+        let started = Instant::now();
+        let e = request(&server, cancel_rx.clone()).await.unwrap_err();
+        assert!(matches!(e.cause, CubeErrorCauseType::Timeout), "{}", e);
+        assert!(started.elapsed() < Duration::from_secs(3));
+        // Backoff grows, so the server is not flooded with requests
+        assert!(server.received_requests().await.unwrap().len() <= 5);
+
+        // Single request is longer than the query timeout
+        let server = start_server(1, Duration::from_secs(10)).await;
+        let started = Instant::now();
+        let e = request(&server, cancel_rx).await.unwrap_err();
+        assert!(matches!(e.cause, CubeErrorCauseType::Timeout), "{}", e);
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn test_request_cancel() {
+        // Cancelled between polls
+        let server = start_server(u32::MAX, Duration::from_millis(0)).await;
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        let cancel = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            cancel_tx.send(true).unwrap();
+        });
+        let e = request(&server, cancel_rx).await.unwrap_err();
+        assert!(matches!(e.cause, CubeErrorCauseType::User), "{}", e);
+        assert_eq!(e.message, "Query execution was cancelled");
+        cancel.await.unwrap();
+
+        // Cancelled in the middle of a request
+        let server = start_server(1, Duration::from_secs(10)).await;
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        let cancel = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            cancel_tx.send(true).unwrap();
+        });
+        let started = Instant::now();
+        let e = request(&server, cancel_rx).await.unwrap_err();
+        assert_eq!(e.message, "Query execution was cancelled");
+        assert!(started.elapsed() < Duration::from_millis(900));
+        cancel.await.unwrap();
+
+        // Dropped sender doesn't cancel the request
+        let server = start_server(2, Duration::from_millis(0)).await;
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        drop(cancel_tx);
+        request(&server, cancel_rx).await.unwrap();
+    }
+}
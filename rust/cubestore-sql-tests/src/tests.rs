@@ -43,6 +43,7 @@ pub fn sql_tests() -> Vec<(&'static str, TestFn)> {
             three_tables_join_with_filter,
         ),
         t("three_tables_join_with_union", three_tables_join_with_union),
+        t("distributed_join", distributed_join),
         t("in_list", in_list),
         t("numeric_cast", numeric_cast),
         t("numbers_to_bool", numbers_to_bool),
@@ -82,6 +83,9 @@ pub fn sql_tests() -> Vec<(&'static str, TestFn)> {
         t("hyperloglog_inplace_group_by", hyperloglog_inplace_group_by),
         t("hyperloglog_postgres", hyperloglog_postgres),
         t("hyperloglog_snowflake", hyperloglog_snowflake),
+        t("hyperloglog_init", hyperloglog_init),
+        t("hyperloglog_export", hyperloglog_export),
+        t("tdigest", tdigest),
         t("planning_inplace_aggregate", planning_inplace_aggregate),
         t("planning_hints", planning_hints),
         t("planning_inplace_aggregate2", planning_inplace_aggregate2),
@@ -119,8 +123,14 @@ pub fn sql_tests() -> Vec<(&'static str, TestFn)> {
             rolling_window_one_week_interval,
         ),
         t("rolling_window_offsets", rolling_window_offsets),
+        t("window_functions", window_functions),
         t("decimal_index", decimal_index),
         t("float_index", float_index),
+        t("bloom_filter_index", bloom_filter_index),
+        t("create_bloom_filter_index", create_bloom_filter_index),
+        t("storage_options", storage_options),
+        t("aggregate_index", aggregate_index),
+        t("aggregate_index_backfill", aggregate_index_backfill),
         t("date_add", date_add),
         t("now", now),
         t("dump", dump),
@@ -731,6 +741,84 @@ async fn three_tables_join_with_union(service: Box<dyn SqlClient>) {
     assert_eq!(result.get_rows(), &expected);
 }
 
+async fn distributed_join(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Orders(customer_id int, amount int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("CREATE TABLE s.Customers(customer_id int, region int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("CREATE TABLE s.Regions(region int, name text)")
+        .await
+        .unwrap();
+
+    // Both orders and customers are above the broadcast threshold of tests and get split into
+    // multiple partitions, so they are joined by ranges of the key. Regions are broadcast.
+    for batch in 0..4 {
+        let values = (0..25)
+            .map(|i| format!("({}, 1)", 5 + (batch * 25 + i) % 50))
+            .join(", ");
+        service
+            .exec_query(&format!(
+                "INSERT INTO s.Orders(customer_id, amount) VALUES {}",
+                values
+            ))
+            .await
+            .unwrap();
+    }
+    for batch in 0..3 {
+        let values = (0..15)
+            .map(|i| batch * 15 + i)
+            .map(|id| format!("({}, {})", id, id % 3))
+            .join(", ");
+        service
+            .exec_query(&format!(
+                "INSERT INTO s.Customers(customer_id, region) VALUES {}",
+                values
+            ))
+            .await
+            .unwrap();
+    }
+    service
+        .exec_query("INSERT INTO s.Regions(region, name) VALUES (0, 'r0'), (1, 'r1'), (2, 'r2')")
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query(
+            "SELECT COUNT(*), SUM(amount) \
+             FROM s.Orders o JOIN s.Customers c ON o.customer_id = c.customer_id",
+        )
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[(80, 80)]));
+
+    let r = service
+        .exec_query(
+            "SELECT COUNT(*), COUNT(c.customer_id) \
+             FROM s.Orders o LEFT JOIN s.Customers c ON o.customer_id = c.customer_id",
+        )
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[(100, 80)]));
+
+    let r = service
+        .exec_query(
+            "SELECT name, COUNT(*) \
+             FROM s.Orders o \
+             JOIN s.Customers c ON o.customer_id = c.customer_id \
+             JOIN s.Regions r ON c.region = r.region \
+             GROUP BY 1 ORDER BY 1",
+        )
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[("r0", 26), ("r1", 26), ("r2", 28)]));
+}
+
 async fn in_list(service: Box<dyn SqlClient>) {
     service.exec_query("CREATE SCHEMA foo").await.unwrap();
 
@@ -1886,6 +1974,200 @@ async fn hyperloglog_snowflake(service: Box<dyn SqlClient>) {
         .unwrap_err();
 }
 
+async fn hyperloglog_init(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(grp int, user_id int, name text, score float)")
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Data(grp, user_id, name, score) VALUES \
+               (1, 1, 'a', 1.5), (1, 2, 'b', 2.5), (1, 1, 'a', 1.5), (1, 3, NULL, NULL), \
+               (2, 4, 'c', 3.5), (2, 5, 'a', 1.5), (2, 4, 'c', 3.5)",
+        )
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query(
+            "SELECT cardinality(hll_init(user_id)), cardinality(hll_init_agg(user_id)), \
+                    cardinality(hll_init(name)), cardinality(hll_init_agg(name)), \
+                    cardinality(hll_init(score)) \
+             FROM s.Data",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![
+            TableValue::Int(5),
+            TableValue::Int(5),
+            TableValue::Int(3),
+            TableValue::Int(3),
+            TableValue::Int(3)
+        ]]
+    );
+
+    let r = service
+        .exec_query(
+            "SELECT grp, cardinality(hll_init(user_id)), cardinality(hll_init_agg(name)) \
+             FROM s.Data GROUP BY 1 ORDER BY 1",
+        )
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[(1, 3, 2), (2, 2, 2)]));
+
+    // Sparse Airlift sketch with a single bucket computed from the Murmur3 hash of 1.
+    let r = service
+        .exec_query("SELECT hll_init(user_id) FROM s.Data WHERE user_id = 1")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![TableValue::Bytes(vec![
+            0x02, 0x0C, 0x01, 0x00, 0x80, 0x03, 0x44, 0x00
+        ])]]
+    );
+
+    // Built sketches can be stored and merged with the ones produced by Presto.
+    service
+        .exec_query("CREATE TABLE s.Sketches(id int, hll HYPERLOGLOG)")
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Sketches(id, hll) VALUES (1, X'020C010080034400'), \
+                                                    (2, X'020C0200C02FF58941D5F0C6')",
+        )
+        .await
+        .unwrap();
+    let r = service
+        .exec_query("SELECT cardinality(merge(hll)) FROM s.Sketches")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[3]));
+}
+
+async fn hyperloglog_export(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Sketches(id int, hll HYPERLOGLOG)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Sketches(id, hll) VALUES (1, X'020C010080034400')")
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query(
+            "SELECT hll_export(hll, 'airlift'), hll_export(hll, 'snowflake'), \
+                    hll_export(hll, 'postgres') \
+             FROM s.Sketches",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![
+            TableValue::Bytes(vec![0x02, 0x0C, 0x01, 0x00, 0x80, 0x03, 0x44, 0x00]),
+            TableValue::Bytes(
+                r#"{"precision":12,"sparse":{"indices":[4],"maxLzCounts":[2]},"version":4}"#
+                    .as_bytes()
+                    .to_vec()
+            ),
+            TableValue::Bytes(vec![0x13, 0x8C, 0x7F, 0x00, 0x41, 0x00]),
+        ]]
+    );
+
+    // Merged sketches can be exported too.
+    service
+        .exec_query("INSERT INTO s.Sketches(id, hll) VALUES (2, X'020C0200C02FF58941D5F0C6')")
+        .await
+        .unwrap();
+    let r = service
+        .exec_query("SELECT cardinality(hll_export(merge(hll), 'airlift')) FROM s.Sketches")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[3]));
+
+    // ZetaSketch sketches are converted to dense Airlift sketches.
+    service
+        .exec_query("CREATE TABLE s.Data(id int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(id) VALUES (1), (2), (3), (2), (5)")
+        .await
+        .unwrap();
+    let r = service
+        .exec_query("SELECT cardinality(hll_export(hll_init_agg(id), 'airlift')) FROM s.Data")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[4]));
+
+    service
+        .exec_query("SELECT hll_export(hll, 'parquet') FROM s.Sketches")
+        .await
+        .unwrap_err();
+}
+
+async fn tdigest(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, td tdigest)")
+        .await
+        .unwrap();
+
+    // Digests of values 1..=5 and 6..=10 in Presto format.
+    let low = "X'0100000000000000F03F00000000000014400000000000005940000000000000144005000000000000000000F03F000000000000F03F000000000000F03F000000000000F03F000000000000F03F000000000000F03F0000000000000040000000000000084000000000000010400000000000001440'";
+    let high = "X'0100000000000000184000000000000024400000000000005940000000000000144005000000000000000000F03F000000000000F03F000000000000F03F000000000000F03F000000000000F03F00000000000018400000000000001C40000000000000204000000000000022400000000000002440'";
+    service
+        .exec_query(&format!(
+            "INSERT INTO s.Data(id, td) VALUES (1, {l}), (2, {h})",
+            l = low,
+            h = high
+        ))
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query("SELECT id, quantile(td, 0.5) FROM s.Data ORDER BY 1")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![TableValue::Int(1), TableValue::Float(3.0.into())],
+            vec![TableValue::Int(2), TableValue::Float(8.0.into())],
+        ]
+    );
+
+    let r = service
+        .exec_query("SELECT percentile_merge(td, 0.5), percentile_merge(td, 0.9) FROM s.Data")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![
+            TableValue::Float(6.0.into()),
+            TableValue::Float(10.0.into())
+        ]]
+    );
+
+    // Quantile must be in [0, 1].
+    service
+        .exec_query("SELECT percentile_merge(td, 1.5) FROM s.Data")
+        .await
+        .unwrap_err();
+    // Malformed digests are rejected on insert.
+    service
+        .exec_query("INSERT INTO s.Data(id, td) VALUES (3, X'0102')")
+        .await
+        .unwrap_err();
+}
+
 async fn planning_inplace_aggregate(service: Box<dyn SqlClient>) {
     service.exec_query("CREATE SCHEMA s").await.unwrap();
     service
@@ -3669,6 +3951,100 @@ async fn rolling_window_offsets(service: Box<dyn SqlClient>) {
     );
 }
 
+async fn window_functions(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(tenant int, day int, amount int)")
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Data(tenant, day, amount) VALUES \
+               (1, 1, 10), (1, 2, 20), (1, 3, 30), (2, 1, 5), (2, 2, 5)",
+        )
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query(
+            "SELECT tenant, day, ROW_NUMBER() OVER (PARTITION BY tenant ORDER BY day) \
+             FROM s.Data ORDER BY 1, 2",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[(1, 1, 1), (1, 2, 2), (1, 3, 3), (2, 1, 1), (2, 2, 2)])
+    );
+
+    let r = service
+        .exec_query(
+            "SELECT tenant, day, RANK() OVER (PARTITION BY tenant ORDER BY amount DESC) \
+             FROM s.Data ORDER BY 1, 2",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[(1, 1, 3), (1, 2, 2), (1, 3, 1), (2, 1, 1), (2, 2, 1)])
+    );
+
+    let r = service
+        .exec_query(
+            "SELECT tenant, day, \
+                    LAG(amount) OVER (PARTITION BY tenant ORDER BY day), \
+                    LEAD(amount) OVER (PARTITION BY tenant ORDER BY day) \
+             FROM s.Data ORDER BY 1, 2",
+        )
+        .await
+        .unwrap();
+    let int = |i: i64| TableValue::Int(i);
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![int(1), int(1), TableValue::Null, int(20)],
+            vec![int(1), int(2), int(10), int(30)],
+            vec![int(1), int(3), int(20), TableValue::Null],
+            vec![int(2), int(1), TableValue::Null, int(5)],
+            vec![int(2), int(2), int(5), TableValue::Null],
+        ]
+    );
+
+    // Running totals and moving averages.
+    let r = service
+        .exec_query(
+            "SELECT tenant, day, \
+                    SUM(amount) OVER (PARTITION BY tenant ORDER BY day \
+                                      ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW), \
+                    AVG(amount) OVER (PARTITION BY tenant ORDER BY day \
+                                      ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) \
+             FROM s.Data ORDER BY 1, 2",
+        )
+        .await
+        .unwrap();
+    let float = |f: f64| TableValue::Float(f.into());
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![int(1), int(1), int(10), float(10.)],
+            vec![int(1), int(2), int(30), float(15.)],
+            vec![int(1), int(3), int(60), float(25.)],
+            vec![int(2), int(1), int(5), float(5.)],
+            vec![int(2), int(2), int(10), float(5.)],
+        ]
+    );
+
+    // Window functions are computed after the aggregation.
+    let r = service
+        .exec_query(
+            "SELECT tenant, SUM(amount), RANK() OVER (ORDER BY SUM(amount) DESC) \
+             FROM s.Data GROUP BY 1 ORDER BY 1",
+        )
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[(1, 60, 1), (2, 10, 2)]));
+}
+
 async fn decimal_index(service: Box<dyn SqlClient>) {
     service.exec_query("CREATE SCHEMA s").await.unwrap();
     service
@@ -3732,6 +4108,319 @@ async fn float_index(service: Box<dyn SqlClient>) {
     assert_eq!(to_rows(&r), rows(&[(3., 4.), (2., 3.), (1., 2.)]));
 }
 
+async fn bloom_filter_index(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, email text) WITH (bloom_filter_columns = 'email')")
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Data(id, email) VALUES (1, 'a@x.com'), (2, 'b@x.com'), (3, NULL)",
+        )
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(id, email) VALUES (4, 'c@x.com'), (5, 'a@x.com')")
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query("SELECT id FROM s.Data WHERE email = 'a@x.com' ORDER BY id")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[1, 5]));
+
+    let r = service
+        .exec_query(
+            "SELECT id FROM s.Data WHERE email = 'c@x.com' OR email = 'b@x.com' ORDER BY id",
+        )
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[2, 4]));
+
+    let r = service
+        .exec_query("SELECT count(*) FROM s.Data WHERE email = 'd@x.com'")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[0]));
+
+    service
+        .exec_query("CREATE TABLE s.Bad(id int) WITH (bloom_filter_columns = 'email')")
+        .await
+        .unwrap_err();
+}
+
+async fn create_bloom_filter_index(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, email text)")
+        .await
+        .unwrap();
+    service
+        .exec_query("CREATE INDEX by_email ON s.Data (email, id)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(id, email) VALUES (1, 'a@x.com'), (2, 'b@x.com')")
+        .await
+        .unwrap();
+
+    // Files written before the bloom index are scanned without filters.
+    service
+        .exec_query("CREATE INDEX email_bloom ON s.Data USING bloom (email)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(id, email) VALUES (3, 'c@x.com'), (4, 'a@x.com')")
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query("SELECT id FROM s.Data WHERE email = 'a@x.com' ORDER BY id")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[1, 4]));
+
+    let r = service
+        .exec_query("SELECT count(*) FROM s.Data WHERE email = 'd@x.com'")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[0]));
+
+    service
+        .exec_query("CREATE INDEX id_bloom ON s.Data USING bloom (missing)")
+        .await
+        .unwrap_err();
+    service
+        .exec_query("CREATE INDEX id_hash ON s.Data USING hash (id)")
+        .await
+        .unwrap_err();
+}
+
+async fn storage_options(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query(
+            "CREATE TABLE s.Data(id int, dim text, payload bytes) \
+             WITH (codec = 'zstd', column_codecs = 'payload: none', \
+                   column_dictionary = 'payload: off', row_group_size = 2)",
+        )
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Data(id, dim, payload) VALUES (1, 'a', X'01'), (2, 'b', X'02'), \
+                                                          (3, 'a', X'03')",
+        )
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query("SELECT id, dim FROM s.Data ORDER BY id")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[(1, "a"), (2, "b"), (3, "a")]));
+
+    let r = service
+        .exec_query(
+            "SELECT table_name, chunk_count, chunks_row_count, chunks_size > 0 \
+             FROM system.partitions WHERE active = true AND table_schema = 's'",
+        )
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[("Data", 1, 3, true)]));
+
+    service
+        .exec_query("CREATE TABLE s.Bad1(id int) WITH (codec = 'rar')")
+        .await
+        .unwrap_err();
+    service
+        .exec_query("CREATE TABLE s.Bad2(id int) WITH (column_codecs = 'foo: zstd')")
+        .await
+        .unwrap_err();
+    service
+        .exec_query("CREATE TABLE s.Bad3(id int) WITH (row_group_size = 0)")
+        .await
+        .unwrap_err();
+}
+
+async fn aggregate_index(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Orders(day int, tenant text, amount int, hits int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("CREATE AGGREGATE INDEX daily ON s.Orders (day, tenant) SUM(amount), MAX(hits)")
+        .await
+        .unwrap();
+    service
+        .exec_query("CREATE AGGREGATE INDEX bad1 ON s.Orders (day) SUM(tenant)")
+        .await
+        .unwrap_err();
+    service
+        .exec_query("CREATE AGGREGATE INDEX bad2 ON s.Orders (day) AVG(amount)")
+        .await
+        .unwrap_err();
+    service
+        .exec_query("CREATE AGGREGATE INDEX bad3 ON s.Orders (day) SUM(day)")
+        .await
+        .unwrap_err();
+
+    service
+        .exec_query(
+            "INSERT INTO s.Orders(day, tenant, amount, hits) VALUES (1, 'a', 10, 1), \
+                                                                   (1, 'a', 20, 5), \
+                                                                   (1, 'b', 5, 2)",
+        )
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Orders(day, tenant, amount, hits) VALUES (1, 'a', 1, 3), \
+                                                                   (2, 'b', 7, 4)",
+        )
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query(
+            "SELECT day, tenant, SUM(amount), MAX(hits) FROM s.Orders GROUP BY 1, 2 ORDER BY 1, 2",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[(1, "a", 31, 5), (1, "b", 5, 2), (2, "b", 7, 4)])
+    );
+
+    let r = service
+        .exec_query("SELECT tenant, SUM(amount) FROM s.Orders WHERE day = 1 GROUP BY 1 ORDER BY 1")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[("a", 31), ("b", 5)]));
+
+    let p = service
+        .plan_query("SELECT tenant, SUM(amount) FROM s.Orders GROUP BY 1")
+        .await
+        .unwrap();
+    assert!(pp_phys_plan(p.worker.as_ref()).contains("index: daily:"));
+
+    // Results of these queries differ on aggregated rows, so the default index must be used.
+    for q in &[
+        "SELECT day, COUNT(*) FROM s.Orders GROUP BY 1",
+        "SELECT day, tenant FROM s.Orders",
+        "SELECT day, MIN(amount) FROM s.Orders GROUP BY 1",
+        "SELECT day, SUM(amount) FROM s.Orders WHERE hits > 1 GROUP BY 1",
+    ] {
+        let p = service.plan_query(q).await.unwrap();
+        assert!(
+            pp_phys_plan(p.worker.as_ref()).contains("index: default:"),
+            "{}",
+            q
+        );
+    }
+    let r = service
+        .exec_query("SELECT day, tenant FROM s.Orders ORDER BY 1, 2")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[(1, "a"), (1, "a"), (1, "a"), (1, "b"), (2, "b")])
+    );
+
+    service
+        .exec_query(
+            "CREATE TABLE s.Events(day int, users hyperloglog) \
+             AGGREGATE INDEX by_day (day) MERGE(users)",
+        )
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Events(day, users) VALUES (1, X'020C0200C02FF58941D5F0C6'), \
+                                                     (1, X'020C0200C02FF58941D5F0C6'), \
+                                                     (2, X'020C0200C02FF58941D5F0C6')",
+        )
+        .await
+        .unwrap();
+    let r = service
+        .exec_query("SELECT day, cardinality(merge(users)) FROM s.Events GROUP BY 1 ORDER BY 1")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[(1, 2), (2, 2)]));
+}
+
+async fn aggregate_index_backfill(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Orders(day int, tenant text, amount int, hits int)")
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Orders(day, tenant, amount, hits) VALUES (1, 'a', 10, 1), \
+                                                                   (1, 'a', 20, 5), \
+                                                                   (1, 'b', 5, 2)",
+        )
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Orders(day, tenant, amount, hits) VALUES (1, 'a', 1, 3), \
+                                                                   (2, 'b', 7, 4)",
+        )
+        .await
+        .unwrap();
+
+    // Rows that already exist are added to the new index.
+    service
+        .exec_query("CREATE AGGREGATE INDEX daily ON s.Orders (day, tenant) SUM(amount), MAX(hits)")
+        .await
+        .unwrap();
+    let p = service
+        .plan_query("SELECT day, tenant, SUM(amount), MAX(hits) FROM s.Orders GROUP BY 1, 2")
+        .await
+        .unwrap();
+    assert!(pp_phys_plan(p.worker.as_ref()).contains("index: daily:"));
+
+    let r = service
+        .exec_query(
+            "SELECT day, tenant, SUM(amount), MAX(hits) FROM s.Orders GROUP BY 1, 2 ORDER BY 1, 2",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[(1, "a", 31, 5), (1, "b", 5, 2), (2, "b", 7, 4)])
+    );
+
+    // New rows go to both indexes.
+    service
+        .exec_query("INSERT INTO s.Orders(day, tenant, amount, hits) VALUES (2, 'b', 3, 9)")
+        .await
+        .unwrap();
+    let r = service
+        .exec_query(
+            "SELECT day, tenant, SUM(amount), MAX(hits) FROM s.Orders GROUP BY 1, 2 ORDER BY 1, 2",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[(1, "a", 31, 5), (1, "b", 5, 2), (2, "b", 10, 9)])
+    );
+    let r = service
+        .exec_query("SELECT day, tenant FROM s.Orders ORDER BY 1, 2")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[(1, "a"), (1, "a"), (1, "a"), (1, "b"), (2, "b"), (2, "b")])
+    );
+}
+
 async fn date_add(service: Box<dyn SqlClient>) {
     let check_fun = |name, t, i, expected| {
         let expected = timestamp_from_string(expected).unwrap();
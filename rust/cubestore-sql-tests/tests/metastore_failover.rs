@@ -0,0 +1,172 @@
+//! Runs a metastore leader and its standby in separate processes. The leader goes away after
+//! uploading its state and the test checks a worker switches to the promoted standby.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_timer::Delay;
+use serde_derive::{Deserialize, Serialize};
+
+use cubestore::config::{Config, FileStoreProvider};
+use cubestore::util::respawn;
+use cubestore_sql_tests::multiproc::{
+    multiproc_child_main, run_multiproc_test, MultiProcTest, SignalInit, WaitCompletion, WorkerProc,
+};
+
+const TEST_NAME: &str = "metastore-failover";
+const LEADER_PORT: u16 = 51346;
+const STANDBY_PORT: u16 = 51347;
+const WORKER_PORT: u16 = 51348;
+
+#[cfg(not(target_os = "windows"))]
+fn main() {
+    Config::configure_worker_services();
+
+    respawn::register_handler(multiproc_child_main::<MetaStoreFailoverTest>);
+    respawn::init();
+
+    run_multiproc_test(MetaStoreFailoverTest);
+}
+
+/// All nodes share the remote storage, but keep local files in separate directories.
+fn node_config(node: &str) -> Config {
+    let remote_dir = Config::test(TEST_NAME).remote_dir().clone();
+    Config::test(&format!("{}-{}", TEST_NAME, node)).update_config(|mut c| {
+        c.store_provider = FileStoreProvider::Filesystem {
+            remote_dir: Some(remote_dir),
+        };
+        c
+    })
+}
+
+struct MetaStoreFailoverTest;
+
+#[derive(Serialize, Deserialize)]
+enum Node {
+    Leader,
+    Standby,
+}
+
+#[async_trait]
+impl MultiProcTest for MetaStoreFailoverTest {
+    type WorkerArgs = Node;
+    type WorkerProc = NodeFn;
+
+    fn worker_arguments(&self) -> Vec<Node> {
+        vec![Node::Leader, Node::Standby]
+    }
+
+    async fn drive(self) {
+        node_config("worker")
+            .update_config(|mut c| {
+                c.server_name = format!("localhost:{}", WORKER_PORT);
+                c.worker_bind_address = Some(c.server_name.clone());
+                c.select_workers = vec![c.server_name.clone()];
+                c.metastore_remote_address = Some(format!(
+                    "localhost:{},localhost:{}",
+                    LEADER_PORT, STANDBY_PORT
+                ));
+                c
+            })
+            .start_test_worker(|services| async move {
+                // The leader is already gone, retry until the standby takes over.
+                loop {
+                    match services
+                        .meta_store
+                        .create_schema("baz".to_string(), false)
+                        .await
+                    {
+                        Ok(_) => break,
+                        Err(_) => Delay::new(Duration::from_millis(500)).await,
+                    }
+                }
+                let mut schemas = services
+                    .meta_store
+                    .get_schemas()
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|s| s.get_row().get_name().to_string())
+                    .collect::<Vec<_>>();
+                schemas.sort();
+                assert_eq!(schemas, vec!["bar", "baz", "foo"]);
+            })
+            .await;
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn worker_init_timeout(&self) -> Duration {
+        Duration::from_secs(20)
+    }
+}
+
+#[derive(Default)]
+struct NodeFn;
+#[async_trait]
+impl WorkerProc<Node> for NodeFn {
+    async fn run(self, node: Node, init: SignalInit, done: WaitCompletion) {
+        if !std::env::var("CUBESTORE_TEST_LOG_WORKER").is_ok() {
+            *cubestore::config::TEST_LOGGING_INITIALIZED.write().await = true;
+        }
+        match node {
+            Node::Leader => {
+                let config = node_config("leader").update_config(|mut c| {
+                    c.server_name = format!("localhost:{}", LEADER_PORT);
+                    c.metastore_bind_address = Some(c.server_name.clone());
+                    c
+                });
+                let _ = std::fs::remove_dir_all(config.remote_dir());
+                // Keep the remote state in place for the standby.
+                config
+                    .start_test_with_options(false, |services| async move {
+                        let meta_store = services.rocks_meta_store.unwrap();
+                        services
+                            .meta_store
+                            .create_schema("foo".to_string(), false)
+                            .await
+                            .unwrap();
+                        meta_store.upload_check_point().await.unwrap();
+                        services
+                            .meta_store
+                            .create_schema("bar".to_string(), false)
+                            .await
+                            .unwrap();
+                        meta_store.run_upload().await.unwrap();
+                    })
+                    .await;
+                init.signal().await;
+                done.wait_completion().await;
+            }
+            Node::Standby => {
+                let config = node_config("standby").update_config(|mut c| {
+                    c.server_name = format!("localhost:{}", STANDBY_PORT);
+                    c.metastore_bind_address = Some(c.server_name.clone());
+                    c.metastore_standby_of = Some(format!("localhost:{}", LEADER_PORT));
+                    c.metastore_failover_timeout_secs = 2;
+                    c
+                });
+                // Start following the leader once it uploaded the first checkpoint.
+                let current = config.remote_dir().join("metastore-current");
+                while !current.exists() {
+                    Delay::new(Duration::from_millis(100)).await;
+                }
+                init.signal().await;
+                let remote_dir = config.remote_dir().clone();
+                config
+                    .start_test_with_options(false, |_| async move {
+                        done.wait_completion().await;
+                    })
+                    .await;
+                let _ = std::fs::remove_dir_all(remote_dir);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn main() {
+    // We do not procspawn on Windows.
+}
@@ -2,7 +2,7 @@
 //! The convention is to prefix all metrics with `cs.` (short for CubeStore).
 
 use crate::util::metrics;
-use crate::util::metrics::{Counter, Histogram};
+use crate::util::metrics::{Counter, Gauge, Histogram};
 
 /// The number of process startups.
 pub static STARTUPS: Counter = metrics::counter("cs.startup");
@@ -13,3 +13,27 @@ pub static DATA_QUERY_TIME_MS: Histogram = metrics::histogram("cs.sql.query.data
 /// Incoming SQL queries that only read metadata or do trivial computations.
 pub static META_QUERIES: Counter = metrics::counter("cs.sql.query.meta");
 pub static META_QUERY_TIME_MS: Histogram = metrics::histogram("cs.sql.query.meta.ms");
+/// Parquet row groups considered by data queries and the ones skipped using their statistics.
+pub static DATA_QUERY_ROW_GROUPS: Counter = metrics::counter("cs.sql.query.data.row_groups");
+pub static DATA_QUERY_ROW_GROUPS_SKIPPED: Counter =
+    metrics::counter("cs.sql.query.data.row_groups.skipped");
+/// Files in the remote fs that are not referenced by the metastore, found by the remote GC, and
+/// the ones it removed.
+pub static REMOTE_GC_ORPHANED_FILES: Counter = metrics::counter("cs.remote_gc.orphaned_files");
+pub static REMOTE_GC_REMOVED_FILES: Counter = metrics::counter("cs.remote_gc.removed_files");
+pub static REMOTE_GC_REMOVED_BYTES: Counter = metrics::counter("cs.remote_gc.removed_bytes");
+/// Reads of Parquet files that were found in the local directory and the ones that required a
+/// download from the remote fs.
+pub static LOCAL_CACHE_HITS: Counter = metrics::counter("cs.remote_fs.local_cache.hit");
+pub static LOCAL_CACHE_MISSES: Counter = metrics::counter("cs.remote_fs.local_cache.miss");
+/// Files removed from the local directory to fit into the local cache budget.
+pub static LOCAL_CACHE_EVICTIONS: Counter = metrics::counter("cs.remote_fs.local_cache.evict");
+pub static LOCAL_CACHE_EVICTED_BYTES: Counter =
+    metrics::counter("cs.remote_fs.local_cache.evict.bytes");
+pub static LOCAL_CACHE_SIZE: Gauge = metrics::gauge("cs.remote_fs.local_cache.size");
+/// Select workers that sent a heart beat to the router recently.
+pub static CLUSTER_LIVE_WORKERS: Gauge = metrics::gauge("cs.cluster.live_workers");
+/// Selects sent to another replica after a worker failed to respond, and duplicates sent to
+/// another replica because a worker was slow.
+pub static SELECT_REPLICA_RETRIES: Counter = metrics::counter("cs.cluster.select.replica_retry");
+pub static SELECT_HEDGED_REQUESTS: Counter = metrics::counter("cs.cluster.select.hedged");
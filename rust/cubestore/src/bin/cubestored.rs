@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use cubestore::app_metrics;
 use cubestore::config::{validate_config, Config, CubeServices};
 use cubestore::http::status::serve_status_probes;
@@ -24,7 +25,20 @@ fn main() {
     init_metrics("127.0.0.1:0", "127.0.0.1:8125", metrics_mode);
     init_cube_logger(true);
 
-    let config = Config::default();
+    let mut config = Config::default();
+    let restore_to = match restore_metastore_to_arg() {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(restore_to) = restore_to {
+        config = config.update_config(|mut c| {
+            c.metastore_restore_to = Some(restore_to);
+            c
+        });
+    }
     Config::configure_worker_services();
 
     let trim_every = config.config_obj().malloc_trim_every_secs();
@@ -42,7 +56,10 @@ fn main() {
     runtime.block_on(async move {
         validate_config(config.config_obj().as_ref()).report_and_abort_on_errors();
 
-        config.configure_injector().await;
+        if let Err(e) = config.configure_injector().await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
 
         serve_status_probes(&config);
 
@@ -55,6 +72,25 @@ fn main() {
     });
 }
 
+/// `--restore-metastore-to <timestamp>`, overrides `CUBESTORE_METASTORE_RESTORE_TO`.
+fn restore_metastore_to_arg() -> Result<Option<DateTime<Utc>>, String> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let i = match args.iter().position(|a| a == "--restore-metastore-to") {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    let value = args.get(i + 1).ok_or_else(|| {
+        "--restore-metastore-to requires a timestamp, e.g. 2021-08-01T12:00:00Z".to_string()
+    })?;
+    match value.parse() {
+        Ok(t) => Ok(Some(t)),
+        Err(e) => Err(format!(
+            "Could not parse --restore-metastore-to '{}': {}. Expected a timestamp, e.g. 2021-08-01T12:00:00Z",
+            value, e
+        )),
+    }
+}
+
 async fn stop_on_ctrl_c(s: &CubeServices) {
     let s = s.clone();
     cube_ext::spawn(async move {
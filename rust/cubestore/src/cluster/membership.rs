@@ -0,0 +1,282 @@
+use crate::config::ConfigObj;
+use crate::metastore::{IdRow, Partition};
+use crate::CubeError;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Select workers that are currently alive and the assignment of work to them.
+///
+/// Work is assigned with rendezvous hashing: each key goes to the worker with the highest hash of
+/// the key and the worker name. When a worker joins or leaves, only keys that it wins or owned
+/// move, other workers keep their partitions warm. Workers with the next highest hashes are the
+/// replicas of the key.
+pub struct ClusterMembership {
+    server_name: String,
+    replication_factor: usize,
+    workers: RwLock<Arc<Vec<String>>>,
+}
+
+crate::di_service!(ClusterMembership, []);
+
+impl ClusterMembership {
+    pub fn new(config: &dyn ConfigObj) -> Arc<ClusterMembership> {
+        let mut workers = config.select_workers().clone();
+        workers.sort();
+        Arc::new(ClusterMembership {
+            server_name: config.server_name().to_string(),
+            replication_factor: config.partition_replication_factor().max(1),
+            workers: RwLock::new(Arc::new(workers)),
+        })
+    }
+
+    pub fn workers(&self) -> Arc<Vec<String>> {
+        self.workers.read().unwrap().clone()
+    }
+
+    pub fn is_member(&self, node_name: &str) -> bool {
+        self.workers().iter().any(|w| w == node_name)
+    }
+
+    /// Returns false if the set of workers did not change.
+    pub fn update(&self, mut workers: Vec<String>) -> bool {
+        workers.sort();
+        let mut current = self.workers.write().unwrap();
+        if **current == workers {
+            return false;
+        }
+        *current = Arc::new(workers);
+        true
+    }
+
+    /// Picks a worker by opaque id for any distributing work in a cluster.
+    /// Ids usually come from multi-partitions of the metastore.
+    pub fn pick_worker_by_ids(&self, ids: impl IntoIterator<Item = u64>) -> String {
+        self.pick_worker(Self::ids_key(ids))
+    }
+
+    /// Same as [pick_worker_by_ids], but uses ranges of partitions. This is a hack
+    /// to keep the same node for partitions produced by compaction that merged
+    /// chunks into the main table of a single partition.
+    pub fn pick_worker_by_partitions<'a>(
+        &self,
+        partitions: impl IntoIterator<Item = &'a IdRow<Partition>>,
+    ) -> String {
+        self.pick_worker(Self::partitions_key(partitions))
+    }
+
+    pub fn pick_worker_by_key(&self, key: impl Hash) -> String {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.pick_worker(hasher.finish())
+    }
+
+    /// Workers that serve the ids, in the order of preference. The first one is the result of
+    /// [pick_worker_by_ids].
+    pub fn replicas_by_ids(&self, ids: impl IntoIterator<Item = u64>) -> Vec<String> {
+        self.pick_workers(Self::ids_key(ids), self.replication_factor)
+    }
+
+    /// Same as [replicas_by_ids], but uses ranges of partitions.
+    pub fn replicas_by_partitions<'a>(
+        &self,
+        partitions: impl IntoIterator<Item = &'a IdRow<Partition>>,
+    ) -> Vec<String> {
+        self.pick_workers(Self::partitions_key(partitions), self.replication_factor)
+    }
+
+    fn ids_key(ids: impl IntoIterator<Item = u64>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for p in ids {
+            p.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    fn partitions_key<'a>(partitions: impl IntoIterator<Item = &'a IdRow<Partition>>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for partition in partitions {
+            partition.get_row().get_min_val().hash(&mut hasher);
+            partition.get_row().get_max_val().hash(&mut hasher);
+            partition.get_row().get_index_id().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    fn pick_worker(&self, key: u64) -> String {
+        self.pick_workers(key, 1).into_iter().next().unwrap()
+    }
+
+    fn pick_workers(&self, key: u64, n: usize) -> Vec<String> {
+        let workers = self.workers();
+        if workers.is_empty() {
+            return vec![self.server_name.clone()];
+        }
+        let mut scored = workers
+            .iter()
+            .map(|w| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                w.hash(&mut hasher);
+                (hasher.finish(), w)
+            })
+            .collect::<Vec<_>>();
+        scored.sort_unstable_by(|l, r| r.cmp(l));
+        scored
+            .into_iter()
+            .take(n)
+            .map(|(_, w)| w.to_string())
+            .collect()
+    }
+}
+
+/// Kept by the metastore leader. Workers from the config are considered alive until they miss
+/// the first heart beat timeout, so a restarted router keeps the assignment while they reconnect.
+pub struct WorkerHeartBeats {
+    timeout: Duration,
+    last_seen: HashMap<String, Instant>,
+}
+
+impl WorkerHeartBeats {
+    pub fn new(config: &dyn ConfigObj) -> WorkerHeartBeats {
+        let now = Instant::now();
+        WorkerHeartBeats {
+            timeout: Duration::from_secs(config.worker_heart_beat_timeout_secs()),
+            last_seen: config
+                .select_workers()
+                .iter()
+                .map(|w| (w.to_string(), now))
+                .collect(),
+        }
+    }
+
+    pub fn heart_beat(&mut self, node_name: String, now: Instant) -> Result<(), CubeError> {
+        if node_name.is_empty() {
+            return Err(CubeError::user("Worker name can't be empty".to_string()));
+        }
+        self.last_seen.insert(node_name, now);
+        Ok(())
+    }
+
+    pub fn live_workers(&mut self, now: Instant) -> Vec<String> {
+        let timeout = self.timeout;
+        self.last_seen
+            .retain(|_, seen| now.saturating_duration_since(*seen) <= timeout);
+        let mut workers = self.last_seen.keys().cloned().collect::<Vec<_>>();
+        workers.sort();
+        workers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn membership(workers: &[&str]) -> Arc<ClusterMembership> {
+        let config = Config::test("cluster_membership").update_config(|mut c| {
+            c.select_workers = workers.iter().map(|w| w.to_string()).collect();
+            c
+        });
+        ClusterMembership::new(config.config_obj().as_ref())
+    }
+
+    #[test]
+    fn join_moves_a_fraction_of_work() {
+        let m = membership(&["w1", "w2", "w3"]);
+        let before = (0..1000)
+            .map(|id| m.pick_worker_by_ids([id]))
+            .collect::<Vec<_>>();
+        for w in &["w1", "w2", "w3"] {
+            assert!(before.iter().any(|b| b.as_str() == *w));
+        }
+
+        assert!(m.update(vec![
+            "w4".to_string(),
+            "w1".to_string(),
+            "w2".to_string(),
+            "w3".to_string()
+        ]));
+        let mut moved = 0;
+        for id in 0..1000 {
+            let after = m.pick_worker_by_ids([id]);
+            if after != before[id as usize] {
+                assert_eq!(after, "w4");
+                moved += 1;
+            }
+        }
+        assert!(150 < moved && moved < 350, "moved {}", moved);
+
+        // Leaving moves back only the work of the node that left.
+        assert!(m.update(vec!["w1".to_string(), "w2".to_string(), "w3".to_string()]));
+        for id in 0..1000 {
+            assert_eq!(m.pick_worker_by_ids([id]), before[id as usize]);
+        }
+        assert!(!m.update(vec!["w3".to_string(), "w2".to_string(), "w1".to_string()]));
+    }
+
+    #[test]
+    fn replicas() {
+        let config = Config::test("cluster_replicas").update_config(|mut c| {
+            c.select_workers = vec!["w1".to_string(), "w2".to_string(), "w3".to_string()];
+            c.partition_replication_factor = 2;
+            c
+        });
+        let m = ClusterMembership::new(config.config_obj().as_ref());
+        let mut second_replicas = HashMap::<String, usize>::new();
+        for id in 0..1000 {
+            let replicas = m.replicas_by_ids([id]);
+            assert_eq!(replicas.len(), 2);
+            assert_eq!(replicas[0], m.pick_worker_by_ids([id]));
+            assert_ne!(replicas[0], replicas[1]);
+            *second_replicas.entry(replicas[1].clone()).or_default() += 1;
+        }
+        assert_eq!(second_replicas.len(), 3);
+
+        // Replicas are limited by the number of workers.
+        m.update(vec!["w1".to_string()]);
+        assert_eq!(m.replicas_by_ids([1]), vec!["w1"]);
+        m.update(Vec::new());
+        assert_eq!(m.replicas_by_ids([1]), vec!["localhost"]);
+    }
+
+    #[test]
+    fn no_workers() {
+        let m = membership(&[]);
+        assert_eq!(m.pick_worker_by_ids([1]), "localhost");
+        assert!(!m.is_member("localhost"));
+    }
+
+    #[test]
+    fn heart_beats_expire() {
+        let config = Config::test("worker_heart_beats").update_config(|mut c| {
+            c.select_workers = vec!["w1".to_string()];
+            c.worker_heart_beat_timeout_secs = 10;
+            c
+        });
+        let start = Instant::now();
+        let mut heart_beats = WorkerHeartBeats::new(config.config_obj().as_ref());
+        assert_eq!(heart_beats.live_workers(start), vec!["w1"]);
+
+        heart_beats
+            .heart_beat("w2".to_string(), start + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            heart_beats.live_workers(start + Duration::from_secs(10)),
+            vec!["w1", "w2"]
+        );
+        assert_eq!(
+            heart_beats.live_workers(start + Duration::from_secs(11)),
+            vec!["w2"]
+        );
+        assert_eq!(
+            heart_beats.live_workers(start + Duration::from_secs(16)),
+            Vec::<String>::new()
+        );
+        assert!(heart_beats
+            .heart_beat(String::new(), start + Duration::from_secs(16))
+            .is_err());
+    }
+}
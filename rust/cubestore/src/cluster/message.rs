@@ -3,10 +3,62 @@ use crate::queryplanner::query_executor::SerializedRecordBatchStream;
 use crate::queryplanner::serialized_plan::SerializedPlan;
 use crate::CubeError;
 use arrow::datatypes::SchemaRef;
+use datafusion::cube_ext;
 use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
 use std::io::ErrorKind;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Version of the protocol between nodes. Increment on any change to [NetworkMessage] or the
+/// payloads it carries. Nodes use the highest version both of them support.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest version this node can still talk. Raise it when support of older versions is removed.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Precedes the version on every connection to tell the protocol apart from other traffic.
+const PROTOCOL_MAGIC: [u8; 4] = *b"CSNP";
+
+/// Compression of record batches sent to other nodes.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, CubeError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => Ok(lz4::block::compress(data, None, true)?),
+            Compression::Zstd => Ok(zstd::encode_all(data, 0)?),
+        }
+    }
+
+    pub fn decompress(self, data: Vec<u8>) -> Result<Vec<u8>, CubeError> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Lz4 => Ok(lz4::block::decompress(&data, None)?),
+            Compression::Zstd => Ok(zstd::decode_all(data.as_slice())?),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(CubeError::user(format!(
+                "unknown compression '{}', expected 'none', 'lz4' or 'zstd'",
+                s
+            ))),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum NetworkMessage {
@@ -54,8 +106,118 @@ impl NetworkMessage {
         }
     }
 
+    fn batches_mut(&mut self) -> Vec<&mut SerializedRecordBatchStream> {
+        match self {
+            NetworkMessage::SelectResult(Ok((_, batches))) => batches.iter_mut().collect(),
+            NetworkMessage::SelectResultBatch(Ok(Some(b))) => vec![b],
+            NetworkMessage::AddMemoryChunk { data, .. } => vec![data],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Compresses record batches in the message before sending it to another node. Receivers
+    /// [decompress] them regardless of their own settings.
+    pub async fn compress(mut self, compression: Compression) -> Result<Self, CubeError> {
+        if compression == Compression::None || self.batches_mut().is_empty() {
+            return Ok(self);
+        }
+        cube_ext::spawn_blocking(move || -> Result<Self, CubeError> {
+            for b in self.batches_mut() {
+                b.compress(compression)?;
+            }
+            Ok(self)
+        })
+        .await?
+    }
+
+    /// Decompresses record batches of a received message.
+    pub async fn decompress(mut self) -> Result<Self, CubeError> {
+        if self.batches_mut().iter().all(|b| !b.is_compressed()) {
+            return Ok(self);
+        }
+        cube_ext::spawn_blocking(move || -> Result<Self, CubeError> {
+            for b in self.batches_mut() {
+                b.decompress()?;
+            }
+            Ok(self)
+        })
+        .await?
+    }
+
+    /// Sends the range of protocol versions we support to the node we connected to. The reply
+    /// with the version to use is read by [receive_handshake_reply], it can be delayed until the
+    /// first response when the version of the node is already known.
+    pub async fn send_handshake<S: AsyncWrite + Unpin + Send>(
+        socket: &mut S,
+    ) -> Result<(), CubeError> {
+        let mut handshake = PROTOCOL_MAGIC.to_vec();
+        handshake.extend_from_slice(&MIN_PROTOCOL_VERSION.to_be_bytes());
+        handshake.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        socket.write_all(&handshake).await?;
+        Ok(())
+    }
+
+    /// Reads the protocol version chosen by the node we connected to.
+    pub async fn receive_handshake_reply<S: AsyncRead + Unpin + Send>(
+        socket: &mut S,
+        address: &str,
+    ) -> Result<u32, CubeError> {
+        let version = match socket.read_u32().await {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Err(CubeError::internal(format!(
+                    "{} closed the connection during the handshake, it probably runs an older \
+                     version of Cube Store",
+                    address
+                )))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if version < MIN_PROTOCOL_VERSION || PROTOCOL_VERSION < version {
+            return Err(version_mismatch(address, None));
+        }
+        Ok(version)
+    }
+
+    /// Reads the range of protocol versions supported by the connected node and replies with the
+    /// highest version both nodes support, which is returned. The connection must be closed if
+    /// there is no such version.
+    pub async fn server_handshake<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        socket: &mut S,
+        address: &str,
+    ) -> Result<u32, CubeError> {
+        let mut header = [0u8; 8];
+        socket.read_exact(&mut header).await?;
+        if &header == b"GET /ws " {
+            // Common misconfig of CubeJS can cause it to send HTTP message to the metastore port.
+            return Err(CubeError::internal(format!(
+                "HTTP message on metastore port"
+            )));
+        }
+        if header[..4] != PROTOCOL_MAGIC {
+            return Err(CubeError::internal(format!(
+                "Unknown protocol on the connection from {}, it probably runs an older version \
+                 of Cube Store",
+                address
+            )));
+        }
+        let min_version = u32::from_be_bytes(header[4..].try_into().unwrap());
+        let max_version = socket.read_u32().await?;
+        let version = max_version.min(PROTOCOL_VERSION);
+        if version < min_version.max(MIN_PROTOCOL_VERSION) {
+            // Zero tells the other node there is no common version.
+            socket.write_u32(0).await?;
+            return Err(version_mismatch(address, Some((min_version, max_version))));
+        }
+        socket.write_u32(version).await?;
+        Ok(version)
+    }
+
     /// Returns true iff the client accepted the message.
-    pub async fn maybe_send(&self, socket: &mut TcpStream) -> Result<bool, CubeError> {
+    pub async fn maybe_send<S: AsyncWrite + Unpin + Send>(
+        &self,
+        socket: &mut S,
+    ) -> Result<bool, CubeError> {
         match self.send_impl(socket).await {
             Ok(()) => Ok(true),
             Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => Ok(false),
@@ -63,11 +225,17 @@ impl NetworkMessage {
         }
     }
 
-    pub async fn send(&self, socket: &mut TcpStream) -> Result<(), CubeError> {
+    pub async fn send<S: AsyncWrite + Unpin + Send>(
+        &self,
+        socket: &mut S,
+    ) -> Result<(), CubeError> {
         Ok(self.send_impl(socket).await?)
     }
 
-    async fn send_impl(&self, socket: &mut TcpStream) -> Result<(), std::io::Error> {
+    async fn send_impl<S: AsyncWrite + Unpin + Send>(
+        &self,
+        socket: &mut S,
+    ) -> Result<(), std::io::Error> {
         let mut ser = flexbuffers::FlexbufferSerializer::new();
         self.serialize(&mut ser).unwrap();
         let message_buffer = ser.take_buffer();
@@ -83,7 +251,7 @@ impl NetworkMessage {
         Ok(())
     }
 
-    pub async fn receive(socket: &mut TcpStream) -> Result<Self, CubeError> {
+    pub async fn receive<S: AsyncRead + Unpin + Send>(socket: &mut S) -> Result<Self, CubeError> {
         match Self::maybe_receive(socket).await? {
             Some(m) => Ok(m),
             None => Err(CubeError::internal("connection closed".to_string())),
@@ -91,7 +259,9 @@ impl NetworkMessage {
     }
 
     /// Either receives a message or waits for the connection to close.
-    pub async fn maybe_receive(socket: &mut TcpStream) -> Result<Option<Self>, CubeError> {
+    pub async fn maybe_receive<S: AsyncRead + Unpin + Send>(
+        socket: &mut S,
+    ) -> Result<Option<Self>, CubeError> {
         let len = socket.read_u64().await;
         if let Err(e) = &len {
             // TODO: corner case with `0 < n < 8` read bytes.
@@ -102,13 +272,6 @@ impl NetworkMessage {
         let len = len?;
 
         if MAX_NETWORK_MSG_LEN < len {
-            // Common misconfig of CubeJS can cause it to send HTTP message to the metastore port.
-            // The constant is the numeric value of the 'GET /ws ' string.
-            if len == 5135603447297962784 {
-                return Err(CubeError::internal(format!(
-                    "HTTP message on metastore port"
-                )));
-            }
             return Err(CubeError::internal(format!(
                 "invalid metastore message: declared length is too large, {} bytes",
                 len
@@ -122,5 +285,155 @@ impl NetworkMessage {
     }
 }
 
+fn version_mismatch(address: &str, versions: Option<(u32, u32)>) -> CubeError {
+    let versions = match versions {
+        Some((min, max)) => format!("versions {} to {}", min, max),
+        None => "other versions".to_string(),
+    };
+    CubeError::user(format!(
+        "Protocol version mismatch: {} supports {}, this node supports versions {} to {}. Nodes \
+         of the cluster must run compatible versions of Cube Store",
+        address, versions, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+    ))
+}
+
 // Anything larger is considered to be an invalid message.
 const MAX_NETWORK_MSG_LEN: u64 = 20 * 1024 * 1024 * 1024; // 20GiB
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+    use std::sync::Arc;
+    use tokio::io::duplex;
+
+    async fn client_handshake<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        socket: &mut S,
+    ) -> Result<u32, CubeError> {
+        NetworkMessage::send_handshake(socket).await?;
+        NetworkMessage::receive_handshake_reply(socket, "server").await
+    }
+
+    /// Handshake of a node that supports versions from `min` to `max`.
+    async fn other_versions(mut client: tokio::io::DuplexStream, min: u32, max: u32) -> u32 {
+        client.write_all(&PROTOCOL_MAGIC).await.unwrap();
+        client.write_u32(min).await.unwrap();
+        client.write_u32(max).await.unwrap();
+        client.read_u32().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let (mut client, mut server) = duplex(64);
+        let (c, s) = tokio::join!(
+            client_handshake(&mut client),
+            NetworkMessage::server_handshake(&mut server, "client")
+        );
+        assert_eq!(c.unwrap(), PROTOCOL_VERSION);
+        assert_eq!(s.unwrap(), PROTOCOL_VERSION);
+
+        // Newer nodes use the newest version we support.
+        let (client, mut server) = duplex(64);
+        let (v, s) = tokio::join!(
+            other_versions(client, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION + 5),
+            NetworkMessage::server_handshake(&mut server, "client")
+        );
+        assert_eq!(v, PROTOCOL_VERSION);
+        assert_eq!(s.unwrap(), PROTOCOL_VERSION);
+
+        // Nodes without common versions are rejected.
+        let (client, mut server) = duplex(64);
+        let (v, s) = tokio::join!(
+            other_versions(client, PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 5),
+            NetworkMessage::server_handshake(&mut server, "client")
+        );
+        assert_eq!(v, 0);
+        let e = s.unwrap_err();
+        assert!(e.message.contains("Protocol version mismatch"), "{}", e);
+
+        let (mut client, mut server) = duplex(64);
+        let other_node = async move {
+            let mut handshake = [0u8; 12];
+            server.read_exact(&mut handshake).await.unwrap();
+            server.write_u32(0).await.unwrap();
+        };
+        let (c, _) = tokio::join!(client_handshake(&mut client), other_node);
+        let e = c.unwrap_err();
+        assert!(e.message.contains("Protocol version mismatch"), "{}", e);
+
+        // Older nodes send messages right away.
+        let (mut client, mut server) = duplex(1024);
+        let (sent, s) = tokio::join!(
+            NetworkMessage::NotifyJobListeners.send(&mut client),
+            NetworkMessage::server_handshake(&mut server, "client")
+        );
+        sent.unwrap();
+        let e = s.unwrap_err();
+        assert!(e.message.contains("older version"), "{}", e);
+
+        // And close the connection when they see the handshake.
+        let (mut client, mut server) = duplex(64);
+        let older_node = async move {
+            let mut header = [0u8; 8];
+            server.read_exact(&mut header).await.unwrap();
+        };
+        let (c, _) = tokio::join!(client_handshake(&mut client), older_node);
+        let e = c.unwrap_err();
+        assert!(e.message.contains("older version"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn compressed_batches() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let ids = (0..1000).collect::<Vec<i64>>();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(ids.clone())),
+                Arc::new(StringArray::from(vec!["foo"; 1000])),
+            ],
+        )
+        .unwrap();
+
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd].iter() {
+            let batches = SerializedRecordBatchStream::write(&schema, vec![batch.clone()]).unwrap();
+            let m = NetworkMessage::SelectResult(Ok((schema.clone(), batches)))
+                .compress(*compression)
+                .await
+                .unwrap();
+
+            let (mut client, mut server) = duplex(1024);
+            let (sent, received) =
+                tokio::join!(m.send(&mut client), NetworkMessage::receive(&mut server));
+            sent.unwrap();
+            let received = received.unwrap().decompress().await.unwrap();
+            let mut batches = match received {
+                NetworkMessage::SelectResult(Ok((_, batches))) => batches,
+                m => panic!("unexpected message: {:?}", m),
+            };
+            assert_eq!(batches.len(), 1);
+            assert!(!batches[0].is_compressed());
+            let read = batches.remove(0).read().unwrap();
+            assert_eq!(read.num_rows(), 1000);
+            let read_ids = read
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            assert_eq!(read_ids.values(), ids.as_slice());
+        }
+    }
+
+    #[test]
+    fn parse_compression() {
+        assert_eq!("none".parse::<Compression>().unwrap(), Compression::None);
+        assert_eq!("LZ4".parse::<Compression>().unwrap(), Compression::Lz4);
+        assert_eq!("zstd".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert!("gzip".parse::<Compression>().is_err());
+    }
+}
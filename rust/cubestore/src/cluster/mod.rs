@@ -1,4 +1,6 @@
+pub mod membership;
 pub mod message;
+pub mod replica_health;
 
 pub mod transport;
 #[cfg(not(target_os = "windows"))]
@@ -8,7 +10,10 @@ pub mod worker_pool;
 use crate::cluster::worker_pool::{worker_main, MessageProcessor, WorkerPool};
 
 use crate::ack_error;
+use crate::app_metrics;
+use crate::cluster::membership::ClusterMembership;
 use crate::cluster::message::NetworkMessage;
+use crate::cluster::replica_health::ReplicaHealth;
 use crate::cluster::transport::{ClusterTransport, MetaStoreTransport, WorkerConnection};
 use crate::config::injection::{DIService, Injector};
 use crate::config::is_router;
@@ -25,10 +30,12 @@ use crate::metastore::{
 };
 use crate::queryplanner::query_executor::{QueryExecutor, SerializedRecordBatchStream};
 use crate::queryplanner::serialized_plan::SerializedPlan;
+use crate::remotefs::queue::QueueRemoteFs;
 use crate::remotefs::RemoteFs;
 use crate::store::compaction::CompactionService;
 use crate::store::ChunkDataStore;
 use crate::util::aborting_join_handle::AbortingJoinHandle;
+use crate::util::tls::{NetworkStream, TlsSettings};
 use crate::CubeError;
 use arrow::datatypes::SchemaRef;
 use arrow::error::ArrowError;
@@ -40,22 +47,21 @@ use datafusion::cube_ext;
 use datafusion::physical_plan::{RecordBatchStream, SendableRecordBatchStream};
 use flatbuffers::bitflags::_core::pin::Pin;
 use futures::future::join_all;
+use futures::stream::FuturesUnordered;
 use futures::task::{Context, Poll};
-use futures::{Future, Stream};
+use futures::{Future, Stream, StreamExt};
 use futures_timer::Delay;
 use itertools::Itertools;
 use log::{debug, error, info, warn};
 use mockall::automock;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
 use std::path::Path;
 use std::sync::Weak;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime};
 use tokio::fs;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
@@ -74,17 +80,19 @@ pub trait Cluster: DIService + Send + Sync {
 
     fn config(&self) -> Arc<dyn ConfigObj>;
 
-    /// Send full select to a worker, which will act as the main node for the query.
+    /// Send full select to a worker, which will act as the main node for the query. The select
+    /// goes to the next of the passed workers if one is not available.
     async fn route_select(
         &self,
-        node_name: &str,
+        workers: &[String],
         plan: SerializedPlan,
     ) -> Result<(SchemaRef, Vec<SerializedRecordBatchStream>), CubeError>;
 
-    /// Runs select on a single worker node to get partial results from that worker.
+    /// Runs select on a single worker node to get partial results from that worker. The select
+    /// goes to the next of the passed replicas if a worker is not available or too slow.
     async fn run_select(
         &self,
-        node_name: &str,
+        replicas: &[String],
         plan: SerializedPlan,
     ) -> Result<Vec<RecordBatch>, CubeError>;
 
@@ -92,7 +100,7 @@ pub trait Cluster: DIService + Send + Sync {
     /// This allows to send only a limited number of results, if the caller does not need all.
     async fn run_select_stream(
         &self,
-        node_name: &str,
+        replicas: &[String],
         plan: SerializedPlan,
     ) -> Result<SendableRecordBatchStream, CubeError>;
 
@@ -121,6 +129,9 @@ pub trait Cluster: DIService + Send + Sync {
 
     fn node_name_by_partition(&self, p: &IdRow<Partition>) -> String;
 
+    /// Select workers that are currently alive, work is distributed among them.
+    fn membership(&self) -> Arc<ClusterMembership>;
+
     async fn node_name_for_import(
         &self,
         table_id: u64,
@@ -148,9 +159,12 @@ pub struct ClusterImpl {
     // TODO revisit cycle dependencies: try to extract cluster transport separately?
     // Weak to avoid cycle reference counting and memory leaks
     injector: Weak<Injector>,
-    remote_fs: Arc<dyn RemoteFs>,
+    remote_fs: Arc<QueueRemoteFs>,
     meta_store: Arc<dyn MetaStore>,
     cluster_transport: Arc<dyn ClusterTransport>,
+    membership: Arc<ClusterMembership>,
+    tls: Arc<TlsSettings>,
+    replica_health: Mutex<ReplicaHealth>,
     connect_timeout: Duration,
     server_name: String,
     server_addresses: Vec<String>,
@@ -267,45 +281,62 @@ impl Cluster for ClusterImpl {
 
     async fn route_select(
         &self,
-        node_name: &str,
+        workers: &[String],
         plan: SerializedPlan,
     ) -> Result<(SchemaRef, Vec<SerializedRecordBatchStream>), CubeError> {
-        let response = self
-            .send_or_process_locally(&node_name, NetworkMessage::RouterSelect(plan))
-            .await?;
-        match response {
-            NetworkMessage::SelectResult(r) => r,
-            _ => panic!("unexpected response for route select"),
-        }
+        // Full selects can run for long, so only unavailable workers are skipped.
+        self.run_on_replicas(workers, false, |node_name| {
+            let plan = plan.clone();
+            async move {
+                let response = self
+                    .send_or_process_locally(&node_name, NetworkMessage::RouterSelect(plan))
+                    .await?;
+                match response {
+                    NetworkMessage::SelectResult(r) => Ok(r),
+                    _ => panic!("unexpected response for route select"),
+                }
+            }
+        })
+        .await
     }
 
     #[instrument(level = "trace", skip(self, plan_node))]
     async fn run_select(
         &self,
-        node_name: &str,
+        replicas: &[String],
         plan_node: SerializedPlan,
     ) -> Result<Vec<RecordBatch>, CubeError> {
-        let response = self
-            .send_or_process_locally(node_name, NetworkMessage::Select(plan_node))
-            .await?;
-        match response {
-            NetworkMessage::SelectResult(r) => {
-                r.and_then(|(_, batches)| batches.into_iter().map(|b| b.read()).collect())
+        self.run_on_replicas(replicas, true, |node_name| {
+            let plan_node = plan_node.clone();
+            async move {
+                let response = self
+                    .send_or_process_locally(&node_name, NetworkMessage::Select(plan_node))
+                    .await?;
+                match response {
+                    NetworkMessage::SelectResult(r) => {
+                        Ok(r.and_then(|(_, batches)| {
+                            batches.into_iter().map(|b| b.read()).collect()
+                        }))
+                    }
+                    _ => panic!("unexpected response for select"),
+                }
             }
-            _ => panic!("unexpected response for select"),
-        }
+        })
+        .await
     }
 
     async fn run_select_stream(
         &self,
-        node_name: &str,
+        replicas: &[String],
         plan: SerializedPlan,
     ) -> Result<SendableRecordBatchStream, CubeError> {
-        self.this
-            .upgrade()
-            .unwrap()
-            .run_select_stream_impl(node_name, plan)
-            .await
+        let this = self.this.upgrade().unwrap();
+        self.run_on_replicas(replicas, true, |node_name| {
+            let this = this.clone();
+            let plan = plan.clone();
+            async move { this.run_select_stream_impl(&node_name, plan).await }
+        })
+        .await
     }
 
     async fn available_nodes(&self) -> Result<Vec<String>, CubeError> {
@@ -367,25 +398,22 @@ impl Cluster for ClusterImpl {
 
     fn node_name_by_partition(&self, p: &IdRow<Partition>) -> String {
         if let Some(id) = p.get_row().multi_partition_id() {
-            pick_worker_by_ids(self.config_obj.as_ref(), [id]).to_string()
+            self.membership.pick_worker_by_ids([id])
         } else {
-            pick_worker_by_partitions(self.config_obj.as_ref(), [p]).to_string()
+            self.membership.pick_worker_by_partitions([p])
         }
     }
 
+    fn membership(&self) -> Arc<ClusterMembership> {
+        self.membership.clone()
+    }
+
     async fn node_name_for_import(
         &self,
         table_id: u64,
         location: &str,
     ) -> Result<String, CubeError> {
-        let workers = self.config_obj.select_workers();
-        if workers.is_empty() {
-            return Ok(self.server_name.to_string());
-        }
-        let mut hasher = DefaultHasher::new();
-        table_id.hash(&mut hasher);
-        location.hash(&mut hasher);
-        Ok(workers[(hasher.finish() % workers.len() as u64) as usize].to_string())
+        Ok(self.membership.pick_worker_by_key((table_id, location)))
     }
 
     async fn warmup_partition(
@@ -393,14 +421,16 @@ impl Cluster for ClusterImpl {
         partition: IdRow<Partition>,
         chunks: Vec<IdRow<Chunk>>,
     ) -> Result<(), CubeError> {
-        let node_name = self.node_name_by_partition(&partition);
+        let replicas = self.replicas_by_partition(&partition);
         let mut futures = Vec::new();
-        if let Some(name) = partition.get_row().get_full_name(partition.get_id()) {
-            futures.push(self.warmup_download(&node_name, name));
-        }
-        for chunk in chunks.iter() {
-            let name = chunk.get_row().get_full_name(chunk.get_id());
-            futures.push(self.warmup_download(&node_name, name));
+        for node_name in replicas.iter() {
+            if let Some(name) = partition.get_row().get_full_name(partition.get_id()) {
+                futures.push(self.warmup_download(node_name, name));
+            }
+            for chunk in chunks.iter() {
+                let name = chunk.get_row().get_full_name(chunk.get_id());
+                futures.push(self.warmup_download(node_name, name));
+            }
         }
         join_all(futures)
             .await
@@ -428,7 +458,7 @@ impl Cluster for ClusterImpl {
                 NetworkMessage::SelectResult(res)
             }
             NetworkMessage::WarmupDownload(remote_path) => {
-                let res = self.remote_fs.download_file(&remote_path).await;
+                let res = self.remote_fs.warmup_download(&remote_path).await;
                 NetworkMessage::WarmupDownloadResult(res.map(|_| ()))
             }
             NetworkMessage::SelectResult(_) | NetworkMessage::WarmupDownloadResult(_) => {
@@ -803,13 +833,15 @@ impl ClusterImpl {
         server_name: String,
         server_addresses: Vec<String>,
         injector: Weak<Injector>,
-        remote_fs: Arc<dyn RemoteFs>,
+        remote_fs: Arc<QueueRemoteFs>,
         connect_timeout: Duration,
         meta_store: Arc<dyn MetaStore>,
         config_obj: Arc<dyn ConfigObj>,
         query_executor: Arc<dyn QueryExecutor>,
         meta_store_sender: Sender<MetaStoreEvent>,
         cluster_transport: Arc<dyn ClusterTransport>,
+        membership: Arc<ClusterMembership>,
+        tls: Arc<TlsSettings>,
     ) -> Arc<ClusterImpl> {
         let (close_worker_socket_tx, close_worker_socket_rx) = watch::channel(false);
         Arc::new_cyclic(|this| ClusterImpl {
@@ -821,6 +853,11 @@ impl ClusterImpl {
             connect_timeout,
             meta_store,
             cluster_transport,
+            membership,
+            tls,
+            replica_health: Mutex::new(ReplicaHealth::new(Duration::from_secs(
+                config_obj.worker_heart_beat_timeout_secs(),
+            ))),
             job_notify: Arc::new(Notify::new()),
             meta_store_sender,
             #[cfg(not(target_os = "windows"))]
@@ -864,6 +901,13 @@ impl ClusterImpl {
             ));
         }
 
+        if self.config_obj.worker_heart_beat_interval_secs() > 0 {
+            let cluster = self.this.upgrade().unwrap();
+            futures.push(cube_ext::spawn(async move {
+                cluster.membership_loop().await;
+            }));
+        }
+
         for _ in 0..self.config_obj.job_runners_count() {
             // TODO number of job event loops
             let job_runner = JobRunner {
@@ -899,6 +943,51 @@ impl ClusterImpl {
         Ok(())
     }
 
+    /// Workers send heart beats to the metastore and get the current set of workers in response,
+    /// the router reads it directly. Workers that got new partitions assigned warm them up.
+    async fn membership_loop(&self) {
+        let interval = Duration::from_secs(self.config_obj.worker_heart_beat_interval_secs());
+        let cluster = self.this.upgrade().unwrap();
+        heart_beat_loop(
+            interval,
+            &self.stop_token,
+            move || self.update_membership(),
+            move |cancel| {
+                let cluster = cluster.clone();
+                async move { cluster.warmup_select_worker_until(&cancel).await }
+            },
+        )
+        .await
+    }
+
+    /// Returns true when the set of workers has changed and this node has to warm up.
+    async fn update_membership(&self) -> bool {
+        let workers = if self.is_select_worker() {
+            self.meta_store
+                .worker_heart_beat(self.server_name.clone())
+                .await
+        } else {
+            self.meta_store.get_live_workers().await
+        };
+        match workers {
+            Ok(workers) => {
+                let count = workers.len();
+                let changed = self.membership.update(workers);
+                if changed {
+                    info!("Select workers changed: {:?}", self.membership.workers());
+                }
+                if !self.is_select_worker() {
+                    app_metrics::CLUSTER_LIVE_WORKERS.report(count as i64);
+                }
+                changed && self.is_select_worker()
+            }
+            Err(e) => {
+                error!("Error updating select workers: {}", e);
+                false
+            }
+        }
+    }
+
     pub async fn send_to_worker(
         &self,
         worker_node: &str,
@@ -933,9 +1022,24 @@ impl ClusterImpl {
                         return;
                     }
                 };
+                let m = match m.decompress().await {
+                    Ok(m) => m,
+                    Err(e) => {
+                        error!("Network error: {}", e);
+                        return;
+                    }
+                };
 
+                let compression = c.config_obj.cluster_compression();
                 if !m.is_streaming_request() {
                     let response = c.process_message_on_worker(m).await;
+                    let response = match response.compress(compression).await {
+                        Ok(r) => r,
+                        Err(e) => {
+                            error!("Network error: {}", e);
+                            return;
+                        }
+                    };
                     if let Err(e) = response.send(&mut socket).await {
                         error!("Network error: {}", e);
                         return;
@@ -944,6 +1048,13 @@ impl ClusterImpl {
                     let mut p = c.start_stream_on_worker(m).await;
                     loop {
                         let (response, finished) = p.next().await;
+                        let response = match response.compress(compression).await {
+                            Ok(r) => r,
+                            Err(e) => {
+                                error!("Network error: {}", e);
+                                return;
+                            }
+                        };
                         match response.maybe_send(&mut socket).await {
                             // All ok, continue streaming.
                             Ok(true) => {}
@@ -990,7 +1101,7 @@ impl ClusterImpl {
         address: &str,
         cluster: Arc<ClusterImpl>,
         on_socket_bound: oneshot::Sender<()>,
-        process_fn: impl Fn(Arc<ClusterImpl>, TcpStream) -> F + Send + Sync + Clone + 'static,
+        process_fn: impl Fn(Arc<ClusterImpl>, NetworkStream) -> F + Send + Sync + Clone + 'static,
     ) -> Result<(), CubeError> {
         let listener = TcpListener::bind(address.clone()).await?;
         let _ = on_socket_bound.send(());
@@ -999,7 +1110,7 @@ impl ClusterImpl {
 
         loop {
             let mut stop_receiver = cluster.close_worker_socket_rx.write().await;
-            let (socket, _) = tokio::select! {
+            let (socket, peer) = tokio::select! {
                 res = stop_receiver.changed() => {
                     if res.is_err() || *stop_receiver.borrow() {
                         return Ok(());
@@ -1021,6 +1132,18 @@ impl ClusterImpl {
             let process_fn_to_move = process_fn.clone();
 
             cube_ext::spawn(async move {
+                let mut socket = match cluster_to_move.tls.accept_node(socket).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        error!("Network error: {}", e);
+                        return;
+                    }
+                };
+                let peer = peer.to_string();
+                if let Err(e) = NetworkMessage::server_handshake(&mut socket, &peer).await {
+                    error!("Network error: {}", e);
+                    return;
+                }
                 process_fn_to_move(cluster_to_move, socket).await;
             });
         }
@@ -1069,6 +1192,7 @@ impl ClusterImpl {
         let start = SystemTime::now();
         debug!("Running select: {:?}", plan_node);
         let to_download = plan_node.files_to_download();
+        let _used_files = self.remote_fs.use_local_files(to_download.clone());
         let file_futures = to_download
             .iter()
             .map(|remote| self.remote_fs.download_file(remote))
@@ -1266,6 +1390,105 @@ impl ClusterImpl {
         }
     }
 
+    /// Workers that keep the partition warm. The first one is [Cluster::node_name_by_partition].
+    fn replicas_by_partition(&self, p: &IdRow<Partition>) -> Vec<String> {
+        if let Some(id) = p.get_row().multi_partition_id() {
+            self.membership.replicas_by_ids([id])
+        } else {
+            self.membership.replicas_by_partitions([p])
+        }
+    }
+
+    /// Sends the request to replicas in the order of preference, healthy ones first. Moves on to
+    /// the next replica if the current one fails to respond or, if the replica timeout is set, is
+    /// slower than it. The last one has no timeout. With the hedge delay set, a slow request is also sent
+    /// to the next replica without cancelling the first one and the first response wins. Neither
+    /// applies when `retry_slow` is false.
+    ///
+    /// The outer error of a request means the worker did not respond. Errors of requests processed
+    /// by a worker are returned as is.
+    async fn run_on_replicas<T, F, Fut>(
+        &self,
+        replicas: &[String],
+        retry_slow: bool,
+        request: F,
+    ) -> Result<T, CubeError>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<Result<T, CubeError>, CubeError>>,
+    {
+        let replicas = self
+            .replica_health
+            .lock()
+            .unwrap()
+            .order(replicas, Instant::now());
+        let replica_timeout = self.config_obj.select_replica_timeout_secs();
+        let hedge_delay = self.config_obj.select_hedge_delay_ms();
+        let start_request = |i: usize| {
+            let node_name = replicas[i].clone();
+            let last = i + 1 == replicas.len();
+            let response = request(node_name.clone());
+            async move {
+                let response = if last || !retry_slow || replica_timeout == 0 {
+                    response.await
+                } else {
+                    let replica_timeout = Duration::from_secs(replica_timeout);
+                    match timeout(replica_timeout, response).await {
+                        Ok(r) => r,
+                        Err(_) => Err(CubeError::internal(format!(
+                            "Select on {} timed out after {:?}",
+                            node_name, replica_timeout
+                        ))),
+                    }
+                };
+                (node_name, response)
+            }
+        };
+
+        let mut pending = FuturesUnordered::new();
+        let mut next = 0;
+        let mut errors = Vec::new();
+        loop {
+            if pending.is_empty() {
+                if next == replicas.len() {
+                    return Err(CubeError::internal(errors.join("; ")));
+                }
+                if next != 0 {
+                    app_metrics::SELECT_REPLICA_RETRIES.increment();
+                }
+                pending.push(start_request(next));
+                next += 1;
+            }
+            let can_hedge = retry_slow && hedge_delay != 0 && next < replicas.len();
+            let response = tokio::select! {
+                Some(r) = pending.next() => Some(r),
+                _ = Delay::new(Duration::from_millis(hedge_delay)), if can_hedge => None,
+            };
+            match response {
+                None => {
+                    app_metrics::SELECT_HEDGED_REQUESTS.increment();
+                    pending.push(start_request(next));
+                    next += 1;
+                }
+                Some((node_name, Ok(r))) => {
+                    self.replica_health
+                        .lock()
+                        .unwrap()
+                        .report_success(&node_name);
+                    return r;
+                }
+                Some((node_name, Err(e))) => {
+                    warn!("Select on {} failed: {}", node_name, e);
+                    self.replica_health
+                        .lock()
+                        .unwrap()
+                        .report_failure(&node_name, Instant::now());
+                    errors.push(e.message);
+                }
+            }
+        }
+    }
+
     #[instrument(level = "trace", skip(self, m))]
     async fn call_streaming(
         self: &Arc<Self>,
@@ -1301,23 +1524,28 @@ impl ClusterImpl {
         }
     }
 
+    /// The outer error means the worker did not respond and the select can go to a replica.
     async fn run_select_stream_impl(
         self: &Arc<Self>,
         node_name: &str,
         plan: SerializedPlan,
-    ) -> Result<SendableRecordBatchStream, CubeError> {
+    ) -> Result<Result<SendableRecordBatchStream, CubeError>, CubeError> {
         let init_message = NetworkMessage::SelectStart(plan);
         let mut c = self.call_streaming(node_name, init_message).await?;
         let schema = match c.receive().await? {
             NetworkMessage::SelectResultSchema(s) => s,
             _ => panic!("unexpected response to select stream"),
-        }?;
-        return Ok(Box::pin(SelectStream {
+        };
+        let schema = match schema {
+            Ok(s) => s,
+            Err(e) => return Ok(Err(e)),
+        };
+        return Ok(Ok(Box::pin(SelectStream {
             schema,
             connection: Some(c),
             pending: Mutex::new(None),
             finished: false,
-        }));
+        })));
 
         type ConnPtr = Box<dyn WorkerConnection>;
         struct SelectStream {
@@ -1411,12 +1639,13 @@ impl ClusterImpl {
     /// Can take awhile, use the passed cancellation token to stop the worker before it finishes.
     /// Designed to run in the background.
     pub async fn warmup_select_worker(&self) {
-        if self.config_obj.select_workers().len() == 0 {
-            log::error!("No select workers specified");
-            return;
-        }
-        if !self.config_obj.select_workers().contains(&self.server_name) {
-            log::error!("Current node is not a select worker");
+        self.warmup_select_worker_until(&self.stop_token).await
+    }
+
+    async fn warmup_select_worker_until(&self, cancel: &CancellationToken) {
+        if !self.membership.is_member(&self.server_name) {
+            // Warmup runs again once the router registers this node.
+            log::info!("Current node is not a select worker yet");
             return;
         }
         if !self.config_obj.enable_startup_warmup() {
@@ -1435,24 +1664,24 @@ impl ClusterImpl {
         log::debug!("Got {} partitions, running the warmup", partitions.len());
 
         for (p, chunks) in partitions {
-            if self.node_name_by_partition(&p) != self.server_name {
+            if !self.replicas_by_partition(&p).contains(&self.server_name) {
                 continue;
             }
             if let Some(file) = p.get_row().get_full_name(p.get_id()) {
-                if self.stop_token.is_cancelled() {
+                if cancel.is_cancelled() {
                     log::debug!("Startup warmup cancelled");
                     return;
                 }
                 // TODO: propagate 'not found' and log in debug mode. Compaction might remove files,
                 //       so they are not errors most of the time.
-                ack_error!(self.remote_fs.download_file(&file).await);
+                ack_error!(self.remote_fs.warmup_download(&file).await);
             }
             for c in chunks {
-                if self.stop_token.is_cancelled() {
+                if cancel.is_cancelled() {
                     log::debug!("Startup warmup cancelled");
                     return;
                 }
-                ack_error!(self.remote_fs.download_file(&chunk_file_name(c)).await);
+                ack_error!(self.remote_fs.warmup_download(&chunk_file_name(c)).await);
             }
         }
         log::debug!("Startup warmup finished");
@@ -1542,41 +1771,83 @@ fn is_self_reference(name: &str) -> bool {
     name.starts_with("@loop:")
 }
 
-/// Picks a worker by opaque id for any distributing work in a cluster.
-/// Ids usually come from multi-partitions of the metastore.
-pub fn pick_worker_by_ids(
-    config: &'a dyn ConfigObj,
-    ids: impl IntoIterator<Item = u64>,
-) -> &'a str {
-    let workers = config.select_workers();
-    if workers.is_empty() {
-        return config.server_name().as_str();
-    }
-
-    let mut hasher = DefaultHasher::new();
-    for p in ids {
-        p.hash(&mut hasher);
+/// Calls `heart_beat` every `interval` until `stop_token` is cancelled. When it returns true,
+/// `warmup` is started in a separate task, so heart beats go on while the warmup downloads files
+/// and the node is not considered dead meanwhile. The previous warmup is cancelled at that point.
+async fn heart_beat_loop<HB, HBF, W, WF>(
+    interval: Duration,
+    stop_token: &CancellationToken,
+    heart_beat: HB,
+    warmup: W,
+) where
+    HB: Fn() -> HBF,
+    HBF: Future<Output = bool>,
+    W: Fn(CancellationToken) -> WF,
+    WF: Future<Output = ()> + Send + 'static,
+{
+    let mut warmup_token: Option<CancellationToken> = None;
+    loop {
+        if heart_beat().await {
+            if let Some(token) = warmup_token.take() {
+                token.cancel();
+            }
+            let token = stop_token.child_token();
+            cube_ext::spawn(warmup(token.clone()));
+            warmup_token = Some(token);
+        }
+        tokio::select! {
+            _ = stop_token.cancelled() => {
+                return;
+            }
+            _ = Delay::new(interval) => {}
+        }
     }
-    workers[(hasher.finish() % workers.len() as u64) as usize].as_str()
 }
 
-/// Same as [pick_worker_by_ids], but uses ranges of partitions. This is a hack
-/// to keep the same node for partitions produced by compaction that merged
-/// chunks into the main table of a single partition.
-pub fn pick_worker_by_partitions(
-    config: &'a dyn ConfigObj,
-    partitions: impl IntoIterator<Item = &'a IdRow<Partition>>,
-) -> &'a str {
-    let workers = config.select_workers();
-    if workers.is_empty() {
-        return config.server_name().as_str();
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn slow_warmup_does_not_stop_heart_beats() {
+        let stop_token = CancellationToken::new();
+        let heart_beats = AtomicUsize::new(0);
+        let (cancelled_tx, cancelled_rx) = oneshot::channel();
+        let cancelled_tx = Mutex::new(Some(cancelled_tx));
+
+        let heart_beats_ref = &heart_beats;
+        let heart_beat_loop = heart_beat_loop(
+            Duration::from_millis(10),
+            &stop_token,
+            // Only the first heart beat changes membership.
+            move || async move { heart_beats_ref.fetch_add(1, Ordering::SeqCst) == 0 },
+            |cancel: CancellationToken| {
+                let cancelled_tx = cancelled_tx.lock().unwrap().take().unwrap();
+                async move {
+                    // Warmup that never finishes by itself.
+                    cancel.cancelled().await;
+                    cancelled_tx.send(()).unwrap();
+                }
+            },
+        );
+        let stop = async {
+            while heart_beats.load(Ordering::SeqCst) < 5 {
+                Delay::new(Duration::from_millis(10)).await;
+            }
+            stop_token.cancel();
+        };
 
-    let mut hasher = DefaultHasher::new();
-    for partition in partitions {
-        partition.get_row().get_min_val().hash(&mut hasher);
-        partition.get_row().get_max_val().hash(&mut hasher);
-        partition.get_row().get_index_id().hash(&mut hasher);
+        timeout(
+            Duration::from_secs(10),
+            futures::future::join(heart_beat_loop, stop),
+        )
+        .await
+        .expect("heart beats stopped during warmup");
+        // Warmup is cancelled together with the loop.
+        timeout(Duration::from_secs(10), cancelled_rx)
+            .await
+            .unwrap()
+            .unwrap();
     }
-    workers[(hasher.finish() % workers.len() as u64) as usize].as_str()
 }
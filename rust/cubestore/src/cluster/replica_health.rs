@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Remembers workers that recently failed to answer a select, so requests go to other replicas
+/// first. A worker is considered healthy again after it answers or once the penalty expires.
+pub struct ReplicaHealth {
+    penalty: Duration,
+    failed_at: HashMap<String, Instant>,
+}
+
+impl ReplicaHealth {
+    pub fn new(penalty: Duration) -> ReplicaHealth {
+        ReplicaHealth {
+            penalty,
+            failed_at: HashMap::new(),
+        }
+    }
+
+    pub fn report_success(&mut self, node_name: &str) {
+        self.failed_at.remove(node_name);
+    }
+
+    pub fn report_failure(&mut self, node_name: &str, now: Instant) {
+        self.failed_at.insert(node_name.to_string(), now);
+    }
+
+    pub fn is_healthy(&self, node_name: &str, now: Instant) -> bool {
+        match self.failed_at.get(node_name) {
+            Some(t) => self.penalty <= now.saturating_duration_since(*t),
+            None => true,
+        }
+    }
+
+    /// Moves unhealthy replicas to the end, keeping the order of preference otherwise.
+    pub fn order(&self, replicas: &[String], now: Instant) -> Vec<String> {
+        let (mut healthy, unhealthy): (Vec<_>, Vec<_>) = replicas
+            .iter()
+            .cloned()
+            .partition(|r| self.is_healthy(r, now));
+        healthy.extend(unhealthy);
+        healthy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unhealthy_replicas_go_last() {
+        let mut health = ReplicaHealth::new(Duration::from_secs(10));
+        let replicas = vec!["w1".to_string(), "w2".to_string(), "w3".to_string()];
+        let start = Instant::now();
+        assert_eq!(health.order(&replicas, start), replicas);
+
+        health.report_failure("w1", start);
+        assert_eq!(health.order(&replicas, start), vec!["w2", "w3", "w1"]);
+        health.report_failure("w2", start + Duration::from_secs(5));
+        assert_eq!(
+            health.order(&replicas, start + Duration::from_secs(5)),
+            vec!["w3", "w1", "w2"]
+        );
+
+        // Penalty expires.
+        assert_eq!(
+            health.order(&replicas, start + Duration::from_secs(10)),
+            vec!["w1", "w3", "w2"]
+        );
+        health.report_success("w2");
+        assert_eq!(
+            health.order(&replicas, start + Duration::from_secs(10)),
+            replicas
+        );
+    }
+}
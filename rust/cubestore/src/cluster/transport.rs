@@ -1,11 +1,13 @@
-use crate::cluster::message::NetworkMessage;
+use crate::cluster::message::{Compression, NetworkMessage};
 use crate::config::injection::DIService;
 use crate::config::ConfigObj;
+use crate::util::tls::{NetworkStream, TlsSettings};
 use crate::CubeError;
 use async_trait::async_trait;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::net::TcpStream;
 
 /// Client-side connection for exchanging messages between the server and the client.
 /// Created by [ClusterTransport].
@@ -59,28 +61,108 @@ impl dyn ClusterTransport {
 
 pub struct ClusterTransportImpl {
     config: Arc<dyn ConfigObj>,
+    tls: Arc<TlsSettings>,
+    versions: Arc<PeerVersions>,
 }
 
 crate::di_service!(ClusterTransportImpl, [ClusterTransport]);
 
 impl ClusterTransportImpl {
-    pub fn new(config: Arc<dyn ConfigObj>) -> Arc<Self> {
-        Arc::new(Self { config })
+    pub fn new(config: Arc<dyn ConfigObj>, tls: Arc<TlsSettings>) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            tls,
+            versions: Arc::new(PeerVersions::default()),
+        })
+    }
+}
+
+/// Protocol versions negotiated with other nodes by their addresses.
+#[derive(Default)]
+struct PeerVersions(Mutex<HashMap<String, u32>>);
+
+/// Connection to the worker or metastore port of another node.
+struct NodeStream {
+    stream: NetworkStream,
+    address: String,
+    /// Known version of the node. The request is sent without waiting for the handshake reply,
+    /// which is checked before the response.
+    unconfirmed_version: Option<u32>,
+    versions: Arc<PeerVersions>,
+}
+
+impl NodeStream {
+    /// Connects to the node and agrees on the protocol version with it.
+    async fn connect(
+        config: &dyn ConfigObj,
+        tls: &TlsSettings,
+        versions: &Arc<PeerVersions>,
+        address: &str,
+    ) -> Result<NodeStream, CubeError> {
+        tokio::time::timeout(Duration::from_secs(config.connection_timeout()), async {
+            let mut stream = tls.connect_to_node(address).await?;
+            NetworkMessage::send_handshake(&mut stream).await?;
+            let known_version = versions.0.lock().unwrap().get(address).cloned();
+            if known_version.is_none() {
+                let version = NetworkMessage::receive_handshake_reply(&mut stream, address).await?;
+                versions
+                    .0
+                    .lock()
+                    .unwrap()
+                    .insert(address.to_string(), version);
+            }
+            Ok::<_, CubeError>(NodeStream {
+                stream,
+                address: address.to_string(),
+                unconfirmed_version: known_version,
+                versions: versions.clone(),
+            })
+        })
+        .await
+        .map_err(|e| CubeError::internal(format!("Can't connect to {}: {}", address, e)))?
+    }
+
+    async fn maybe_send(&mut self, m: &NetworkMessage) -> Result<bool, CubeError> {
+        m.maybe_send(&mut self.stream).await
+    }
+
+    async fn maybe_receive(&mut self) -> Result<Option<NetworkMessage>, CubeError> {
+        if let Some(known_version) = self.unconfirmed_version.take() {
+            let version =
+                NetworkMessage::receive_handshake_reply(&mut self.stream, &self.address).await;
+            if version.as_ref().ok() != Some(&known_version) {
+                // The node was restarted with another version, negotiate again next time.
+                self.versions.0.lock().unwrap().remove(&self.address);
+                return Err(match version {
+                    Err(e) => e,
+                    Ok(v) => CubeError::internal(format!(
+                        "{} switched from protocol version {} to {}",
+                        self.address, known_version, v
+                    )),
+                });
+            }
+        }
+        NetworkMessage::maybe_receive(&mut self.stream).await
     }
 }
 
 struct Connection {
-    stream: TcpStream,
+    stream: NodeStream,
+    compression: Compression,
 }
 
 #[async_trait]
 impl WorkerConnection for Connection {
     async fn maybe_send(&mut self, m: NetworkMessage) -> Result<bool, CubeError> {
-        m.maybe_send(&mut self.stream).await
+        let m = m.compress(self.compression).await?;
+        self.stream.maybe_send(&m).await
     }
 
     async fn maybe_receive(&mut self) -> Result<Option<NetworkMessage>, CubeError> {
-        NetworkMessage::maybe_receive(&mut self.stream).await
+        match self.stream.maybe_receive().await? {
+            Some(m) => Ok(Some(m.decompress().await?)),
+            None => Ok(None),
+        }
     }
 }
 
@@ -90,13 +172,17 @@ impl ClusterTransport for ClusterTransportImpl {
         &self,
         worker_node: String,
     ) -> Result<Box<dyn WorkerConnection>, CubeError> {
-        let stream = tokio::time::timeout(
-            Duration::from_secs(self.config.connection_timeout()),
-            TcpStream::connect(worker_node.to_string()),
+        let stream = NodeStream::connect(
+            self.config.as_ref(),
+            &self.tls,
+            &self.versions,
+            &worker_node,
         )
-        .await?
-        .map_err(|e| CubeError::internal(format!("Can't connect to {}: {}", worker_node, e)))?;
-        Ok(Box::new(Connection { stream }))
+        .await?;
+        Ok(Box::new(Connection {
+            stream,
+            compression: self.config.cluster_compression(),
+        }))
     }
 }
 
@@ -107,35 +193,133 @@ pub trait MetaStoreTransport: DIService {
 
 pub struct MetaStoreTransportImpl {
     config: Arc<dyn ConfigObj>,
+    tls: Arc<TlsSettings>,
+    /// Index of the address in `metastore_remote_address` that accepted the last connection.
+    current_address: AtomicUsize,
+    versions: Arc<PeerVersions>,
 }
 
 crate::di_service!(MetaStoreTransportImpl, [MetaStoreTransport]);
 
 impl MetaStoreTransportImpl {
-    pub fn new(config: Arc<dyn ConfigObj>) -> Arc<Self> {
-        Arc::new(Self { config })
+    pub fn new(config: Arc<dyn ConfigObj>, tls: Arc<TlsSettings>) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            tls,
+            current_address: AtomicUsize::new(0),
+            versions: Arc::new(PeerVersions::default()),
+        })
     }
 }
 
 #[async_trait]
 impl MetaStoreTransport for MetaStoreTransportImpl {
+    /// Addresses are tried in order starting from the one that worked last time, so workers
+    /// switch to a standby once it takes over. Calls are not retried after they were sent.
     async fn meta_store_call(&self, m: NetworkMessage) -> Result<NetworkMessage, CubeError> {
-        let meta_remote_addr = self
+        let addresses = self
             .config
             .metastore_remote_address()
             .as_ref()
             .expect("Meta store remote addr is not defined")
-            .to_string();
-        let mut stream = tokio::time::timeout(
-            Duration::from_secs(self.config.connection_timeout()),
-            TcpStream::connect(&meta_remote_addr),
-        )
-        .await?
-        .map_err(|e| {
-            CubeError::internal(format!("Can't connect to {}: {}", meta_remote_addr, e))
-        })?;
-        m.send(&mut stream).await?;
-        let message = NetworkMessage::receive(&mut stream).await?;
-        Ok(message)
+            .split(',')
+            .map(|a| a.trim())
+            .filter(|a| !a.is_empty())
+            .collect::<Vec<_>>();
+        let current = self.current_address.load(Ordering::Relaxed);
+        let mut errors = Vec::new();
+        for i in 0..addresses.len() {
+            let index = (current + i) % addresses.len();
+            let mut stream = match NodeStream::connect(
+                self.config.as_ref(),
+                &self.tls,
+                &self.versions,
+                addresses[index],
+            )
+            .await
+            {
+                Ok(s) => s,
+                Err(e) => {
+                    errors.push(e.message);
+                    continue;
+                }
+            };
+            if index != current {
+                log::info!("Switching to metastore at {}", addresses[index]);
+                self.current_address.store(index, Ordering::Relaxed);
+            }
+            if !stream.maybe_send(&m).await? {
+                return Err(CubeError::internal("connection closed".to_string()));
+            }
+            return match stream.maybe_receive().await? {
+                Some(message) => Ok(message),
+                None => Err(CubeError::internal("connection closed".to_string())),
+            };
+        }
+        Err(CubeError::internal(errors.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::message::PROTOCOL_VERSION;
+    use crate::config::Config;
+    use tokio::net::TcpListener;
+
+    async fn serve(listener: TcpListener, connections: usize) -> Result<(), CubeError> {
+        for _ in 0..connections {
+            let (mut socket, _) = listener.accept().await?;
+            NetworkMessage::server_handshake(&mut socket, "client").await?;
+            NetworkMessage::receive(&mut socket).await?;
+            NetworkMessage::NotifyJobListenersSuccess
+                .send(&mut socket)
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipelined_handshake() {
+        let config = Config::test("pipelined_handshake").config_obj();
+        let tls = TlsSettings::from_config(config.as_ref()).unwrap();
+        let versions = Arc::new(PeerVersions::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(serve(listener, 3));
+
+        // The version is negotiated on the first connection and then sent along with requests.
+        for known_version in [None, Some(PROTOCOL_VERSION)].iter() {
+            let mut stream = NodeStream::connect(config.as_ref(), &tls, &versions, &address)
+                .await
+                .unwrap();
+            assert_eq!(stream.unconfirmed_version, *known_version);
+            assert!(stream
+                .maybe_send(&NetworkMessage::NotifyJobListeners)
+                .await
+                .unwrap());
+            match stream.maybe_receive().await.unwrap() {
+                Some(NetworkMessage::NotifyJobListenersSuccess) => {}
+                m => panic!("unexpected message: {:?}", m),
+            }
+        }
+
+        // Nodes restarted with another version are negotiated with again.
+        versions
+            .0
+            .lock()
+            .unwrap()
+            .insert(address.clone(), PROTOCOL_VERSION + 1);
+        let mut stream = NodeStream::connect(config.as_ref(), &tls, &versions, &address)
+            .await
+            .unwrap();
+        assert!(stream
+            .maybe_send(&NetworkMessage::NotifyJobListeners)
+            .await
+            .unwrap());
+        assert!(stream.maybe_receive().await.is_err());
+        assert!(versions.0.lock().unwrap().get(&address).is_none());
+
+        server.await.unwrap().unwrap();
     }
 }
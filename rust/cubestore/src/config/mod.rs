@@ -2,6 +2,8 @@
 pub mod injection;
 pub mod processing_loop;
 
+use crate::cluster::membership::ClusterMembership;
+use crate::cluster::message::Compression;
 use crate::cluster::transport::{
     ClusterTransport, ClusterTransportImpl, MetaStoreTransport, MetaStoreTransportImpl,
 };
@@ -15,9 +17,12 @@ use crate::metastore::{MetaStore, MetaStoreRpcClient, RocksMetaStore};
 use crate::mysql::{MySqlServer, SqlAuthDefaultImpl, SqlAuthService};
 use crate::queryplanner::query_executor::{QueryExecutor, QueryExecutorImpl};
 use crate::queryplanner::{QueryPlanner, QueryPlannerImpl};
+use crate::remotefs::azure::AzureBlobRemoteFs;
+use crate::remotefs::encrypted::{EncryptedRemoteFs, MasterKeys};
 use crate::remotefs::gcs::GCSRemoteFs;
 use crate::remotefs::queue::QueueRemoteFs;
 use crate::remotefs::s3::S3RemoteFs;
+use crate::remotefs::s3_compatible::S3CompatibleRemoteFs;
 use crate::remotefs::{LocalDirRemoteFs, RemoteFs};
 use crate::scheduler::SchedulerImpl;
 use crate::sql::{SqlService, SqlServiceImpl};
@@ -25,7 +30,9 @@ use crate::store::compaction::{CompactionService, CompactionServiceImpl};
 use crate::store::{ChunkDataStore, ChunkStore, WALDataStore, WALStore};
 use crate::streaming::{StreamingService, StreamingServiceImpl};
 use crate::telemetry::{start_track_event_loop, stop_track_event_loop};
+use crate::util::tls::TlsSettings;
 use crate::CubeError;
+use chrono::{DateTime, Utc};
 use datafusion::cube_ext;
 use futures::future::join_all;
 use log::Level;
@@ -108,6 +115,22 @@ impl CubeServices {
             let scheduler = self.scheduler.clone();
             futures.extend(SchedulerImpl::spawn_processing_loops(scheduler));
 
+            let config = self.injector.get_service_typed::<dyn ConfigObj>().await;
+            if config.encryption_rewrap()
+                && (config.encryption_keys().is_some() || config.encryption_keys_file().is_some())
+            {
+                let remote_fs = self
+                    .injector
+                    .get_service::<EncryptedRemoteFs>("original_remote_fs")
+                    .await;
+                futures.push(cube_ext::spawn(async move {
+                    if let Err(e) = remote_fs.rewrap_remote_files().await {
+                        error!("Error while rewrapping remote files: {}", e);
+                    }
+                    Ok(())
+                }));
+            }
+
             if self.injector.has_service_typed::<MySqlServer>().await {
                 let mysql_server = self.injector.get_service_typed::<MySqlServer>().await;
                 futures.push(cube_ext::spawn(async move {
@@ -212,6 +235,7 @@ pub fn validate_config(c: &dyn ConfigObj) -> ValidationMessages {
         "CUBESTORE_PORT",
         "CUBESTORE_META_BIND_ADDR",
         "CUBESTORE_META_PORT",
+        "CUBESTORE_META_STANDBY_OF",
     ];
     router_vars.retain(|v| env::var(v).is_ok());
     if !is_router(c) && !router_vars.is_empty() {
@@ -225,6 +249,7 @@ pub fn validate_config(c: &dyn ConfigObj) -> ValidationMessages {
     let mut remote_vars = vec![
         "CUBESTORE_S3_BUCKET",
         "CUBESTORE_GCS_BUCKET",
+        "CUBESTORE_AZURE_CONTAINER",
         "CUBESTORE_REMOTE_DIR",
     ];
     remote_vars.retain(|v| env::var(v).is_ok());
@@ -250,10 +275,26 @@ pub enum FileStoreProvider {
         bucket_name: String,
         sub_path: Option<String>,
     },
+    /// S3-compatible store on a custom endpoint, e.g. MinIO.
+    S3Compatible {
+        endpoint: String,
+        region: String,
+        bucket_name: String,
+        sub_path: Option<String>,
+        path_style: bool,
+        verify_tls: bool,
+    },
     GCS {
         bucket_name: String,
         sub_path: Option<String>,
     },
+    Azure {
+        account: String,
+        container: String,
+        /// Defaults to `https://<account>.blob.core.windows.net`.
+        endpoint: Option<String>,
+        sub_path: Option<String>,
+    },
 }
 
 #[derive(Clone)]
@@ -319,6 +360,56 @@ pub trait ConfigObj: DIService {
     fn malloc_trim_every_secs(&self) -> u64;
 
     fn max_cached_queries(&self) -> usize;
+
+    fn broadcast_join_max_rows(&self) -> u64;
+
+    fn metastore_restore_to(&self) -> Option<DateTime<Utc>>;
+
+    fn metastore_snapshots_retention_secs(&self) -> u64;
+
+    fn remote_gc_interval_secs(&self) -> u64;
+
+    fn remote_gc_grace_period_secs(&self) -> u64;
+
+    fn remote_gc_dry_run(&self) -> bool;
+
+    fn local_files_cache_size(&self) -> u64;
+
+    fn encryption_keys(&self) -> &Option<String>;
+
+    fn encryption_keys_file(&self) -> &Option<PathBuf>;
+
+    fn encryption_rewrap(&self) -> bool;
+
+    fn metastore_standby_of(&self) -> &Option<String>;
+
+    fn metastore_failover_timeout_secs(&self) -> u64;
+
+    fn metastore_failover_probes(&self) -> u64;
+
+    fn worker_heart_beat_interval_secs(&self) -> u64;
+
+    fn worker_heart_beat_timeout_secs(&self) -> u64;
+
+    fn partition_replication_factor(&self) -> usize;
+
+    fn select_replica_timeout_secs(&self) -> u64;
+
+    fn select_hedge_delay_ms(&self) -> u64;
+
+    fn tls_cert_file(&self) -> &Option<PathBuf>;
+
+    fn tls_key_file(&self) -> &Option<PathBuf>;
+
+    fn tls_ca_file(&self) -> &Option<PathBuf>;
+
+    fn cluster_tls(&self) -> bool;
+
+    fn cluster_tls_mutual(&self) -> bool;
+
+    fn client_tls(&self) -> bool;
+
+    fn cluster_compression(&self) -> Compression;
 }
 
 #[derive(Debug, Clone)]
@@ -353,6 +444,65 @@ pub struct ConfigObjImpl {
     pub enable_startup_warmup: bool,
     pub malloc_trim_every_secs: u64,
     pub max_cached_queries: usize,
+    /// Join inputs with at most this many rows are sent to every worker that processes the other
+    /// side of the join.
+    pub broadcast_join_max_rows: u64,
+    /// When set, the metastore is restored to its state at this time from remote snapshots on
+    /// startup, discarding the local copy.
+    pub metastore_restore_to: Option<DateTime<Utc>>,
+    /// Metastore snapshots are kept for this long after being superseded by a newer one. Data
+    /// files that are no longer used are kept at least as long, so restored snapshots can read
+    /// them.
+    pub metastore_snapshots_retention_secs: u64,
+    /// How often to look for files in the remote fs that are not referenced by the metastore.
+    /// Zero disables the search.
+    pub remote_gc_interval_secs: u64,
+    /// Unreferenced files modified more recently than this are left alone, they may belong to
+    /// operations that are still in progress.
+    pub remote_gc_grace_period_secs: u64,
+    /// Only report unreferenced files instead of removing them.
+    pub remote_gc_dry_run: bool,
+    /// Maximum total size in bytes of Parquet files kept in the local directory. Least recently
+    /// used files are removed when it is exceeded. Zero means no limit.
+    pub local_files_cache_size: u64,
+    /// Master keys to encrypt files uploaded to the remote fs, see `MasterKeys::parse` for the
+    /// format. Takes precedence over `encryption_keys_file`.
+    pub encryption_keys: Option<String>,
+    pub encryption_keys_file: Option<PathBuf>,
+    /// Rewrite remote files that are in plain text or encrypted with old keys on router start.
+    pub encryption_rewrap: bool,
+    /// Metastore address of the router this node replaces when it fails. The node does not
+    /// serve anything until then.
+    pub metastore_standby_of: Option<String>,
+    pub metastore_failover_timeout_secs: u64,
+    /// Number of consecutive failed connection attempts to the leader, in addition to the
+    /// failover timeout, before the standby takes over.
+    pub metastore_failover_probes: u64,
+    /// Workers that did not send a heart beat for the timeout are removed from the cluster and
+    /// their partitions are assigned to others.
+    pub worker_heart_beat_interval_secs: u64,
+    pub worker_heart_beat_timeout_secs: u64,
+    /// Number of workers that keep each partition warm and can serve selects on it.
+    pub partition_replication_factor: usize,
+    /// Slow selects are retried on the next replica after the timeout, so it should be longer
+    /// than the longest select. Zero disables retries of slow selects.
+    pub select_replica_timeout_secs: u64,
+    /// Zero disables hedged selects.
+    pub select_hedge_delay_ms: u64,
+    /// PEM files with the certificate chain and the private key of this node.
+    pub tls_cert_file: Option<PathBuf>,
+    pub tls_key_file: Option<PathBuf>,
+    /// PEM file with the CA certificates that sign certificates of other nodes.
+    pub tls_ca_file: Option<PathBuf>,
+    /// Encrypt connections to the worker and metastore ports.
+    pub cluster_tls: bool,
+    /// Also require nodes to present their certificates when connecting to each other.
+    pub cluster_tls_mutual: bool,
+    /// Encrypt connections to the HTTP port. MySQL connections are not encrypted: the MySQL server
+    /// can't upgrade connections to TLS during the handshake.
+    pub client_tls: bool,
+    /// Compression of record batches sent between nodes.
+    pub cluster_compression: Compression,
 }
 
 crate::di_service!(ConfigObjImpl, [ConfigObj]);
@@ -468,6 +618,81 @@ impl ConfigObj for ConfigObjImpl {
     fn max_cached_queries(&self) -> usize {
         self.max_cached_queries
     }
+    fn broadcast_join_max_rows(&self) -> u64 {
+        self.broadcast_join_max_rows
+    }
+    fn metastore_restore_to(&self) -> Option<DateTime<Utc>> {
+        self.metastore_restore_to
+    }
+    fn metastore_snapshots_retention_secs(&self) -> u64 {
+        self.metastore_snapshots_retention_secs
+    }
+    fn remote_gc_interval_secs(&self) -> u64 {
+        self.remote_gc_interval_secs
+    }
+    fn remote_gc_grace_period_secs(&self) -> u64 {
+        self.remote_gc_grace_period_secs
+    }
+    fn remote_gc_dry_run(&self) -> bool {
+        self.remote_gc_dry_run
+    }
+    fn local_files_cache_size(&self) -> u64 {
+        self.local_files_cache_size
+    }
+    fn encryption_keys(&self) -> &Option<String> {
+        &self.encryption_keys
+    }
+    fn encryption_keys_file(&self) -> &Option<PathBuf> {
+        &self.encryption_keys_file
+    }
+    fn encryption_rewrap(&self) -> bool {
+        self.encryption_rewrap
+    }
+    fn metastore_standby_of(&self) -> &Option<String> {
+        &self.metastore_standby_of
+    }
+    fn metastore_failover_timeout_secs(&self) -> u64 {
+        self.metastore_failover_timeout_secs
+    }
+    fn metastore_failover_probes(&self) -> u64 {
+        self.metastore_failover_probes
+    }
+    fn worker_heart_beat_interval_secs(&self) -> u64 {
+        self.worker_heart_beat_interval_secs
+    }
+    fn worker_heart_beat_timeout_secs(&self) -> u64 {
+        self.worker_heart_beat_timeout_secs
+    }
+    fn partition_replication_factor(&self) -> usize {
+        self.partition_replication_factor
+    }
+    fn select_replica_timeout_secs(&self) -> u64 {
+        self.select_replica_timeout_secs
+    }
+    fn select_hedge_delay_ms(&self) -> u64 {
+        self.select_hedge_delay_ms
+    }
+    fn tls_cert_file(&self) -> &Option<PathBuf> {
+        &self.tls_cert_file
+    }
+    fn tls_key_file(&self) -> &Option<PathBuf> {
+        &self.tls_key_file
+    }
+    fn tls_ca_file(&self) -> &Option<PathBuf> {
+        &self.tls_ca_file
+    }
+    fn cluster_tls(&self) -> bool {
+        self.cluster_tls
+    }
+    fn cluster_tls_mutual(&self) -> bool {
+        self.cluster_tls_mutual
+    }
+    fn client_tls(&self) -> bool {
+        self.client_tls
+    }
+    fn cluster_compression(&self) -> Compression {
+        self.cluster_compression
+    }
 }
 
 lazy_static! {
@@ -524,7 +749,20 @@ impl Config {
                 compaction_chunks_count_threshold: 4,
                 compaction_chunks_total_size_threshold: 524288,
                 store_provider: {
-                    if let Ok(bucket_name) = env::var("CUBESTORE_S3_BUCKET") {
+                    if let (Ok(bucket_name), Ok(endpoint)) = (
+                        env::var("CUBESTORE_S3_BUCKET"),
+                        env::var("CUBESTORE_S3_ENDPOINT"),
+                    ) {
+                        FileStoreProvider::S3Compatible {
+                            endpoint,
+                            bucket_name,
+                            region: env::var("CUBESTORE_S3_REGION")
+                                .unwrap_or_else(|_| "us-east-1".to_string()),
+                            sub_path: env::var("CUBESTORE_S3_SUB_PATH").ok(),
+                            path_style: env_bool("CUBESTORE_S3_PATH_STYLE", true),
+                            verify_tls: env_bool("CUBESTORE_S3_VERIFY_TLS", true),
+                        }
+                    } else if let Ok(bucket_name) = env::var("CUBESTORE_S3_BUCKET") {
                         FileStoreProvider::S3 {
                             bucket_name,
                             region: env::var("CUBESTORE_S3_REGION").expect(
@@ -537,6 +775,15 @@ impl Config {
                             bucket_name,
                             sub_path: env::var("CUBESTORE_GCS_SUB_PATH").ok(),
                         }
+                    } else if let Ok(container) = env::var("CUBESTORE_AZURE_CONTAINER") {
+                        FileStoreProvider::Azure {
+                            account: env::var("CUBESTORE_AZURE_ACCOUNT").expect(
+                                "CUBESTORE_AZURE_ACCOUNT required when CUBESTORE_AZURE_CONTAINER is set",
+                            ),
+                            container,
+                            endpoint: env::var("CUBESTORE_AZURE_ENDPOINT").ok(),
+                            sub_path: env::var("CUBESTORE_AZURE_SUB_PATH").ok(),
+                        }
                     } else if let Ok(remote_dir) = env::var("CUBESTORE_REMOTE_DIR") {
                         FileStoreProvider::Filesystem {
                             remote_dir: Some(PathBuf::from(remote_dir)),
@@ -586,6 +833,47 @@ impl Config {
                 enable_startup_warmup: env_bool("CUBESTORE_STARTUP_WARMUP", true),
                 malloc_trim_every_secs: env_parse("CUBESTORE_MALLOC_TRIM_EVERY_SECS", 30),
                 max_cached_queries: env_parse("CUBESTORE_MAX_CACHED_QUERIES", 10_000),
+                broadcast_join_max_rows: env_parse("CUBESTORE_BROADCAST_JOIN_MAX_ROWS", 1_000_000),
+                metastore_restore_to: env_optparse("CUBESTORE_METASTORE_RESTORE_TO"),
+                metastore_snapshots_retention_secs: env_parse(
+                    "CUBESTORE_METASTORE_SNAPSHOTS_RETENTION_SECS",
+                    60 * 60,
+                ),
+                remote_gc_interval_secs: env_parse("CUBESTORE_REMOTE_GC_INTERVAL_SECS", 60 * 60),
+                remote_gc_grace_period_secs: env_parse(
+                    "CUBESTORE_REMOTE_GC_GRACE_PERIOD_SECS",
+                    24 * 60 * 60,
+                ),
+                remote_gc_dry_run: env_bool("CUBESTORE_REMOTE_GC_DRY_RUN", false),
+                local_files_cache_size: env_parse::<u64>("CUBESTORE_LOCAL_FILES_CACHE_SIZE_MB", 0)
+                    * 1024
+                    * 1024,
+                encryption_keys: env::var("CUBESTORE_ENCRYPTION_KEYS").ok(),
+                encryption_keys_file: env::var("CUBESTORE_ENCRYPTION_KEYS_FILE")
+                    .ok()
+                    .map(PathBuf::from),
+                encryption_rewrap: env_bool("CUBESTORE_ENCRYPTION_REWRAP", false),
+                metastore_standby_of: env::var("CUBESTORE_META_STANDBY_OF").ok(),
+                metastore_failover_timeout_secs: env_parse("CUBESTORE_META_FAILOVER_TIMEOUT", 30),
+                metastore_failover_probes: env_parse("CUBESTORE_META_FAILOVER_PROBES", 5),
+                worker_heart_beat_interval_secs: env_parse(
+                    "CUBESTORE_WORKER_HEARTBEAT_INTERVAL",
+                    5,
+                ),
+                worker_heart_beat_timeout_secs: env_parse("CUBESTORE_WORKER_HEARTBEAT_TIMEOUT", 30),
+                partition_replication_factor: env_parse(
+                    "CUBESTORE_PARTITION_REPLICATION_FACTOR",
+                    1,
+                ),
+                select_replica_timeout_secs: env_parse("CUBESTORE_SELECT_REPLICA_TIMEOUT", 0),
+                select_hedge_delay_ms: env_parse("CUBESTORE_SELECT_HEDGE_DELAY_MS", 0),
+                tls_cert_file: env::var("CUBESTORE_TLS_CERT_FILE").ok().map(PathBuf::from),
+                tls_key_file: env::var("CUBESTORE_TLS_KEY_FILE").ok().map(PathBuf::from),
+                tls_ca_file: env::var("CUBESTORE_TLS_CA_FILE").ok().map(PathBuf::from),
+                cluster_tls: env_bool("CUBESTORE_CLUSTER_TLS", false),
+                cluster_tls_mutual: env_bool("CUBESTORE_CLUSTER_TLS_MUTUAL", false),
+                client_tls: env_bool("CUBESTORE_CLIENT_TLS", false),
+                cluster_compression: env_parse("CUBESTORE_CLUSTER_COMPRESSION", Compression::None),
             }),
         }
     }
@@ -632,6 +920,31 @@ impl Config {
                 enable_startup_warmup: true,
                 malloc_trim_every_secs: 0,
                 max_cached_queries: 10_000,
+                broadcast_join_max_rows: 10,
+                metastore_restore_to: None,
+                metastore_snapshots_retention_secs: 3 * 60,
+                remote_gc_interval_secs: 0,
+                remote_gc_grace_period_secs: 24 * 60 * 60,
+                remote_gc_dry_run: false,
+                local_files_cache_size: 0,
+                encryption_keys: None,
+                encryption_keys_file: None,
+                encryption_rewrap: false,
+                metastore_standby_of: None,
+                metastore_failover_timeout_secs: 30,
+                metastore_failover_probes: 5,
+                worker_heart_beat_interval_secs: 5,
+                worker_heart_beat_timeout_secs: 30,
+                partition_replication_factor: 1,
+                select_replica_timeout_secs: 0,
+                select_hedge_delay_ms: 0,
+                tls_cert_file: None,
+                tls_key_file: None,
+                tls_ca_file: None,
+                cluster_tls: false,
+                cluster_tls_mutual: false,
+                client_tls: false,
+                cluster_compression: Compression::None,
             }),
         }
     }
@@ -738,18 +1051,39 @@ impl Config {
         self.local_dir().join("metastore")
     }
 
-    async fn configure_remote_fs(&self) {
+    async fn configure_remote_fs(&self) -> Result<(), CubeError> {
         let config_obj_to_register = self.config_obj.clone();
         self.injector
             .register_typed::<dyn ConfigObj, _, _, _>(async move |_| config_obj_to_register)
             .await;
 
+        let encryption_keys = MasterKeys::from_config(self.config_obj.as_ref())?;
+        if let (Some(_), FileStoreProvider::Filesystem { remote_dir: None }) =
+            (&encryption_keys, &self.config_obj.store_provider)
+        {
+            return Err(CubeError::user(
+                "Encryption keys are set, but there is no remote storage to encrypt files in"
+                    .to_string(),
+            ));
+        }
+        // With encryption the backend keeps its encrypted copies of files in a separate directory
+        // and the plain text ones are managed by `EncryptedRemoteFs`.
+        let (remote_fs_name, data_dir) = match encryption_keys {
+            Some(_) => (
+                "backend_remote_fs",
+                self.config_obj
+                    .data_dir
+                    .join(EncryptedRemoteFs::STAGING_DIR),
+            ),
+            None => ("original_remote_fs", self.config_obj.data_dir.clone()),
+        };
+
         match &self.config_obj.store_provider {
             FileStoreProvider::Filesystem { remote_dir } => {
                 let remote_dir = remote_dir.clone();
-                let data_dir = self.config_obj.data_dir.clone();
+                let data_dir = data_dir.clone();
                 self.injector
-                    .register("original_remote_fs", async move |_| {
+                    .register(remote_fs_name, async move |_| {
                         let arc: Arc<dyn DIService> = LocalDirRemoteFs::new(remote_dir, data_dir);
                         arc
                     })
@@ -760,39 +1094,106 @@ impl Config {
                 bucket_name,
                 sub_path,
             } => {
-                let data_dir = self.config_obj.data_dir.clone();
+                let data_dir = data_dir.clone();
                 let region = region.to_string();
                 let bucket_name = bucket_name.to_string();
                 let sub_path = sub_path.clone();
                 self.injector
-                    .register("original_remote_fs", async move |_| {
+                    .register(remote_fs_name, async move |_| {
                         let arc: Arc<dyn DIService> =
                             S3RemoteFs::new(data_dir, region, bucket_name, sub_path).unwrap();
                         arc
                     })
                     .await;
             }
+            FileStoreProvider::S3Compatible {
+                endpoint,
+                region,
+                bucket_name,
+                sub_path,
+                path_style,
+                verify_tls,
+            } => {
+                let data_dir = data_dir.clone();
+                let endpoint = endpoint.to_string();
+                let region = region.to_string();
+                let bucket_name = bucket_name.to_string();
+                let sub_path = sub_path.clone();
+                let path_style = *path_style;
+                let verify_tls = *verify_tls;
+                self.injector
+                    .register(remote_fs_name, async move |_| {
+                        let arc: Arc<dyn DIService> = S3CompatibleRemoteFs::new(
+                            data_dir,
+                            endpoint,
+                            region,
+                            bucket_name,
+                            sub_path,
+                            path_style,
+                            verify_tls,
+                        )
+                        .unwrap();
+                        arc
+                    })
+                    .await;
+            }
             FileStoreProvider::GCS {
                 bucket_name,
                 sub_path,
             } => {
-                let data_dir = self.config_obj.data_dir.clone();
+                let data_dir = data_dir.clone();
                 let bucket_name = bucket_name.to_string();
                 let sub_path = sub_path.clone();
                 self.injector
-                    .register("original_remote_fs", async move |_| {
+                    .register(remote_fs_name, async move |_| {
                         let arc: Arc<dyn DIService> =
                             GCSRemoteFs::new(data_dir, bucket_name, sub_path).unwrap();
                         arc
                     })
                     .await;
             }
+            FileStoreProvider::Azure {
+                account,
+                container,
+                endpoint,
+                sub_path,
+            } => {
+                let data_dir = data_dir.clone();
+                let account = account.to_string();
+                let container = container.to_string();
+                let endpoint = endpoint.clone();
+                let sub_path = sub_path.clone();
+                self.injector
+                    .register(remote_fs_name, async move |_| {
+                        let arc: Arc<dyn DIService> = AzureBlobRemoteFs::new(
+                            data_dir, account, container, endpoint, sub_path,
+                        )
+                        .unwrap();
+                        arc
+                    })
+                    .await;
+            }
             FileStoreProvider::Local => unimplemented!(), // TODO
         };
+
+        if let Some(keys) = encryption_keys {
+            let data_dir = self.config_obj.data_dir.clone();
+            self.injector
+                .register("original_remote_fs", async move |i| {
+                    let arc: Arc<dyn DIService> = EncryptedRemoteFs::new(
+                        i.get_service("backend_remote_fs").await,
+                        data_dir,
+                        keys,
+                    );
+                    arc
+                })
+                .await;
+        }
+        Ok(())
     }
 
     async fn remote_fs(&self) -> Result<Arc<dyn RemoteFs + 'static>, CubeError> {
-        self.configure_remote_fs().await;
+        self.configure_remote_fs().await?;
         Ok(self.injector.get_service("original_remote_fs").await)
     }
 
@@ -800,8 +1201,8 @@ impl Config {
         self.injector.clone()
     }
 
-    pub async fn configure_injector(&self) {
-        self.configure_remote_fs().await;
+    pub async fn configure_injector(&self) -> Result<(), CubeError> {
+        self.configure_remote_fs().await?;
 
         self.injector
             .register_typed_with_default::<dyn RemoteFs, QueueRemoteFs, _, _>(async move |i| {
@@ -815,16 +1216,24 @@ impl Config {
         let (event_sender, _) = broadcast::channel(10000); // TODO config
         let event_sender_to_move = event_sender.clone();
 
+        let tls = TlsSettings::from_config(self.config_obj.as_ref())?;
+        self.injector
+            .register_typed::<TlsSettings, _, _, _>(async move |_| tls)
+            .await;
+
         self.injector
             .register_typed::<dyn ClusterTransport, _, _, _>(async move |i| {
-                ClusterTransportImpl::new(i.get_service_typed().await)
+                ClusterTransportImpl::new(i.get_service_typed().await, i.get_service_typed().await)
             })
             .await;
 
         if let Some(_) = self.config_obj.metastore_remote_address() {
             self.injector
                 .register_typed::<dyn MetaStoreTransport, _, _, _>(async move |i| {
-                    MetaStoreTransportImpl::new(i.get_service_typed().await)
+                    MetaStoreTransportImpl::new(
+                        i.get_service_typed().await,
+                        i.get_service_typed().await,
+                    )
                 })
                 .await;
         }
@@ -926,17 +1335,26 @@ impl Config {
             .await;
 
         self.injector
-            .register_typed::<dyn QueryPlanner, _, _, _>(async move |i| {
-                QueryPlannerImpl::new(i.get_service_typed().await, i.get_service_typed().await)
+            .register_typed::<ClusterMembership, _, _, _>(async move |i| {
+                ClusterMembership::new(i.get_service_typed::<dyn ConfigObj>().await.as_ref())
             })
             .await;
 
         self.injector
-            .register_typed::<dyn QueryExecutor, _, _, _>(async move |_| {
-                Arc::new(QueryExecutorImpl)
+            .register_typed::<dyn QueryPlanner, _, _, _>(async move |i| {
+                QueryPlannerImpl::new(
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                )
             })
             .await;
 
+        self.injector
+            .register_typed::<dyn QueryExecutor, _, _, _>(async move |_| QueryExecutorImpl::new())
+            .await;
+
         let cluster_meta_store_sender = event_sender_to_move.clone();
 
         self.injector
@@ -955,6 +1373,8 @@ impl Config {
                     i.get_service_typed().await,
                     cluster_meta_store_sender,
                     i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
                 )
             })
             .await;
@@ -1013,19 +1433,24 @@ impl Config {
 
             self.injector
                 .register_typed::<HttpServer, _, _, _>(async move |i| {
+                    let c = i.get_service_typed::<dyn ConfigObj>().await;
                     HttpServer::new(
-                        i.get_service_typed::<dyn ConfigObj>()
-                            .await
-                            .http_bind_address()
-                            .as_ref()
-                            .unwrap()
-                            .to_string(),
+                        c.http_bind_address().as_ref().unwrap().to_string(),
                         i.get_service_typed().await,
                         i.get_service_typed().await,
+                        if c.client_tls() {
+                            Some((
+                                c.tls_cert_file().clone().unwrap(),
+                                c.tls_key_file().clone().unwrap(),
+                            ))
+                        } else {
+                            None
+                        },
                     )
                 })
                 .await;
         }
+        Ok(())
     }
 
     pub async fn cube_services(&self) -> CubeServices {
@@ -1045,14 +1470,14 @@ impl Config {
     }
 
     pub async fn configure(&self) -> CubeServices {
-        self.configure_injector().await;
+        self.configure_injector().await.unwrap();
         self.cube_services().await
     }
 
     pub fn configure_worker_services() {
         let mut services = WORKER_SERVICES.write().unwrap();
         *services = Some(WorkerServices {
-            query_executor: Arc::new(QueryExecutorImpl),
+            query_executor: QueryExecutorImpl::new(),
         })
     }
 
@@ -26,7 +26,10 @@ use log::trace;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
 use tempfile::NamedTempFile;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
@@ -38,6 +41,8 @@ pub struct HttpServer {
     bind_address: String,
     sql_service: Arc<dyn SqlService>,
     auth: Arc<dyn SqlAuthService>,
+    /// Certificate and private key files, serves plain HTTP if not set.
+    tls: Option<(PathBuf, PathBuf)>,
     worker_loop: WorkerLoop,
     cancel_token: CancellationToken,
 }
@@ -68,11 +73,13 @@ impl HttpServer {
         bind_address: String,
         auth: Arc<dyn SqlAuthService>,
         sql_service: Arc<dyn SqlService>,
+        tls: Option<(PathBuf, PathBuf)>,
     ) -> Arc<Self> {
         Arc::new(Self {
             bind_address,
             auth,
             sql_service,
+            tls,
             worker_loop: WorkerLoop::new("HttpServer message processing"),
             cancel_token: CancellationToken::new(),
         })
@@ -224,8 +231,9 @@ impl HttpServer {
             },
         );
         let cancel_token = self.cancel_token.clone();
-        let (_, server_future) = warp::serve(query_route.or(upload_route).recover(
-            |err: Rejection| async move {
+        let routes = query_route
+            .or(upload_route)
+            .recover(|err: Rejection| async move {
                 let mut obj = HashMap::new();
                 if let Some(ws_error) = err.find::<CubeRejection>() {
                     match ws_error {
@@ -247,9 +255,23 @@ impl HttpServer {
                 } else {
                     Err(err)
                 }
-            },
-        ))
-        .bind_with_graceful_shutdown(addr, async move { cancel_token.cancelled().await });
+            });
+        let shutdown = async move { cancel_token.cancelled().await };
+        let server_future: Pin<Box<dyn Future<Output = ()> + Send>> = match &self.tls {
+            Some((cert_file, key_file)) => Box::pin(
+                warp::serve(routes)
+                    .tls()
+                    .cert_path(cert_file)
+                    .key_path(key_file)
+                    .bind_with_graceful_shutdown(addr, shutdown)
+                    .1,
+            ),
+            None => Box::pin(
+                warp::serve(routes)
+                    .bind_with_graceful_shutdown(addr, shutdown)
+                    .1,
+            ),
+        };
         let _ = tokio::join!(process_loop, server_future);
 
         Ok(())
@@ -29,7 +29,7 @@ use crate::config::injection::DIService;
 use crate::config::ConfigObj;
 use crate::import::limits::ConcurrencyLimits;
 use crate::metastore::table::Table;
-use crate::metastore::{is_valid_plain_binary_hll, HllFlavour, IdRow};
+use crate::metastore::{is_valid_plain_binary_hll, is_valid_tdigest, HllFlavour, IdRow};
 use crate::metastore::{Column, ColumnType, ImportFormat, MetaStore};
 use crate::remotefs::RemoteFs;
 use crate::sql::timestamp_from_string;
@@ -144,6 +144,11 @@ impl ImportFormat {
                                         is_valid_plain_binary_hll(&data, *f)?;
                                         TableValue::Bytes(data)
                                     }
+                                    ColumnType::Tdigest => {
+                                        let data = base64::decode(value)?;
+                                        is_valid_tdigest(&data)?;
+                                        TableValue::Bytes(data)
+                                    }
                                     ColumnType::Timestamp => {
                                         TableValue::Timestamp(timestamp_from_string(value)?)
                                     }
@@ -420,12 +425,12 @@ impl ImportServiceImpl {
         Ok(File::open(local_file).await?)
     }
 
-    fn temp_uploads_path(location: &str) -> String {
+    pub fn temp_uploads_path(location: &str) -> String {
         location.replace("temp://", "temp-uploads/")
     }
 
     async fn drop_temp_uploads(&self, location: &str) -> Result<(), CubeError> {
-        // TODO There also should be a process which collects orphaned uploads due to failed imports
+        // Uploads left behind by failed imports are collected by the remote GC in the scheduler.
         if location.starts_with("temp://") {
             self.remote_fs
                 .delete_file(&ImportServiceImpl::temp_uploads_path(location))
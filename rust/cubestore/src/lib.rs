@@ -22,6 +22,7 @@ use crate::metastore::TableId;
 use crate::remotefs::queue::RemoteFsOpResult;
 use arrow::error::ArrowError;
 use cubehll::HllError;
+use cubetdigest::TDigestError;
 use cubezetasketch::ZetaError;
 use flexbuffers::{DeserializationError, ReaderError};
 use log::SetLoggerError;
@@ -386,6 +387,12 @@ impl From<ZetaError> for CubeError {
     }
 }
 
+impl From<TDigestError> for CubeError {
+    fn from(v: TDigestError) -> Self {
+        return CubeError::from_error(v);
+    }
+}
+
 impl From<cloud_storage::Error> for CubeError {
     fn from(v: cloud_storage::Error) -> Self {
         return CubeError::from_error(v);
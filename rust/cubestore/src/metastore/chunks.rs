@@ -2,6 +2,8 @@ use super::{BaseRocksSecondaryIndex, Chunk, IndexId, RocksSecondaryIndex, RocksT
 use crate::base_rocks_secondary_index;
 use crate::metastore::{IdRow, MetaStoreEvent};
 use crate::rocks_table_impl;
+use crate::table::bloom::bloom_filter_file_name;
+use crate::table::stats::ColumnStats;
 use byteorder::{BigEndian, WriteBytesExt};
 use chrono::{DateTime, Utc};
 use rocksdb::DB;
@@ -18,9 +20,27 @@ impl Chunk {
             last_used: None,
             in_memory,
             created_at: Some(Utc::now()),
+            column_stats: None,
+            has_bloom_filter: false,
         }
     }
 
+    pub fn update_column_stats(&self, column_stats: Option<Vec<ColumnStats>>) -> Chunk {
+        let mut to_update = self.clone();
+        to_update.column_stats = column_stats;
+        to_update
+    }
+
+    pub fn get_column_stats(&self) -> &Option<Vec<ColumnStats>> {
+        &self.column_stats
+    }
+
+    pub fn update_has_bloom_filter(&self, has_bloom_filter: bool) -> Chunk {
+        let mut to_update = self.clone();
+        to_update.has_bloom_filter = has_bloom_filter;
+        to_update
+    }
+
     pub fn get_row_count(&self) -> u64 {
         self.row_count
     }
@@ -29,6 +49,15 @@ impl Chunk {
         chunk_file_name(chunk_id)
     }
 
+    /// Name of the file with bloom filters of the chunk, if it has one.
+    pub fn get_bloom_filter_name(&self, chunk_id: u64) -> Option<String> {
+        if self.has_bloom_filter {
+            Some(bloom_filter_file_name(&chunk_file_name(chunk_id)))
+        } else {
+            None
+        }
+    }
+
     pub fn get_partition_id(&self) -> u64 {
         self.partition_id
     }
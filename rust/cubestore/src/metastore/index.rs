@@ -1,5 +1,6 @@
 use super::{
-    BaseRocksSecondaryIndex, Column, Index, IndexId, RocksSecondaryIndex, RocksTable, TableId,
+    AggregateColumnIndex, BaseRocksSecondaryIndex, Column, Index, IndexId, RocksSecondaryIndex,
+    RocksTable, TableId,
 };
 use crate::metastore::{IdRow, MetaStoreEvent};
 use crate::{rocks_table_impl, CubeError};
@@ -30,9 +31,37 @@ impl Index {
             sort_key_size,
             partition_split_key_size,
             multi_index_id,
+            bloom_filter_columns: None,
+            row_group_size: None,
+            aggregate_columns: None,
+            backfilling: false,
         })
     }
 
+    pub fn with_aggregate_columns(mut self, columns: Option<Vec<AggregateColumnIndex>>) -> Index {
+        self.aggregate_columns = columns;
+        self
+    }
+
+    pub fn with_backfilling(mut self, backfilling: bool) -> Index {
+        self.backfilling = backfilling;
+        self
+    }
+
+    pub fn with_row_group_size(mut self, row_group_size: Option<u64>) -> Index {
+        self.row_group_size = row_group_size;
+        self
+    }
+
+    pub fn with_bloom_filter_columns(mut self, columns: Vec<u64>) -> Index {
+        self.bloom_filter_columns = if columns.is_empty() {
+            None
+        } else {
+            Some(columns)
+        };
+        self
+    }
+
     pub fn table_id(&self) -> u64 {
         return self.table_id;
     }
@@ -61,6 +90,33 @@ impl Index {
     pub fn multi_index_id(&self) -> Option<u64> {
         self.multi_index_id
     }
+
+    pub fn bloom_filter_columns(&self) -> &[u64] {
+        self.bloom_filter_columns
+            .as_ref()
+            .map(|c| c.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn row_group_size(&self) -> Option<u64> {
+        self.row_group_size
+    }
+
+    /// Aggregating indexes keep a single row per sort key, see [AggregateColumnIndex].
+    pub fn is_aggregating(&self) -> bool {
+        self.aggregate_columns.is_some()
+    }
+
+    pub fn is_backfilling(&self) -> bool {
+        self.backfilling
+    }
+
+    pub fn aggregate_columns(&self) -> &[AggregateColumnIndex] {
+        self.aggregate_columns
+            .as_ref()
+            .map(|c| c.as_slice())
+            .unwrap_or(&[])
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -11,7 +11,7 @@ pub mod wal;
 
 use async_trait::async_trait;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use log::{error, info};
+use log::{error, info, warn};
 use rocksdb::{
     DBIterator, Direction, IteratorMode, MergeOperands, Options, ReadOptions, Snapshot, WriteBatch,
     WriteBatchIterator, DB,
@@ -22,6 +22,7 @@ use std::{collections::hash_map::DefaultHasher, env, io::Cursor, sync::Arc, time
 use tokio::fs;
 use tokio::sync::{Notify, RwLock};
 
+use crate::cluster::membership::WorkerHeartBeats;
 use crate::config::injection::DIService;
 use crate::config::{Config, ConfigObj};
 use crate::metastore::chunks::{ChunkIndexKey, ChunkRocksIndex};
@@ -37,7 +38,10 @@ use crate::metastore::source::{
 };
 use crate::metastore::table::{TableIndexKey, TablePath};
 use crate::metastore::wal::{WALIndexKey, WALRocksIndex};
-use crate::remotefs::{LocalDirRemoteFs, RemoteFs};
+use crate::remotefs::{LocalDirRemoteFs, RemoteFile, RemoteFs};
+use crate::store::WALStore;
+use crate::table::bloom::bloom_filter_file_name;
+use crate::table::stats::ColumnStats;
 use crate::table::{Row, TableValue};
 use crate::util::lock::acquire_lock;
 use crate::util::time_span::{warn_long, warn_long_fut};
@@ -49,6 +53,7 @@ use chrono::{DateTime, Utc};
 use chunks::ChunkRocksTable;
 use core::{fmt, mem};
 use cubehll::HllSketch;
+use cubetdigest::TDigest;
 use cubezetasketch::HyperLogLogPlusPlus;
 use datafusion::cube_ext;
 use futures::future::join_all;
@@ -72,10 +77,11 @@ use std::mem::take;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Mutex;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use table::Table;
 use table::{TableRocksIndex, TableRocksTable};
 use tokio::fs::File;
+use tokio::net::TcpStream;
 use tokio::sync::broadcast::Sender;
 use wal::WALRocksTable;
 
@@ -290,24 +296,47 @@ impl DataFrameValue<String> for Option<Vec<u64>> {
     }
 }
 
+impl DataFrameValue<String> for Option<Vec<AggregateColumnIndex>> {
+    fn value(v: &Self) -> String {
+        v.as_ref()
+            .map(|v| {
+                format!(
+                    "[{}]",
+                    v.iter()
+                        .map(|a| format!("{}({})", a.function(), a.index()))
+                        .join(", ")
+                )
+            })
+            .unwrap_or("NULL".to_string())
+    }
+}
+
 impl DataFrameValue<String> for Option<Row> {
     fn value(v: &Self) -> String {
         v.as_ref()
             .map(|v| {
                 format!(
                     "({})",
-                    v.values()
-                        .iter()
-                        .map(|tv| match tv {
-                            TableValue::Null => "NULL".to_string(),
-                            TableValue::String(s) => format!("\"{}\"", s),
-                            TableValue::Int(i) => i.to_string(),
-                            TableValue::Timestamp(t) => format!("{:?}", t),
-                            TableValue::Bytes(b) => format!("{:?}", b),
-                            TableValue::Boolean(b) => format!("{:?}", b),
-                            TableValue::Decimal(v) => format!("{}", v.raw_value()),
-                            TableValue::Float(v) => format!("{}", v),
-                        })
+                    v.values().iter().map(table_value_to_string).join(", ")
+                )
+            })
+            .unwrap_or("NULL".to_string())
+    }
+}
+
+impl DataFrameValue<String> for Option<Vec<ColumnStats>> {
+    fn value(v: &Self) -> String {
+        v.as_ref()
+            .map(|v| {
+                format!(
+                    "[{}]",
+                    v.iter()
+                        .map(|s| format!(
+                            "({}, {}, {})",
+                            table_value_to_string(s.min()),
+                            table_value_to_string(s.max()),
+                            s.null_count()
+                        ))
                         .join(", ")
                 )
             })
@@ -315,6 +344,19 @@ impl DataFrameValue<String> for Option<Row> {
     }
 }
 
+fn table_value_to_string(tv: &TableValue) -> String {
+    match tv {
+        TableValue::Null => "NULL".to_string(),
+        TableValue::String(s) => format!("\"{}\"", s),
+        TableValue::Int(i) => i.to_string(),
+        TableValue::Timestamp(t) => format!("{:?}", t),
+        TableValue::Bytes(b) => format!("{:?}", b),
+        TableValue::Boolean(b) => format!("{:?}", b),
+        TableValue::Decimal(v) => format!("{}", v.raw_value()),
+        TableValue::Float(v) => format!("{}", v),
+    }
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
 pub enum HllFlavour {
     Airlift,    // Compatible with Presto, Athena, etc.
@@ -339,12 +381,18 @@ pub fn is_valid_plain_binary_hll(data: &[u8], f: HllFlavour) -> Result<(), CubeE
     return Ok(());
 }
 
+pub fn is_valid_tdigest(data: &[u8]) -> Result<(), CubeError> {
+    TDigest::read(data)?;
+    return Ok(());
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
 pub enum ColumnType {
     String,
     Int,
     Bytes,
     HyperLogLog(HllFlavour), // HLL Sketches, compatible with presto.
+    Tdigest,                 // T-Digest quantile sketches, see `cubetdigest`.
     Timestamp,
     Decimal { scale: i32, precision: i32 },
     Float,
@@ -362,6 +410,7 @@ impl Display for ColumnType {
             ColumnType::HyperLogLog(HllFlavour::ZetaSketch) => "hyperloglogpp",
             ColumnType::HyperLogLog(HllFlavour::Postgres) => "hll_postgres",
             ColumnType::HyperLogLog(HllFlavour::Snowflake) => "hll_snowflake",
+            ColumnType::Tdigest => "tdigest",
             ColumnType::Timestamp => "timestamp",
             ColumnType::Float => "float",
             ColumnType::Boolean => "boolean",
@@ -411,7 +460,9 @@ impl From<&Column> for parquet::schema::types::Type {
                     .build()
                     .unwrap()
             }
-            crate::metastore::ColumnType::Bytes | ColumnType::HyperLogLog(_) => {
+            crate::metastore::ColumnType::Bytes
+            | ColumnType::HyperLogLog(_)
+            | ColumnType::Tdigest => {
                 types::Type::primitive_type_builder(&column.get_name(), Type::BYTE_ARRAY)
                     .with_converted_type(ConvertedType::NONE)
                     .with_repetition(Repetition::OPTIONAL)
@@ -447,6 +498,12 @@ pub struct Column {
     name: String,
     column_type: ColumnType,
     column_index: usize,
+    /// Compression of the column in data files, Parquet defaults are used if not set.
+    #[serde(default)]
+    codec: Option<ColumnCodec>,
+    /// Whether dictionary encoding is used for the column, Parquet defaults are used if not set.
+    #[serde(default)]
+    dictionary: Option<bool>,
 }
 
 impl Into<Field> for Column {
@@ -469,6 +526,7 @@ impl<'a> Into<Field> for &'a Column {
                 }
                 ColumnType::Bytes => DataType::Binary,
                 ColumnType::HyperLogLog(_) => DataType::Binary,
+                ColumnType::Tdigest => DataType::Binary,
                 ColumnType::Float => DataType::Float64,
             },
             true,
@@ -491,12 +549,132 @@ impl fmt::Display for Column {
             ColumnType::HyperLogLog(HllFlavour::ZetaSketch) => "HYPERLOGLOGPP".to_string(),
             ColumnType::HyperLogLog(HllFlavour::Postgres) => "HLL_POSTGRES".to_string(),
             ColumnType::HyperLogLog(HllFlavour::Snowflake) => "HLL_SNOWFLAKE".to_string(),
+            ColumnType::Tdigest => "TDIGEST".to_string(),
             ColumnType::Float => "FLOAT".to_string(),
         };
         f.write_fmt(format_args!("{} {}", self.name, column_type))
     }
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum ColumnCodec {
+    Uncompressed,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl ColumnCodec {
+    pub fn from_name(name: &str) -> Result<ColumnCodec, CubeError> {
+        match name.to_lowercase().as_str() {
+            "none" | "uncompressed" => Ok(ColumnCodec::Uncompressed),
+            "snappy" => Ok(ColumnCodec::Snappy),
+            "lz4" => Ok(ColumnCodec::Lz4),
+            "zstd" => Ok(ColumnCodec::Zstd),
+            _ => Err(CubeError::user(format!(
+                "Unknown codec '{}', expected one of: zstd, snappy, lz4, none",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ColumnCodec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ColumnCodec::Uncompressed => "none",
+            ColumnCodec::Snappy => "snappy",
+            ColumnCodec::Lz4 => "lz4",
+            ColumnCodec::Zstd => "zstd",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Aggregation that merges values of a non-key column in rows of an aggregating index that have
+/// equal sort keys.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum AggregateFunction {
+    Sum,
+    Min,
+    Max,
+    Merge,
+}
+
+impl AggregateFunction {
+    pub fn from_name(name: &str) -> Result<AggregateFunction, CubeError> {
+        match name.to_uppercase().as_str() {
+            "SUM" => Ok(AggregateFunction::Sum),
+            "MIN" => Ok(AggregateFunction::Min),
+            "MAX" => Ok(AggregateFunction::Max),
+            "MERGE" => Ok(AggregateFunction::Merge),
+            _ => Err(CubeError::user(format!(
+                "Unsupported aggregate function '{}' in aggregate index, expected one of: SUM, MIN, MAX, MERGE",
+                name
+            ))),
+        }
+    }
+
+    /// Checks the function can be used to aggregate values of the column in an index.
+    pub fn validate_column_type(&self, column: &Column) -> Result<(), CubeError> {
+        let supported = match (self, column.get_column_type()) {
+            (AggregateFunction::Sum, ColumnType::Int)
+            | (AggregateFunction::Sum, ColumnType::Decimal { .. })
+            | (AggregateFunction::Sum, ColumnType::Float) => true,
+            (AggregateFunction::Min, ColumnType::HyperLogLog(_))
+            | (AggregateFunction::Min, ColumnType::Bytes)
+            | (AggregateFunction::Max, ColumnType::HyperLogLog(_))
+            | (AggregateFunction::Max, ColumnType::Bytes)
+            | (AggregateFunction::Min, ColumnType::Tdigest)
+            | (AggregateFunction::Max, ColumnType::Tdigest) => false,
+            (AggregateFunction::Min, _) | (AggregateFunction::Max, _) => true,
+            (AggregateFunction::Merge, ColumnType::HyperLogLog(_)) => true,
+            _ => false,
+        };
+        if !supported {
+            return Err(CubeError::user(format!(
+                "{} can't be used to aggregate column {}",
+                self, column
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AggregateFunction::Sum => "SUM",
+            AggregateFunction::Min => "MIN",
+            AggregateFunction::Max => "MAX",
+            AggregateFunction::Merge => "MERGE",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Non-key column of an aggregating index and the function used to merge its values.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct AggregateColumnIndex {
+    /// Position of the column in the index.
+    index: u64,
+    function: AggregateFunction,
+}
+
+impl AggregateColumnIndex {
+    pub fn new(index: u64, function: AggregateFunction) -> AggregateColumnIndex {
+        AggregateColumnIndex { index, function }
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn function(&self) -> AggregateFunction {
+        self.function
+    }
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
 pub enum ImportFormat {
     CSV,
@@ -519,7 +697,21 @@ pub struct Index {
     #[serde(default)]
     partition_split_key_size: Option<u64>,
     #[serde(default)]
-    multi_index_id: Option<u64>
+    multi_index_id: Option<u64>,
+    /// Positions of the columns that have bloom filters in every data file of the index.
+    #[serde(default)]
+    bloom_filter_columns: Option<Vec<u64>>,
+    /// Maximum number of rows in row groups of the data files, the default is used if not set.
+    #[serde(default)]
+    row_group_size: Option<u64>,
+    /// Set for aggregating indexes. Rows of these indexes are unique by the sort key and values
+    /// of the remaining columns are aggregated with the specified functions.
+    #[serde(default)]
+    aggregate_columns: Option<Vec<AggregateColumnIndex>>,
+    /// Set while rows that existed before the index was created are added to it. Queries don't
+    /// use the index until it's cleared.
+    #[serde(default)]
+    backfilling: bool
 }
 }
 
@@ -528,6 +720,9 @@ pub struct IndexDef {
     pub name: String,
     pub columns: Vec<String>,
     pub multi_index: Option<String>,
+    /// Aggregate functions and the columns they are applied to, only set for aggregating indexes.
+    #[serde(default)]
+    pub aggregates: Option<Vec<(AggregateFunction, String)>>,
 }
 
 data_frame_from! {
@@ -545,7 +740,13 @@ pub struct Partition {
     main_table_row_count: u64,
     /// Not used or updated anymore.
     #[serde(default)]
-    last_used: Option<DateTime<Utc>>
+    last_used: Option<DateTime<Utc>>,
+    /// Per-column statistics of the main table file in the order of index columns.
+    #[serde(default)]
+    column_stats: Option<Vec<ColumnStats>>,
+    /// Whether bloom filters are stored beside the main table file.
+    #[serde(default)]
+    has_bloom_filter: bool
 }
 }
 
@@ -562,7 +763,13 @@ pub struct Chunk {
     #[serde(default)]
     in_memory: bool,
     #[serde(default)]
-    created_at: Option<DateTime<Utc>>
+    created_at: Option<DateTime<Utc>>,
+    /// Per-column statistics in the order of index columns.
+    #[serde(default)]
+    column_stats: Option<Vec<ColumnStats>>,
+    /// Whether bloom filters are stored beside the chunk file.
+    #[serde(default)]
+    has_bloom_filter: bool
 }
 }
 
@@ -741,6 +948,8 @@ pub trait MetaStore: DIService + Send + Sync {
         indexes: Vec<IndexDef>,
         is_ready: bool,
         unique_key_column_names: Option<Vec<String>>,
+        bloom_filter_column_names: Option<Vec<String>>,
+        row_group_size: Option<u64>,
     ) -> Result<IdRow<Table>, CubeError>;
     async fn table_ready(&self, id: u64, is_ready: bool) -> Result<IdRow<Table>, CubeError>;
     async fn get_table(
@@ -776,12 +985,13 @@ pub trait MetaStore: DIService + Send + Sync {
         partition_id: u64,
         old_chunk_ids: Vec<u64>,
         new_chunk: u64,
+        new_chunk_stats: Vec<ColumnStats>,
     ) -> Result<bool, CubeError>;
     async fn swap_active_partitions(
         &self,
         current_active: Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>,
         new_active: Vec<IdRow<Partition>>,
-        new_active_min_max: Vec<(u64, (Option<Row>, Option<Row>))>,
+        new_active_min_max: Vec<(u64, (Option<Row>, Option<Row>), Vec<ColumnStats>)>,
     ) -> Result<(), CubeError>;
     async fn delete_partition(&self, partition_id: u64) -> Result<IdRow<Partition>, CubeError>;
     async fn mark_partition_warmed_up(&self, partition_id: u64) -> Result<(), CubeError>;
@@ -803,6 +1013,25 @@ pub trait MetaStore: DIService + Send + Sync {
         table_name: String,
         index_def: IndexDef,
     ) -> Result<IdRow<Index>, CubeError>;
+    /// Unlike [MetaStore::create_index], works for tables with data. The index is created in
+    /// backfilling state if the table has data. Returns active partitions and chunks of the default
+    /// index at the moment of creation, their rows must be added to the new index before
+    /// [MetaStore::finish_index_backfill] is called.
+    async fn create_aggregate_index(
+        &self,
+        schema_name: String,
+        table_name: String,
+        index_def: IndexDef,
+    ) -> Result<(IdRow<Index>, Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>), CubeError>;
+    async fn finish_index_backfill(&self, index_id: u64) -> Result<IdRow<Index>, CubeError>;
+    /// Adds bloom filters on the columns to the table and all its indexes. Only files written after
+    /// the change get the filters, older files are scanned as before.
+    async fn add_bloom_filter_columns(
+        &self,
+        schema_name: String,
+        table_name: String,
+        column_names: Vec<String>,
+    ) -> Result<IdRow<Table>, CubeError>;
     async fn get_default_index(&self, table_id: u64) -> Result<IdRow<Index>, CubeError>;
     async fn get_table_indexes(&self, table_id: u64) -> Result<Vec<IdRow<Index>>, CubeError>;
     async fn get_active_partitions_by_index_id(
@@ -868,6 +1097,8 @@ pub trait MetaStore: DIService + Send + Sync {
         partition_id: u64,
         row_count: usize,
         in_memory: bool,
+        column_stats: Option<Vec<ColumnStats>>,
+        has_bloom_filter: bool,
     ) -> Result<IdRow<Chunk>, CubeError>;
     async fn get_chunk(&self, chunk_id: u64) -> Result<IdRow<Chunk>, CubeError>;
     async fn get_chunks_by_partition(
@@ -936,6 +1167,14 @@ pub trait MetaStore: DIService + Send + Sync {
         table_name: Vec<(String, String)>,
     ) -> Result<Vec<(IdRow<Schema>, IdRow<Table>, Vec<IdRow<Index>>)>, CubeError>;
 
+    /// Names of all files in the remote fs that partitions, chunks and WALs may refer to,
+    /// including deactivated ones that are still waiting for garbage collection.
+    async fn get_all_remote_file_names(&self) -> Result<Vec<String>, CubeError>;
+
+    /// Registers a live select worker, returns all live workers.
+    async fn worker_heart_beat(&self, node_name: String) -> Result<Vec<String>, CubeError>;
+    async fn get_live_workers(&self) -> Result<Vec<String>, CubeError>;
+
     async fn debug_dump(&self, out_path: String) -> Result<(), CubeError>;
 }
 
@@ -1098,6 +1337,18 @@ impl MemorySequence {
     }
 }
 
+/// How often a metastore standby checks the leader is alive and downloads its new logs.
+const STANDBY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const STANDBY_SYNC_INTERVAL: Duration = Duration::from_secs(10);
+/// Remote file with the fencing token of the current metastore leader and its server name.
+const LEADER_FILE: &str = "metastore-leader";
+
+struct MetaStoreReplica {
+    snapshot: u128,
+    meta_store: Arc<RocksMetaStore>,
+    last_applied_log: Option<u64>,
+}
+
 #[derive(Clone)]
 pub struct RocksMetaStore {
     pub db: Arc<RwLock<Arc<DB>>>,
@@ -1112,6 +1363,11 @@ pub struct RocksMetaStore {
     upload_loop: Arc<WorkerLoop>,
     config: Arc<dyn ConfigObj>,
     cached_tables: Arc<Mutex<Option<Arc<Vec<TablePath>>>>>,
+    /// Not persisted, workers register again after the router restarts.
+    worker_heart_beats: Arc<Mutex<WorkerHeartBeats>>,
+    /// Fencing token in [LEADER_FILE] when this node became the leader. `None` if it never did,
+    /// uploads are not checked then.
+    leader_token: Arc<RwLock<Option<u64>>>,
 }
 
 trait BaseRocksSecondaryIndex<T>: Debug {
@@ -1701,8 +1957,10 @@ impl RocksMetaStore {
             last_upload_seq: Arc::new(RwLock::new(db_arc.latest_sequence_number())),
             last_check_seq: Arc::new(RwLock::new(db_arc.latest_sequence_number())),
             upload_loop: Arc::new(WorkerLoop::new("Meta Store Upload")),
+            worker_heart_beats: Arc::new(Mutex::new(WorkerHeartBeats::new(config.as_ref()))),
             config,
             cached_tables: Arc::new(Mutex::new(None)),
+            leader_token: Arc::new(RwLock::new(None)),
         };
         meta_store
     }
@@ -1720,6 +1978,31 @@ impl RocksMetaStore {
         remote_fs: Arc<dyn RemoteFs>,
         config: Arc<dyn ConfigObj>,
     ) -> Result<Arc<RocksMetaStore>, CubeError> {
+        let meta_store = Self::load_or_restore_from_remote(path, remote_fs, config.clone()).await?;
+        if config.upload_to_remote() && meta_store.leader_token.read().await.is_none() {
+            meta_store.acquire_leadership().await?;
+        }
+        Ok(meta_store)
+    }
+
+    async fn load_or_restore_from_remote(
+        path: impl AsRef<Path>,
+        remote_fs: Arc<dyn RemoteFs>,
+        config: Arc<dyn ConfigObj>,
+    ) -> Result<Arc<RocksMetaStore>, CubeError> {
+        if let Some(restore_to) = config.metastore_restore_to() {
+            let marker = Self::restore_marker(restore_to);
+            if !remote_fs.list(&marker).await?.iter().any(|f| *f == marker) {
+                return Self::restore_from_remote(path, remote_fs, config, restore_to).await;
+            }
+            info!(
+                "Metastore was already restored to {}, ignoring the restore setting",
+                restore_to
+            );
+        }
+        if let Some(leader_address) = config.metastore_standby_of().clone() {
+            return Self::run_standby(path, remote_fs, config, &leader_address).await;
+        }
         if !fs::metadata(path.as_ref()).await.is_ok() {
             let re = Regex::new(r"^metastore-(\d+)").unwrap();
 
@@ -1803,6 +2086,397 @@ impl RocksMetaStore {
         Ok(Self::new(path, remote_fs, config))
     }
 
+    /// Restores the state at `restore_to` from the newest remote checkpoint taken before it and
+    /// the logs uploaded after that checkpoint, up to `restore_to`. Logs are uploaded once a
+    /// minute, so changes made within a minute before `restore_to` may be missing.
+    /// The existing local metastore is kept next to the restored one.
+    ///
+    /// Every `restore_to` is restored once, a marker in the remote fs makes restarts with the same
+    /// setting load the current metastore instead of discarding changes made after the restore.
+    async fn restore_from_remote(
+        path: impl AsRef<Path>,
+        remote_fs: Arc<dyn RemoteFs>,
+        config: Arc<dyn ConfigObj>,
+        restore_to: DateTime<Utc>,
+    ) -> Result<Arc<RocksMetaStore>, CubeError> {
+        let restore_to_millis = restore_to.timestamp_millis().max(0) as u128;
+        let snapshot = Self::list_remote_snapshots(remote_fs.as_ref())
+            .await?
+            .into_iter()
+            .filter(|s| *s <= restore_to_millis)
+            .max()
+            .ok_or_else(|| {
+                CubeError::user(format!(
+                    "Can't restore metastore to {}: no snapshots were taken before it",
+                    restore_to
+                ))
+            })?;
+        info!(
+            "Restoring metastore to {} from snapshot metastore-{}",
+            restore_to, snapshot
+        );
+
+        Self::move_local_metastore(path.as_ref(), "before-restore").await?;
+        Self::download_checkpoint(remote_fs.as_ref(), snapshot, path.as_ref()).await?;
+        let meta_store = Self::new(path.as_ref(), remote_fs.clone(), config.clone());
+
+        for (_, log_file) in Self::list_remote_logs(remote_fs.as_ref(), snapshot).await? {
+            if *log_file.updated() > restore_to {
+                break;
+            }
+            if !Self::apply_remote_log(&meta_store, remote_fs.as_ref(), log_file.remote_path())
+                .await?
+            {
+                break;
+            }
+        }
+
+        meta_store.take_over_remote(&config).await?;
+        let marker = Self::restore_marker(restore_to);
+        let marker_file = remote_fs.temp_upload_path(&marker).await?;
+        fs::write(&marker_file, restore_to.to_rfc3339()).await?;
+        remote_fs.upload_file(&marker_file, &marker).await?;
+        info!("Restored metastore to {}", restore_to);
+        Ok(meta_store)
+    }
+
+    /// Keeps a replica of the metastore that the leader at `leader_address` uploads to
+    /// `remote_fs` and returns it once the leader stops accepting connections for the failover
+    /// timeout and the configured number of probes in a row. The leader uploads its logs once a
+    /// minute, so changes made within a minute before its failure are lost.
+    /// Taking over increments the fencing token in [LEADER_FILE]: the former leader checks it
+    /// before uploads and stops uploading, it must be restarted as a standby of the new one.
+    async fn run_standby(
+        path: impl AsRef<Path>,
+        remote_fs: Arc<dyn RemoteFs>,
+        config: Arc<dyn ConfigObj>,
+        leader_address: &str,
+    ) -> Result<Arc<RocksMetaStore>, CubeError> {
+        // The local state may be ahead of the uploaded one if this node was the leader before.
+        Self::move_local_metastore(path.as_ref(), "before-standby").await?;
+        let failover_timeout = Duration::from_secs(config.metastore_failover_timeout_secs());
+        let failover_probes = config.metastore_failover_probes();
+        info!("Running as a metastore standby of {}", leader_address);
+
+        let mut replica = None;
+        let mut last_sync = None;
+        let mut leader_seen = Instant::now();
+        let mut failed_probes = 0;
+        loop {
+            if last_sync.map_or(true, |t: Instant| t.elapsed() >= STANDBY_SYNC_INTERVAL) {
+                if let Err(e) =
+                    Self::sync_replica(path.as_ref(), &remote_fs, &config, &mut replica).await
+                {
+                    error!("Error syncing metastore replica: {}", e);
+                }
+                last_sync = Some(Instant::now());
+            }
+            if Self::is_leader_alive(leader_address).await {
+                leader_seen = Instant::now();
+                failed_probes = 0;
+            } else {
+                failed_probes += 1;
+                if failover_timeout <= leader_seen.elapsed() && failover_probes <= failed_probes {
+                    break;
+                }
+            }
+            Delay::new(STANDBY_CHECK_INTERVAL).await;
+        }
+
+        warn!(
+            "Metastore leader {} is not available for {:?} and {} probes, taking over",
+            leader_address, failover_timeout, failed_probes
+        );
+        Self::sync_replica(path.as_ref(), &remote_fs, &config, &mut replica).await?;
+        let meta_store = match replica {
+            Some(r) => r.meta_store,
+            None => Self::new(path.as_ref(), remote_fs.clone(), config.clone()),
+        };
+        meta_store.take_over_remote(&config).await?;
+        info!("Promoted metastore standby to leader");
+        Ok(meta_store)
+    }
+
+    /// Loads the checkpoint `metastore-current` points to, if it changed since the last call, and
+    /// applies the logs uploaded for it that were not applied yet.
+    async fn sync_replica(
+        path: &Path,
+        remote_fs: &Arc<dyn RemoteFs>,
+        config: &Arc<dyn ConfigObj>,
+        replica: &mut Option<MetaStoreReplica>,
+    ) -> Result<(), CubeError> {
+        let snapshot = match Self::read_remote_current_snapshot(remote_fs.as_ref()).await? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        if replica.as_ref().map(|r| r.snapshot) != Some(snapshot) {
+            // Close the outdated replica before replacing its files.
+            *replica = None;
+            if fs::metadata(path).await.is_ok() {
+                fs::remove_dir_all(path).await?;
+            }
+            Self::download_checkpoint(remote_fs.as_ref(), snapshot, path).await?;
+            info!(
+                "Loaded metastore replica from snapshot metastore-{}",
+                snapshot
+            );
+            *replica = Some(MetaStoreReplica {
+                snapshot,
+                meta_store: Self::new(path, remote_fs.clone(), config.clone()),
+                last_applied_log: None,
+            });
+        }
+
+        let replica = replica.as_mut().unwrap();
+        for (seq, log_file) in Self::list_remote_logs(remote_fs.as_ref(), snapshot).await? {
+            if replica.last_applied_log.map_or(false, |l| seq <= l) {
+                continue;
+            }
+            if !Self::apply_remote_log(
+                &replica.meta_store,
+                remote_fs.as_ref(),
+                log_file.remote_path(),
+            )
+            .await?
+            {
+                break;
+            }
+            replica.last_applied_log = Some(seq);
+        }
+        Ok(())
+    }
+
+    async fn is_leader_alive(leader_address: &str) -> bool {
+        match tokio::time::timeout(STANDBY_CHECK_INTERVAL, TcpStream::connect(leader_address)).await
+        {
+            Ok(Ok(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Makes this metastore the one restored by other nodes: the state loaded from remote is not
+    /// uploaded again and a checkpoint is uploaded right away to update `metastore-current`.
+    async fn take_over_remote(&self, config: &Arc<dyn ConfigObj>) -> Result<(), CubeError> {
+        let loaded_seq = acquire_lock("meta store take over", self.db.read())
+            .await?
+            .latest_sequence_number();
+        *self.last_upload_seq.write().await = loaded_seq;
+        *self.last_check_seq.write().await = loaded_seq;
+        if config.upload_to_remote() {
+            self.acquire_leadership().await?;
+            self.upload_check_point().await?;
+        }
+        Ok(())
+    }
+
+    /// Increments the fencing token in [LEADER_FILE], so the previous leader stops uploading.
+    /// The remote fs has no conditional writes, an upload of the previous leader that passed the
+    /// check right before this may still land.
+    async fn acquire_leadership(&self) -> Result<(), CubeError> {
+        let token = Self::read_leader_token(self.remote_fs.as_ref())
+            .await?
+            .map_or(0, |(token, _)| token)
+            + 1;
+        let leader_file = self.remote_fs.temp_upload_path(LEADER_FILE).await?;
+        fs::write(
+            &leader_file,
+            format!("{}\n{}", token, self.config.server_name()),
+        )
+        .await?;
+        self.remote_fs
+            .upload_file(&leader_file, LEADER_FILE)
+            .await?;
+        *self.leader_token.write().await = Some(token);
+        info!("Became metastore leader with fencing token {}", token);
+        Ok(())
+    }
+
+    /// Fails if another node became the leader after this one, so uploads of a leader that was
+    /// taken over don't overwrite the state of the new one.
+    async fn check_leadership(&self) -> Result<(), CubeError> {
+        let token = match *self.leader_token.read().await {
+            Some(token) => token,
+            None => return Ok(()),
+        };
+        match Self::read_leader_token(self.remote_fs.as_ref()).await? {
+            Some((current, _)) if current == token => Ok(()),
+            Some((current, leader)) => Err(CubeError::internal(format!(
+                "Metastore was taken over by {} with fencing token {} (ours is {}), not uploading. \
+                 Restart this node as a standby of the new leader",
+                leader, current, token
+            ))),
+            None => Err(CubeError::internal(format!(
+                "{} is missing, not uploading metastore",
+                LEADER_FILE
+            ))),
+        }
+    }
+
+    /// Fencing token and server name of the current leader.
+    async fn read_leader_token(
+        remote_fs: &dyn RemoteFs,
+    ) -> Result<Option<(u64, String)>, CubeError> {
+        if !remote_fs
+            .list(LEADER_FILE)
+            .await?
+            .iter()
+            .any(|f| f == LEADER_FILE)
+        {
+            return Ok(None);
+        }
+        // Local copy is left from the previous download and may be outdated.
+        let local = remote_fs.local_file(LEADER_FILE).await?;
+        if fs::metadata(&local).await.is_ok() {
+            fs::remove_file(&local).await?;
+        }
+        remote_fs.download_file(LEADER_FILE).await?;
+        let contents = fs::read_to_string(&local).await?;
+        let mut lines = contents.lines();
+        let token = lines
+            .next()
+            .and_then(|t| t.trim().parse::<u64>().ok())
+            .ok_or_else(|| {
+                CubeError::internal(format!("Malformed {}: {}", LEADER_FILE, contents))
+            })?;
+        Ok(Some((token, lines.next().unwrap_or("").to_string())))
+    }
+
+    /// Moves the existing local metastore aside, so it can be inspected later.
+    async fn move_local_metastore(path: &Path, suffix: &str) -> Result<(), CubeError> {
+        if fs::metadata(path).await.is_ok() {
+            let backup = format!(
+                "{}-{}-{}",
+                path.to_string_lossy(),
+                suffix,
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis()
+            );
+            warn!(
+                "Moving existing metastore {} to {}",
+                path.to_string_lossy(),
+                backup
+            );
+            fs::rename(path, &backup).await?;
+        }
+        Ok(())
+    }
+
+    /// Time of the checkpoint `metastore-current` points to.
+    async fn read_remote_current_snapshot(
+        remote_fs: &dyn RemoteFs,
+    ) -> Result<Option<u128>, CubeError> {
+        if remote_fs.list("metastore-current").await?.is_empty() {
+            return Ok(None);
+        }
+        // Local copy is left from the previous download and may be outdated.
+        let local = remote_fs.local_file("metastore-current").await?;
+        if fs::metadata(&local).await.is_ok() {
+            fs::remove_file(&local).await?;
+        }
+        remote_fs.download_file("metastore-current").await?;
+        let current = fs::read_to_string(&local).await?;
+        Ok(Self::snapshot_of_remote_file(current.trim(), false))
+    }
+
+    async fn download_checkpoint(
+        remote_fs: &dyn RemoteFs,
+        snapshot: u128,
+        path: &Path,
+    ) -> Result<(), CubeError> {
+        let checkpoint = format!("metastore-{}", snapshot);
+        fs::create_dir_all(path).await?;
+        for file in remote_fs.list(&checkpoint).await?.iter() {
+            if file.split('/').next() != Some(checkpoint.as_str()) {
+                continue;
+            }
+            remote_fs.download_file(file).await?;
+            let local = remote_fs.local_file(file).await?;
+            let local = Path::new(&local);
+            fs::copy(local, path.join(local.file_name().unwrap())).await?;
+        }
+        Ok(())
+    }
+
+    /// Logs uploaded after the checkpoint `snapshot`, ordered by their first sequence number.
+    async fn list_remote_logs(
+        remote_fs: &dyn RemoteFs,
+        snapshot: u128,
+    ) -> Result<Vec<(u64, RemoteFile)>, CubeError> {
+        let logs_dir = format!("metastore-{}-logs", snapshot);
+        let seq_re = Regex::new(r"/(\d+)\.flex$").unwrap();
+        let mut logs = remote_fs
+            .list_with_metadata(&logs_dir)
+            .await?
+            .into_iter()
+            .filter(|f| f.remote_path().split('/').next() == Some(logs_dir.as_str()))
+            .filter_map(|f| {
+                let seq = seq_re
+                    .captures(f.remote_path())
+                    .and_then(|c| u64::from_str(c.get(1).unwrap().as_str()).ok())?;
+                Some((seq, f))
+            })
+            .collect::<Vec<_>>();
+        logs.sort_by_key(|(seq, _)| *seq);
+        Ok(logs)
+    }
+
+    /// Returns false if the log is corrupted and should not be applied along with the ones
+    /// uploaded after it.
+    async fn apply_remote_log(
+        meta_store: &RocksMetaStore,
+        remote_fs: &dyn RemoteFs,
+        remote_path: &str,
+    ) -> Result<bool, CubeError> {
+        remote_fs.download_file(remote_path).await?;
+        let path_to_log = remote_fs.local_file(remote_path).await?;
+        match WriteBatchContainer::read_from_file(&path_to_log).await {
+            Ok(batch) => {
+                let db = acquire_lock("meta store apply log", meta_store.db.write()).await?;
+                db.write(batch.write_batch())?;
+                Ok(true)
+            }
+            Err(e) => {
+                error!(
+                    "Corrupted metastore WAL file. Discarding: {:?} {}",
+                    remote_path, e
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    /// Remote file that marks the metastore as restored to `restore_to`.
+    fn restore_marker(restore_to: DateTime<Utc>) -> String {
+        format!("metastore-restored-to-{}", restore_to.timestamp_millis())
+    }
+
+    /// Times of checkpoints available in `remote_fs`, in millis since epoch.
+    async fn list_remote_snapshots(remote_fs: &dyn RemoteFs) -> Result<Vec<u128>, CubeError> {
+        let mut snapshots = remote_fs
+            .list("metastore-")
+            .await?
+            .iter()
+            .filter_map(|f| Self::snapshot_of_remote_file(f, false))
+            .collect::<Vec<_>>();
+        snapshots.sort();
+        snapshots.dedup();
+        Ok(snapshots)
+    }
+
+    /// Time of the checkpoint `remote_path` belongs to. Files of its logs are only considered
+    /// when `with_logs` is set.
+    fn snapshot_of_remote_file(remote_path: &str, with_logs: bool) -> Option<u128> {
+        let dir = remote_path.split('/').next()?.strip_prefix("metastore-")?;
+        let dir = match dir.strip_suffix("-logs") {
+            Some(d) if with_logs => d,
+            Some(_) => return None,
+            None => dir,
+        };
+        u128::from_str(dir).ok()
+    }
+
     pub async fn add_listener(&self, listener: Sender<MetaStoreEvent>) {
         self.listeners.write().await.push(listener);
     }
@@ -1881,6 +2555,7 @@ impl RocksMetaStore {
             trace!("Persisting meta store snapshot: nothing to update");
             return Ok(());
         }
+        self.check_leadership().await?;
         let last_upload_seq = self.last_upload_seq().await;
         let (serializer, min, max) = {
             let updates = acquire_lock("meta store upload", self.db.write())
@@ -1933,7 +2608,8 @@ impl RocksMetaStore {
         Ok(())
     }
 
-    async fn upload_check_point(&self) -> Result<(), CubeError> {
+    pub async fn upload_check_point(&self) -> Result<(), CubeError> {
+        self.check_leadership().await?;
         let mut check_point_time = self.last_checkpoint_time.write().await;
         let remote_fs = self.remote_fs.clone();
 
@@ -1945,7 +2621,13 @@ impl RocksMetaStore {
             RocksMetaStore::prepare_checkpoint(db, &check_point_time).await?
         };
 
-        RocksMetaStore::upload_checkpoint(remote_fs, remote_path, checkpoint_path).await?;
+        RocksMetaStore::upload_checkpoint(
+            remote_fs,
+            remote_path,
+            checkpoint_path,
+            Duration::from_secs(self.config.metastore_snapshots_retention_secs()),
+        )
+        .await?;
         self.write_completed_notify.notify_waiters();
         Ok(())
     }
@@ -1962,6 +2644,7 @@ impl RocksMetaStore {
         remote_fs: Arc<dyn RemoteFs>,
         remote_path: String,
         checkpoint_path: PathBuf,
+        snapshots_retention: Duration,
     ) -> Result<(), CubeError> {
         let mut dir = fs::read_dir(checkpoint_path).await?;
 
@@ -1989,26 +2672,26 @@ impl RocksMetaStore {
         }
 
         let existing_metastore_files = remote_fs.list("metastore-").await?;
+        let retained_since = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+            .saturating_sub(snapshots_retention.as_millis());
+        // The newest snapshot taken before the retention period is still needed to restore
+        // points in time at the start of the period.
+        let oldest_retained = existing_metastore_files
+            .iter()
+            .filter_map(|f| RocksMetaStore::snapshot_of_remote_file(f, false))
+            .filter(|s| *s < retained_since)
+            .max();
         let to_delete = existing_metastore_files
             .into_iter()
-            .filter_map(|existing| {
-                let path = existing
-                    .split("/")
-                    .nth(0)
-                    .map(|p| u128::from_str(&p.replace("metastore-", "").replace("-logs", "")));
-                if let Some(Ok(millis)) = path {
-                    if SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis()
-                        - millis
-                        > 3 * 60 * 1000
-                    {
-                        return Some(existing);
-                    }
-                }
-                None
-            })
+            .filter(
+                |existing| match RocksMetaStore::snapshot_of_remote_file(existing, true) {
+                    Some(millis) => millis < retained_since && Some(millis) != oldest_retained,
+                    None => false,
+                },
+            )
             .collect::<Vec<_>>();
         for v in join_all(
             to_delete
@@ -2154,6 +2837,58 @@ impl RocksMetaStore {
 }
 
 impl RocksMetaStore {
+    fn bloom_filter_column_indices(
+        columns: &[Column],
+        column_names: &[String],
+    ) -> Result<Vec<u64>, CubeError> {
+        column_names
+            .iter()
+            .map(|bloom_column| {
+                let column = columns
+                    .iter()
+                    .find(|c| &c.name == bloom_column)
+                    .ok_or_else(|| {
+                        CubeError::user(format!(
+                            "Bloom filter column {} not found among column definitions {:?}",
+                            bloom_column, columns
+                        ))
+                    })?;
+                if let ColumnType::HyperLogLog(_) = column.get_column_type() {
+                    return Err(CubeError::user(format!(
+                        "Bloom filter can't be built for HyperLogLog column {}",
+                        bloom_column
+                    )));
+                }
+                if let ColumnType::Tdigest = column.get_column_type() {
+                    return Err(CubeError::user(format!(
+                        "Bloom filter can't be built for TDigest column {}",
+                        bloom_column
+                    )));
+                }
+                Ok(column.column_index as u64)
+            })
+            .collect()
+    }
+
+    /// Positions of the bloom filter columns of the table among the index columns.
+    fn index_bloom_filter_columns(table: &Table, index_columns: &[Column]) -> Vec<u64> {
+        table
+            .bloom_filter_columns()
+            .map(|columns| {
+                columns
+                    .iter()
+                    .filter_map(|c| {
+                        // Aggregating indexes might not have all the bloom filter columns.
+                        index_columns
+                            .iter()
+                            .position(|ic| ic.get_name() == c.get_name())
+                            .map(|p| p as u64)
+                    })
+                    .collect_vec()
+            })
+            .unwrap_or_default()
+    }
+
     fn add_index(
         batch_pipe: &mut BatchPipe,
         rocks_index: &IndexRocksTable,
@@ -2163,6 +2898,7 @@ impl RocksMetaStore {
         multi_index: Option<&IdRow<MultiIndex>>,
         multi_partitions: &[IdRow<MultiPartition>],
         index_def: IndexDef,
+        backfilling: bool,
     ) -> Result<IdRow<Index>, CubeError> {
         debug_assert_eq!(multi_index.is_some(), !multi_partitions.is_empty());
         if let Some(not_found) = index_def
@@ -2177,6 +2913,21 @@ impl RocksMetaStore {
                 table_id.get_row().get_table_name()
             )));
         }
+        if index_def.aggregates.is_some() {
+            if table_id.get_row().unique_key_columns().is_some() {
+                return Err(CubeError::user(format!(
+                    "Aggregate index '{}' can't be created for table '{}' with unique key",
+                    index_def.name,
+                    table_id.get_row().get_table_name()
+                )));
+            }
+            if multi_index.is_some() {
+                return Err(CubeError::user(format!(
+                    "Aggregate index '{}' can't be added to partitioned index",
+                    index_def.name
+                )));
+            }
+        }
         let unique_key_columns = table_id.get_row().unique_key_columns();
         if let Some(unique_key) = &unique_key_columns {
             if let Some(not_found) = index_def
@@ -2236,15 +2987,48 @@ impl RocksMetaStore {
         }
 
         let sorted_key_size = index_columns.len() as u64;
-        // Put the rest of the columns.
-        for i in 0..table_cols.len() {
-            if taken[i] {
-                continue;
+        let mut aggregate_columns = None;
+        if let Some(aggregates) = index_def.aggregates {
+            // Aggregating indexes only keep the aggregated columns in addition to the sort key.
+            let mut columns = Vec::with_capacity(aggregates.len());
+            for (function, name) in aggregates {
+                let i = table_cols
+                    .iter()
+                    .position(|tc| tc.name == name)
+                    .ok_or_else(|| {
+                        CubeError::user(format!(
+                            "Column '{}' in aggregate index '{}' is not found in table '{}'",
+                            name,
+                            index_def.name,
+                            table_id.get_row().get_table_name()
+                        ))
+                    })?;
+                if taken[i] {
+                    return Err(CubeError::user(format!(
+                        "Column '{}' is used more than once in aggregate index '{}'",
+                        name, index_def.name
+                    )));
+                }
+                function.validate_column_type(&table_cols[i])?;
+                taken[i] = true;
+                columns.push(AggregateColumnIndex::new(
+                    index_columns.len() as u64,
+                    function,
+                ));
+                index_columns.push(table_cols[i].clone().replace_index(index_columns.len()));
             }
+            aggregate_columns = Some(columns);
+        } else {
+            // Put the rest of the columns.
+            for i in 0..table_cols.len() {
+                if taken[i] {
+                    continue;
+                }
 
-            index_columns.push(table_cols[i].clone().replace_index(index_columns.len()));
+                index_columns.push(table_cols[i].clone().replace_index(index_columns.len()));
+            }
+            assert_eq!(index_columns.len(), table_cols.len());
         }
-        assert_eq!(index_columns.len(), table_cols.len());
 
         // Validate the columns match types specified in the MultiIndex.
         if let Some(mi) = multi_index {
@@ -2270,6 +3054,8 @@ impl RocksMetaStore {
             }
         }
 
+        let bloom_filter_columns =
+            RocksMetaStore::index_bloom_filter_columns(table_id.get_row(), &index_columns);
         let index = Index::try_new(
             index_def.name,
             table_id.get_id(),
@@ -2278,7 +3064,11 @@ impl RocksMetaStore {
             // Seq column shouldn't participate in partition split. Otherwise we can't do shared nothing calculations across partitions.
             table_id.get_row().seq_column().map(|_| sorted_key_size - 1),
             multi_index.map(|i| i.id),
-        )?;
+        )?
+        .with_bloom_filter_columns(bloom_filter_columns)
+        .with_row_group_size(table_id.get_row().row_group_size())
+        .with_aggregate_columns(aggregate_columns)
+        .with_backfilling(backfilling);
         let index_id = rocks_index.insert(index, batch_pipe)?;
         if multi_partitions.is_empty() {
             rocks_partition.insert(Partition::new(index_id.id, None, None, None), batch_pipe)?;
@@ -2523,6 +3313,8 @@ impl MetaStore for RocksMetaStore {
         indexes: Vec<IndexDef>,
         is_ready: bool,
         unique_key_column_names: Option<Vec<String>>,
+        bloom_filter_column_names: Option<Vec<String>>,
+        row_group_size: Option<u64>,
     ) -> Result<IdRow<Table>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             let rocks_table = TableRocksTable::new(db_ref.clone());
@@ -2561,6 +3353,9 @@ impl MetaStore for RocksMetaStore {
             } else {
                 None
             };
+            let bloom_filter_column_indices = bloom_filter_column_names
+                .map(|names| RocksMetaStore::bloom_filter_column_indices(&columns, &names))
+                .transpose()?;
             let table = Table::new(
                 table_name,
                 schema_id.get_id(),
@@ -2570,7 +3365,9 @@ impl MetaStore for RocksMetaStore {
                 is_ready,
                 unique_key_column_indices,
                 seq_column_index,
-            );
+                bloom_filter_column_indices,
+            )
+            .with_row_group_size(row_group_size);
             let table_id = rocks_table.insert(table, batch_pipe)?;
             for index_def in indexes.into_iter() {
                 let multi_index;
@@ -2602,6 +3399,7 @@ impl MetaStore for RocksMetaStore {
                     multi_index.as_ref(),
                     &multi_partitions,
                     index_def,
+                    false,
                 )?;
             }
             let def_index_columns = table_id
@@ -2611,7 +3409,7 @@ impl MetaStore for RocksMetaStore {
                 .unwrap_or(table_columns.clone())
                 .iter()
                 .filter_map(|c| match c.get_column_type() {
-                    ColumnType::Bytes => None,
+                    ColumnType::Bytes | ColumnType::Tdigest => None,
                     _ => {
                         if seq_column_index.is_none()
                             || seq_column_index.is_some()
@@ -2636,7 +3434,9 @@ impl MetaStore for RocksMetaStore {
                     name: "default".to_string(),
                     multi_index: None,
                     columns: def_index_columns,
+                    aggregates: None,
                 },
+                false,
             )?;
 
             Ok(table_id)
@@ -2790,6 +3590,7 @@ impl MetaStore for RocksMetaStore {
         partition_id: u64,
         old_chunk_ids: Vec<u64>,
         new_chunk: u64,
+        new_chunk_stats: Vec<ColumnStats>,
     ) -> Result<bool, CubeError> {
         self.write_operation(move |db, pipe| {
             let p = PartitionRocksTable::new(db.clone()).get_row_or_not_found(partition_id)?;
@@ -2800,6 +3601,11 @@ impl MetaStore for RocksMetaStore {
                     return Ok(false);
                 }
             }
+            ChunkRocksTable::new(db.clone()).update_with_fn(
+                new_chunk,
+                |c| c.update_column_stats(Some(new_chunk_stats)),
+                pipe,
+            )?;
             RocksMetaStore::swap_chunks_impl(old_chunk_ids, vec![new_chunk], db, pipe)?;
             Ok(true)
         })
@@ -2810,7 +3616,7 @@ impl MetaStore for RocksMetaStore {
         &self,
         current_active: Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>,
         new_active: Vec<IdRow<Partition>>,
-        mut new_active_min_max: Vec<(u64, (Option<Row>, Option<Row>))>,
+        mut new_active_min_max: Vec<(u64, (Option<Row>, Option<Row>), Vec<ColumnStats>)>,
     ) -> Result<(), CubeError> {
         trace!(
             "Swapping partitions: deactivating ({}), deactivating chunks ({}), activating ({})",
@@ -2829,8 +3635,9 @@ impl MetaStore for RocksMetaStore {
                 &current_active,
                 &new_active,
                 move |i, p| {
-                    let (rows, (min, max)) = take(&mut new_active_min_max[i]);
+                    let (rows, (min, max), stats) = take(&mut new_active_min_max[i]);
                     p.update_min_max_and_row_count(min, max, rows)
+                        .update_column_stats(Some(stats))
                 },
                 |current_i| {
                     Err(CubeError::internal(format!(
@@ -3101,11 +3908,134 @@ impl MetaStore for RocksMetaStore {
                 None,
                 &[],
                 index_def,
+                false,
             )?)
         })
         .await
     }
 
+    async fn create_aggregate_index(
+        &self,
+        schema_name: String,
+        table_name: String,
+        index_def: IndexDef,
+    ) -> Result<(IdRow<Index>, Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>), CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let rocks_index = IndexRocksTable::new(db_ref.clone());
+            let rocks_partition = PartitionRocksTable::new(db_ref.clone());
+            let rocks_chunk = ChunkRocksTable::new(db_ref.clone());
+            let rocks_table = TableRocksTable::new(db_ref.clone());
+            let rocks_schema = SchemaRocksTable::new(db_ref.clone());
+
+            let table = RocksMetaStore::get_table_by_name(
+                schema_name,
+                table_name,
+                rocks_table,
+                rocks_schema,
+            )?;
+
+            let mut source = Vec::new();
+            if *table.get_row().has_data() {
+                let default_index = get_default_index_impl(db_ref.clone(), table.get_id())?;
+                for p in rocks_partition.get_rows_by_index(
+                    &PartitionIndexKey::ByIndexId(default_index.get_id()),
+                    &PartitionRocksIndex::IndexId,
+                )? {
+                    if !p.get_row().is_active() {
+                        continue;
+                    }
+                    let chunks = Self::chunks_by_partitioned_with_non_repartitioned(
+                        p.get_id(),
+                        &rocks_chunk,
+                        &rocks_partition,
+                    )?;
+                    source.push((p, chunks));
+                }
+            }
+
+            let index = RocksMetaStore::add_index(
+                batch_pipe,
+                &rocks_index,
+                &rocks_partition,
+                table.get_row().get_columns(),
+                &table,
+                None,
+                &[],
+                index_def,
+                !source.is_empty(),
+            )?;
+            Ok((index, source))
+        })
+        .await
+    }
+
+    async fn finish_index_backfill(&self, index_id: u64) -> Result<IdRow<Index>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            IndexRocksTable::new(db_ref).update_with_fn(
+                index_id,
+                |i| i.clone().with_backfilling(false),
+                batch_pipe,
+            )
+        })
+        .await
+    }
+
+    async fn add_bloom_filter_columns(
+        &self,
+        schema_name: String,
+        table_name: String,
+        column_names: Vec<String>,
+    ) -> Result<IdRow<Table>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let rocks_index = IndexRocksTable::new(db_ref.clone());
+            let rocks_table = TableRocksTable::new(db_ref.clone());
+            let rocks_schema = SchemaRocksTable::new(db_ref.clone());
+
+            let table = RocksMetaStore::get_table_by_name(
+                schema_name,
+                table_name,
+                TableRocksTable::new(db_ref.clone()),
+                rocks_schema,
+            )?;
+            let mut columns = table
+                .get_row()
+                .bloom_filter_columns()
+                .map(|columns| columns.iter().map(|c| c.get_index() as u64).collect_vec())
+                .unwrap_or_default();
+            for c in RocksMetaStore::bloom_filter_column_indices(
+                table.get_row().get_columns(),
+                &column_names,
+            )? {
+                if !columns.contains(&c) {
+                    columns.push(c);
+                }
+            }
+
+            let table = rocks_table.update_with_fn(
+                table.get_id(),
+                |t| t.update_bloom_filter_column_indices(columns),
+                batch_pipe,
+            )?;
+            let indexes = rocks_index.get_rows_by_index(
+                &IndexIndexKey::TableId(table.get_id()),
+                &IndexRocksIndex::TableID,
+            )?;
+            for index in indexes {
+                let index_columns = RocksMetaStore::index_bloom_filter_columns(
+                    table.get_row(),
+                    index.get_row().get_columns(),
+                );
+                rocks_index.update_with_fn(
+                    index.get_id(),
+                    |i| i.clone().with_bloom_filter_columns(index_columns),
+                    batch_pipe,
+                )?;
+            }
+            Ok(table)
+        })
+        .await
+    }
+
     async fn get_default_index(&self, table_id: u64) -> Result<IdRow<Index>, CubeError> {
         self.read_operation(move |db_ref| get_default_index_impl(db_ref, table_id))
             .await
@@ -3291,11 +4221,15 @@ impl MetaStore for RocksMetaStore {
         partition_id: u64,
         row_count: usize,
         in_memory: bool,
+        column_stats: Option<Vec<ColumnStats>>,
+        has_bloom_filter: bool,
     ) -> Result<IdRow<Chunk>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             let rocks_chunk = ChunkRocksTable::new(db_ref.clone());
 
-            let chunk = Chunk::new(partition_id, row_count, in_memory);
+            let chunk = Chunk::new(partition_id, row_count, in_memory)
+                .update_column_stats(column_stats)
+                .update_has_bloom_filter(has_bloom_filter);
             let id_row = rocks_chunk.insert(chunk, batch_pipe)?;
 
             Ok(id_row)
@@ -3734,6 +4668,42 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn get_all_remote_file_names(&self) -> Result<Vec<String>, CubeError> {
+        self.read_operation(move |db_ref| {
+            let mut files = Vec::new();
+            for p in PartitionRocksTable::new(db_ref.clone()).all_rows()? {
+                let file_name = partition::partition_file_name(p.get_id());
+                files.push(bloom_filter_file_name(&file_name));
+                files.push(file_name);
+            }
+            for c in ChunkRocksTable::new(db_ref.clone()).all_rows()? {
+                let file_name = chunks::chunk_file_name(c.get_id());
+                files.push(bloom_filter_file_name(&file_name));
+                files.push(file_name);
+            }
+            for w in WALRocksTable::new(db_ref).all_rows()? {
+                files.push(WALStore::wal_remote_path(w.get_id()));
+            }
+            Ok(files)
+        })
+        .await
+    }
+
+    async fn worker_heart_beat(&self, node_name: String) -> Result<Vec<String>, CubeError> {
+        let mut heart_beats = self.worker_heart_beats.lock().unwrap();
+        let now = Instant::now();
+        heart_beats.heart_beat(node_name, now)?;
+        Ok(heart_beats.live_workers(now))
+    }
+
+    async fn get_live_workers(&self) -> Result<Vec<String>, CubeError> {
+        Ok(self
+            .worker_heart_beats
+            .lock()
+            .unwrap()
+            .live_workers(Instant::now()))
+    }
+
     async fn debug_dump(&self, out_path: String) -> Result<(), CubeError> {
         self.read_operation(|db| {
             let mut e =
@@ -4159,7 +5129,7 @@ fn swap_active_partitions_impl(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Config;
+    use crate::config::{Config, FileStoreProvider};
     use crate::remotefs::LocalDirRemoteFs;
     use futures_timer::Delay;
     use std::thread::sleep;
@@ -4447,6 +5417,8 @@ mod tests {
                     vec![],
                     true,
                     None,
+                    None,
+                    None,
                 )
                 .await
                 .unwrap();
@@ -4462,7 +5434,9 @@ mod tests {
                     None,
                     vec![],
                     true,
-                    None
+                    None,
+                    None,
+                    None,
                 )
                 .await
                 .is_err());
@@ -4664,6 +5638,276 @@ mod tests {
             fs::remove_dir_all(config.remote_dir()).unwrap();
         }
     }
+
+    #[tokio::test]
+    async fn restore_to_point_in_time() {
+        let restore_to;
+        {
+            let config = Config::test("restore_to_point_in_time");
+
+            let _ = fs::remove_dir_all(config.local_dir());
+            let _ = fs::remove_dir_all(config.remote_dir());
+
+            let services = config.configure().await;
+            services.start_processing_loops().await.unwrap();
+            let rocks_meta_store = services.rocks_meta_store.as_ref().unwrap();
+            services
+                .meta_store
+                .create_schema("foo".to_string(), false)
+                .await
+                .unwrap();
+            rocks_meta_store.upload_check_point().await.unwrap();
+            services
+                .meta_store
+                .create_schema("bar".to_string(), false)
+                .await
+                .unwrap();
+            rocks_meta_store.run_upload().await.unwrap();
+
+            Delay::new(Duration::from_millis(100)).await;
+            restore_to = Utc::now();
+            Delay::new(Duration::from_millis(100)).await;
+
+            services
+                .meta_store
+                .delete_schema("bar".to_string())
+                .await
+                .unwrap();
+            services
+                .meta_store
+                .create_schema("baz".to_string(), false)
+                .await
+                .unwrap();
+            rocks_meta_store.run_upload().await.unwrap();
+            rocks_meta_store.upload_check_point().await.unwrap();
+            services.stop_processing_loops().await.unwrap();
+
+            Delay::new(Duration::from_millis(1000)).await; // TODO logger init conflict
+        }
+
+        {
+            // The local metastore is still in place, restore must replace it.
+            let config = Config::test("restore_to_point_in_time").update_config(|mut c| {
+                c.metastore_restore_to = Some(restore_to);
+                c
+            });
+
+            let services = config.configure().await;
+            let mut schemas = services
+                .meta_store
+                .get_schemas()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|s| s.get_row().get_name().to_string())
+                .collect::<Vec<_>>();
+            schemas.sort();
+            assert_eq!(schemas, vec!["bar", "foo"]);
+            services.stop_processing_loops().await.unwrap();
+
+            Delay::new(Duration::from_millis(1000)).await; // TODO logger init conflict
+            fs::remove_dir_all(config.local_dir()).unwrap();
+        }
+
+        {
+            // Cold start picks up the restored state, the same restore point is not restored again.
+            let config = Config::test("restore_to_point_in_time").update_config(|mut c| {
+                c.metastore_restore_to = Some(restore_to);
+                c
+            });
+
+            let services = config.configure().await;
+            services
+                .meta_store
+                .get_schema("bar".to_string())
+                .await
+                .unwrap();
+            assert!(services
+                .meta_store
+                .get_schema("baz".to_string())
+                .await
+                .is_err());
+
+            services
+                .meta_store
+                .create_schema("qux".to_string(), false)
+                .await
+                .unwrap();
+            let rocks_meta_store = services.rocks_meta_store.as_ref().unwrap();
+            rocks_meta_store.run_upload().await.unwrap();
+            rocks_meta_store.upload_check_point().await.unwrap();
+            services.stop_processing_loops().await.unwrap();
+
+            Delay::new(Duration::from_millis(1000)).await; // TODO logger init conflict
+            fs::remove_dir_all(config.local_dir()).unwrap();
+        }
+
+        {
+            let config = Config::test("restore_to_point_in_time").update_config(|mut c| {
+                c.metastore_restore_to = Some(restore_to);
+                c
+            });
+
+            let services = config.configure().await;
+            services
+                .meta_store
+                .get_schema("qux".to_string())
+                .await
+                .unwrap();
+
+            fs::remove_dir_all(config.local_dir()).unwrap();
+            fs::remove_dir_all(config.remote_dir()).unwrap();
+        }
+    }
+
+    async fn schema_names(meta_store: &RocksMetaStore) -> Vec<String> {
+        let mut schemas = meta_store
+            .get_schemas()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.get_row().get_name().to_string())
+            .collect::<Vec<_>>();
+        schemas.sort();
+        schemas
+    }
+
+    #[tokio::test]
+    async fn standby_takes_over() {
+        let config = Config::test("standby_takes_over");
+        let standby_config = Config::test("standby_takes_over_standby").update_config(|mut c| {
+            c.store_provider = FileStoreProvider::Filesystem {
+                remote_dir: Some(config.remote_dir().clone()),
+            };
+            // Nothing listens on this port, so the standby promotes itself after a few probes.
+            c.metastore_standby_of = Some("127.0.0.1:1".to_string());
+            c.metastore_failover_timeout_secs = 0;
+            c
+        });
+        let _ = fs::remove_dir_all(config.local_dir());
+        let _ = fs::remove_dir_all(config.remote_dir());
+        let _ = fs::remove_dir_all(standby_config.local_dir());
+
+        let services = config.configure().await;
+        let leader = services.rocks_meta_store.as_ref().unwrap();
+        services
+            .meta_store
+            .create_schema("foo".to_string(), false)
+            .await
+            .unwrap();
+        leader.upload_check_point().await.unwrap();
+        services
+            .meta_store
+            .create_schema("bar".to_string(), false)
+            .await
+            .unwrap();
+        leader.run_upload().await.unwrap();
+
+        {
+            // The replica follows logs uploaded by the leader.
+            let remote_fs: Arc<dyn RemoteFs> = LocalDirRemoteFs::new(
+                Some(config.remote_dir().clone()),
+                standby_config.local_dir().clone(),
+            );
+            let replica_path = standby_config.meta_store_path();
+            let standby_config_obj = standby_config.config_obj();
+            let mut replica = None;
+            RocksMetaStore::sync_replica(
+                &replica_path,
+                &remote_fs,
+                &standby_config_obj,
+                &mut replica,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                schema_names(&replica.as_ref().unwrap().meta_store).await,
+                vec!["bar", "foo"]
+            );
+
+            services
+                .meta_store
+                .create_schema("baz".to_string(), false)
+                .await
+                .unwrap();
+            leader.run_upload().await.unwrap();
+            RocksMetaStore::sync_replica(
+                &replica_path,
+                &remote_fs,
+                &standby_config_obj,
+                &mut replica,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                schema_names(&replica.as_ref().unwrap().meta_store).await,
+                vec!["bar", "baz", "foo"]
+            );
+        }
+        services.stop_processing_loops().await.unwrap();
+        Delay::new(Duration::from_millis(1000)).await; // TODO logger init conflict
+
+        {
+            // The promoted standby accepts writes and becomes the source for other nodes.
+            let standby = standby_config.configure().await;
+            let promoted = standby.rocks_meta_store.as_ref().unwrap();
+            assert_eq!(schema_names(promoted).await, vec!["bar", "baz", "foo"]);
+            standby
+                .meta_store
+                .create_schema("qux".to_string(), false)
+                .await
+                .unwrap();
+            promoted.run_upload().await.unwrap();
+            standby.stop_processing_loops().await.unwrap();
+            Delay::new(Duration::from_millis(1000)).await; // TODO logger init conflict
+        }
+
+        {
+            // The former leader is fenced off by the promoted standby.
+            services
+                .meta_store
+                .create_schema("stale".to_string(), false)
+                .await
+                .unwrap();
+            assert!(leader.run_upload().await.is_err());
+            assert!(leader.upload_check_point().await.is_err());
+        }
+
+        {
+            // Cold start of the former leader picks up the state of the new one.
+            fs::remove_dir_all(config.local_dir()).unwrap();
+            let services = config.configure().await;
+            assert_eq!(
+                schema_names(services.rocks_meta_store.as_ref().unwrap()).await,
+                vec!["bar", "baz", "foo", "qux"]
+            );
+            services.stop_processing_loops().await.unwrap();
+        }
+
+        fs::remove_dir_all(config.local_dir()).unwrap();
+        fs::remove_dir_all(config.remote_dir()).unwrap();
+        fs::remove_dir_all(standby_config.local_dir()).unwrap();
+    }
+
+    #[test]
+    fn snapshot_of_remote_file() {
+        assert_eq!(
+            RocksMetaStore::snapshot_of_remote_file("metastore-123/CURRENT", false),
+            Some(123)
+        );
+        assert_eq!(
+            RocksMetaStore::snapshot_of_remote_file("metastore-123-logs/5.flex", false),
+            None
+        );
+        assert_eq!(
+            RocksMetaStore::snapshot_of_remote_file("metastore-123-logs/5.flex", true),
+            Some(123)
+        );
+        assert_eq!(
+            RocksMetaStore::snapshot_of_remote_file("metastore-current", true),
+            None
+        );
+    }
 }
 
 impl RocksMetaStore {
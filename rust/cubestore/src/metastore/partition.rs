@@ -4,6 +4,8 @@ use super::{
 use crate::base_rocks_secondary_index;
 use crate::metastore::{IdRow, MetaStoreEvent};
 use crate::rocks_table_impl;
+use crate::table::bloom::bloom_filter_file_name;
+use crate::table::stats::ColumnStats;
 use crate::table::Row;
 use byteorder::{BigEndian, WriteBytesExt};
 use rocksdb::DB;
@@ -26,6 +28,8 @@ impl Partition {
             warmed_up: false,
             main_table_row_count: 0,
             last_used: None,
+            column_stats: None,
+            has_bloom_filter: false,
         }
     }
 
@@ -40,6 +44,8 @@ impl Partition {
             warmed_up: false,
             main_table_row_count: 0,
             last_used: None,
+            column_stats: None,
+            has_bloom_filter: false,
         }
     }
     pub fn get_min_val(&self) -> &Option<Row> {
@@ -57,6 +63,14 @@ impl Partition {
         }
     }
 
+    /// Name of the file with bloom filters of the main table file, if it has one.
+    pub fn get_bloom_filter_name(&self, partition_id: u64) -> Option<String> {
+        match self.has_main_table_file() && self.has_bloom_filter {
+            false => None,
+            true => Some(bloom_filter_file_name(&partition_file_name(partition_id))),
+        }
+    }
+
     pub fn has_main_table_file(&self) -> bool {
         self.main_table_row_count != 0
     }
@@ -92,6 +106,24 @@ impl Partition {
         p
     }
 
+    pub fn update_column_stats(&self, column_stats: Option<Vec<ColumnStats>>) -> Partition {
+        let mut p = self.clone();
+        p.column_stats = column_stats;
+        p
+    }
+
+    pub fn update_has_bloom_filter(&self, has_bloom_filter: bool) -> Partition {
+        let mut p = self.clone();
+        p.has_bloom_filter = has_bloom_filter;
+        p
+    }
+
+    /// Statistics of the main table file. `None` for partitions written before statistics were
+    /// introduced and for partitions without main table file.
+    pub fn get_column_stats(&self) -> &Option<Vec<ColumnStats>> {
+        &self.column_stats
+    }
+
     pub fn get_index_id(&self) -> u64 {
         self.index_id
     }
@@ -1,5 +1,6 @@
 use super::{
-    BaseRocksSecondaryIndex, Column, ColumnType, IndexId, RocksSecondaryIndex, RocksTable, TableId,
+    BaseRocksSecondaryIndex, Column, ColumnCodec, ColumnType, IndexId, RocksSecondaryIndex,
+    RocksTable, TableId,
 };
 use crate::base_rocks_secondary_index;
 use crate::data_frame_from;
@@ -31,7 +32,11 @@ pub struct Table {
     #[serde(default)]
     unique_key_column_indices: Option<Vec<u64>>,
     #[serde(default)]
-    seq_column_index: Option<u64>
+    seq_column_index: Option<u64>,
+    #[serde(default)]
+    bloom_filter_column_indices: Option<Vec<u64>>,
+    #[serde(default)]
+    row_group_size: Option<u64>
 }
 }
 
@@ -59,6 +64,7 @@ impl Table {
         is_ready: bool,
         unique_key_column_indices: Option<Vec<u64>>,
         seq_column_index: Option<u64>,
+        bloom_filter_column_indices: Option<Vec<u64>>,
     ) -> Table {
         Table {
             table_name,
@@ -71,8 +77,19 @@ impl Table {
             created_at: Some(Utc::now()),
             unique_key_column_indices,
             seq_column_index,
+            bloom_filter_column_indices,
+            row_group_size: None,
         }
     }
+
+    pub fn with_row_group_size(mut self, row_group_size: Option<u64>) -> Table {
+        self.row_group_size = row_group_size;
+        self
+    }
+
+    pub fn row_group_size(&self) -> Option<u64> {
+        self.row_group_size
+    }
     pub fn get_columns(&self) -> &Vec<Column> {
         &self.columns
     }
@@ -127,6 +144,18 @@ impl Table {
             .map(|indices| indices.iter().map(|i| &self.columns[*i as usize]).collect())
     }
 
+    pub fn bloom_filter_columns(&self) -> Option<Vec<&Column>> {
+        self.bloom_filter_column_indices
+            .as_ref()
+            .map(|indices| indices.iter().map(|i| &self.columns[*i as usize]).collect())
+    }
+
+    pub fn update_bloom_filter_column_indices(&self, indices: Vec<u64>) -> Self {
+        let mut table = self.clone();
+        table.bloom_filter_column_indices = Some(indices);
+        table
+    }
+
     pub fn seq_column(&self) -> Option<&Column> {
         self.seq_column_index
             .as_ref()
@@ -144,8 +173,20 @@ impl Column {
             name,
             column_type,
             column_index,
+            codec: None,
+            dictionary: None,
         }
     }
+
+    pub fn with_codec(mut self, codec: Option<ColumnCodec>) -> Column {
+        self.codec = codec;
+        self
+    }
+
+    pub fn with_dictionary(mut self, dictionary: Option<bool>) -> Column {
+        self.dictionary = dictionary;
+        self
+    }
     pub fn get_name(&self) -> &String {
         &self.name
     }
@@ -158,11 +199,18 @@ impl Column {
         self.column_index
     }
 
+    pub fn get_codec(&self) -> Option<ColumnCodec> {
+        self.codec
+    }
+
+    pub fn get_dictionary(&self) -> Option<bool> {
+        self.dictionary
+    }
+
     pub fn replace_index(&self, column_index: usize) -> Column {
         Column {
-            name: self.name.clone(),
-            column_type: self.column_type.clone(),
             column_index,
+            ..self.clone()
         }
     }
 }
@@ -1,6 +1,10 @@
 use crate::CubeError;
 use cubehll::HllSketch;
 use cubezetasketch::HyperLogLogPlusPlus;
+use std::borrow::Cow;
+
+/// Airlift supports up to 65536 buckets.
+const MAX_AIRLIFT_PRECISION: i32 = 16;
 
 #[derive(Debug)]
 pub enum Hll {
@@ -9,6 +13,20 @@ pub enum Hll {
 }
 
 impl Hll {
+    /// Sketch for the empty set, same as produced by `approx_set` in Presto.
+    pub fn new_airlift() -> Hll {
+        return Hll::Airlift(HllSketch::new(4096).unwrap());
+    }
+
+    /// Sketch for the empty set, same as produced by `HLL_COUNT.INIT` in BigQuery.
+    pub fn new_zeta_sketch() -> Hll {
+        let p = HyperLogLogPlusPlus::DEFAULT_NORMAL_PRECISION;
+        return Hll::ZetaSketch(
+            HyperLogLogPlusPlus::new(p, p + HyperLogLogPlusPlus::DEFAULT_SPARSE_PRECISION_DELTA)
+                .unwrap(),
+        );
+    }
+
     pub fn read(data: &[u8]) -> Result<Hll, CubeError> {
         if data.is_empty() {
             return Err(CubeError::internal(
@@ -32,6 +50,47 @@ impl Hll {
         }
     }
 
+    /// Serializes the sketch in one of the formats understood by other systems:
+    ///  - `airlift` is the binary format of Presto and Athena, see `HllSketch::write`,
+    ///  - `snowflake` is the JSON produced by `HLL_EXPORT` in Snowflake,
+    ///  - `postgres` is the HLL storage spec used by the `hll` extension of Postgres.
+    pub fn export(&self, format: &str) -> Result<Vec<u8>, CubeError> {
+        let h = self.to_airlift()?;
+        match format.to_lowercase().as_str() {
+            "airlift" => return Ok(h.write()),
+            "snowflake" => return Ok(h.write_snowflake().into_bytes()),
+            "postgres" => return Ok(h.write_hll_storage_spec()),
+            _ => {
+                return Err(CubeError::user(format!(
+                    "Unknown HLL export format '{}', expected one of 'airlift', 'snowflake' or 'postgres'",
+                    format
+                )))
+            }
+        }
+    }
+
+    /// ZetaSketch sketches are converted into dense Airlift sketches with the same registers.
+    /// Hash functions differ, so the result can not be merged with Airlift sketches of the same
+    /// values, but it estimates the cardinality just as well.
+    pub fn to_airlift(&self) -> Result<Cow<HllSketch>, CubeError> {
+        match self {
+            Hll::Airlift(h) => return Ok(Cow::Borrowed(h)),
+            Hll::ZetaSketch(h) => {
+                let p = h.precision();
+                if MAX_AIRLIFT_PRECISION < p {
+                    return Err(CubeError::user(format!(
+                        "Cannot convert HyperLogLog++ sketch with precision {}, maximum supported precision is {}",
+                        p, MAX_AIRLIFT_PRECISION
+                    )));
+                }
+                return Ok(Cow::Owned(HllSketch::from_dense_values(
+                    p as u8,
+                    h.normal_registers()?,
+                )?));
+            }
+        }
+    }
+
     pub fn is_compatible(&self, other: &Hll) -> bool {
         match (self, other) {
             (Hll::Airlift(l), Hll::Airlift(r)) => l.index_bit_len() == r.index_bit_len(),
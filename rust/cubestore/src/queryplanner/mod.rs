@@ -1,5 +1,6 @@
 pub mod hll;
 mod optimizations;
+mod parquet_scan;
 mod partition_filter;
 mod planning;
 pub use planning::PlanningMeta;
@@ -13,6 +14,7 @@ mod filter_by_key_range;
 mod now;
 pub mod udfs;
 
+use crate::cluster::membership::ClusterMembership;
 use crate::config::injection::DIService;
 use crate::config::ConfigObj;
 use crate::metastore::multi_index::MultiPartition;
@@ -25,9 +27,10 @@ use crate::queryplanner::serialized_plan::SerializedPlan;
 use crate::queryplanner::topk::ClusterAggregateTopK;
 use crate::queryplanner::udfs::aggregate_udf_by_kind;
 use crate::queryplanner::udfs::{scalar_udf_by_kind, CubeAggregateUDFKind, CubeScalarUDFKind};
-use crate::store::DataFrame;
+use crate::remotefs::RemoteFs;
+use crate::store::{ChunkStore, DataFrame};
 use crate::{app_metrics, metastore, CubeError};
-use arrow::array::StringArray;
+use arrow::array::{BooleanArray, Int64Array, StringArray};
 use arrow::datatypes::Field;
 use arrow::{array::Array, datatypes::Schema, datatypes::SchemaRef};
 use arrow::{datatypes::DataType, record_batch::RecordBatch};
@@ -45,6 +48,7 @@ use datafusion::prelude::ExecutionConfig;
 use datafusion::sql::parser::Statement;
 use datafusion::sql::planner::{ContextProvider, SqlToRel};
 use datafusion::{cube_ext, datasource::TableProvider, prelude::ExecutionContext};
+use futures::{StreamExt, TryStreamExt};
 use log::{debug, trace};
 use mockall::automock;
 use serde_derive::{Deserialize, Serialize};
@@ -67,6 +71,8 @@ crate::di_service!(MockQueryPlanner, [QueryPlanner]);
 pub struct QueryPlannerImpl {
     meta_store: Arc<dyn MetaStore>,
     config: Arc<dyn ConfigObj>,
+    remote_fs: Arc<dyn RemoteFs>,
+    membership: Arc<ClusterMembership>,
 }
 
 crate::di_service!(QueryPlannerImpl, [QueryPlanner]);
@@ -84,6 +90,7 @@ impl QueryPlanner for QueryPlannerImpl {
         let schema_provider = MetaStoreSchemaProvider::new(
             self.meta_store.get_tables_with_path().await?,
             self.meta_store.clone(),
+            self.remote_fs.clone(),
         );
 
         let query_planner = SqlToRel::new(&schema_provider);
@@ -101,6 +108,7 @@ impl QueryPlanner for QueryPlannerImpl {
             .await?;
             let workers = compute_workers(
                 self.config.as_ref(),
+                self.membership.as_ref(),
                 &logical_plan,
                 &meta.multi_part_subtree,
             )?;
@@ -135,8 +143,15 @@ impl QueryPlannerImpl {
     pub fn new(
         meta_store: Arc<dyn MetaStore>,
         config: Arc<dyn ConfigObj>,
+        remote_fs: Arc<dyn RemoteFs>,
+        membership: Arc<ClusterMembership>,
     ) -> Arc<QueryPlannerImpl> {
-        Arc::new(QueryPlannerImpl { meta_store, config })
+        Arc::new(QueryPlannerImpl {
+            meta_store,
+            config,
+            remote_fs,
+            membership,
+        })
     }
 }
 
@@ -153,6 +168,7 @@ struct MetaStoreSchemaProvider {
     _data: Arc<Vec<TablePath>>,
     by_name: HashSet<TableKey>,
     meta_store: Arc<dyn MetaStore>,
+    remote_fs: Arc<dyn RemoteFs>,
 }
 
 /// Points into [MetaStoreSchemaProvider::data], never null.
@@ -183,12 +199,17 @@ impl Hash for TableKey {
 }
 
 impl MetaStoreSchemaProvider {
-    pub fn new(tables: Arc<Vec<TablePath>>, meta_store: Arc<dyn MetaStore>) -> Self {
+    pub fn new(
+        tables: Arc<Vec<TablePath>>,
+        meta_store: Arc<dyn MetaStore>,
+        remote_fs: Arc<dyn RemoteFs>,
+    ) -> Self {
         let by_name = tables.iter().map(|t| TableKey(t)).collect();
         Self {
             _data: tables,
             by_name,
             meta_store,
+            remote_fs,
         }
     }
 }
@@ -212,6 +233,7 @@ impl ContextProvider for MetaStoreSchemaProvider {
                     false,
                     None,
                     None,
+                    None,
                 ),
             ),
             schema: Arc::new(IdRow::new(0, metastore::Schema::new(schema.to_string()))),
@@ -239,12 +261,19 @@ impl ContextProvider for MetaStoreSchemaProvider {
         res.or_else(|| match (schema, table) {
             ("information_schema", "tables") => Some(Arc::new(InfoSchemaTableProvider::new(
                 self.meta_store.clone(),
+                self.remote_fs.clone(),
                 InfoSchemaTable::Tables,
             ))),
             ("information_schema", "schemata") => Some(Arc::new(InfoSchemaTableProvider::new(
                 self.meta_store.clone(),
+                self.remote_fs.clone(),
                 InfoSchemaTable::Schemata,
             ))),
+            ("system", "partitions") => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                self.remote_fs.clone(),
+                InfoSchemaTable::SystemPartitions,
+            ))),
             _ => None,
         })
     }
@@ -257,16 +286,21 @@ impl ContextProvider for MetaStoreSchemaProvider {
             "unix_timestamp" | "UNIX_TIMESTAMP" => CubeScalarUDFKind::UnixTimestamp,
             "date_add" | "DATE_ADD" => CubeScalarUDFKind::DateAdd,
             "date_sub" | "DATE_SUB" => CubeScalarUDFKind::DateSub,
+            "quantile" | "QUANTILE" => CubeScalarUDFKind::TdigestQuantile,
+            "hll_export" | "HLL_EXPORT" => CubeScalarUDFKind::HllExport,
             _ => return None,
         };
         return Some(Arc::new(scalar_udf_by_kind(kind).descriptor()));
     }
 
     fn get_aggregate_meta(&self, name: &str) -> Option<Arc<AggregateUDF>> {
-        // HyperLogLog.
+        // HyperLogLog and TDigest.
         // TODO: case-insensitive names.
         let kind = match name {
             "merge" | "MERGE" => CubeAggregateUDFKind::MergeHll,
+            "percentile_merge" | "PERCENTILE_MERGE" => CubeAggregateUDFKind::PercentileMerge,
+            "hll_init" | "HLL_INIT" => CubeAggregateUDFKind::HllInit,
+            "hll_init_agg" | "HLL_INIT_AGG" => CubeAggregateUDFKind::HllInitAgg,
             _ => return None,
         };
         return Some(Arc::new(aggregate_udf_by_kind(kind).descriptor()));
@@ -277,6 +311,8 @@ impl ContextProvider for MetaStoreSchemaProvider {
 pub enum InfoSchemaTable {
     Tables,
     Schemata,
+    /// Partitions with row counts and sizes of their files on the remote storage.
+    SystemPartitions,
 }
 
 impl InfoSchemaTable {
@@ -291,10 +327,26 @@ impl InfoSchemaTable {
                 DataType::Utf8,
                 false,
             )])),
+            InfoSchemaTable::SystemPartitions => Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Int64, false),
+                Field::new("table_schema", DataType::Utf8, false),
+                Field::new("table_name", DataType::Utf8, false),
+                Field::new("index_name", DataType::Utf8, false),
+                Field::new("active", DataType::Boolean, false),
+                Field::new("main_table_row_count", DataType::Int64, false),
+                Field::new("main_table_size", DataType::Int64, true),
+                Field::new("chunk_count", DataType::Int64, false),
+                Field::new("chunks_row_count", DataType::Int64, false),
+                Field::new("chunks_size", DataType::Int64, false),
+            ])),
         }
     }
 
-    async fn scan(&self, meta_store: Arc<dyn MetaStore>) -> Result<RecordBatch, CubeError> {
+    async fn scan(
+        &self,
+        meta_store: Arc<dyn MetaStore>,
+        remote_fs: Arc<dyn RemoteFs>,
+    ) -> Result<RecordBatch, CubeError> {
         match self {
             InfoSchemaTable::Tables => {
                 let tables = meta_store.get_tables_with_path().await?;
@@ -326,18 +378,152 @@ impl InfoSchemaTable {
                 ))];
                 Ok(RecordBatch::try_new(schema, columns)?)
             }
+            InfoSchemaTable::SystemPartitions => {
+                let tables = meta_store.get_tables_with_path().await?;
+                let tables = tables
+                    .iter()
+                    .map(|t| (t.table.get_id(), t))
+                    .collect::<HashMap<_, _>>();
+                let indexes = meta_store
+                    .index_table()
+                    .all_rows()
+                    .await?
+                    .into_iter()
+                    .map(|i| (i.get_id(), i))
+                    .collect::<HashMap<_, _>>();
+                let mut chunks = HashMap::<u64, Vec<_>>::new();
+                for c in meta_store.chunks_table().all_rows().await? {
+                    if c.get_row().active() {
+                        chunks
+                            .entry(c.get_row().get_partition_id())
+                            .or_default()
+                            .push(c);
+                    }
+                }
+                let partitions = meta_store.partition_table().all_rows().await?;
+                // Sizes are reported only for files of active partitions, files of inactive ones
+                // are about to be deleted.
+                let mut files = Vec::new();
+                for p in partitions.iter().filter(|p| p.get_row().is_active()) {
+                    files.extend(p.get_row().get_full_name(p.get_id()));
+                    for c in chunks.get(&p.get_id()).map(|c| c.as_slice()).unwrap_or(&[]) {
+                        files.push(ChunkStore::chunk_remote_path(c.get_id()));
+                    }
+                }
+                let file_sizes = remote_file_sizes(remote_fs.as_ref(), files).await?;
+
+                let mut ids = Vec::new();
+                let mut table_schemas = Vec::new();
+                let mut table_names = Vec::new();
+                let mut index_names = Vec::new();
+                let mut active = Vec::new();
+                let mut main_table_row_counts = Vec::new();
+                let mut main_table_sizes = Vec::new();
+                let mut chunk_counts = Vec::new();
+                let mut chunks_row_counts = Vec::new();
+                let mut chunks_sizes = Vec::new();
+                for p in partitions {
+                    let index = match indexes.get(&p.get_row().get_index_id()) {
+                        Some(i) => i,
+                        None => continue,
+                    };
+                    let table = match tables.get(&index.get_row().table_id()) {
+                        Some(t) => t,
+                        None => continue,
+                    };
+                    let partition_chunks =
+                        chunks.get(&p.get_id()).map(|c| c.as_slice()).unwrap_or(&[]);
+                    ids.push(p.get_id() as i64);
+                    table_schemas.push(table.schema.get_row().get_name().clone());
+                    table_names.push(table.table.get_row().get_table_name().clone());
+                    index_names.push(index.get_row().get_name().clone());
+                    active.push(p.get_row().is_active());
+                    main_table_row_counts.push(p.get_row().main_table_row_count() as i64);
+                    main_table_sizes.push(
+                        p.get_row()
+                            .get_full_name(p.get_id())
+                            .and_then(|f| file_sizes.get(&f).cloned()),
+                    );
+                    chunk_counts.push(partition_chunks.len() as i64);
+                    chunks_row_counts.push(
+                        partition_chunks
+                            .iter()
+                            .map(|c| c.get_row().get_row_count() as i64)
+                            .sum::<i64>(),
+                    );
+                    chunks_sizes.push(
+                        partition_chunks
+                            .iter()
+                            .filter_map(|c| {
+                                file_sizes.get(&ChunkStore::chunk_remote_path(c.get_id()))
+                            })
+                            .sum::<i64>(),
+                    );
+                }
+
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(Int64Array::from(ids)),
+                    Arc::new(StringArray::from(
+                        table_schemas.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        table_names.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        index_names.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(BooleanArray::from(active)),
+                    Arc::new(Int64Array::from(main_table_row_counts)),
+                    Arc::new(Int64Array::from(main_table_sizes)),
+                    Arc::new(Int64Array::from(chunk_counts)),
+                    Arc::new(Int64Array::from(chunks_row_counts)),
+                    Arc::new(Int64Array::from(chunks_sizes)),
+                ];
+                Ok(RecordBatch::try_new(self.schema(), columns)?)
+            }
         }
     }
 }
 
+/// Looks up sizes of the remote files one by one, so the whole remote storage is not listed to
+/// report a few of its files.
+async fn remote_file_sizes(
+    remote_fs: &dyn RemoteFs,
+    files: Vec<String>,
+) -> Result<HashMap<String, i64>, CubeError> {
+    const CONCURRENCY: usize = 16;
+    let sizes = futures::stream::iter(files.into_iter().map(|f| async move {
+        let size = remote_fs
+            .list_with_metadata(&f)
+            .await?
+            .into_iter()
+            .find(|r| r.remote_path() == f)
+            .map(|r| r.file_size() as i64);
+        Ok::<_, CubeError>(size.map(|size| (f, size)))
+    }))
+    .buffer_unordered(CONCURRENCY)
+    .try_collect::<Vec<_>>()
+    .await?;
+    Ok(sizes.into_iter().flatten().collect())
+}
+
 pub struct InfoSchemaTableProvider {
     meta_store: Arc<dyn MetaStore>,
+    remote_fs: Arc<dyn RemoteFs>,
     table: InfoSchemaTable,
 }
 
 impl InfoSchemaTableProvider {
-    fn new(meta_store: Arc<dyn MetaStore>, table: InfoSchemaTable) -> InfoSchemaTableProvider {
-        InfoSchemaTableProvider { meta_store, table }
+    fn new(
+        meta_store: Arc<dyn MetaStore>,
+        remote_fs: Arc<dyn RemoteFs>,
+        table: InfoSchemaTable,
+    ) -> InfoSchemaTableProvider {
+        InfoSchemaTableProvider {
+            meta_store,
+            remote_fs,
+            table,
+        }
     }
 }
 
@@ -359,6 +545,7 @@ impl TableProvider for InfoSchemaTableProvider {
     ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
         let exec = InfoSchemaTableExec {
             meta_store: self.meta_store.clone(),
+            remote_fs: self.remote_fs.clone(),
             table: self.table.clone(),
             projection: projection.clone(),
             projected_schema: project_schema(&self.schema(), projection.as_deref()),
@@ -390,6 +577,7 @@ fn project_schema(s: &Schema, projection: Option<&[usize]>) -> SchemaRef {
 #[derive(Clone)]
 pub struct InfoSchemaTableExec {
     meta_store: Arc<dyn MetaStore>,
+    remote_fs: Arc<dyn RemoteFs>,
     table: InfoSchemaTable,
     projected_schema: SchemaRef,
     projection: Option<Vec<usize>>,
@@ -430,7 +618,10 @@ impl ExecutionPlan for InfoSchemaTableExec {
         &self,
         partition: usize,
     ) -> Result<SendableRecordBatchStream, DataFusionError> {
-        let batch = self.table.scan(self.meta_store.clone()).await?;
+        let batch = self
+            .table
+            .scan(self.meta_store.clone(), self.remote_fs.clone())
+            .await?;
         let mem_exec =
             MemoryExec::try_new(&vec![vec![batch]], self.schema(), self.projection.clone())?;
         mem_exec.execute(partition).await
@@ -481,11 +672,13 @@ impl TableProvider for CubeTableLogical {
 
 fn compute_workers(
     config: &dyn ConfigObj,
+    membership: &ClusterMembership,
     p: &LogicalPlan,
     tree: &HashMap<u64, MultiPartition>,
 ) -> Result<Vec<String>, CubeError> {
     struct Visitor<'a> {
         config: &'a dyn ConfigObj,
+        membership: &'a ClusterMembership,
         tree: &'a HashMap<u64, MultiPartition>,
         workers: Vec<String>,
     }
@@ -496,19 +689,24 @@ fn compute_workers(
             match plan {
                 LogicalPlan::Extension { node } => {
                     let snapshots;
+                    let join_tree;
                     if let Some(cs) = node.as_any().downcast_ref::<ClusterSendNode>() {
                         snapshots = &cs.snapshots;
+                        join_tree = cs.join_tree.as_ref();
                     } else if let Some(cs) = node.as_any().downcast_ref::<ClusterAggregateTopK>() {
                         snapshots = &cs.snapshots;
+                        join_tree = cs.join_tree.as_ref();
                     } else {
                         return Ok(true);
                     }
                     let workers = ClusterSendExec::distribute_to_workers(
                         self.config,
+                        self.membership,
                         snapshots.as_slice(),
+                        join_tree,
                         self.tree,
                     );
-                    self.workers = workers.into_iter().map(|w| w.0).collect();
+                    self.workers = workers.into_iter().map(|w| w.0[0].clone()).collect();
                     Ok(false)
                 }
                 _ => Ok(true),
@@ -518,6 +716,7 @@ fn compute_workers(
 
     let mut v = Visitor {
         config,
+        membership,
         tree,
         workers: Vec::new(),
     };
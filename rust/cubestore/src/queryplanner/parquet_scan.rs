@@ -0,0 +1,302 @@
+use crate::app_metrics;
+use crate::metastore::{Column, Index};
+use crate::queryplanner::partition_filter::PartitionFilter;
+use crate::queryplanner::serialized_plan::RowFilter;
+use crate::table::data::{append_value, create_array_builder};
+use crate::table::parquet::{arrow_schema, row_group_column_stats};
+use crate::table::stats::ColumnStats;
+use crate::table::TableValue;
+use crate::CubeError;
+use arrow::array::ArrayRef;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::cube_ext;
+use datafusion::cube_ext::stream::StreamWithSchema;
+use datafusion::error::DataFusionError;
+use datafusion::logical_plan;
+use datafusion::physical_optimizer::pruning::{PruningPredicate, PruningStatistics};
+use datafusion::physical_plan::{
+    ExecutionPlan, OptimizerHints, Partitioning, SendableRecordBatchStream,
+};
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::file::metadata::RowGroupMetaData;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use std::any::Any;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Reads a Parquet file of a partition or a chunk. Row groups that can't match the key ranges of
+/// `row_filter`, the query filters in `column_filter` or the pruning `predicate` of the query
+/// are skipped using row group statistics. Rows of the remaining row groups are not filtered, so
+/// this is usually followed by [super::filter_by_key_range::FilterByKeyRangeExec] and filters of
+/// the query.
+///
+/// Files of an index are written with the schema of its columns, so the file itself is opened
+/// only when the scan is executed.
+#[derive(Debug, Clone)]
+pub struct ParquetScanExec {
+    path: String,
+    columns: Vec<Column>,
+    file_schema: SchemaRef,
+    schema: SchemaRef,
+    projection: Option<Vec<usize>>,
+    batch_size: usize,
+    key_len: usize,
+    row_filter: Arc<RowFilter>,
+    column_filter: Arc<PartitionFilter>,
+    predicate: Option<Arc<PruningPredicate>>,
+}
+
+impl ParquetScanExec {
+    /// `column_filter` and `predicate` must be built with the schema of all index columns.
+    pub fn try_new(
+        path: &str,
+        index: &Index,
+        projection: Option<Vec<usize>>,
+        batch_size: usize,
+        row_filter: Arc<RowFilter>,
+        column_filter: Arc<PartitionFilter>,
+        predicate: Option<Arc<PruningPredicate>>,
+    ) -> Result<ParquetScanExec, CubeError> {
+        let file_schema = Arc::new(arrow_schema(index));
+        let schema = match &projection {
+            None => file_schema.clone(),
+            Some(p) => Arc::new(Schema::new(
+                p.iter().map(|i| file_schema.field(*i).clone()).collect(),
+            )),
+        };
+        Ok(ParquetScanExec {
+            path: path.to_string(),
+            columns: index.get_columns().clone(),
+            file_schema,
+            schema,
+            projection,
+            batch_size,
+            key_len: index.sort_key_size() as usize,
+            row_filter,
+            column_filter,
+            predicate,
+        })
+    }
+
+    fn read(&self, sender: mpsc::Sender<Result<RecordBatch, ArrowError>>) -> Result<(), CubeError> {
+        let mut file_reader = SerializedFileReader::try_from(self.path.as_str())?;
+
+        let total_row_groups = file_reader.metadata().num_row_groups();
+        let stats = file_reader
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|rg| row_group_column_stats(rg, &self.file_schema))
+            .collect::<Vec<_>>();
+        let predicate_matches = match &self.predicate {
+            Some(predicate) => match predicate.prune(&RowGroupsStatistics {
+                columns: &self.columns,
+                stats: &stats,
+            }) {
+                Ok(matches) => Some(matches),
+                Err(e) => {
+                    log::debug!("Unable to prune row groups of {}: {}", self.path, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        file_reader.filter_row_groups(&|_: &RowGroupMetaData, i: usize| {
+            let stats_match = match &stats[i] {
+                Some(stats) => {
+                    self.row_filter
+                        .can_match_column_stats(&stats[0..self.key_len])
+                        && self.column_filter.can_match_column_stats(stats)
+                }
+                None => true,
+            };
+            stats_match && predicate_matches.as_ref().map_or(true, |m| m[i])
+        });
+        let skipped_row_groups = total_row_groups - file_reader.metadata().num_row_groups();
+        app_metrics::DATA_QUERY_ROW_GROUPS.add(total_row_groups as i64);
+        app_metrics::DATA_QUERY_ROW_GROUPS_SKIPPED.add(skipped_row_groups as i64);
+        log::trace!(
+            "Skipped {} of {} row groups in {}",
+            skipped_row_groups,
+            total_row_groups,
+            self.path
+        );
+
+        let mut arrow_reader = ParquetFileArrowReader::new(Arc::new(file_reader));
+        let batches = match &self.projection {
+            None => arrow_reader.get_record_reader(self.batch_size)?,
+            Some(p) => arrow_reader.get_record_reader_by_columns(p.clone(), self.batch_size)?,
+        };
+        for b in batches {
+            if sender.blocking_send(b).is_err() {
+                // Receiver is dropped, query was cancelled or has finished early.
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for ParquetScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+        assert!(children.is_empty());
+        Ok(Arc::new(self.clone()))
+    }
+
+    fn output_hints(&self) -> OptimizerHints {
+        // Files are sorted by the index key, but sort order is only known to the planner.
+        OptimizerHints::default()
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<SendableRecordBatchStream, DataFusionError> {
+        assert_eq!(partition, 0);
+        let (sender, receiver) = mpsc::channel(2);
+        let scan = self.clone();
+        cube_ext::spawn_blocking(move || {
+            if let Err(e) = scan.read(sender.clone()) {
+                let _ = sender.blocking_send(Err(ArrowError::ExternalError(Box::new(e))));
+            }
+        });
+        Ok(Box::pin(StreamWithSchema::wrap(
+            self.schema.clone(),
+            ReceiverStream::new(receiver),
+        )))
+    }
+}
+
+/// Statistics of row groups for [PruningPredicate], missing statistics are passed as nulls.
+struct RowGroupsStatistics<'a> {
+    columns: &'a [Column],
+    stats: &'a [Option<Vec<ColumnStats>>],
+}
+
+impl RowGroupsStatistics<'_> {
+    fn values(
+        &self,
+        column: &logical_plan::Column,
+        value: impl Fn(&ColumnStats) -> &TableValue,
+    ) -> Option<ArrayRef> {
+        let i = self
+            .columns
+            .iter()
+            .position(|c| c.get_name() == &column.name)?;
+        let column_type = self.columns[i].get_column_type();
+        let mut builder = create_array_builder(column_type);
+        for stats in self.stats {
+            match stats {
+                Some(stats) => append_value(builder.as_mut(), column_type, value(&stats[i])),
+                None => append_value(builder.as_mut(), column_type, &TableValue::Null),
+            }
+        }
+        Some(builder.finish())
+    }
+}
+
+impl PruningStatistics for RowGroupsStatistics<'_> {
+    fn min_values(&self, column: &logical_plan::Column) -> Option<ArrayRef> {
+        self.values(column, |s| s.min())
+    }
+
+    fn max_values(&self, column: &logical_plan::Column) -> Option<ArrayRef> {
+        self.values(column, |s| s.max())
+    }
+
+    fn num_containers(&self) -> usize {
+        self.stats.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::ColumnType;
+    use crate::queryplanner::serialized_plan::RowRange;
+    use crate::table::parquet::ParquetTableStore;
+    use arrow::array::Int64Array;
+    use datafusion::logical_plan::{col, lit};
+    use datafusion::physical_plan::collect;
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn prune_row_groups_by_predicate() {
+        let index = Index::try_new(
+            "index".to_string(),
+            1,
+            vec![
+                Column::new("a".to_string(), ColumnType::Int, 0),
+                Column::new("b".to_string(), ColumnType::Int, 1),
+            ],
+            1,
+            None,
+            None,
+        )
+        .unwrap();
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let data: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3, 4])),
+            Arc::new(Int64Array::from(vec![10, 20, 30, 40])),
+        ];
+        ParquetTableStore::new(index.clone(), 2)
+            .write_data(path, data)
+            .unwrap();
+
+        let mut row_filter = RowFilter::default();
+        row_filter.append_or(RowRange {
+            start: None,
+            end: None,
+        });
+        let schema = Arc::new(arrow_schema(&index));
+        let scan = |predicate: Option<Arc<PruningPredicate>>| {
+            ParquetScanExec::try_new(
+                path,
+                &index,
+                None,
+                4096,
+                Arc::new(row_filter.clone()),
+                Arc::new(PartitionFilter::extract(&schema, &[])),
+                predicate,
+            )
+            .unwrap()
+        };
+        let rows = |batches: Vec<RecordBatch>| batches.iter().map(|b| b.num_rows()).sum::<usize>();
+
+        let all = collect(Arc::new(scan(None))).await.unwrap();
+        assert_eq!(rows(all), 4);
+
+        let predicate =
+            PruningPredicate::try_new(&col("b").gt(lit(25i64)), schema.clone()).unwrap();
+        let pruned = collect(Arc::new(scan(Some(Arc::new(predicate)))))
+            .await
+            .unwrap();
+        assert_eq!(rows(pruned), 2);
+    }
+}
@@ -1,4 +1,6 @@
-use crate::table::{cmp_same_types, TableValue};
+use crate::table::bloom::BloomFilterSet;
+use crate::table::stats::ColumnStats;
+use crate::table::{cmp_same_types, TableValue, TimestampValue};
 use arrow::datatypes::{DataType, Schema};
 use datafusion::logical_plan::{Column, Expr, Operator};
 use datafusion::scalar::ScalarValue;
@@ -43,6 +45,40 @@ impl PartitionFilter {
             (None, None) => true,
         }
     }
+
+    /// Returns whether any row with column values inside `stats` could potentially match the
+    /// filter. Unlike [can_match], every column is checked independently, so this works for
+    /// filters extracted with a schema of all columns, not only the sort key prefix.
+    /// Statistics must be in the order of the schema passed to [PartitionFilter::extract].
+    pub fn can_match_column_stats(&self, stats: &[ColumnStats]) -> bool {
+        if self.min_max.is_empty() {
+            return true;
+        }
+        self.min_max.iter().any(|mm| mm.can_match_ranges(stats))
+    }
+
+    /// Returns whether every condition of the filter restricts at least one of `columns` to a
+    /// single non-null value, i.e. whether checking bloom filters of these columns may help.
+    pub fn has_point_values(&self, columns: &[u64]) -> bool {
+        !self.min_max.is_empty()
+            && self.min_max.iter().all(|mm| {
+                columns
+                    .iter()
+                    .any(|c| mm.point_value(*c as usize).is_some())
+            })
+    }
+
+    /// Returns whether any rows of a file with bloom filters `filters` could potentially match
+    /// the filter. Only columns restricted to a single value are checked.
+    /// Column positions must be in the order of the schema passed to [PartitionFilter::extract].
+    pub fn can_match_bloom_filters(&self, filters: &BloomFilterSet) -> bool {
+        if self.min_max.is_empty() {
+            return true;
+        }
+        self.min_max
+            .iter()
+            .any(|mm| mm.can_match_bloom_filters(filters))
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -92,6 +128,51 @@ impl MinMaxCondition {
         return true;
     }
 
+    pub fn can_match_ranges(&self, stats: &[ColumnStats]) -> bool {
+        if stats.len() != self.min.len() {
+            // Statistics are missing or were collected for a different set of columns.
+            return true;
+        }
+        for (i, s) in stats.iter().enumerate() {
+            let (stat_min, stat_max) = s.range();
+            if let Some(min) = &self.min[i] {
+                if cmp_if_same_types(stat_max, min) == Some(Ordering::Less) {
+                    return false;
+                }
+            }
+            if let Some(max) = &self.max[i] {
+                if cmp_if_same_types(max, stat_min) == Some(Ordering::Less) {
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+
+    /// Returns the value of the column if the condition only matches a single non-null value.
+    fn point_value(&self, column: usize) -> Option<&TableValue> {
+        match (self.min.get(column), self.max.get(column)) {
+            (Some(Some(min)), Some(Some(max))) if min == max && *min != TableValue::Null => {
+                Some(min)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn can_match_bloom_filters(&self, filters: &BloomFilterSet) -> bool {
+        for i in 0..self.min.len() {
+            let v = match self.point_value(i) {
+                Some(v) => v,
+                None => continue,
+            };
+            match filters.get(i as u64) {
+                Some(f) if !f.may_contain(v) => return false,
+                _ => {}
+            }
+        }
+        return true;
+    }
+
     pub fn can_match(&self, min_row: &[TableValue], max_row: &[TableValue]) -> bool {
         let n = self.min.len();
         assert_eq!(n, min_row.len());
@@ -379,11 +460,23 @@ impl Builder<'_> {
             t if Self::is_signed_int(t) => Self::extract_signed_int(v),
             DataType::Boolean => Self::extract_bool(v),
             DataType::Utf8 => Self::extract_string(v),
+            DataType::Timestamp(_, None) => Self::extract_timestamp(v),
             _ => None,
             // TODO: more data types
         }
     }
 
+    fn extract_timestamp(v: &ScalarValue) -> Option<TableValue> {
+        let nanos = match v {
+            ScalarValue::TimestampSecond(v) => v.as_ref()?.checked_mul(1_000_000_000)?,
+            ScalarValue::TimestampMillisecond(v) => v.as_ref()?.checked_mul(1_000_000)?,
+            ScalarValue::TimestampMicrosecond(v) => v.as_ref()?.checked_mul(1_000)?,
+            ScalarValue::TimestampNanosecond(v) => *v.as_ref()?,
+            _ => return None, // TODO: casts.
+        };
+        Some(TableValue::Timestamp(TimestampValue::new(nanos)))
+    }
+
     fn extract_bool(v: &ScalarValue) -> Option<TableValue> {
         match v {
             ScalarValue::Boolean(v) => v.as_ref().map(|v| TableValue::Boolean(*v)),
@@ -486,6 +579,18 @@ impl Builder<'_> {
     }
 }
 
+/// Compares values like [cmp_same_types], but returns `None` for non-null values of different
+/// types instead of panicking. Such values can't be used to rule anything out.
+fn cmp_if_same_types(l: &TableValue, r: &TableValue) -> Option<Ordering> {
+    match (l, r) {
+        (TableValue::Null, _) | (_, TableValue::Null) => Some(cmp_same_types(l, r)),
+        (l, r) if std::mem::discriminant(l) == std::mem::discriminant(r) => {
+            Some(cmp_same_types(l, r))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -951,6 +1056,85 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_column_stats() {
+        let s = schema(&[("a", DataType::Int64), ("b", DataType::Utf8)]);
+        let extract = |sql| PartitionFilter::extract(&s, &[parse(sql, &s)]);
+        let stats = |a: (i64, i64), b: (&str, &str), b_nulls: u64| {
+            vec![
+                ColumnStats::new(TableValue::Int(a.0), TableValue::Int(a.1), 0),
+                ColumnStats::new(
+                    TableValue::String(b.0.to_string()),
+                    TableValue::String(b.1.to_string()),
+                    b_nulls,
+                ),
+            ]
+        };
+
+        // Unlike key ranges, the second column is checked regardless of the first one.
+        let f = extract("b = 'x'");
+        assert!(!f.can_match_column_stats(&stats((0, 10), ("a", "c"), 0)));
+        assert!(f.can_match_column_stats(&stats((0, 10), ("a", "z"), 0)));
+
+        let f = extract("a > 5 AND b < 'b'");
+        assert!(!f.can_match_column_stats(&stats((0, 5), ("a", "z"), 0)));
+        assert!(!f.can_match_column_stats(&stats((0, 10), ("c", "z"), 0)));
+        assert!(f.can_match_column_stats(&stats((0, 10), ("a", "z"), 0)));
+
+        let f = extract("a = 1 OR b = 'x'");
+        assert!(f.can_match_column_stats(&stats((0, 10), ("a", "c"), 0)));
+        assert!(f.can_match_column_stats(&stats((20, 30), ("a", "z"), 0)));
+        assert!(!f.can_match_column_stats(&stats((20, 30), ("a", "c"), 0)));
+
+        // Nulls are ordered first.
+        let f = extract("b = NULL");
+        assert!(!f.can_match_column_stats(&stats((0, 10), ("a", "c"), 0)));
+        assert!(f.can_match_column_stats(&stats((0, 10), ("a", "c"), 1)));
+
+        // Missing statistics.
+        assert!(extract("a = 100").can_match_column_stats(&[]));
+
+        // Statistics of another type, e.g. written before the column type was changed.
+        let f = extract("a = 100");
+        assert!(f.can_match_column_stats(&[
+            ColumnStats::new(
+                TableValue::String("a".to_string()),
+                TableValue::String("c".to_string()),
+                0
+            ),
+            ColumnStats::new(TableValue::Int(0), TableValue::Int(10), 0),
+        ]));
+    }
+
+    #[test]
+    fn test_bloom_filters() {
+        use arrow::array::{ArrayRef, Int64Array, StringArray};
+
+        let s = schema(&[("a", DataType::Int64), ("b", DataType::Utf8)]);
+        let extract = |sql| PartitionFilter::extract(&s, &[parse(sql, &s)]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec!["x", "y", "z"])),
+        ];
+        let filters = BloomFilterSet::from_columns(&columns, &[1]);
+
+        assert!(extract("b = 'x'").has_point_values(&[1]));
+        assert!(!extract("b = 'x'").has_point_values(&[0]));
+        assert!(!extract("b > 'x'").has_point_values(&[1]));
+        assert!(!extract("b = NULL").has_point_values(&[1]));
+        assert!(!extract("b = 'x' OR a = 1").has_point_values(&[1]));
+        assert!(!PartitionFilter::extract(&s, &[]).has_point_values(&[1]));
+
+        assert!(extract("b = 'x'").can_match_bloom_filters(&filters));
+        assert!(!extract("b = 'foo'").can_match_bloom_filters(&filters));
+        assert!(!extract("b = 'foo' AND a > 1").can_match_bloom_filters(&filters));
+        assert!(extract("b = 'foo' OR b = 'z'").can_match_bloom_filters(&filters));
+        assert!(!extract("b = 'foo' OR b = 'bar'").can_match_bloom_filters(&filters));
+        // No filter for the column.
+        assert!(extract("a = 100").can_match_bloom_filters(&filters));
+        assert!(PartitionFilter::extract(&s, &[]).can_match_bloom_filters(&filters));
+    }
+
     #[test]
     fn test_unhandled_expressions() {
         let s = schema(&[("a", DataType::Int64), ("b", DataType::Int64)]);
@@ -24,7 +24,10 @@ use arrow::datatypes::{Field, SchemaRef};
 use async_trait::async_trait;
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::ExecutionContextState;
-use datafusion::logical_plan::{DFSchemaRef, Expr, LogicalPlan, UserDefinedLogicalNode};
+use datafusion::logical_plan::{
+    Column, DFSchemaRef, Expr, JoinType, LogicalPlan, UserDefinedLogicalNode,
+};
+use datafusion::physical_plan::aggregates::AggregateFunction as DFAggregateFunction;
 use datafusion::physical_plan::empty::EmptyExec;
 use datafusion::physical_plan::planner::ExtensionPlanner;
 use datafusion::physical_plan::{
@@ -37,12 +40,13 @@ use itertools::Itertools;
 use crate::cluster::Cluster;
 use crate::metastore::multi_index::MultiPartition;
 use crate::metastore::table::{Table, TablePath};
-use crate::metastore::{Chunk, IdRow, Index, MetaStore, Partition, Schema};
+use crate::metastore::{AggregateFunction, Chunk, IdRow, Index, MetaStore, Partition, Schema};
 use crate::queryplanner::optimizations::rewrite_plan::{rewrite_plan, PlanRewriter};
 use crate::queryplanner::partition_filter::PartitionFilter;
 use crate::queryplanner::query_executor::{ClusterSendExec, CubeTable};
 use crate::queryplanner::serialized_plan::{IndexSnapshot, PartitionSnapshot, SerializedPlan};
 use crate::queryplanner::topk::{materialize_topk, plan_topk, ClusterAggregateTopK};
+use crate::queryplanner::udfs::{aggregate_kind_by_name, CubeAggregateUDFKind};
 use crate::queryplanner::CubeTableLogical;
 use crate::CubeError;
 use serde::{Deserialize as SerdeDeser, Deserializer, Serialize as SerdeSer, Serializer};
@@ -86,7 +90,7 @@ pub async fn choose_index_ext(
 ) -> Result<(LogicalPlan, PlanningMeta), DataFusionError> {
     // Prepare information to choose the index.
     let mut collector = CollectConstraints::default();
-    rewrite_plan(p, &None, &mut collector)?;
+    rewrite_plan(p, &ConstraintsContext::default(), &mut collector)?;
 
     // Consult metastore to choose the index.
     let tables = metastore
@@ -226,8 +230,24 @@ struct SortColumns {
     required: bool,
 }
 
+/// Aggregation that reads directly from the table scan, allows to use aggregating indexes.
+#[derive(Clone)]
+struct AggregateColumns {
+    group_by: Vec<String>,
+    aggregates: Vec<(AggregateFunction, String)>,
+    /// Columns used in filters between the aggregation and the scan.
+    filter_columns: HashSet<String>,
+}
+
+#[derive(Clone, Default)]
+struct ConstraintsContext {
+    sort_on: Option<SortColumns>,
+    aggregates: Option<AggregateColumns>,
+}
+
 struct IndexConstraints {
     sort_on: Option<SortColumns>,
+    aggregates: Option<AggregateColumns>,
     table: TablePath,
     projection: Option<Vec<usize>>,
     filters: Vec<Expr>,
@@ -239,7 +259,7 @@ struct CollectConstraints {
 }
 
 impl PlanRewriter for CollectConstraints {
-    type Context = Option<SortColumns>;
+    type Context = ConstraintsContext;
 
     fn rewrite(
         &mut self,
@@ -255,7 +275,8 @@ impl PlanRewriter for CollectConstraints {
             } => {
                 let table = source.as_any().downcast_ref::<CubeTableLogical>().unwrap();
                 self.constraints.push(IndexConstraints {
-                    sort_on: c.clone(),
+                    sort_on: c.sort_on.clone(),
+                    aggregates: c.aggregates.clone(),
                     table: table.table.clone(),
                     projection: projection.clone(),
                     filters: filters.clone(),
@@ -269,26 +290,28 @@ impl PlanRewriter for CollectConstraints {
     fn enter_node(
         &mut self,
         n: &LogicalPlan,
-        _: &Option<SortColumns>,
-    ) -> Option<Option<SortColumns>> {
-        fn column_name(expr: &Expr) -> Option<String> {
-            match expr {
-                Expr::Alias(e, _) => column_name(e),
-                Expr::Column(col) => Some(col.name.clone()), // TODO use alias
-                _ => None,
-            }
-        }
+        _: &ConstraintsContext,
+    ) -> Option<ConstraintsContext> {
         match n {
-            LogicalPlan::Aggregate { group_expr, .. } => {
+            LogicalPlan::Aggregate {
+                group_expr,
+                aggr_expr,
+                input,
+                ..
+            } => {
                 let sort_on = group_expr.iter().map(column_name).collect::<Vec<_>>();
-                if !sort_on.is_empty() && sort_on.iter().all(|c| c.is_some()) {
-                    Some(Some(SortColumns {
+                let sort_on = if !sort_on.is_empty() && sort_on.iter().all(|c| c.is_some()) {
+                    Some(SortColumns {
                         sort_on: sort_on.into_iter().map(|c| c.unwrap()).collect(),
                         required: false,
-                    }))
+                    })
                 } else {
-                    Some(None)
-                }
+                    None
+                };
+                Some(ConstraintsContext {
+                    sort_on,
+                    aggregates: extract_aggregate_columns(group_expr, aggr_expr, input),
+                })
             }
             _ => None,
         }
@@ -297,18 +320,21 @@ impl PlanRewriter for CollectConstraints {
     fn enter_join_left(
         &mut self,
         join: &LogicalPlan,
-        _: &Option<SortColumns>,
-    ) -> Option<Option<SortColumns>> {
+        _: &ConstraintsContext,
+    ) -> Option<ConstraintsContext> {
         let join_on;
         if let LogicalPlan::Join { on, .. } = join {
             join_on = on;
         } else {
             panic!("expected join node");
         }
-        Some(Some(SortColumns {
-            sort_on: join_on.iter().map(|(l, _)| l.name.clone()).collect(),
-            required: true,
-        }))
+        Some(ConstraintsContext {
+            sort_on: Some(SortColumns {
+                sort_on: join_on.iter().map(|(l, _)| l.name.clone()).collect(),
+                required: true,
+            }),
+            aggregates: None,
+        })
     }
 
     fn enter_join_right(
@@ -322,13 +348,210 @@ impl PlanRewriter for CollectConstraints {
         } else {
             panic!("expected join node");
         }
-        Some(Some(SortColumns {
-            sort_on: join_on.iter().map(|(_, r)| r.name.clone()).collect(),
-            required: true,
-        }))
+        Some(ConstraintsContext {
+            sort_on: Some(SortColumns {
+                sort_on: join_on.iter().map(|(_, r)| r.name.clone()).collect(),
+                required: true,
+            }),
+            aggregates: None,
+        })
+    }
+}
+
+fn column_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Alias(e, _) => column_name(e),
+        Expr::Column(col) => Some(col.name.clone()), // TODO use alias
+        _ => None,
+    }
+}
+
+/// Returns `None` unless the aggregation reads directly from the table scan, possibly through
+/// filters and column projections, and all aggregates are applied to plain columns.
+fn extract_aggregate_columns(
+    group_expr: &[Expr],
+    aggr_expr: &[Expr],
+    input: &LogicalPlan,
+) -> Option<AggregateColumns> {
+    let group_by = group_expr
+        .iter()
+        .map(column_name)
+        .collect::<Option<Vec<_>>>()?;
+    let aggregates = aggr_expr
+        .iter()
+        .map(aggregate_column)
+        .collect::<Option<Vec<_>>>()?;
+    let mut filter_columns = HashSet::new();
+    let mut input = input;
+    loop {
+        match input {
+            LogicalPlan::TableScan { .. } => break,
+            LogicalPlan::Filter {
+                predicate,
+                input: filter_input,
+            } => {
+                if !collect_columns(predicate, &mut filter_columns) {
+                    return None;
+                }
+                input = filter_input;
+            }
+            LogicalPlan::Projection {
+                expr,
+                input: projection_input,
+                ..
+            } => {
+                if !expr.iter().all(|e| matches!(e, Expr::Column(_))) {
+                    return None;
+                }
+                input = projection_input;
+            }
+            _ => return None,
+        }
+    }
+    Some(AggregateColumns {
+        group_by,
+        aggregates,
+        filter_columns,
+    })
+}
+
+fn aggregate_column(e: &Expr) -> Option<(AggregateFunction, String)> {
+    match e {
+        Expr::Alias(e, _) => aggregate_column(e),
+        Expr::AggregateFunction {
+            fun,
+            args,
+            distinct: false,
+        } if args.len() == 1 => {
+            let function = match fun {
+                DFAggregateFunction::Sum => AggregateFunction::Sum,
+                DFAggregateFunction::Min => AggregateFunction::Min,
+                DFAggregateFunction::Max => AggregateFunction::Max,
+                _ => return None,
+            };
+            match &args[0] {
+                Expr::Column(c) => Some((function, c.name.clone())),
+                _ => None,
+            }
+        }
+        Expr::AggregateUDF { fun, args } if args.len() == 1 => {
+            if !matches!(
+                aggregate_kind_by_name(&fun.name.to_uppercase()),
+                Some(CubeAggregateUDFKind::MergeHll)
+            ) {
+                return None;
+            }
+            match &args[0] {
+                Expr::Column(c) => Some((AggregateFunction::Merge, c.name.clone())),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Adds names of the columns used in `e` to `out`. Returns false on expressions it can't analyze.
+fn collect_columns(e: &Expr, out: &mut HashSet<String>) -> bool {
+    match e {
+        Expr::Column(c) => {
+            out.insert(c.name.clone());
+            true
+        }
+        Expr::Literal(_) => true,
+        Expr::Alias(e, _)
+        | Expr::Not(e)
+        | Expr::IsNull(e)
+        | Expr::IsNotNull(e)
+        | Expr::Negative(e)
+        | Expr::Cast { expr: e, .. } => collect_columns(e, out),
+        Expr::BinaryExpr { left, right, .. } => {
+            collect_columns(left, out) && collect_columns(right, out)
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => collect_columns(expr, out) && collect_columns(low, out) && collect_columns(high, out),
+        Expr::InList { expr, list, .. } => {
+            collect_columns(expr, out) && list.iter().all(|e| collect_columns(e, out))
+        }
+        Expr::ScalarFunction { args, .. } | Expr::ScalarUDF { args, .. } => {
+            args.iter().all(|e| collect_columns(e, out))
+        }
+        _ => false,
     }
 }
 
+/// Checks whether the sort key of the index starts with `columns`.
+fn sort_key_starts_with(columns: &[String], i: &IdRow<Index>) -> bool {
+    // TODO: columns may be larger than sort_key_size of the index.
+    let columns_in_index = columns
+        .iter()
+        .map(|c| {
+            i.get_row()
+                .get_columns()
+                .iter()
+                .find(|ic| ic.get_name().as_str() == c.as_str())
+                .cloned()
+        })
+        .collect::<Option<Vec<_>>>();
+    let columns_in_index = match columns_in_index {
+        None => return false,
+        Some(c) => c,
+    };
+    CubeTable::project_to_index_positions(&columns_in_index, i)
+        .iter()
+        .enumerate()
+        .all(|(i, col_i)| Some(i) == *col_i)
+}
+
+/// Aggregating index can only be used when its rows give the same result as the rows of the table,
+/// i.e. the query filters and groups by the sort key columns and aggregates the rest of the
+/// columns with the functions of the index. Min and max can also be applied to the key columns.
+fn aggregating_index_covers(
+    c: &IndexConstraints,
+    projection_columns: &[crate::metastore::Column],
+    i: &Index,
+) -> bool {
+    let aggregates = match &c.aggregates {
+        Some(a) => a,
+        None => return false,
+    };
+    let key_columns = &i.get_columns()[0..i.sort_key_size() as usize];
+    let is_key = |name: &str| key_columns.iter().any(|c| c.get_name() == name);
+    let aggregate_function = |name: &str| {
+        i.aggregate_columns()
+            .iter()
+            .find(|a| i.get_columns()[a.index() as usize].get_name() == name)
+            .map(|a| a.function())
+    };
+    if !projection_columns
+        .iter()
+        .all(|c| is_key(c.get_name()) || aggregate_function(c.get_name()).is_some())
+    {
+        return false;
+    }
+    if !aggregates.group_by.iter().all(|c| is_key(c)) {
+        return false;
+    }
+    let mut filter_columns = aggregates.filter_columns.clone();
+    if !c
+        .filters
+        .iter()
+        .all(|f| collect_columns(f, &mut filter_columns))
+    {
+        return false;
+    }
+    if !filter_columns.iter().all(|c| is_key(c)) {
+        return false;
+    }
+    aggregates.aggregates.iter().all(|(f, c)| {
+        if is_key(c) {
+            *f == AggregateFunction::Min || *f == AggregateFunction::Max
+        } else {
+            aggregate_function(c) == Some(*f)
+        }
+    })
+}
+
 struct ChooseIndex<'a> {
     next_index: usize,
     chosen_indices: &'a [IndexSnapshot],
@@ -397,6 +620,7 @@ impl ChooseIndex<'_> {
                 return Ok(ClusterSendNode {
                     input: Arc::new(p),
                     snapshots: vec![vec![snapshot]],
+                    join_tree: None,
                 }
                 .into_plan());
             }
@@ -428,31 +652,29 @@ async fn pick_index(
         let mut partitioned_index = None;
         let mut ordinary_index = None;
         let mut ordinary_score = usize::MAX;
+        let mut aggregating_index: Option<(IdRow<Index>, bool)> = None;
         for i in indices {
+            if i.get_row().is_backfilling() {
+                continue;
+            }
+            if i.get_row().is_aggregating() {
+                if !aggregating_index_covers(c, &projection_columns, i.get_row()) {
+                    continue;
+                }
+                // Prefer the index with the least number of rows, which we approximate by the
+                // number of columns.
+                if aggregating_index.as_ref().map_or(true, |(a, _)| {
+                    i.get_row().get_columns().len() < a.get_row().get_columns().len()
+                }) {
+                    let sorted = sort_on
+                        .as_ref()
+                        .map_or(false, |(cols, _)| sort_key_starts_with(cols, &i));
+                    aggregating_index = Some((i, sorted));
+                }
+                continue;
+            }
             if let Some((join_on_columns, _)) = sort_on.as_ref() {
-                // TODO: join_on_columns may be larger than sort_key_size of the index.
-                let join_columns_in_index = join_on_columns
-                    .iter()
-                    .map(|c| {
-                        i.get_row()
-                            .get_columns()
-                            .iter()
-                            .find(|ic| ic.get_name().as_str() == c.as_str())
-                            .cloned()
-                    })
-                    .collect::<Option<Vec<_>>>();
-                let join_columns_in_index = match join_columns_in_index {
-                    None => continue,
-                    Some(c) => c,
-                };
-                let join_columns_indices =
-                    CubeTable::project_to_index_positions(&join_columns_in_index, &i);
-
-                let matches = join_columns_indices
-                    .iter()
-                    .enumerate()
-                    .all(|(i, col_i)| Some(i) == *col_i);
-                if !matches {
+                if !sort_key_starts_with(join_on_columns, &i) {
                     continue;
                 }
             }
@@ -473,7 +695,9 @@ async fn pick_index(
                 }
             }
         }
-        if let Some(index) = ordinary_index {
+        if let Some((index, sorted)) = aggregating_index {
+            (index, None, if sorted { sort_on } else { None })
+        } else if let Some(index) = ordinary_index {
             (index, partitioned_index, sort_on)
         } else {
             if let Some((join_on_columns, true)) = sort_on.as_ref() {
@@ -535,8 +759,11 @@ fn pick_partitions(
 ) -> Result<Vec<PartitionSnapshot>, DataFusionError> {
     let partition_filter = PartitionFilter::extract(&partition_filter_schema(&i.index), &c.filters);
     log::trace!("Extracted partition filter is {:?}", partition_filter);
+    let column_filter = PartitionFilter::extract(&column_filter_schema(&i.index), &c.filters);
+    log::trace!("Extracted column filter is {:?}", column_filter);
     let candidate_partitions = partitions.len();
     let mut pruned_partitions = 0;
+    let mut pruned_chunks = 0;
 
     let mut partition_snapshots = Vec::new();
     for (partition, chunks) in partitions.into_iter() {
@@ -556,12 +783,35 @@ fn pick_partitions(
             continue;
         }
 
+        let chunks_count = chunks.len();
+        let chunks = chunks
+            .into_iter()
+            .filter(|c| match c.get_row().get_column_stats() {
+                Some(stats) => column_filter.can_match_column_stats(stats),
+                None => true,
+            })
+            .collect_vec();
+        pruned_chunks += chunks_count - chunks.len();
+
+        // Main table file is read along with the partition, so the partition can be skipped only
+        // when neither its file nor any of the remaining chunks can match.
+        let main_table_can_match = partition.get_row().has_main_table_file()
+            && match partition.get_row().get_column_stats() {
+                Some(stats) => column_filter.can_match_column_stats(stats),
+                None => true,
+            };
+        if !main_table_can_match && chunks.is_empty() {
+            pruned_partitions += 1;
+            continue;
+        }
+
         partition_snapshots.push(PartitionSnapshot { chunks, partition });
     }
     log::trace!(
-        "Pruned {} of {} partitions",
+        "Pruned {} of {} partitions and {} chunks",
         pruned_partitions,
-        candidate_partitions
+        candidate_partitions,
+        pruned_chunks
     );
 
     Ok(partition_snapshots)
@@ -579,10 +829,80 @@ fn partition_filter_schema(index: &IdRow<Index>) -> arrow::datatypes::Schema {
     arrow::datatypes::Schema::new(schema_fields)
 }
 
+/// Schema of all index columns, column statistics are stored in this order.
+fn column_filter_schema(index: &IdRow<Index>) -> arrow::datatypes::Schema {
+    arrow::datatypes::Schema::new(
+        index
+            .get_row()
+            .columns()
+            .iter()
+            .map(|c| c.clone().into())
+            .collect(),
+    )
+}
+
 #[derive(Debug, Clone)]
 pub struct ClusterSendNode {
     pub input: Arc<LogicalPlan>,
     pub snapshots: Vec<Vec<IndexSnapshot>>,
+    /// Set when `input` joins the snapshot groups. None means there is at most one group.
+    pub join_tree: Option<JoinTree>,
+}
+
+/// Describes how the snapshot groups of [ClusterSendNode] are joined together. Used to decide
+/// which partitions must be processed by the same worker, see [ClusterSendExec].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JoinTree {
+    /// Partitions of the snapshot group with this index.
+    Group(usize),
+    Join {
+        /// The side whose rows are preserved by the join. Inner joins preserve neither, in this
+        /// case it is the left side.
+        probe: Box<JoinTree>,
+        /// The side that can always be broadcast, i.e. sent in full to every worker processing
+        /// the `probe` side.
+        build: Box<JoinTree>,
+        /// Inner joins allow to broadcast the `probe` side too.
+        inner: bool,
+        /// Number of leading sort key columns both sides are joined on. Non-zero only when both
+        /// sides are scans of a single index, allows to join the sides partition by partition.
+        key_len: usize,
+    },
+    /// All partitions of these groups must be processed together by a single worker.
+    Single(Vec<usize>),
+}
+
+impl JoinTree {
+    /// Indices of all snapshot groups in this tree.
+    pub fn groups(&self) -> Vec<usize> {
+        match self {
+            JoinTree::Group(g) => vec![*g],
+            JoinTree::Join { probe, build, .. } => {
+                let mut r = probe.groups();
+                r.extend(build.groups());
+                r
+            }
+            JoinTree::Single(gs) => gs.clone(),
+        }
+    }
+
+    fn shift_groups(self, offset: usize) -> JoinTree {
+        match self {
+            JoinTree::Group(g) => JoinTree::Group(g + offset),
+            JoinTree::Join {
+                probe,
+                build,
+                inner,
+                key_len,
+            } => JoinTree::Join {
+                probe: Box::new(probe.shift_groups(offset)),
+                build: Box::new(build.shift_groups(offset)),
+                inner,
+                key_len,
+            },
+            JoinTree::Single(gs) => JoinTree::Single(gs.into_iter().map(|g| g + offset).collect()),
+        }
+    }
 }
 
 impl ClusterSendNode {
@@ -629,12 +949,14 @@ impl UserDefinedLogicalNode for ClusterSendNode {
         Arc::new(ClusterSendNode {
             input: Arc::new(inputs[0].clone()),
             snapshots: self.snapshots.clone(),
+            join_tree: self.join_tree.clone(),
         })
     }
 }
 
 fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionError> {
     let snapshots;
+    let join_tree;
     match &mut p {
         // These nodes have no children, return unchanged.
         LogicalPlan::TableScan { .. }
@@ -644,11 +966,13 @@ fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionErr
         // The ClusterSend itself, return unchanged.
         LogicalPlan::Extension { .. } => return Ok(p),
         // These nodes collect results from multiple partitions, return unchanged.
+        // Window functions are computed on the router after the distributed aggregation.
         LogicalPlan::Aggregate { .. }
         | LogicalPlan::Sort { .. }
         | LogicalPlan::Limit { .. }
         | LogicalPlan::Skip { .. }
-        | LogicalPlan::Repartition { .. } => return Ok(p),
+        | LogicalPlan::Repartition { .. }
+        | LogicalPlan::Window { .. } => return Ok(p),
         // We can always pull cluster send for these nodes.
         LogicalPlan::Projection { input, .. } | LogicalPlan::Filter { input, .. } => {
             let send;
@@ -658,6 +982,7 @@ fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionErr
                 return Ok(p);
             }
             snapshots = send.snapshots.clone();
+            join_tree = send.join_tree.clone();
             // Code after 'match' will wrap `p` in ClusterSend.
             *input = send.input.clone();
         }
@@ -666,23 +991,37 @@ fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionErr
             if inputs.iter().all(|p| try_extract_cluster_send(p).is_none()) {
                 return Ok(p);
             }
-            let mut union_snapshots = Vec::new();
-            for i in inputs {
+            let mut sends = Vec::with_capacity(inputs.len());
+            for i in inputs.iter_mut() {
                 let send;
                 if let Some(s) = try_extract_cluster_send(i) {
-                    send = s;
+                    send = s.clone();
                 } else {
                     return Err(DataFusionError::Plan(
                         "UNION argument not supported".to_string(),
                     ));
                 }
-                union_snapshots.extend(send.snapshots.concat());
                 // Code after 'match' will wrap `p` in ClusterSend.
                 *i = send.input.as_ref().clone();
+                sends.push(send);
+            }
+            if sends.iter().all(|s| s.join_tree.is_none()) {
+                snapshots = vec![sends.iter().flat_map(|s| s.snapshots.concat()).collect()];
+                join_tree = None;
+            } else {
+                // Partitions of joined tables may only be split between workers in the way
+                // chosen for the particular join. Process all of them together instead.
+                snapshots = sends.into_iter().flat_map(|s| s.snapshots).collect_vec();
+                join_tree = Some(JoinTree::Single((0..snapshots.len()).collect()));
             }
-            snapshots = vec![union_snapshots];
         }
-        LogicalPlan::Join { left, right, .. } => {
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            join_type,
+            ..
+        } => {
             let lsend;
             let rsend;
             if let (Some(l), Some(r)) = (
@@ -696,6 +1035,37 @@ fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionErr
                     "JOIN argument not supported".to_string(),
                 ));
             }
+            let lsize = lsend.snapshots.len();
+            let ltree = lsend.join_tree.clone().unwrap_or(JoinTree::Group(0));
+            let rtree = (rsend.join_tree.clone())
+                .unwrap_or(JoinTree::Group(0))
+                .shift_groups(lsize);
+            let key_len = if join_key_is_sort_prefix(lsend, rsend, on) {
+                on.len()
+            } else {
+                0
+            };
+            join_tree = Some(match join_type {
+                JoinType::Inner => JoinTree::Join {
+                    probe: Box::new(ltree),
+                    build: Box::new(rtree),
+                    inner: true,
+                    key_len,
+                },
+                JoinType::Left => JoinTree::Join {
+                    probe: Box::new(ltree),
+                    build: Box::new(rtree),
+                    inner: false,
+                    key_len,
+                },
+                JoinType::Right => JoinTree::Join {
+                    probe: Box::new(rtree),
+                    build: Box::new(ltree),
+                    inner: false,
+                    key_len,
+                },
+                _ => JoinTree::Single((0..lsize + rsend.snapshots.len()).collect()),
+            });
             snapshots = lsend
                 .snapshots
                 .iter()
@@ -706,7 +1076,7 @@ fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionErr
             *left = lsend.input.clone();
             *right = rsend.input.clone();
         }
-        LogicalPlan::Window { .. } | LogicalPlan::CrossJoin { .. } => {
+        LogicalPlan::CrossJoin { .. } => {
             return Err(DataFusionError::Internal(
                 "unsupported operation".to_string(),
             ))
@@ -716,10 +1086,33 @@ fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionErr
     Ok(ClusterSendNode {
         input: Arc::new(p),
         snapshots,
+        join_tree,
     }
     .into_plan())
 }
 
+/// Checks both sides are scans of a single index sorted by the join key, so their partitions can
+/// be matched by key ranges.
+fn join_key_is_sort_prefix(
+    l: &ClusterSendNode,
+    r: &ClusterSendNode,
+    on: &[(Column, Column)],
+) -> bool {
+    let (l, r) = match (l.snapshots.as_slice(), r.snapshots.as_slice()) {
+        ([l], [r]) if l.len() == 1 && r.len() == 1 => (&l[0], &r[0]),
+        _ => return false,
+    };
+    let lcols = on.iter().map(|(c, _)| c.name.clone()).collect_vec();
+    let rcols = on.iter().map(|(_, c)| c.name.clone()).collect_vec();
+    if !sort_key_starts_with(&lcols, &l.index) || !sort_key_starts_with(&rcols, &r.index) {
+        return false;
+    }
+    // Partition bounds of both sides are compared, key types must match exactly.
+    let lindex = l.index.get_row().get_columns();
+    let rindex = r.index.get_row().get_columns();
+    (0..on.len()).all(|i| lindex[i].get_column_type() == rindex[i].get_column_type())
+}
+
 pub struct CubeExtensionPlanner {
     pub cluster: Option<Arc<dyn Cluster>>,
     pub serialized_plan: Arc<SerializedPlan>,
@@ -741,6 +1134,7 @@ impl ExtensionPlanner for CubeExtensionPlanner {
             Ok(Some(self.plan_cluster_send(
                 input.clone(),
                 &cs.snapshots,
+                cs.join_tree.as_ref(),
                 input.schema(),
                 false,
                 usize::MAX,
@@ -760,6 +1154,7 @@ impl CubeExtensionPlanner {
         &self,
         input: Arc<dyn ExecutionPlan>,
         snapshots: &Vec<Vec<IndexSnapshot>>,
+        join_tree: Option<&JoinTree>,
         schema: SchemaRef,
         use_streaming: bool,
         max_batch_rows: usize,
@@ -774,6 +1169,7 @@ impl CubeExtensionPlanner {
                 c.clone(),
                 self.serialized_plan.clone(),
                 snapshots,
+                join_tree,
                 input,
                 use_streaming,
             )))
@@ -876,10 +1272,12 @@ pub mod tests {
     use crate::metastore::multi_index::MultiPartition;
     use crate::metastore::table::{Table, TablePath};
     use crate::metastore::{Chunk, Column, ColumnType, IdRow, Index, Partition, Schema};
-    use crate::queryplanner::planning::{choose_index, PlanIndexStore};
+    use crate::queryplanner::planning::{choose_index, PlanIndexStore, PlanningMeta};
     use crate::queryplanner::pretty_printers::PPOptions;
     use crate::queryplanner::{pretty_printers, CubeTableLogical};
     use crate::sql::parser::{CubeStoreParser, Statement};
+    use crate::table::stats::ColumnStats;
+    use crate::table::TableValue;
     use crate::CubeError;
     use datafusion::catalog::TableReference;
     use std::collections::HashMap;
@@ -1093,6 +1491,86 @@ pub mod tests {
         assert!(!pp.contains("TopK"), "plan contained topk:\n{}", pp);
     }
 
+    #[tokio::test]
+    pub async fn test_window_functions() {
+        let indices = default_indices();
+        // Window functions are computed on the router, after the distributed aggregation.
+        let plan = initial_plan(
+            "SELECT order_customer `customer`, SUM(order_amount) `amount`, \
+                    RANK() OVER (ORDER BY SUM(order_amount) DESC) `rank` \
+             FROM s.Orders GROUP BY 1",
+            &indices,
+        );
+        let plan = choose_index(&plan, &indices).await.unwrap().0;
+        let pp = pretty_printers::pp_plan(&plan);
+        let nodes = pp
+            .lines()
+            .map(|l| l.trim_start().split(',').next().unwrap())
+            .collect_vec();
+        assert_eq!(
+            nodes,
+            vec![
+                "Projection",
+                "Window",
+                "Aggregate",
+                "ClusterSend",
+                "Scan s.Orders"
+            ],
+            "unexpected plan:\n{}",
+            pp
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_prune_partitions_by_column_stats() {
+        let mut indices = default_indices();
+        let stats = |name_min: i64, name_max: i64| {
+            let mut stats = vec![ColumnStats::new(TableValue::Int(0), TableValue::Int(100), 0); 4];
+            stats[1] = ColumnStats::new(TableValue::Int(name_min), TableValue::Int(name_max), 0);
+            stats
+        };
+        for (min, max) in &[(0, 10), (20, 30)] {
+            indices.partitions.push(
+                Partition::new(0, None, None, None)
+                    .update_row_count(10)
+                    .update_column_stats(Some(stats(*min, *max))),
+            );
+        }
+
+        let partition_ids = |meta: &PlanningMeta| {
+            meta.indices[0]
+                .partitions
+                .iter()
+                .map(|p| p.partition.get_id())
+                .collect_vec()
+        };
+
+        let plan = initial_plan(
+            "SELECT customer_id FROM s.Customers WHERE customer_name = 25",
+            &indices,
+        );
+        let meta = choose_index(&plan, &indices).await.unwrap().1;
+        assert_eq!(partition_ids(&meta), vec![1]);
+
+        let plan = initial_plan(
+            "SELECT customer_id FROM s.Customers WHERE customer_name < 15",
+            &indices,
+        );
+        let meta = choose_index(&plan, &indices).await.unwrap().1;
+        assert_eq!(partition_ids(&meta), vec![0]);
+
+        // Partitions without statistics are never pruned.
+        indices
+            .partitions
+            .push(Partition::new(0, None, None, None).update_row_count(10));
+        let plan = initial_plan(
+            "SELECT customer_id FROM s.Customers WHERE customer_name = 25",
+            &indices,
+        );
+        let meta = choose_index(&plan, &indices).await.unwrap().1;
+        assert_eq!(partition_ids(&meta), vec![1, 2]);
+    }
+
     /// Most tests in this module use this schema.
     fn default_indices() -> TestIndices {
         const SCHEMA: u64 = 0;
@@ -1113,6 +1591,7 @@ pub mod tests {
             true,
             None,
             None,
+            None,
         ));
         i.indices.push(
             Index::try_new(
@@ -1142,6 +1621,7 @@ pub mod tests {
             true,
             None,
             None,
+            None,
         ));
         i.indices.push(
             Index::try_new(
@@ -1175,6 +1655,7 @@ pub mod tests {
             true,
             None,
             None,
+            None,
         ));
 
         i
@@ -27,6 +27,7 @@ use datafusion::physical_plan::expressions::Column;
 use datafusion::physical_plan::merge::MergeExec;
 use datafusion::physical_plan::projection::ProjectionExec;
 use datafusion::physical_plan::union::UnionExec;
+use datafusion::physical_plan::windows::WindowAggExec;
 
 #[derive(Default, Clone, Copy)]
 pub struct PPOptions {
@@ -181,9 +182,13 @@ pub fn pp_plan_ext(p: &LogicalPlan, opts: &PPOptions) -> String {
                         panic!("unknown extension node");
                     }
                 }
-                LogicalPlan::Window { .. } | LogicalPlan::CrossJoin { .. } => {
-                    panic!("unsupported logical plan node")
+                LogicalPlan::Window { window_expr, .. } => {
+                    self.output += "Window";
+                    if self.opts.show_aggregations {
+                        self.output += &format!(", aggs: {:?}", window_expr)
+                    }
                 }
+                LogicalPlan::CrossJoin { .. } => panic!("unsupported logical plan node"),
             }
 
             self.level += 1;
@@ -385,6 +390,14 @@ fn pp_phys_plan_indented(p: &dyn ExecutionPlan, indent: usize, o: &PPOptions, ou
             }
         } else if let Some(_) = a.downcast_ref::<UnionExec>() {
             *out += "Union";
+        } else if let Some(w) = a.downcast_ref::<WindowAggExec>() {
+            *out += "Window";
+            if o.show_aggregations {
+                *out += &format!(
+                    ", aggs: [{}]",
+                    w.window_expr().iter().map(|e| e.name()).join(", ")
+                )
+            }
         } else {
             panic!("unhandled ExecutionPlan: {:?}", p);
         }
@@ -1,4 +1,6 @@
-use crate::cluster::{pick_worker_by_ids, pick_worker_by_partitions, Cluster};
+use crate::cluster::membership::ClusterMembership;
+use crate::cluster::message::Compression;
+use crate::cluster::Cluster;
 use crate::config::injection::DIService;
 use crate::config::ConfigObj;
 use crate::metastore::multi_index::MultiPartition;
@@ -6,9 +8,14 @@ use crate::metastore::table::Table;
 use crate::metastore::{Column, ColumnType, IdRow, Index, Partition};
 use crate::queryplanner::filter_by_key_range::FilterByKeyRangeExec;
 use crate::queryplanner::optimizations::CubeQueryPlanner;
-use crate::queryplanner::planning::get_worker_plan;
+use crate::queryplanner::parquet_scan::ParquetScanExec;
+use crate::queryplanner::partition_filter::PartitionFilter;
+use crate::queryplanner::planning::{get_worker_plan, JoinTree};
 use crate::queryplanner::serialized_plan::{IndexSnapshot, RowFilter, RowRange, SerializedPlan};
 use crate::store::DataFrame;
+use crate::table::bloom::{BloomFilterCache, BloomFilterSet};
+use crate::table::data::cmp_row_key_heap;
+use crate::table::parquet::arrow_schema;
 use crate::table::{Row, TableValue, TimestampValue};
 use crate::{app_metrics, CubeError};
 use arrow::array::{
@@ -30,11 +37,11 @@ use datafusion::error::Result as DFResult;
 use datafusion::execution::context::{ExecutionConfig, ExecutionContext};
 use datafusion::logical_plan;
 use datafusion::logical_plan::{Expr, LogicalPlan};
+use datafusion::physical_optimizer::pruning::PruningPredicate;
 use datafusion::physical_plan::empty::EmptyExec;
 use datafusion::physical_plan::memory::MemoryExec;
 use datafusion::physical_plan::merge::MergeExec;
 use datafusion::physical_plan::merge_sort::{LastRowByUniqueKeyExec, MergeSortExec};
-use datafusion::physical_plan::parquet::ParquetExec;
 use datafusion::physical_plan::projection::ProjectionExec;
 use datafusion::physical_plan::{
     collect, ExecutionPlan, OptimizerHints, Partitioning, PhysicalExpr, SendableRecordBatchStream,
@@ -44,7 +51,7 @@ use log::{debug, error, trace, warn};
 use mockall::automock;
 use serde_derive::{Deserialize, Serialize};
 use std::any::Any;
-use std::cmp::min;
+use std::cmp::{min, Ordering};
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::io::Cursor;
@@ -84,7 +91,9 @@ pub trait QueryExecutor: DIService + Send + Sync {
 
 crate::di_service!(MockQueryExecutor, [QueryExecutor]);
 
-pub struct QueryExecutorImpl;
+pub struct QueryExecutorImpl {
+    bloom_filters: BloomFilterCache,
+}
 
 crate::di_service!(QueryExecutorImpl, [QueryExecutor]);
 
@@ -195,7 +204,7 @@ impl QueryExecutor for QueryExecutorImpl {
         plan: SerializedPlan,
         cluster: Arc<dyn Cluster>,
     ) -> Result<(Arc<dyn ExecutionPlan>, LogicalPlan), CubeError> {
-        let plan_to_move = plan.logical_plan(HashMap::new(), HashMap::new())?;
+        let plan_to_move = plan.logical_plan(HashMap::new(), HashMap::new(), HashMap::new())?;
         let serialized_plan = Arc::new(plan);
         let ctx = self.router_context(cluster.clone(), serialized_plan.clone())?;
         Ok((
@@ -210,7 +219,12 @@ impl QueryExecutor for QueryExecutorImpl {
         remote_to_local_names: HashMap<String, String>,
         chunk_id_to_record_batches: HashMap<u64, Vec<RecordBatch>>,
     ) -> Result<(Arc<dyn ExecutionPlan>, LogicalPlan), CubeError> {
-        let plan_to_move = plan.logical_plan(remote_to_local_names, chunk_id_to_record_batches)?;
+        let bloom_filters = self.bloom_filters.load(&remote_to_local_names).await?;
+        let plan_to_move = plan.logical_plan(
+            remote_to_local_names,
+            chunk_id_to_record_batches,
+            bloom_filters,
+        )?;
         let plan = Arc::new(plan);
         let ctx = self.worker_context(plan.clone())?;
         let plan_ctx = ctx.clone();
@@ -222,6 +236,14 @@ impl QueryExecutor for QueryExecutorImpl {
 }
 
 impl QueryExecutorImpl {
+    const BLOOM_FILTER_CACHE_CAPACITY: usize = 4096;
+
+    pub fn new() -> Arc<QueryExecutorImpl> {
+        Arc::new(QueryExecutorImpl {
+            bloom_filters: BloomFilterCache::new(Self::BLOOM_FILTER_CACHE_CAPACITY),
+        })
+    }
+
     fn router_context(
         &self,
         cluster: Arc<dyn Cluster>,
@@ -258,6 +280,9 @@ pub struct CubeTable {
     worker_partition_ids: Vec<(u64, RowFilter)>,
     #[serde(skip, default)]
     chunk_id_to_record_batches: HashMap<u64, Vec<RecordBatch>>,
+    /// Bloom filters of the downloaded files, keyed by remote names of the filter files.
+    #[serde(skip, default)]
+    bloom_filters: HashMap<String, Arc<BloomFilterSet>>,
     schema: SchemaRef,
 }
 
@@ -293,6 +318,7 @@ impl CubeTable {
             remote_to_local_names,
             worker_partition_ids,
             chunk_id_to_record_batches: HashMap::new(),
+            bloom_filters: HashMap::new(),
         })
     }
 
@@ -302,12 +328,14 @@ impl CubeTable {
         remote_to_local_names: HashMap<String, String>,
         worker_partition_ids: Vec<(u64, RowFilter)>,
         chunk_id_to_record_batches: HashMap<u64, Vec<RecordBatch>>,
+        bloom_filters: HashMap<String, Arc<BloomFilterSet>>,
     ) -> CubeTable {
         debug_assert!(worker_partition_ids.iter().is_sorted_by_key(|(id, _)| id));
         let mut t = self.clone();
         t.remote_to_local_names = remote_to_local_names;
         t.worker_partition_ids = worker_partition_ids;
         t.chunk_id_to_record_batches = chunk_id_to_record_batches;
+        t.bloom_filters = bloom_filters;
         t
     }
 
@@ -315,6 +343,18 @@ impl CubeTable {
         &self.index_snapshot
     }
 
+    /// Files without bloom filters are always scanned.
+    fn can_match_bloom_filters(
+        &self,
+        column_filter: &PartitionFilter,
+        bloom_filter_file: Option<String>,
+    ) -> bool {
+        match bloom_filter_file.and_then(|f| self.bloom_filters.get(&f)) {
+            Some(filters) => column_filter.can_match_bloom_filters(filters),
+            None => true,
+        }
+    }
+
     fn async_scan(
         &self,
         projection: &Option<Vec<usize>>,
@@ -360,7 +400,20 @@ impl CubeTable {
             partition_projection
         });
 
-        let predicate = combine_filters(filters);
+        let index = self.index_snapshot.index().get_row();
+        let index_schema = Arc::new(arrow_schema(index));
+        let column_filter = Arc::new(PartitionFilter::extract(&index_schema, filters));
+        let use_bloom_filters = column_filter.has_point_values(index.bloom_filter_columns());
+        let mut pruned_by_bloom_filters = 0;
+        let predicate = combine_filters(filters).and_then(|predicate| {
+            match PruningPredicate::try_new(&predicate, index_schema.clone()) {
+                Ok(p) => Some(Arc::new(p)),
+                Err(e) => {
+                    log::debug!("Row groups can't be pruned by {:?}: {}", predicate, e);
+                    None
+                }
+            }
+        });
         for partition_snapshot in partition_snapshots {
             let partition = partition_snapshot.partition();
             let filter = self
@@ -373,18 +426,33 @@ impl CubeTable {
 
             let key_len = self.index_snapshot.index.get_row().sort_key_size() as usize;
 
-            if let Some(remote_path) = partition.get_row().get_full_name(partition.get_id()) {
+            let main_table_pruned = use_bloom_filters
+                && !self.can_match_bloom_filters(
+                    &column_filter,
+                    partition
+                        .get_row()
+                        .get_bloom_filter_name(partition.get_id()),
+                );
+            if main_table_pruned {
+                pruned_by_bloom_filters += 1;
+            }
+            if let Some(remote_path) = partition
+                .get_row()
+                .get_full_name(partition.get_id())
+                .filter(|_| !main_table_pruned)
+            {
                 let local_path = self
                     .remote_to_local_names
                     .get(remote_path.as_str())
                     .expect(format!("Missing remote path {}", remote_path).as_str());
-                let arc: Arc<dyn ExecutionPlan> = Arc::new(ParquetExec::try_from_path(
+                let arc: Arc<dyn ExecutionPlan> = Arc::new(ParquetScanExec::try_new(
                     &local_path,
+                    index,
                     partition_projection.clone(),
-                    predicate.clone(),
                     batch_size,
-                    1,
-                    None, // TODO: propagate limit
+                    filter.clone(),
+                    column_filter.clone(),
+                    predicate.clone(),
                 )?);
                 let arc = FilterByKeyRangeExec::issue_filters(arc, filter.clone(), key_len);
                 partition_execs.push(arc);
@@ -392,6 +460,15 @@ impl CubeTable {
 
             let chunks = partition_snapshot.chunks();
             for chunk in chunks {
+                if use_bloom_filters
+                    && !self.can_match_bloom_filters(
+                        &column_filter,
+                        chunk.get_row().get_bloom_filter_name(chunk.get_id()),
+                    )
+                {
+                    pruned_by_bloom_filters += 1;
+                    continue;
+                }
                 let node: Arc<dyn ExecutionPlan> = if chunk.get_row().in_memory() {
                     let record_batches = self
                         .chunk_id_to_record_batches
@@ -418,13 +495,14 @@ impl CubeTable {
                         .remote_to_local_names
                         .get(&remote_path)
                         .expect(format!("Missing remote path {}", remote_path).as_str());
-                    Arc::new(ParquetExec::try_from_path(
+                    Arc::new(ParquetScanExec::try_new(
                         local_path,
+                        index,
                         partition_projection.clone(),
-                        predicate.clone(),
                         batch_size,
-                        1,
-                        None, // TODO: propagate limit
+                        filter.clone(),
+                        column_filter.clone(),
+                        predicate.clone(),
                     )?)
                 };
 
@@ -432,6 +510,9 @@ impl CubeTable {
                 partition_execs.push(node);
             }
         }
+        if pruned_by_bloom_filters > 0 {
+            trace!("Skipped {} files by bloom filters", pruned_by_bloom_filters);
+        }
 
         // We might need extra projection to re-order data.
         if let Some(projection) = projection_with_seq_column.as_ref() {
@@ -675,7 +756,7 @@ impl ExecutionPlan for CubeTableExec {
 pub struct ClusterSendExec {
     schema: SchemaRef,
     pub partitions: Vec<(
-        /*node*/ String,
+        /*replicas*/ Vec<String>,
         /*partition_id*/ Vec<(u64, RowRange)>,
     )>,
     /// Never executed, only stored to allow consistent optimization on router and worker.
@@ -691,12 +772,15 @@ impl ClusterSendExec {
         cluster: Arc<dyn Cluster>,
         serialized_plan: Arc<SerializedPlan>,
         union_snapshots: &[Vec<IndexSnapshot>],
+        join_tree: Option<&JoinTree>,
         input_for_optimizations: Arc<dyn ExecutionPlan>,
         use_streaming: bool,
     ) -> Self {
         let partitions = Self::distribute_to_workers(
             cluster.config().as_ref(),
+            cluster.membership().as_ref(),
             union_snapshots,
+            join_tree,
             &serialized_plan.planning_meta().multi_part_subtree,
         );
         Self {
@@ -711,18 +795,22 @@ impl ClusterSendExec {
 
     pub fn distribute_to_workers(
         config: &dyn ConfigObj,
+        membership: &ClusterMembership,
         snapshots: &[Vec<IndexSnapshot>],
+        join_tree: Option<&JoinTree>,
         tree: &HashMap<u64, MultiPartition>,
-    ) -> Vec<(String, Vec<(u64, RowRange)>)> {
-        let partitions = Self::logical_partitions(snapshots, tree);
-        Self::assign_nodes(config, partitions)
+    ) -> Vec<(Vec<String>, Vec<(u64, RowRange)>)> {
+        let partitions = Self::logical_partitions(config, snapshots, join_tree, tree);
+        Self::assign_nodes(membership, partitions)
     }
 
     fn logical_partitions(
+        config: &dyn ConfigObj,
         snapshots: &[Vec<IndexSnapshot>],
+        join_tree: Option<&JoinTree>,
         tree: &HashMap<u64, MultiPartition>,
     ) -> Vec<Vec<IdRow<Partition>>> {
-        let mut to_multiply = Vec::new();
+        let mut groups = Vec::with_capacity(snapshots.len());
         let mut multi_partitions = HashMap::<u64, Vec<_>>::new();
         for union in snapshots.iter() {
             let mut ordinary_partitions = Vec::new();
@@ -737,23 +825,23 @@ impl ClusterSendExec {
                     }
                 }
             }
-            if !ordinary_partitions.is_empty() {
-                to_multiply.push(ordinary_partitions);
-            }
+            groups.push(ordinary_partitions);
         }
-        assert!(to_multiply.is_empty() || multi_partitions.is_empty(),
-                "invalid state during partition selection. to_multiply: {:?}, multi_partitions: {:?}, snapshots: {:?}",
-                to_multiply, multi_partitions, snapshots);
+        assert!(groups.iter().all(|g| g.is_empty()) || multi_partitions.is_empty(),
+                "invalid state during partition selection. groups: {:?}, multi_partitions: {:?}, snapshots: {:?}",
+                groups, multi_partitions, snapshots);
         // Multi partitions define how we distribute joins. They may not be present, though.
         if !multi_partitions.is_empty() {
             return Self::distribute_multi_partitions(multi_partitions, tree);
         }
-        // Ordinary partitions need to be duplicated on multiple machines.
-        let partitions = to_multiply
-            .into_iter()
-            .multi_cartesian_product()
-            .collect::<Vec<Vec<_>>>();
-        partitions
+        match join_tree {
+            // Without joins, each partition is processed separately.
+            None => groups.into_iter().flatten().map(|p| vec![p]).collect(),
+            Some(join_tree) => {
+                JoinDistribution::new(join_tree, snapshots, config.broadcast_join_max_rows())
+                    .units(groups)
+            }
+        }
     }
 
     fn distribute_multi_partitions(
@@ -826,16 +914,18 @@ impl ClusterSendExec {
     }
 
     fn assign_nodes(
-        c: &dyn ConfigObj,
+        membership: &ClusterMembership,
         logical: Vec<Vec<IdRow<Partition>>>,
-    ) -> Vec<(String, Vec<(u64, RowRange)>)> {
+    ) -> Vec<(Vec<String>, Vec<(u64, RowRange)>)> {
         let mut m: HashMap<_, Vec<(u64, RowRange)>> = HashMap::new();
         for ps in &logical {
-            let node = match ps[0].get_row().multi_partition_id() {
-                Some(multi_id) => pick_worker_by_ids(c, [multi_id]),
-                None => pick_worker_by_partitions(c, ps.as_slice()),
+            let replicas = match ps[0].get_row().multi_partition_id() {
+                Some(multi_id) => membership.replicas_by_ids([multi_id]),
+                // Joins put the partition that drives the distribution first, other partitions
+                // are sent along with it. Keeps the unit on the worker that warms up the partition.
+                None => membership.replicas_by_partitions(&ps[..1]),
             };
-            m.entry(node.to_string())
+            m.entry(replicas)
                 .or_default()
                 .extend(Self::issue_filters(ps))
         }
@@ -861,6 +951,123 @@ impl ClusterSendExec {
     }
 }
 
+/// Assigns partitions of joined snapshot groups to units of work, see [JoinTree].
+#[derive(Debug)]
+struct JoinDistribution {
+    /// Each partition of the first group forms a separate unit. Partitions of the second group,
+    /// if any, are added to units with overlapping ranges of the first `key_len` key columns.
+    /// No drivers means all partitions are processed as a single unit.
+    drivers: Vec<usize>,
+    key_len: usize,
+    /// Partitions of these groups are added to every unit.
+    broadcast: Vec<usize>,
+}
+
+impl JoinDistribution {
+    fn new(t: &JoinTree, snapshots: &[Vec<IndexSnapshot>], max_broadcast_rows: u64) -> Self {
+        match t {
+            JoinTree::Group(g) => JoinDistribution {
+                drivers: vec![*g],
+                key_len: 0,
+                broadcast: Vec::new(),
+            },
+            JoinTree::Single(groups) => JoinDistribution {
+                drivers: Vec::new(),
+                key_len: 0,
+                broadcast: groups.clone(),
+            },
+            JoinTree::Join {
+                probe,
+                build,
+                inner,
+                key_len,
+            } => {
+                let probe_rows = group_rows(snapshots, &probe.groups());
+                let build_rows = group_rows(snapshots, &build.groups());
+                if *inner && probe_rows <= max_broadcast_rows && probe_rows < build_rows {
+                    Self::new(build, snapshots, max_broadcast_rows).with_broadcast(probe.groups())
+                } else if build_rows <= max_broadcast_rows {
+                    Self::new(probe, snapshots, max_broadcast_rows).with_broadcast(build.groups())
+                } else if *key_len != 0 {
+                    JoinDistribution {
+                        drivers: t.groups(),
+                        key_len: *key_len,
+                        broadcast: Vec::new(),
+                    }
+                } else {
+                    JoinDistribution {
+                        drivers: Vec::new(),
+                        key_len: 0,
+                        broadcast: t.groups(),
+                    }
+                }
+            }
+        }
+    }
+
+    fn with_broadcast(mut self, groups: Vec<usize>) -> Self {
+        self.broadcast.extend(groups);
+        self
+    }
+
+    fn units(&self, mut groups: Vec<Vec<IdRow<Partition>>>) -> Vec<Vec<IdRow<Partition>>> {
+        let broadcast = self
+            .broadcast
+            .iter()
+            .flat_map(|g| take(&mut groups[*g]))
+            .collect_vec();
+        let (first, second) = match self.drivers.as_slice() {
+            [] if broadcast.is_empty() => return Vec::new(),
+            [] => return vec![broadcast],
+            [first] => (take(&mut groups[*first]), Vec::new()),
+            [first, second] => (take(&mut groups[*first]), take(&mut groups[*second])),
+            _ => panic!("unexpected join distribution: {:?}", self),
+        };
+        let mut r = Vec::with_capacity(first.len());
+        for p in first {
+            let matching = second
+                .iter()
+                .filter(|s| key_ranges_overlap(self.key_len, &p, s))
+                .cloned()
+                .collect_vec();
+            let mut unit = vec![p];
+            unit.extend(matching);
+            unit.extend(broadcast.iter().cloned());
+            r.push(unit);
+        }
+        r
+    }
+}
+
+/// Number of rows in partitions and chunks of the snapshot groups.
+fn group_rows(snapshots: &[Vec<IndexSnapshot>], groups: &[usize]) -> u64 {
+    groups
+        .iter()
+        .flat_map(|g| snapshots[*g].iter())
+        .flat_map(|i| i.partitions.iter())
+        .map(|p| {
+            p.partition.get_row().main_table_row_count()
+                + p.chunks
+                    .iter()
+                    .map(|c| c.get_row().get_row_count())
+                    .sum::<u64>()
+        })
+        .sum()
+}
+
+/// Checks if partitions may contain rows with the same values of the first `key_len` columns.
+fn key_ranges_overlap(key_len: usize, l: &IdRow<Partition>, r: &IdRow<Partition>) -> bool {
+    // Rows at the exclusive upper bound may share the key prefix with the rows inside.
+    let precedes = |max: &Option<Row>, min: &Option<Row>| match (max, min) {
+        (Some(max), Some(min)) => {
+            cmp_row_key_heap(key_len, max.values(), min.values()) == Ordering::Less
+        }
+        _ => false,
+    };
+    let (l, r) = (l.get_row(), r.get_row());
+    !precedes(l.get_max_val(), r.get_min_val()) && !precedes(r.get_max_val(), l.get_min_val())
+}
+
 #[async_trait]
 impl ExecutionPlan for ClusterSendExec {
     fn as_any(&self) -> &dyn Any {
@@ -906,7 +1113,7 @@ impl ExecutionPlan for ClusterSendExec {
         &self,
         partition: usize,
     ) -> Result<SendableRecordBatchStream, DataFusionError> {
-        let (node_name, partitions) = &self.partitions[partition];
+        let (replicas, partitions) = &self.partitions[partition];
 
         let mut ps = HashMap::<_, RowFilter>::new();
         for (id, range) in partitions {
@@ -917,9 +1124,9 @@ impl ExecutionPlan for ClusterSendExec {
 
         let plan = self.serialized_plan.with_partition_id_to_execute(ps);
         if self.use_streaming {
-            Ok(self.cluster.run_select_stream(node_name, plan).await?)
+            Ok(self.cluster.run_select_stream(replicas, plan).await?)
         } else {
-            let record_batches = self.cluster.run_select(node_name, plan).await?;
+            let record_batches = self.cluster.run_select(replicas, plan).await?;
             // TODO .to_schema_ref()
             let memory_exec = MemoryExec::try_new(&vec![record_batches], self.schema(), None)?;
             memory_exec.execute(0).await
@@ -1177,6 +1384,7 @@ pub fn arrow_to_column_type(arrow_type: DataType) -> Result<ColumnType, CubeErro
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SerializedRecordBatchStream {
+    compression: Compression,
     #[serde(with = "serde_bytes")] // serde_bytes makes serialization efficient.
     record_batch_file: Vec<u8>,
 }
@@ -1193,14 +1401,36 @@ impl SerializedRecordBatchStream {
             writer.write(&batch)?;
             let cursor = writer.finish()?;
             results.push(Self {
+                compression: Compression::None,
                 record_batch_file: cursor.into_inner(),
             })
         }
         Ok(results)
     }
 
+    /// Does nothing if the batch is already compressed. [read] decompresses the batch.
+    pub fn compress(&mut self, compression: Compression) -> Result<(), CubeError> {
+        if self.compression != Compression::None || compression == Compression::None {
+            return Ok(());
+        }
+        self.record_batch_file = compression.compress(&self.record_batch_file)?;
+        self.compression = compression;
+        Ok(())
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compression != Compression::None
+    }
+
+    pub fn decompress(&mut self) -> Result<(), CubeError> {
+        let file = std::mem::take(&mut self.record_batch_file);
+        self.record_batch_file = self.compression.decompress(file)?;
+        self.compression = Compression::None;
+        Ok(())
+    }
+
     pub fn read(self) -> Result<RecordBatch, CubeError> {
-        let cursor = Cursor::new(self.record_batch_file);
+        let cursor = Cursor::new(self.compression.decompress(self.record_batch_file)?);
         let mut reader = StreamReader::try_new(cursor)?;
         let batch = reader.next();
         if batch.is_none() {
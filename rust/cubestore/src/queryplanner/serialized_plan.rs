@@ -1,6 +1,6 @@
 use crate::metastore::table::{Table, TablePath};
 use crate::metastore::{Chunk, IdRow, Index, Partition};
-use crate::queryplanner::planning::{ClusterSendNode, PlanningMeta};
+use crate::queryplanner::planning::{ClusterSendNode, JoinTree, PlanningMeta};
 use crate::queryplanner::query_executor::CubeTable;
 use crate::queryplanner::topk::{ClusterAggregateTopK, SortColumn};
 use crate::queryplanner::udfs::aggregate_udf_by_kind;
@@ -8,7 +8,9 @@ use crate::queryplanner::udfs::{
     aggregate_kind_by_name, scalar_kind_by_name, scalar_udf_by_kind, CubeAggregateUDFKind,
     CubeScalarUDFKind,
 };
-use crate::table::Row;
+use crate::table::bloom::BloomFilterSet;
+use crate::table::stats::ColumnStats;
+use crate::table::{cmp_same_types, Row, TableValue};
 use crate::CubeError;
 use arrow::datatypes::DataType;
 use arrow::record_batch::RecordBatch;
@@ -16,15 +18,16 @@ use datafusion::cube_ext::alias::LogicalAlias;
 use datafusion::cube_ext::join::SkewedLeftCrossJoin;
 use datafusion::cube_ext::joinagg::CrossJoinAgg;
 use datafusion::cube_ext::rolling::RollingWindowAggregate;
-use datafusion::logical_plan::window_frames::WindowFrameBound;
+use datafusion::logical_plan::window_frames::{WindowFrame, WindowFrameBound};
 use datafusion::logical_plan::{
     Column, DFSchemaRef, Expr, JoinConstraint, JoinType, LogicalPlan, Operator, Partitioning,
     PlanVisitor,
 };
-use datafusion::physical_plan::{aggregates, functions};
+use datafusion::physical_plan::{aggregates, functions, window_functions};
 use datafusion::scalar::ScalarValue;
 use serde_derive::{Deserialize, Serialize};
 use sqlparser::ast::RollingOffset;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
@@ -41,6 +44,37 @@ impl RowRange {
     pub fn matches_all_rows(&self) -> bool {
         self.start.is_none() && self.end.is_none()
     }
+
+    /// Returns false if none of the rows described by statistics of the key columns can be
+    /// inside the range. Every row is between the rows of minimums and maximums of each column.
+    pub fn can_match_column_stats(&self, key_stats: &[ColumnStats]) -> bool {
+        if let Some(start) = &self.start {
+            let max_row = key_stats.iter().map(|s| s.range().1);
+            if cmp_row_prefix(max_row, start.values()) == Ordering::Less {
+                return false;
+            }
+        }
+        if let Some(end) = &self.end {
+            let min_row = key_stats.iter().map(|s| s.range().0);
+            match cmp_row_prefix(min_row, end.values()) {
+                Ordering::Greater => return false,
+                Ordering::Equal if key_stats.len() == end.values().len() => return false,
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+/// Compares values of a row with a prefix of the same length of `r`.
+fn cmp_row_prefix<'a>(l: impl Iterator<Item = &'a TableValue>, r: &[TableValue]) -> Ordering {
+    for (l, r) in l.zip(r.iter()) {
+        match cmp_same_types(l, r) {
+            Ordering::Equal => continue,
+            o => return o,
+        }
+    }
+    Ordering::Equal
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -64,6 +98,12 @@ impl RowFilter {
     pub fn matches_all_rows(&self) -> bool {
         self.or_filters.len() == 1 && self.or_filters[0].matches_all_rows()
     }
+
+    pub fn can_match_column_stats(&self, key_stats: &[ColumnStats]) -> bool {
+        self.or_filters
+            .iter()
+            .any(|r| r.can_match_column_stats(key_stats))
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -191,6 +231,7 @@ pub enum SerializedLogicalPlan {
     ClusterSend {
         input: Arc<SerializedLogicalPlan>,
         snapshots: Vec<Vec<IndexSnapshot>>,
+        join_tree: Option<JoinTree>,
     },
     ClusterAggregateTopK {
         limit: usize,
@@ -200,6 +241,7 @@ pub enum SerializedLogicalPlan {
         sort_columns: Vec<SortColumn>,
         schema: DFSchemaRef,
         snapshots: Vec<Vec<IndexSnapshot>>,
+        join_tree: Option<JoinTree>,
     },
     CrossJoin {
         left: Arc<SerializedLogicalPlan>,
@@ -229,6 +271,11 @@ pub enum SerializedLogicalPlan {
         group_by_dimension: Option<SerializedExpr>,
         aggs: Vec<SerializedExpr>,
     },
+    Window {
+        input: Arc<SerializedLogicalPlan>,
+        window_expr: Vec<SerializedExpr>,
+        schema: DFSchemaRef,
+    },
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -241,6 +288,7 @@ pub struct WorkerContext {
     remote_to_local_names: HashMap<String, String>,
     worker_partition_ids: Vec<(u64, RowFilter)>,
     chunk_id_to_record_batches: HashMap<u64, Vec<RecordBatch>>,
+    bloom_filters: HashMap<String, Arc<BloomFilterSet>>,
 }
 
 impl SerializedLogicalPlan {
@@ -307,6 +355,7 @@ impl SerializedLogicalPlan {
                         worker_context.remote_to_local_names.clone(),
                         worker_context.worker_partition_ids.clone(),
                         worker_context.chunk_id_to_record_batches.clone(),
+                        worker_context.bloom_filters.clone(),
                     )),
                 },
                 projection: projection.clone(),
@@ -367,9 +416,14 @@ impl SerializedLogicalPlan {
                     schema: schema.clone(),
                 }),
             },
-            SerializedLogicalPlan::ClusterSend { input, snapshots } => ClusterSendNode {
+            SerializedLogicalPlan::ClusterSend {
+                input,
+                snapshots,
+                join_tree,
+            } => ClusterSendNode {
                 input: Arc::new(input.logical_plan(worker_context)?),
                 snapshots: snapshots.clone(),
+                join_tree: join_tree.clone(),
             }
             .into_plan(),
             SerializedLogicalPlan::ClusterAggregateTopK {
@@ -380,6 +434,7 @@ impl SerializedLogicalPlan {
                 sort_columns,
                 schema,
                 snapshots,
+                join_tree,
             } => ClusterAggregateTopK {
                 limit: *limit,
                 input: Arc::new(input.logical_plan(worker_context)?),
@@ -388,6 +443,7 @@ impl SerializedLogicalPlan {
                 order_by: sort_columns.clone(),
                 schema: schema.clone(),
                 snapshots: snapshots.clone(),
+                join_tree: join_tree.clone(),
             }
             .into_plan(),
             SerializedLogicalPlan::CrossJoin {
@@ -449,6 +505,15 @@ impl SerializedLogicalPlan {
                     aggs: exprs(&aggs),
                 }),
             },
+            SerializedLogicalPlan::Window {
+                input,
+                window_expr,
+                schema,
+            } => LogicalPlan::Window {
+                input: Arc::new(input.logical_plan(worker_context)?),
+                window_expr: exprs(&window_expr),
+                schema: schema.clone(),
+            },
         })
     }
 }
@@ -518,6 +583,13 @@ pub enum SerializedExpr {
         end: WindowFrameBound,
         offset_to_end: bool,
     },
+    WindowFunction {
+        fun: window_functions::WindowFunction,
+        args: Vec<SerializedExpr>,
+        partition_by: Vec<SerializedExpr>,
+        order_by: Vec<SerializedExpr>,
+        window_frame: Option<WindowFrame>,
+    },
     InList {
         expr: Box<SerializedExpr>,
         list: Vec<SerializedExpr>,
@@ -621,6 +693,19 @@ impl SerializedExpr {
                     true => RollingOffset::End,
                 },
             },
+            SerializedExpr::WindowFunction {
+                fun,
+                args,
+                partition_by,
+                order_by,
+                window_frame,
+            } => Expr::WindowFunction {
+                fun: fun.clone(),
+                args: exprs(&args),
+                partition_by: exprs(&partition_by),
+                order_by: exprs(&order_by),
+                window_frame: window_frame.clone(),
+            },
             SerializedExpr::InList {
                 expr,
                 list,
@@ -667,11 +752,13 @@ impl SerializedPlan {
         &self,
         remote_to_local_names: HashMap<String, String>,
         chunk_id_to_record_batches: HashMap<u64, Vec<RecordBatch>>,
+        bloom_filters: HashMap<String, Arc<BloomFilterSet>>,
     ) -> Result<LogicalPlan, CubeError> {
         self.logical_plan.logical_plan(&WorkerContext {
             remote_to_local_names,
             worker_partition_ids: self.partition_ids_to_execute.clone(),
             chunk_id_to_record_batches,
+            bloom_filters,
         })
     }
 
@@ -713,10 +800,21 @@ impl SerializedPlan {
                 {
                     files.push(file);
                 }
+                // Bloom filters are small, workers use them to skip files on point lookups.
+                if let Some(file) = partition
+                    .partition
+                    .get_row()
+                    .get_bloom_filter_name(partition.partition.get_id())
+                {
+                    files.push(file);
+                }
 
                 for chunk in partition.chunks() {
                     if !chunk.get_row().in_memory() {
-                        files.push(chunk.get_row().get_full_name(chunk.get_id()))
+                        files.push(chunk.get_row().get_full_name(chunk.get_id()));
+                        if let Some(file) = chunk.get_row().get_bloom_filter_name(chunk.get_id()) {
+                            files.push(file);
+                        }
                     }
                 }
             }
@@ -768,7 +866,7 @@ impl SerializedPlan {
             fn pre_visit(&mut self, plan: &LogicalPlan) -> Result<bool, Self::Error> {
                 if let LogicalPlan::TableScan { table_name, .. } = plan {
                     let name_split = table_name.split(".").collect::<Vec<_>>();
-                    if name_split[0].to_string() != "information_schema" {
+                    if name_split[0] != "information_schema" && name_split[0] != "system" {
                         self.seen_data_scans = true;
                         return Ok(false);
                     }
@@ -859,6 +957,7 @@ impl SerializedPlan {
                     SerializedLogicalPlan::ClusterSend {
                         input: Arc::new(Self::serialized_logical_plan(&cs.input)),
                         snapshots: cs.snapshots.clone(),
+                        join_tree: cs.join_tree.clone(),
                     }
                 } else if let Some(topk) = node.as_any().downcast_ref::<ClusterAggregateTopK>() {
                     SerializedLogicalPlan::ClusterAggregateTopK {
@@ -877,6 +976,7 @@ impl SerializedPlan {
                         sort_columns: topk.order_by.clone(),
                         schema: topk.schema.clone(),
                         snapshots: topk.snapshots.clone(),
+                        join_tree: topk.join_tree.clone(),
                     }
                 } else if let Some(j) = node.as_any().downcast_ref::<CrossJoinAgg>() {
                     SerializedLogicalPlan::CrossJoinAgg {
@@ -961,9 +1061,16 @@ impl SerializedPlan {
                     ),
                 },
             },
-            LogicalPlan::Window { .. } | LogicalPlan::CrossJoin { .. } => {
-                panic!("unsupported plan node")
-            }
+            LogicalPlan::Window {
+                input,
+                window_expr,
+                schema,
+            } => SerializedLogicalPlan::Window {
+                input: Arc::new(Self::serialized_logical_plan(&input)),
+                window_expr: Self::exprs(window_expr),
+                schema: schema.clone(),
+            },
+            LogicalPlan::CrossJoin { .. } => panic!("unsupported plan node"),
         }
     }
 
@@ -1082,7 +1189,19 @@ impl SerializedPlan {
                     RollingOffset::End => true,
                 },
             },
-            Expr::WindowFunction { .. } => panic!("window functions are not supported"),
+            Expr::WindowFunction {
+                fun,
+                args,
+                partition_by,
+                order_by,
+                window_frame,
+            } => SerializedExpr::WindowFunction {
+                fun: fun.clone(),
+                args: Self::serialized_exprs(args),
+                partition_by: Self::serialized_exprs(partition_by),
+                order_by: Self::serialized_exprs(order_by),
+                window_frame: window_frame.clone(),
+            },
         }
     }
 
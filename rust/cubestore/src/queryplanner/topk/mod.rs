@@ -5,6 +5,7 @@ pub use execute::AggregateTopKExec;
 pub use plan::materialize_topk;
 pub use plan::plan_topk;
 
+use crate::queryplanner::planning::JoinTree;
 use crate::queryplanner::serialized_plan::IndexSnapshot;
 use arrow::compute::SortOptions;
 use datafusion::logical_plan::{DFSchemaRef, Expr, LogicalPlan, UserDefinedLogicalNode};
@@ -30,6 +31,7 @@ pub struct ClusterAggregateTopK {
     pub order_by: Vec<SortColumn>,
     pub schema: DFSchemaRef,
     pub snapshots: Vec<Vec<IndexSnapshot>>,
+    pub join_tree: Option<JoinTree>,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -116,6 +118,7 @@ impl UserDefinedLogicalNode for ClusterAggregateTopK {
             order_by: self.order_by.clone(),
             schema: self.schema.clone(),
             snapshots: self.snapshots.clone(),
+            join_tree: self.join_tree.clone(),
         })
     }
 }
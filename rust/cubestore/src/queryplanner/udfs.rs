@@ -1,9 +1,14 @@
 use crate::queryplanner::coalesce::{coalesce, SUPPORTED_COALESCE_TYPES};
 use crate::queryplanner::hll::Hll;
 use crate::CubeError;
-use arrow::array::{Array, BinaryArray, TimestampNanosecondArray, UInt64Builder};
+use arrow::array::{
+    Array, BinaryArray, BinaryBuilder, Float64Array, Float64Builder, StringArray,
+    TimestampNanosecondArray, UInt64Builder,
+};
 use arrow::datatypes::{DataType, IntervalUnit, TimeUnit};
 use chrono::{TimeZone, Utc};
+use cubehll::murmur3::{hash64, hash64_long};
+use cubetdigest::TDigest;
 use datafusion::cube_ext::datetime::{date_addsub_array, date_addsub_scalar};
 use datafusion::error::DataFusionError;
 use datafusion::physical_plan::functions::Signature;
@@ -24,6 +29,8 @@ pub enum CubeScalarUDFKind {
     UnixTimestamp,
     DateAdd,
     DateSub,
+    TdigestQuantile, // quantile(), accepting the TDigest sketches.
+    HllExport,       // hll_export(), converts HyperLogLog sketches for other systems.
 }
 
 pub trait CubeScalarUDF {
@@ -40,6 +47,8 @@ pub fn scalar_udf_by_kind(k: CubeScalarUDFKind) -> Box<dyn CubeScalarUDF> {
         CubeScalarUDFKind::UnixTimestamp => Box::new(UnixTimestamp {}),
         CubeScalarUDFKind::DateAdd => Box::new(DateAddSub { is_add: true }),
         CubeScalarUDFKind::DateSub => Box::new(DateAddSub { is_add: false }),
+        CubeScalarUDFKind::TdigestQuantile => Box::new(TdigestQuantile {}),
+        CubeScalarUDFKind::HllExport => Box::new(HllExport {}),
     }
 }
 
@@ -63,12 +72,21 @@ pub fn scalar_kind_by_name(n: &str) -> Option<CubeScalarUDFKind> {
     if n == "DATE_SUB" {
         return Some(CubeScalarUDFKind::DateSub);
     }
+    if n == "QUANTILE" {
+        return Some(CubeScalarUDFKind::TdigestQuantile);
+    }
+    if n == "HLL_EXPORT" {
+        return Some(CubeScalarUDFKind::HllExport);
+    }
     return None;
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum CubeAggregateUDFKind {
-    MergeHll, // merge(), accepting the HyperLogLog sketches.
+    MergeHll,        // merge(), accepting the HyperLogLog sketches.
+    PercentileMerge, // percentile_merge(), accepting the TDigest sketches.
+    HllInit,         // hll_init(), builds Airlift HyperLogLog sketches from values.
+    HllInitAgg,      // hll_init_agg(), builds ZetaSketch HyperLogLog++ sketches from values.
 }
 
 pub trait CubeAggregateUDF {
@@ -81,6 +99,9 @@ pub trait CubeAggregateUDF {
 pub fn aggregate_udf_by_kind(k: CubeAggregateUDFKind) -> Box<dyn CubeAggregateUDF> {
     match k {
         CubeAggregateUDFKind::MergeHll => Box::new(HllMergeUDF {}),
+        CubeAggregateUDFKind::PercentileMerge => Box::new(PercentileMergeUDF {}),
+        CubeAggregateUDFKind::HllInit => Box::new(HllInitUDF { zeta_sketch: false }),
+        CubeAggregateUDFKind::HllInitAgg => Box::new(HllInitUDF { zeta_sketch: true }),
     }
 }
 
@@ -89,6 +110,15 @@ pub fn aggregate_kind_by_name(n: &str) -> Option<CubeAggregateUDFKind> {
     if n == "MERGE" {
         return Some(CubeAggregateUDFKind::MergeHll);
     }
+    if n == "PERCENTILE_MERGE" {
+        return Some(CubeAggregateUDFKind::PercentileMerge);
+    }
+    if n == "HLL_INIT" {
+        return Some(CubeAggregateUDFKind::HllInit);
+    }
+    if n == "HLL_INIT_AGG" {
+        return Some(CubeAggregateUDFKind::HllInitAgg);
+    }
     return None;
 }
 
@@ -327,6 +357,59 @@ impl CubeScalarUDF for HllCardinality {
     }
 }
 
+struct HllExport {}
+impl CubeScalarUDF for HllExport {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::HllExport;
+    }
+
+    fn name(&self) -> &str {
+        return "HLL_EXPORT";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Binary, DataType::Utf8]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Binary))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 2);
+                let len = a
+                    .iter()
+                    .find_map(|v| match v {
+                        ColumnarValue::Array(a) => Some(a.len()),
+                        ColumnarValue::Scalar(_) => None,
+                    })
+                    .unwrap_or(1);
+                let sketches = a[0].clone().into_array(len);
+                let sketches = sketches
+                    .as_any()
+                    .downcast_ref::<BinaryArray>()
+                    .expect("expected binary data");
+                let formats = a[1].clone().into_array(len);
+                let formats = formats
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .expect("expected string data");
+
+                let mut r = BinaryBuilder::new(len);
+                for i in 0..len {
+                    // Empty data is the state of an empty sketch, it has no precision to export.
+                    if sketches.is_null(i) || formats.is_null(i) || sketches.value(i).is_empty() {
+                        r.append_null()?;
+                        continue;
+                    }
+                    let exported = read_sketch(sketches.value(i))?
+                        .export(formats.value(i))
+                        .map_err(|e| DataFusionError::Execution(e.message))?;
+                    r.append_value(&exported)?;
+                }
+                return Ok(ColumnarValue::Array(Arc::new(r.finish())));
+            }),
+        };
+    }
+}
+
 struct HllMergeUDF {}
 impl CubeAggregateUDF for HllMergeUDF {
     fn kind(&self) -> CubeAggregateUDFKind {
@@ -433,6 +516,353 @@ impl HllMergeAccumulator {
     }
 }
 
+/// Builds HyperLogLog sketches from raw values. Hashes values exactly like Presto's `approx_set`
+/// for Airlift sketches or BigQuery's `HLL_COUNT.INIT` for ZetaSketch, so results can be merged
+/// with sketches produced by those systems.
+struct HllInitUDF {
+    zeta_sketch: bool,
+}
+
+impl HllInitUDF {
+    fn signature(&self) -> Signature {
+        let mut types = vec![Signature::Exact(vec![DataType::Int64])];
+        // ZetaSketch does not support floating point values.
+        if !self.zeta_sketch {
+            types.push(Signature::Exact(vec![DataType::Float64]));
+        }
+        types.push(Signature::Exact(vec![DataType::Utf8]));
+        Signature::OneOf(types)
+    }
+
+    fn new_accumulator(zeta_sketch: bool) -> HllInitAccumulator {
+        return HllInitAccumulator {
+            zeta_sketch,
+            acc: HllInitAccumulator::empty_sketch(zeta_sketch),
+        };
+    }
+}
+
+impl CubeAggregateUDF for HllInitUDF {
+    fn kind(&self) -> CubeAggregateUDFKind {
+        match self.zeta_sketch {
+            false => CubeAggregateUDFKind::HllInit,
+            true => CubeAggregateUDFKind::HllInitAgg,
+        }
+    }
+    fn name(&self) -> &str {
+        match self.zeta_sketch {
+            false => "HLL_INIT",
+            true => "HLL_INIT_AGG",
+        }
+    }
+    fn descriptor(&self) -> AggregateUDF {
+        let zeta_sketch = self.zeta_sketch;
+        return AggregateUDF {
+            name: self.name().to_string(),
+            signature: self.signature(),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Binary))),
+            accumulator: Arc::new(move || Ok(Box::new(HllInitUDF::new_accumulator(zeta_sketch)))),
+            state_type: Arc::new(|_| Ok(Arc::new(vec![DataType::Binary]))),
+        };
+    }
+    fn accumulator(&self) -> Box<dyn Accumulator> {
+        return Box::new(HllInitUDF::new_accumulator(self.zeta_sketch));
+    }
+}
+
+#[derive(Debug)]
+struct HllInitAccumulator {
+    zeta_sketch: bool,
+    acc: Hll,
+}
+
+impl HllInitAccumulator {
+    fn empty_sketch(zeta_sketch: bool) -> Hll {
+        match zeta_sketch {
+            false => Hll::new_airlift(),
+            true => Hll::new_zeta_sketch(),
+        }
+    }
+}
+
+impl Accumulator for HllInitAccumulator {
+    fn reset(&mut self) {
+        self.acc = Self::empty_sketch(self.zeta_sketch);
+    }
+
+    fn state(&self) -> Result<SmallVec<[ScalarValue; 2]>, DataFusionError> {
+        return Ok(smallvec![self.evaluate()?]);
+    }
+
+    fn update(&mut self, row: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(row.len(), 1);
+        let name = match self.zeta_sketch {
+            false => "HLL_INIT",
+            true => "HLL_INIT_AGG",
+        };
+        match (&mut self.acc, &row[0]) {
+            // Ignore NULLs.
+            (_, ScalarValue::Int64(None)) => {}
+            (_, ScalarValue::Float64(None)) => {}
+            (_, ScalarValue::Utf8(None)) => {}
+            (Hll::Airlift(h), ScalarValue::Int64(Some(v))) => h.insert_hash(hash64_long(*v)),
+            // Presto hashes the result of `Double.doubleToLongBits`.
+            (Hll::Airlift(h), ScalarValue::Float64(Some(v))) => {
+                let bits = if v.is_nan() {
+                    f64::NAN.to_bits()
+                } else {
+                    v.to_bits()
+                };
+                h.insert_hash(hash64_long(bits as i64))
+            }
+            (Hll::Airlift(h), ScalarValue::Utf8(Some(v))) => h.insert_hash(hash64(v.as_bytes())),
+            (Hll::ZetaSketch(h), ScalarValue::Int64(Some(v))) => h
+                .add(v)
+                .map_err(|e| DataFusionError::Execution(e.message))?,
+            (Hll::ZetaSketch(h), ScalarValue::Utf8(Some(v))) => h
+                .add(v.as_str())
+                .map_err(|e| DataFusionError::Execution(e.message))?,
+            (_, v) => {
+                return Err(CubeError::internal(format!(
+                    "invalid scalar value passed to {}: {:?}",
+                    name, v
+                ))
+                .into())
+            }
+        }
+        return Ok(());
+    }
+
+    fn merge(&mut self, states: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(states.len(), 1);
+
+        let data;
+        if let ScalarValue::Binary(v) = &states[0] {
+            if let Some(d) = v {
+                data = d
+            } else {
+                return Ok(()); // ignore NULL.
+            }
+        } else {
+            return Err(CubeError::internal("invalid state in HLL_INIT".to_string()).into());
+        }
+        let s = read_sketch(&data)?;
+        if !self.acc.is_compatible(&s) {
+            return Err(CubeError::internal(
+                "cannot merge two incompatible HLL sketches".to_string(),
+            )
+            .into());
+        }
+        self.acc.merge_with(&s)?;
+        return Ok(());
+    }
+
+    fn evaluate(&self) -> Result<ScalarValue, DataFusionError> {
+        return Ok(ScalarValue::Binary(Some(self.acc.write())));
+    }
+}
+
 fn read_sketch(data: &[u8]) -> Result<Hll, DataFusionError> {
     return Hll::read(&data).map_err(|e| DataFusionError::Execution(e.message));
 }
+
+struct TdigestQuantile {}
+impl CubeScalarUDF for TdigestQuantile {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::TdigestQuantile;
+    }
+
+    fn name(&self) -> &str {
+        return "QUANTILE";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Binary, DataType::Float64]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Float64))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 2);
+                let len = a
+                    .iter()
+                    .find_map(|v| match v {
+                        ColumnarValue::Array(a) => Some(a.len()),
+                        ColumnarValue::Scalar(_) => None,
+                    })
+                    .unwrap_or(1);
+                let sketches = a[0].clone().into_array(len);
+                let sketches = sketches
+                    .as_any()
+                    .downcast_ref::<BinaryArray>()
+                    .expect("expected binary data");
+                let quantiles = a[1].clone().into_array(len);
+                let quantiles = quantiles
+                    .as_any()
+                    .downcast_ref::<Float64Array>()
+                    .expect("expected float data");
+
+                let mut r = Float64Builder::new(len);
+                for i in 0..len {
+                    // Empty data is the state of an empty sketch.
+                    if sketches.is_null(i) || quantiles.is_null(i) || sketches.value(i).is_empty() {
+                        r.append_null()?;
+                        continue;
+                    }
+                    let q = read_tdigest(sketches.value(i))?
+                        .quantile(quantiles.value(i))
+                        .map_err(|e| DataFusionError::Execution(e.message))?;
+                    match q {
+                        None => r.append_null()?,
+                        Some(q) => r.append_value(q)?,
+                    }
+                }
+                return Ok(ColumnarValue::Array(Arc::new(r.finish())));
+            }),
+        };
+    }
+}
+
+struct PercentileMergeUDF {}
+impl CubeAggregateUDF for PercentileMergeUDF {
+    fn kind(&self) -> CubeAggregateUDFKind {
+        return CubeAggregateUDFKind::PercentileMerge;
+    }
+    fn name(&self) -> &str {
+        return "PERCENTILE_MERGE";
+    }
+    fn descriptor(&self) -> AggregateUDF {
+        return AggregateUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Binary, DataType::Float64]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Float64))),
+            accumulator: Arc::new(|| Ok(Box::new(PercentileMergeAccumulator::default()))),
+            state_type: Arc::new(|_| Ok(Arc::new(vec![DataType::Binary, DataType::Float64]))),
+        };
+    }
+    fn accumulator(&self) -> Box<dyn Accumulator> {
+        return Box::new(PercentileMergeAccumulator::default());
+    }
+}
+
+/// Merges TDigest sketches and estimates the quantile passed as the second argument.
+#[derive(Debug, Default)]
+struct PercentileMergeAccumulator {
+    acc: Option<TDigest>,
+    quantile: Option<f64>,
+}
+
+impl Accumulator for PercentileMergeAccumulator {
+    fn reset(&mut self) {
+        self.acc = None;
+        self.quantile = None;
+    }
+
+    fn state(&self) -> Result<SmallVec<[ScalarValue; 2]>, DataFusionError> {
+        let sketch = match &self.acc {
+            None => Vec::new(),
+            Some(s) => s.write(),
+        };
+        return Ok(smallvec![
+            ScalarValue::Binary(Some(sketch)),
+            ScalarValue::Float64(self.quantile)
+        ]);
+    }
+
+    fn update(&mut self, row: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(row.len(), 2);
+        self.set_quantile(&row[1])?;
+        let data;
+        if let ScalarValue::Binary(v) = &row[0] {
+            if let Some(d) = v {
+                data = d
+            } else {
+                return Ok(()); // ignore NULL.
+            }
+        } else {
+            return Err(CubeError::internal(
+                "invalid scalar value passed to PERCENTILE_MERGE, expecting TDigest sketch"
+                    .to_string(),
+            )
+            .into());
+        }
+        if data.len() == 0 {
+            return Ok(());
+        }
+        return self.merge_sketch(read_tdigest(&data)?);
+    }
+
+    fn merge(&mut self, states: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(states.len(), 2);
+        self.set_quantile(&states[1])?;
+        let data;
+        if let ScalarValue::Binary(v) = &states[0] {
+            if let Some(d) = v {
+                data = d
+            } else {
+                return Ok(()); // ignore NULL.
+            }
+        } else {
+            return Err(
+                CubeError::internal("invalid state in PERCENTILE_MERGE".to_string()).into(),
+            );
+        }
+        // empty state is ok, this means an empty sketch.
+        if data.len() == 0 {
+            return Ok(());
+        }
+        return self.merge_sketch(read_tdigest(&data)?);
+    }
+
+    fn evaluate(&self) -> Result<ScalarValue, DataFusionError> {
+        let (acc, q) = match (&self.acc, self.quantile) {
+            (Some(acc), Some(q)) => (acc, q),
+            _ => return Ok(ScalarValue::Float64(None)),
+        };
+        let v = acc
+            .quantile(q)
+            .map_err(|e| DataFusionError::Execution(e.message))?;
+        return Ok(ScalarValue::Float64(v));
+    }
+}
+
+impl PercentileMergeAccumulator {
+    fn set_quantile(&mut self, v: &ScalarValue) -> Result<(), DataFusionError> {
+        let q = match v {
+            ScalarValue::Float64(None) => return Ok(()),
+            ScalarValue::Float64(Some(q)) => *q,
+            _ => {
+                return Err(CubeError::internal(
+                    "invalid quantile passed to PERCENTILE_MERGE".to_string(),
+                )
+                .into())
+            }
+        };
+        if !(0. ..=1.).contains(&q) {
+            return Err(DataFusionError::Execution(format!(
+                "PERCENTILE_MERGE quantile must be between 0 and 1, got {}",
+                q
+            )));
+        }
+        match self.quantile {
+            Some(prev) if prev != q => Err(DataFusionError::Execution(
+                "PERCENTILE_MERGE quantile must be the same for all rows".to_string(),
+            )),
+            _ => {
+                self.quantile = Some(q);
+                Ok(())
+            }
+        }
+    }
+
+    fn merge_sketch(&mut self, s: TDigest) -> Result<(), DataFusionError> {
+        match &mut self.acc {
+            None => self.acc = Some(s),
+            Some(acc) => acc.merge_with(&s),
+        }
+        return Ok(());
+    }
+}
+
+fn read_tdigest(data: &[u8]) -> Result<TDigest, DataFusionError> {
+    return TDigest::read(&data).map_err(|e| DataFusionError::Execution(e.message));
+}
@@ -0,0 +1,622 @@
+use crate::di_service;
+use crate::remotefs::rest::{
+    canonical_query, check_status, hmac_sha256, uri_encode, xml_element, xml_elements,
+};
+use crate::remotefs::{LocalDirRemoteFs, RemoteFile, RemoteFs};
+use crate::util::lock::acquire_lock;
+use crate::CubeError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use datafusion::cube_ext;
+use futures::StreamExt;
+use log::{debug, info};
+use reqwest::{Body, Client, Method, Response};
+use std::env;
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+use tokio::fs;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Mutex;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use url::Url;
+
+/// Version of the Blob service REST API. Anything since 2019-12-12 allows single-request
+/// uploads of up to 5000 MiB, which is plenty for our files.
+const AZURE_API_VERSION: &str = "2019-12-12";
+
+pub enum AzureCredentials {
+    /// Base64-encoded storage account key, used to sign requests with the SharedKey scheme.
+    SharedKey(String),
+    /// Shared access signature, appended to every request URL.
+    SasToken(String),
+    /// Only works with containers that allow public writes, e.g. in local emulators.
+    Anonymous,
+}
+
+pub struct AzureBlobRemoteFs {
+    dir: PathBuf,
+    client: Client,
+    endpoint: Url,
+    account: String,
+    container: String,
+    credentials: AzureCredentials,
+    sub_path: Option<String>,
+    delete_mut: Mutex<()>,
+}
+
+impl fmt::Debug for AzureBlobRemoteFs {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Do not expose credentials.
+        f.debug_struct("AzureBlobRemoteFs")
+            .field("dir", &self.dir)
+            .field("endpoint", &self.endpoint.as_str())
+            .field("account", &self.account)
+            .field("container", &self.container)
+            .field("sub_path", &self.sub_path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AzureBlobRemoteFs {
+    /// Uses `https://<account>.blob.core.windows.net` unless `endpoint` is set.
+    pub fn new(
+        dir: PathBuf,
+        account: String,
+        container: String,
+        endpoint: Option<String>,
+        sub_path: Option<String>,
+    ) -> Result<Arc<Self>, CubeError> {
+        let credentials = if let Ok(key) = env::var("CUBESTORE_AZURE_ACCESS_KEY") {
+            AzureCredentials::SharedKey(key)
+        } else if let Ok(token) = env::var("CUBESTORE_AZURE_SAS_TOKEN") {
+            AzureCredentials::SasToken(token)
+        } else {
+            AzureCredentials::Anonymous
+        };
+        let endpoint =
+            endpoint.unwrap_or_else(|| format!("https://{}.blob.core.windows.net", account));
+        Self::with_credentials(dir, account, container, endpoint, sub_path, credentials)
+    }
+
+    pub fn with_credentials(
+        dir: PathBuf,
+        account: String,
+        container: String,
+        endpoint: String,
+        sub_path: Option<String>,
+        credentials: AzureCredentials,
+    ) -> Result<Arc<Self>, CubeError> {
+        let endpoint = Url::parse(&endpoint).map_err(|e| {
+            CubeError::user(format!("Invalid Azure endpoint '{}': {}", endpoint, e))
+        })?;
+        if let AzureCredentials::SharedKey(key) = &credentials {
+            base64::decode(key).map_err(|e| {
+                CubeError::user(format!("Azure access key is not valid base64: {}", e))
+            })?;
+        }
+        Ok(Arc::new(Self {
+            dir,
+            client: Client::new(),
+            endpoint,
+            account,
+            container,
+            credentials,
+            sub_path,
+            delete_mut: Mutex::new(()),
+        }))
+    }
+}
+
+di_service!(AzureBlobRemoteFs, [RemoteFs]);
+
+#[async_trait]
+impl RemoteFs for AzureBlobRemoteFs {
+    async fn upload_file(
+        &self,
+        temp_upload_path: &str,
+        remote_path: &str,
+    ) -> Result<(), CubeError> {
+        let time = SystemTime::now();
+        debug!("Uploading {}", remote_path);
+        let file = File::open(temp_upload_path).await?;
+        let size = file.metadata().await?.len();
+        let stream = FramedRead::new(file, BytesCodec::new()).map(|r| r.map(|b| b.to_vec()));
+        let response = self
+            .request(
+                Method::PUT,
+                &self.blob_path(remote_path),
+                &[],
+                Some((Body::wrap_stream(stream), size)),
+            )
+            .await?;
+        check_status("Azure", "upload", remote_path, response).await?;
+        let local_path = self.dir.as_path().join(remote_path);
+        if Path::new(temp_upload_path) != local_path {
+            fs::create_dir_all(local_path.parent().unwrap())
+                .await
+                .map_err(|e| {
+                    CubeError::internal(format!(
+                        "Create dir {}: {}",
+                        local_path.parent().as_ref().unwrap().to_string_lossy(),
+                        e
+                    ))
+                })?;
+            fs::rename(&temp_upload_path, local_path).await?;
+        }
+        info!("Uploaded {} ({:?})", remote_path, time.elapsed()?);
+        Ok(())
+    }
+
+    async fn download_file(&self, remote_path: &str) -> Result<String, CubeError> {
+        let local_file = self.dir.as_path().join(remote_path);
+        let local_dir = local_file.parent().unwrap();
+        let downloads_dir = local_dir.join("downloads");
+
+        let local_file_str = local_file.to_str().unwrap().to_string(); // return value.
+
+        fs::create_dir_all(&downloads_dir).await?;
+        if !local_file.exists() {
+            let time = SystemTime::now();
+            debug!("Downloading {}", remote_path);
+            let response = self
+                .request(Method::GET, &self.blob_path(remote_path), &[], None)
+                .await?;
+            let mut response = check_status("Azure", "download", remote_path, response).await?;
+            let (temp_file, temp_path) =
+                cube_ext::spawn_blocking(move || NamedTempFile::new_in(downloads_dir))
+                    .await??
+                    .into_parts();
+            let mut writer = BufWriter::new(File::from_std(temp_file));
+            let mut size = 0;
+            while let Some(chunk) = response.chunk().await? {
+                writer.write_all(&chunk).await?;
+                size += chunk.len();
+            }
+            writer.flush().await?;
+            cube_ext::spawn_blocking(move || temp_path.persist(local_file)).await??;
+            info!(
+                "Downloaded {} ({:?}) ({} bytes)",
+                remote_path,
+                time.elapsed()?,
+                size
+            );
+        }
+        Ok(local_file_str)
+    }
+
+    async fn delete_file(&self, remote_path: &str) -> Result<(), CubeError> {
+        let time = SystemTime::now();
+        debug!("Deleting {}", remote_path);
+        let response = self
+            .request(Method::DELETE, &self.blob_path(remote_path), &[], None)
+            .await?;
+        check_status("Azure", "delete", remote_path, response).await?;
+        info!("Deleting {} ({:?})", remote_path, time.elapsed()?);
+
+        let _guard = acquire_lock("delete file", self.delete_mut.lock()).await?;
+        let local = self.dir.as_path().join(remote_path);
+        if fs::metadata(local.clone()).await.is_ok() {
+            fs::remove_file(local.clone()).await?;
+            LocalDirRemoteFs::remove_empty_paths(self.dir.as_path().to_path_buf(), local.clone())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, remote_prefix: &str) -> Result<Vec<String>, CubeError> {
+        Ok(self
+            .list_with_metadata(remote_prefix)
+            .await?
+            .into_iter()
+            .map(|f| f.remote_path)
+            .collect::<Vec<_>>())
+    }
+
+    async fn list_with_metadata(&self, remote_prefix: &str) -> Result<Vec<RemoteFile>, CubeError> {
+        let prefix = self.blob_path(remote_prefix);
+        let blob_prefix = self.blob_path("");
+        let mut result = Vec::new();
+        let mut marker = None;
+        loop {
+            let mut query = vec![
+                ("restype", "container".to_string()),
+                ("comp", "list".to_string()),
+                ("prefix", prefix.clone()),
+            ];
+            if let Some(marker) = marker.take() {
+                query.push(("marker", marker));
+            }
+            let response = self.request(Method::GET, "", &query, None).await?;
+            let response = check_status("Azure", "list", remote_prefix, response).await?;
+            let body = response.text().await?;
+            for blob in xml_elements(&body, "Blob") {
+                let name = xml_element(blob, "Name").ok_or_else(|| {
+                    CubeError::internal(format!("Azure list: no name in blob: {}", blob))
+                })?;
+                let updated = xml_element(blob, "Last-Modified").ok_or_else(|| {
+                    CubeError::internal(format!("Azure list: no last modified in blob: {}", name))
+                })?;
+                let size = xml_element(blob, "Content-Length").ok_or_else(|| {
+                    CubeError::internal(format!("Azure list: no content length in blob: {}", name))
+                })?;
+                result.push(RemoteFile {
+                    remote_path: name
+                        .strip_prefix(blob_prefix.as_str())
+                        .unwrap_or(&name)
+                        .to_string(),
+                    updated: DateTime::parse_from_rfc2822(&updated)?.with_timezone(&Utc),
+                    file_size: size.parse()?,
+                });
+            }
+            match xml_element(&body, "NextMarker") {
+                Some(next) if !next.is_empty() => marker = Some(next),
+                _ => break,
+            }
+        }
+        Ok(result)
+    }
+
+    async fn local_path(&self) -> String {
+        self.dir.to_str().unwrap().to_owned()
+    }
+
+    async fn local_file(&self, remote_path: &str) -> Result<String, CubeError> {
+        let buf = self.dir.join(remote_path);
+        fs::create_dir_all(buf.parent().unwrap()).await?;
+        Ok(buf.to_str().unwrap().to_string())
+    }
+}
+
+impl AzureBlobRemoteFs {
+    fn blob_path(&self, remote_path: &str) -> String {
+        match &self.sub_path {
+            Some(p) => format!("{}/{}", p.trim_end_matches('/'), remote_path),
+            None => remote_path.to_string(),
+        }
+    }
+
+    /// Sends a request for `blob`, or for the container itself if `blob` is empty.
+    async fn request(
+        &self,
+        method: Method,
+        blob: &str,
+        query: &[(&str, String)],
+        body: Option<(Body, u64)>,
+    ) -> Result<Response, CubeError> {
+        let mut path = format!(
+            "{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(&self.container, true)
+        );
+        if !blob.is_empty() {
+            path = format!("{}/{}", path, uri_encode(blob, false));
+        }
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let mut query_string = canonical_query(query);
+        if let AzureCredentials::SasToken(token) = &self.credentials {
+            let token = token.trim_start_matches('?');
+            if query_string.is_empty() {
+                query_string = token.to_string();
+            } else {
+                query_string = format!("{}&{}", query_string, token);
+            }
+        }
+        url.set_query(if query_string.is_empty() {
+            None
+        } else {
+            Some(&query_string)
+        });
+
+        let mut headers = vec![
+            (
+                "x-ms-date",
+                Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            ),
+            ("x-ms-version", AZURE_API_VERSION.to_string()),
+        ];
+        if body.is_some() {
+            headers.push(("x-ms-blob-type", "BlockBlob".to_string()));
+            headers.sort();
+        }
+        let (content_length, content_type) = match &body {
+            Some((_, size)) => (size.to_string(), "application/octet-stream"),
+            None => (String::new(), ""),
+        };
+
+        let mut request = self.client.request(method.clone(), url.as_str());
+        for (k, v) in headers.iter() {
+            request = request.header(*k, v.as_str());
+        }
+        if let AzureCredentials::SharedKey(key) = &self.credentials {
+            let signature = sign_shared_key(
+                key,
+                &self.account,
+                method.as_str(),
+                &content_length,
+                content_type,
+                &headers,
+                &path,
+                query,
+            )?;
+            request = request.header(
+                "authorization",
+                format!("SharedKey {}:{}", self.account, signature),
+            );
+        }
+        if let Some((body, size)) = body {
+            request = request
+                .header("content-length", size)
+                .header("content-type", content_type)
+                .body(body);
+        }
+        Ok(request.send().await?)
+    }
+}
+
+/// Signature for the SharedKey authorization scheme of Azure Storage.
+/// `headers` must contain all `x-ms-*` headers sorted by name.
+#[allow(clippy::too_many_arguments)]
+fn sign_shared_key(
+    key: &str,
+    account: &str,
+    method: &str,
+    content_length: &str,
+    content_type: &str,
+    headers: &[(&str, String)],
+    path: &str,
+    query: &[(&str, String)],
+) -> Result<String, CubeError> {
+    let canonical_headers = headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v))
+        .collect::<String>();
+    let mut query = query
+        .iter()
+        .map(|(k, v)| (k.to_lowercase(), v.as_str()))
+        .collect::<Vec<_>>();
+    query.sort();
+    let mut canonical_resource = format!("/{}{}", account, path);
+    for (k, v) in query {
+        canonical_resource += &format!("\n{}:{}", k, v);
+    }
+    // Verb, Content-Encoding, Content-Language, Content-Length, Content-MD5, Content-Type, Date,
+    // If-Modified-Since, If-Match, If-None-Match, If-Unmodified-Since, Range.
+    let string_to_sign = format!(
+        "{}\n\n\n{}\n\n{}\n\n\n\n\n\n\n{}{}",
+        method, content_length, content_type, canonical_headers, canonical_resource
+    );
+    let signature = hmac_sha256(&base64::decode(key)?, string_to_sign.as_bytes())?;
+    Ok(base64::encode(signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remotefs::rest::xml_escape;
+    use chrono::TimeZone;
+    use std::collections::{BTreeMap, HashMap};
+    use tempfile::TempDir;
+    use warp::http::{HeaderMap, Response, StatusCode};
+    use warp::hyper::body::Bytes;
+    use warp::Filter;
+
+    type Blobs = Arc<std::sync::Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    const ACCOUNT: &str = "devstoreaccount1";
+    const KEY: &str = "a2V5";
+
+    /// Minimal Blob service stand-in in the style of Azurite, i.e. with the account in the path.
+    /// Verifies SharedKey signatures and returns at most 2 blobs per list page.
+    fn start_stand_in(container: &'static str) -> (String, Blobs) {
+        let blobs: Blobs = Arc::new(std::sync::Mutex::new(BTreeMap::new()));
+        let state = blobs.clone();
+        let routes = warp::method()
+            .and(warp::path::full())
+            .and(warp::query::<Vec<(String, String)>>())
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(
+                move |method: Method,
+                      full_path: warp::path::FullPath,
+                      query: Vec<(String, String)>,
+                      headers: HeaderMap,
+                      body: Bytes| {
+                    let reply = |status: StatusCode, body: Vec<u8>| {
+                        Response::builder().status(status).body(body).unwrap()
+                    };
+                    let header = |name: &str| {
+                        headers
+                            .get(name)
+                            .map(|h| h.to_str().unwrap().to_string())
+                            .unwrap_or_default()
+                    };
+                    let mut ms_headers = headers
+                        .iter()
+                        .filter(|(k, _)| k.as_str().starts_with("x-ms-"))
+                        .map(|(k, v)| (k.as_str(), v.to_str().unwrap().to_string()))
+                        .collect::<Vec<_>>();
+                    ms_headers.sort();
+                    let query_refs = query
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.clone()))
+                        .collect::<Vec<_>>();
+                    let content_length = match header("content-length").as_str() {
+                        "0" => String::new(),
+                        l => l.to_string(),
+                    };
+                    let expected = sign_shared_key(
+                        KEY,
+                        ACCOUNT,
+                        method.as_str(),
+                        &content_length,
+                        &header("content-type"),
+                        &ms_headers,
+                        full_path.as_str(),
+                        &query_refs,
+                    )
+                    .unwrap();
+                    if header("authorization") != format!("SharedKey {}:{}", ACCOUNT, expected) {
+                        return reply(StatusCode::FORBIDDEN, Vec::new());
+                    }
+
+                    let path = full_path.as_str().trim_start_matches('/');
+                    let mut parts = path.splitn(3, '/');
+                    let (account, c, blob) = (
+                        parts.next().unwrap_or(""),
+                        parts.next().unwrap_or(""),
+                        parts.next().unwrap_or(""),
+                    );
+                    if account != ACCOUNT || c != container {
+                        return reply(StatusCode::NOT_FOUND, Vec::new());
+                    }
+                    let query = query.into_iter().collect::<HashMap<_, _>>();
+                    let mut blobs = state.lock().unwrap();
+                    match (method, blob) {
+                        (Method::GET, "") => {
+                            let prefix = query.get("prefix").cloned().unwrap_or_default();
+                            let marker = query.get("marker").cloned();
+                            let matching = blobs
+                                .iter()
+                                .filter(|(k, _)| k.starts_with(&prefix))
+                                .filter(|(k, _)| marker.as_ref().map(|m| *k >= m).unwrap_or(true))
+                                .collect::<Vec<_>>();
+                            let mut xml = format!(
+                                "<?xml version=\"1.0\" encoding=\"utf-8\"?><EnumerationResults ServiceEndpoint=\"http://127.0.0.1/{}\" ContainerName=\"{}\"><Prefix>{}</Prefix><Blobs>",
+                                ACCOUNT, container, xml_escape(&prefix)
+                            );
+                            for (k, v) in matching.iter().take(2) {
+                                xml += &format!(
+                                    "<Blob><Name>{}</Name><Properties><Last-Modified>Sun, 01 Aug 2021 12:00:00 GMT</Last-Modified><Content-Length>{}</Content-Length><BlobType>BlockBlob</BlobType></Properties></Blob>",
+                                    xml_escape(k),
+                                    v.len()
+                                );
+                            }
+                            xml += "</Blobs>";
+                            match matching.get(2) {
+                                Some((next, _)) => {
+                                    xml += &format!("<NextMarker>{}</NextMarker>", xml_escape(next))
+                                }
+                                None => xml += "<NextMarker />",
+                            }
+                            xml += "</EnumerationResults>";
+                            reply(StatusCode::OK, xml.into_bytes())
+                        }
+                        (Method::PUT, blob) => {
+                            if header("x-ms-blob-type") != "BlockBlob" {
+                                return reply(StatusCode::BAD_REQUEST, Vec::new());
+                            }
+                            blobs.insert(blob.to_string(), body.to_vec());
+                            reply(StatusCode::CREATED, Vec::new())
+                        }
+                        (Method::GET, blob) => match blobs.get(blob) {
+                            Some(v) => reply(StatusCode::OK, v.clone()),
+                            None => reply(StatusCode::NOT_FOUND, Vec::new()),
+                        },
+                        (Method::DELETE, blob) => match blobs.remove(blob) {
+                            Some(_) => reply(StatusCode::ACCEPTED, Vec::new()),
+                            None => reply(StatusCode::NOT_FOUND, Vec::new()),
+                        },
+                        _ => reply(StatusCode::METHOD_NOT_ALLOWED, Vec::new()),
+                    }
+                },
+            );
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}/{}", addr, ACCOUNT), blobs)
+    }
+
+    #[tokio::test]
+    async fn upload_list_download_delete() {
+        let (endpoint, blobs) = start_stand_in("cubestore");
+        let dir = TempDir::new().unwrap();
+        let remote_fs = AzureBlobRemoteFs::with_credentials(
+            dir.path().to_path_buf(),
+            ACCOUNT.to_string(),
+            "cubestore".to_string(),
+            endpoint,
+            Some("prefix".to_string()),
+            AzureCredentials::SharedKey(KEY.to_string()),
+        )
+        .unwrap();
+
+        for (i, name) in ["a", "b", "c"].iter().enumerate() {
+            let remote_path = format!("metastore/{}.chunk.parquet", name);
+            let temp_path = remote_fs.temp_upload_path(&remote_path).await.unwrap();
+            fs::write(&temp_path, vec![i as u8; i + 1]).await.unwrap();
+            remote_fs
+                .upload_file(&temp_path, &remote_path)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            blobs
+                .lock()
+                .unwrap()
+                .get("prefix/metastore/b.chunk.parquet"),
+            Some(&vec![1, 1])
+        );
+
+        let files = remote_fs.list_with_metadata("metastore/").await.unwrap();
+        assert_eq!(
+            files
+                .iter()
+                .map(|f| (f.remote_path(), f.file_size()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("metastore/a.chunk.parquet", 1),
+                ("metastore/b.chunk.parquet", 2),
+                ("metastore/c.chunk.parquet", 3),
+            ]
+        );
+        assert_eq!(files[0].updated(), &Utc.ymd(2021, 8, 1).and_hms(12, 0, 0));
+
+        let local = remote_fs
+            .local_file("metastore/c.chunk.parquet")
+            .await
+            .unwrap();
+        fs::remove_file(&local).await.unwrap();
+        let downloaded = remote_fs
+            .download_file("metastore/c.chunk.parquet")
+            .await
+            .unwrap();
+        assert_eq!(downloaded, local);
+        assert_eq!(fs::read(&downloaded).await.unwrap(), vec![2, 2, 2]);
+
+        remote_fs
+            .delete_file("metastore/c.chunk.parquet")
+            .await
+            .unwrap();
+        assert!(!Path::new(&local).exists());
+        assert_eq!(
+            remote_fs.list("metastore/").await.unwrap(),
+            vec!["metastore/a.chunk.parquet", "metastore/b.chunk.parquet"]
+        );
+
+        let err = remote_fs.delete_file("metastore/c.chunk.parquet").await;
+        assert!(err.is_err(), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn wrong_key_rejected() {
+        let (endpoint, _) = start_stand_in("cubestore");
+        let dir = TempDir::new().unwrap();
+        let remote_fs = AzureBlobRemoteFs::with_credentials(
+            dir.path().to_path_buf(),
+            ACCOUNT.to_string(),
+            "cubestore".to_string(),
+            endpoint,
+            None,
+            AzureCredentials::SharedKey("b3RoZXI=".to_string()),
+        )
+        .unwrap();
+        let err = remote_fs.list("").await.unwrap_err();
+        assert!(err.message.contains("403"), "{}", err.message);
+    }
+}
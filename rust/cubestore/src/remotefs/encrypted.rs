@@ -0,0 +1,723 @@
+use crate::config::ConfigObj;
+use crate::di_service;
+use crate::remotefs::{RemoteFile, RemoteFs};
+use crate::CubeError;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use async_trait::async_trait;
+use datafusion::cube_ext;
+use log::{debug, info, warn};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::{NamedTempFile, PathPersistError};
+use tokio::fs;
+
+const MAGIC: &[u8; 8] = b"CSENC001";
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const NONCE_PREFIX_SIZE: usize = 7;
+const TAG_SIZE: usize = 16;
+const SEGMENT_SIZE: usize = 1024 * 1024;
+
+/// Master keys used to wrap per-file data keys. Each encrypted file records the id of the key
+/// that wrapped its data key, so keys can be rotated by putting a new key first and keeping the
+/// old ones around until all files encrypted with them are gone.
+#[derive(Clone)]
+pub struct MasterKeys {
+    current: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl MasterKeys {
+    /// Parses `<id>:<base64 encoded 256-bit key>` entries separated by commas or new lines.
+    /// The first entry is used to encrypt new files.
+    pub fn parse(s: &str) -> Result<MasterKeys, CubeError> {
+        let mut current = None;
+        let mut keys = HashMap::new();
+        for entry in s.split(|c| c == ',' || c == '\n') {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let (id, key) = match entry.find(':') {
+                Some(i) => (&entry[..i], &entry[i + 1..]),
+                None => {
+                    return Err(CubeError::user(
+                        "Encryption key should be in '<id>:<base64 key>' format, found entry without id"
+                            .to_string(),
+                    ))
+                }
+            };
+            if id.is_empty() || id.len() > u8::MAX as usize {
+                return Err(CubeError::user(format!(
+                    "Encryption key id should be from 1 to 255 bytes long: '{}'",
+                    id
+                )));
+            }
+            let key = base64::decode(key).map_err(|e| {
+                CubeError::user(format!("Can't decode encryption key '{}': {}", id, e))
+            })?;
+            if key.len() != KEY_SIZE {
+                return Err(CubeError::user(format!(
+                    "Encryption key '{}' should be {} bytes long, found {}",
+                    id,
+                    KEY_SIZE,
+                    key.len()
+                )));
+            }
+            if keys.insert(id.to_string(), key).is_some() {
+                return Err(CubeError::user(format!(
+                    "Duplicate encryption key id: '{}'",
+                    id
+                )));
+            }
+            current.get_or_insert_with(|| id.to_string());
+        }
+        match current {
+            Some(current) => Ok(MasterKeys { current, keys }),
+            None => Err(CubeError::user("No encryption keys provided".to_string())),
+        }
+    }
+
+    /// Keys set by the config, either inline or in a file. `None` if encryption is not enabled.
+    pub fn from_config(config: &dyn ConfigObj) -> Result<Option<MasterKeys>, CubeError> {
+        if let Some(keys) = config.encryption_keys() {
+            return Ok(Some(MasterKeys::parse(keys)?));
+        }
+        if let Some(path) = config.encryption_keys_file() {
+            let keys = std::fs::read_to_string(path).map_err(|e| {
+                CubeError::user(format!(
+                    "Can't read encryption keys from {}: {}",
+                    path.to_string_lossy(),
+                    e
+                ))
+            })?;
+            return Ok(Some(MasterKeys::parse(&keys)?));
+        }
+        Ok(None)
+    }
+
+    fn cipher(&self, id: &str) -> Result<Aes256Gcm, CubeError> {
+        let key = self.keys.get(id).ok_or_else(|| {
+            CubeError::internal(format!("Encryption key '{}' is not configured", id))
+        })?;
+        Ok(Aes256Gcm::new(Key::from_slice(key)))
+    }
+}
+
+fn header_aad(key_id: &str) -> Vec<u8> {
+    let mut aad = MAGIC.to_vec();
+    aad.extend_from_slice(key_id.as_bytes());
+    aad
+}
+
+/// Nonces of segments follow the STREAM construction: a random per-file prefix, the segment
+/// number and a flag marking the last segment, so segments can't be reordered or truncated.
+fn segment_nonce(prefix: &[u8], segment: u32, last: bool) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0; NONCE_SIZE];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..NONCE_SIZE - 1].copy_from_slice(&segment.to_be_bytes());
+    nonce[NONCE_SIZE - 1] = last as u8;
+    nonce
+}
+
+fn aead_error(operation: &str, path: &Path) -> CubeError {
+    CubeError::internal(format!(
+        "Can't {} {}: corrupted file or wrong key",
+        operation,
+        path.to_string_lossy()
+    ))
+}
+
+/// Header of an encrypted file: the id of the master key and the data key wrapped by it,
+/// followed by the nonce prefix of segments.
+struct Header {
+    key_id: String,
+    wrap_nonce: [u8; NONCE_SIZE],
+    wrapped_key: [u8; KEY_SIZE + TAG_SIZE],
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+}
+
+impl Header {
+    /// Wraps `data_key` with the current master key.
+    fn new(
+        keys: &MasterKeys,
+        data_key: &[u8],
+        nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+        path: &Path,
+    ) -> Result<Header, CubeError> {
+        let mut wrap_nonce = [0; NONCE_SIZE];
+        OsRng.fill_bytes(&mut wrap_nonce);
+        let wrapped = keys
+            .cipher(&keys.current)?
+            .encrypt(
+                Nonce::from_slice(&wrap_nonce),
+                Payload {
+                    msg: data_key,
+                    aad: &header_aad(&keys.current),
+                },
+            )
+            .map_err(|_| aead_error("encrypt", path))?;
+        let mut wrapped_key = [0; KEY_SIZE + TAG_SIZE];
+        wrapped_key.copy_from_slice(&wrapped);
+        Ok(Header {
+            key_id: keys.current.clone(),
+            wrap_nonce,
+            wrapped_key,
+            nonce_prefix,
+        })
+    }
+
+    /// Reads the header, `None` if the file doesn't start with [MAGIC], i.e. is in plain text.
+    fn read(input: &mut impl Read, len: usize) -> Result<Option<Header>, CubeError> {
+        if len < MAGIC.len() {
+            return Ok(None);
+        }
+        let mut magic = [0; MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Ok(None);
+        }
+        let mut key_id_len = [0; 1];
+        input.read_exact(&mut key_id_len)?;
+        let mut key_id = vec![0; key_id_len[0] as usize];
+        input.read_exact(&mut key_id)?;
+        let mut header = Header {
+            key_id: String::from_utf8(key_id)?,
+            wrap_nonce: [0; NONCE_SIZE],
+            wrapped_key: [0; KEY_SIZE + TAG_SIZE],
+            nonce_prefix: [0; NONCE_PREFIX_SIZE],
+        };
+        input.read_exact(&mut header.wrap_nonce)?;
+        input.read_exact(&mut header.wrapped_key)?;
+        input.read_exact(&mut header.nonce_prefix)?;
+        Ok(Some(header))
+    }
+
+    fn write(&self, output: &mut impl Write) -> Result<(), CubeError> {
+        output.write_all(MAGIC)?;
+        output.write_all(&[self.key_id.len() as u8])?;
+        output.write_all(self.key_id.as_bytes())?;
+        output.write_all(&self.wrap_nonce)?;
+        output.write_all(&self.wrapped_key)?;
+        output.write_all(&self.nonce_prefix)?;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        MAGIC.len() + 1 + self.key_id.len() + NONCE_SIZE + KEY_SIZE + TAG_SIZE + NONCE_PREFIX_SIZE
+    }
+
+    fn data_key(&self, keys: &MasterKeys, path: &Path) -> Result<Vec<u8>, CubeError> {
+        keys.cipher(&self.key_id)?
+            .decrypt(
+                Nonce::from_slice(&self.wrap_nonce),
+                Payload {
+                    msg: &self.wrapped_key,
+                    aad: &header_aad(&self.key_id),
+                },
+            )
+            .map_err(|_| aead_error("decrypt", path))
+    }
+}
+
+/// Encrypts `from` with a new random data key, which is stored in the header of `to` wrapped by
+/// the current master key.
+pub fn encrypt_file(keys: &MasterKeys, from: &Path, to: &Path) -> Result<(), CubeError> {
+    let mut data_key = [0; KEY_SIZE];
+    OsRng.fill_bytes(&mut data_key);
+    let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+    OsRng.fill_bytes(&mut nonce_prefix);
+    let header = Header::new(keys, &data_key, nonce_prefix, from)?;
+
+    let input = File::open(from)?;
+    let len = input.metadata()?.len() as usize;
+    let mut input = BufReader::new(input);
+    let mut output = BufWriter::new(File::create(to)?);
+    header.write(&mut output)?;
+
+    let cipher = Aes256Gcm::new(Key::from_slice(&data_key));
+    let segments = std::cmp::max(1, (len + SEGMENT_SIZE - 1) / SEGMENT_SIZE);
+    let mut buffer = vec![0; SEGMENT_SIZE];
+    for i in 0..segments {
+        let size = std::cmp::min(SEGMENT_SIZE, len - i * SEGMENT_SIZE);
+        input.read_exact(&mut buffer[..size])?;
+        let last = i == segments - 1;
+        let encrypted = cipher
+            .encrypt(
+                Nonce::from_slice(&segment_nonce(&nonce_prefix, i as u32, last)),
+                &buffer[..size],
+            )
+            .map_err(|_| aead_error("encrypt", from))?;
+        output.write_all(&encrypted)?;
+    }
+    output.flush()?;
+    output.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// Decrypts a file written by [encrypt_file] with any of the configured master keys.
+/// Files without the encryption header are copied as is: these were uploaded before encryption
+/// was enabled and stay readable until they're replaced by compaction or [rewrap_file].
+pub fn decrypt_file(keys: &MasterKeys, from: &Path, to: &Path) -> Result<(), CubeError> {
+    let input = File::open(from)?;
+    let len = input.metadata()?.len() as usize;
+    let mut input = BufReader::new(input);
+    let header = match Header::read(&mut input, len)? {
+        Some(header) => header,
+        None => {
+            std::fs::copy(from, to)?;
+            return Ok(());
+        }
+    };
+    let data_key = header.data_key(keys, from)?;
+
+    if len < header.len() + TAG_SIZE {
+        return Err(aead_error("decrypt", from));
+    }
+    let body_len = len - header.len();
+    let cipher = Aes256Gcm::new(Key::from_slice(&data_key));
+    let segments = (body_len + SEGMENT_SIZE + TAG_SIZE - 1) / (SEGMENT_SIZE + TAG_SIZE);
+    let mut output = BufWriter::new(File::create(to)?);
+    let mut buffer = vec![0; SEGMENT_SIZE + TAG_SIZE];
+    for i in 0..segments {
+        let size = std::cmp::min(
+            SEGMENT_SIZE + TAG_SIZE,
+            body_len - i * (SEGMENT_SIZE + TAG_SIZE),
+        );
+        input.read_exact(&mut buffer[..size])?;
+        let last = i == segments - 1;
+        let decrypted = cipher
+            .decrypt(
+                Nonce::from_slice(&segment_nonce(&header.nonce_prefix, i as u32, last)),
+                &buffer[..size],
+            )
+            .map_err(|_| aead_error("decrypt", from))?;
+        output.write_all(&decrypted)?;
+    }
+    output.flush()?;
+    output.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// Writes `from` to `to` so that it's encrypted with the current master key. Data keys of
+/// encrypted files are re-wrapped without touching the segments and plain text files are
+/// encrypted. Returns `false` and writes nothing if the file already uses the current key.
+pub fn rewrap_file(keys: &MasterKeys, from: &Path, to: &Path) -> Result<bool, CubeError> {
+    let input = File::open(from)?;
+    let len = input.metadata()?.len() as usize;
+    let mut input = BufReader::new(input);
+    let header = match Header::read(&mut input, len)? {
+        Some(header) => header,
+        None => {
+            encrypt_file(keys, from, to)?;
+            return Ok(true);
+        }
+    };
+    if header.key_id == keys.current {
+        return Ok(false);
+    }
+    let data_key = header.data_key(keys, from)?;
+    let header = Header::new(keys, &data_key, header.nonce_prefix, from)?;
+
+    let mut output = BufWriter::new(File::create(to)?);
+    header.write(&mut output)?;
+    std::io::copy(&mut input, &mut output)?;
+    output.flush()?;
+    output.get_ref().sync_all()?;
+    Ok(true)
+}
+
+/// Encrypts files before they are uploaded by the wrapped remote fs and decrypts them after
+/// download. Local copies are kept in plain text in `dir`, while the wrapped remote fs should use
+/// a separate directory to stage encrypted files.
+pub struct EncryptedRemoteFs {
+    remote_fs: Arc<dyn RemoteFs>,
+    dir: PathBuf,
+    keys: Arc<MasterKeys>,
+}
+
+impl fmt::Debug for EncryptedRemoteFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedRemoteFs")
+            .field("remote_fs", &self.remote_fs)
+            .field("dir", &self.dir)
+            .field("current_key", &self.keys.current)
+            .finish()
+    }
+}
+
+di_service!(EncryptedRemoteFs, [RemoteFs]);
+
+impl EncryptedRemoteFs {
+    /// Directory inside the data dir where the wrapped remote fs keeps encrypted files.
+    pub const STAGING_DIR: &'static str = "encrypted";
+
+    pub fn new(
+        remote_fs: Arc<dyn RemoteFs>,
+        dir: PathBuf,
+        keys: MasterKeys,
+    ) -> Arc<EncryptedRemoteFs> {
+        Arc::new(EncryptedRemoteFs {
+            remote_fs,
+            dir,
+            keys: Arc::new(keys),
+        })
+    }
+
+    /// Rewrites remote files which are in plain text or use an old master key with the current
+    /// one, so old keys can be removed from the config afterwards. Returns the number of
+    /// rewritten files.
+    ///
+    /// Metastore snapshots and logs are skipped: rewriting them would change the upload times
+    /// used to restore the metastore to a point in time. They are replaced by new snapshots, so
+    /// old keys should be kept for the snapshot retention period after the rewrap.
+    pub async fn rewrap_remote_files(&self) -> Result<u64, CubeError> {
+        let mut rewrapped = 0;
+        let mut failed = 0;
+        for remote_path in self.remote_fs.list("").await? {
+            if remote_path.starts_with("metastore-") {
+                continue;
+            }
+            match self.rewrap_remote_file(&remote_path).await {
+                Ok(true) => rewrapped += 1,
+                Ok(false) => {}
+                // Files can be removed by the GC in the meantime.
+                Err(e) => {
+                    warn!("Can't rewrap {}: {}", remote_path, e);
+                    failed += 1;
+                }
+            }
+        }
+        info!(
+            "Rewrapped {} remote files with encryption key '{}', {} failed",
+            rewrapped, self.keys.current, failed
+        );
+        Ok(rewrapped)
+    }
+
+    async fn rewrap_remote_file(&self, remote_path: &str) -> Result<bool, CubeError> {
+        let from = PathBuf::from(self.remote_fs.download_file(remote_path).await?);
+        let to = PathBuf::from(self.remote_fs.temp_upload_path(remote_path).await?);
+        let keys = self.keys.clone();
+        let to_move = to.clone();
+        let from_move = from.clone();
+        let rewrapped =
+            cube_ext::spawn_blocking(move || rewrap_file(&keys, &from_move, &to_move)).await?;
+        let _ = fs::remove_file(from).await;
+        if rewrapped? {
+            debug!("Rewrapping {}", remote_path);
+            self.remote_fs
+                .upload_file(to.to_str().unwrap(), remote_path)
+                .await?;
+            let _ = fs::remove_file(self.remote_fs.local_file(remote_path).await?).await;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+#[async_trait]
+impl RemoteFs for EncryptedRemoteFs {
+    async fn upload_file(
+        &self,
+        temp_upload_path: &str,
+        remote_path: &str,
+    ) -> Result<(), CubeError> {
+        let encrypted = self.remote_fs.temp_upload_path(remote_path).await?;
+        let keys = self.keys.clone();
+        let from = PathBuf::from(temp_upload_path);
+        let to = PathBuf::from(&encrypted);
+        cube_ext::spawn_blocking(move || encrypt_file(&keys, &from, &to)).await??;
+        self.remote_fs.upload_file(&encrypted, remote_path).await?;
+        // Only the plain text copy is used locally.
+        let _ = fs::remove_file(self.remote_fs.local_file(remote_path).await?).await;
+
+        let local_path = self.local_file(remote_path).await?;
+        if temp_upload_path != local_path {
+            fs::rename(temp_upload_path, &local_path)
+                .await
+                .map_err(|e| {
+                    CubeError::internal(format!(
+                        "Rename {} -> {}: {}",
+                        temp_upload_path, local_path, e
+                    ))
+                })?;
+        }
+        Ok(())
+    }
+
+    async fn download_file(&self, remote_path: &str) -> Result<String, CubeError> {
+        let local_file = PathBuf::from(self.local_file(remote_path).await?);
+        if local_file.exists() {
+            return Ok(local_file.into_os_string().into_string().unwrap());
+        }
+        let encrypted = PathBuf::from(self.remote_fs.download_file(remote_path).await?);
+        debug!("Decrypting {}", remote_path);
+        let downloads_dir = local_file.parent().unwrap().join("downloads");
+        fs::create_dir_all(&downloads_dir).await?;
+        let keys = self.keys.clone();
+        let encrypted_to_move = encrypted.clone();
+        let local_file = cube_ext::spawn_blocking(move || -> Result<PathBuf, CubeError> {
+            let temp_path = NamedTempFile::new_in(downloads_dir)?.into_temp_path();
+            decrypt_file(&keys, &encrypted_to_move, &temp_path)?;
+            temp_path
+                .persist(&local_file)
+                .map_err(|e: PathPersistError| CubeError::from(e.error))?;
+            Ok(local_file)
+        })
+        .await??;
+        let _ = fs::remove_file(encrypted).await;
+        Ok(local_file.into_os_string().into_string().unwrap())
+    }
+
+    async fn delete_file(&self, remote_path: &str) -> Result<(), CubeError> {
+        self.remote_fs.delete_file(remote_path).await?;
+        let local = self.dir.join(remote_path);
+        if fs::metadata(&local).await.is_ok() {
+            fs::remove_file(local).await?;
+        }
+        Ok(())
+    }
+
+    async fn list(&self, remote_prefix: &str) -> Result<Vec<String>, CubeError> {
+        self.remote_fs.list(remote_prefix).await
+    }
+
+    async fn list_with_metadata(&self, remote_prefix: &str) -> Result<Vec<RemoteFile>, CubeError> {
+        self.remote_fs.list_with_metadata(remote_prefix).await
+    }
+
+    async fn local_path(&self) -> String {
+        self.dir.to_str().unwrap().to_owned()
+    }
+
+    async fn local_file(&self, remote_path: &str) -> Result<String, CubeError> {
+        let buf = self.dir.join(remote_path);
+        fs::create_dir_all(buf.parent().unwrap()).await?;
+        Ok(buf.to_str().unwrap().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remotefs::LocalDirRemoteFs;
+
+    fn keys(entries: &[(&str, u8)]) -> MasterKeys {
+        MasterKeys::parse(
+            &entries
+                .iter()
+                .map(|(id, b)| format!("{}:{}", id, base64::encode(&[*b; KEY_SIZE])))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_keys() {
+        let keys = keys(&[("new", 1), ("old", 2)]);
+        assert_eq!(keys.current, "new");
+        assert_eq!(keys.keys.len(), 2);
+
+        assert!(MasterKeys::parse("").is_err());
+        assert!(MasterKeys::parse("abc").is_err());
+        assert!(MasterKeys::parse(&format!("a:{}", base64::encode(&[0; 16]))).is_err());
+        let key = base64::encode(&[0; KEY_SIZE]);
+        assert!(MasterKeys::parse(&format!("a:{},a:{}", key, key)).is_err());
+        assert_eq!(
+            MasterKeys::parse(&format!("\na:{}\nb:{}\n", key, key))
+                .unwrap()
+                .current,
+            "a"
+        );
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain");
+        let encrypted = dir.path().join("encrypted");
+        let decrypted = dir.path().join("decrypted");
+        let old_keys = keys(&[("old", 2)]);
+        let rotated_keys = keys(&[("new", 1), ("old", 2)]);
+
+        for len in [0, 10, SEGMENT_SIZE, 2 * SEGMENT_SIZE + 5].iter() {
+            let data = (0..*len).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            std::fs::write(&plain, &data).unwrap();
+            encrypt_file(&old_keys, &plain, &encrypted).unwrap();
+            let contents = std::fs::read(&encrypted).unwrap();
+            assert!(contents.len() > data.len());
+            assert!(data.is_empty() || !contents.windows(data.len()).any(|w| w == &data[..]));
+
+            decrypt_file(&rotated_keys, &encrypted, &decrypted).unwrap();
+            assert_eq!(std::fs::read(&decrypted).unwrap(), data);
+
+            // Removed keys and tampered files are detected.
+            assert!(decrypt_file(&keys(&[("new", 1)]), &encrypted, &decrypted).is_err());
+            assert!(decrypt_file(&keys(&[("old", 3)]), &encrypted, &decrypted).is_err());
+            let mut tampered = contents.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            std::fs::write(&encrypted, &tampered).unwrap();
+            assert!(decrypt_file(&old_keys, &encrypted, &decrypted).is_err());
+            if *len > SEGMENT_SIZE {
+                std::fs::write(&encrypted, &contents[..contents.len() - 5 - TAG_SIZE]).unwrap();
+                assert!(decrypt_file(&old_keys, &encrypted, &decrypted).is_err());
+            }
+        }
+
+        encrypt_file(&rotated_keys, &plain, &encrypted).unwrap();
+        assert!(decrypt_file(&old_keys, &encrypted, &decrypted).is_err());
+        decrypt_file(&rotated_keys, &encrypted, &decrypted).unwrap();
+
+        // Files uploaded before encryption was enabled are passed through.
+        for data in ["", "abc", "plain text file"].iter() {
+            std::fs::write(&plain, data).unwrap();
+            decrypt_file(&old_keys, &plain, &decrypted).unwrap();
+            assert_eq!(std::fs::read_to_string(&decrypted).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn rewrap() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain");
+        let encrypted = dir.path().join("encrypted");
+        let rewrapped = dir.path().join("rewrapped");
+        let decrypted = dir.path().join("decrypted");
+        let old_keys = keys(&[("old", 2)]);
+        let rotated_keys = keys(&[("new", 1), ("old", 2)]);
+        let new_keys = keys(&[("new", 1)]);
+
+        let data = (0..SEGMENT_SIZE + 5)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        std::fs::write(&plain, &data).unwrap();
+        encrypt_file(&old_keys, &plain, &encrypted).unwrap();
+        assert!(rewrap_file(&rotated_keys, &encrypted, &rewrapped).unwrap());
+        decrypt_file(&new_keys, &rewrapped, &decrypted).unwrap();
+        assert_eq!(std::fs::read(&decrypted).unwrap(), data);
+        // Only the wrapped data key changes.
+        let encrypted_contents = std::fs::read(&encrypted).unwrap();
+        let rewrapped_contents = std::fs::read(&rewrapped).unwrap();
+        let wrapped_key_end = MAGIC.len() + 1 + 3 + NONCE_SIZE + KEY_SIZE + TAG_SIZE;
+        assert_ne!(
+            encrypted_contents[..wrapped_key_end],
+            rewrapped_contents[..wrapped_key_end]
+        );
+        assert_eq!(
+            encrypted_contents[wrapped_key_end..],
+            rewrapped_contents[wrapped_key_end..]
+        );
+
+        std::fs::remove_file(&rewrapped).unwrap();
+        encrypt_file(&new_keys, &plain, &encrypted).unwrap();
+        assert!(!rewrap_file(&new_keys, &encrypted, &rewrapped).unwrap());
+        assert!(!rewrapped.exists());
+
+        assert!(rewrap_file(&new_keys, &plain, &rewrapped).unwrap());
+        assert!(std::fs::read(&rewrapped).unwrap().starts_with(MAGIC));
+        decrypt_file(&new_keys, &rewrapped, &decrypted).unwrap();
+        assert_eq!(std::fs::read(&decrypted).unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn upload_and_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote_dir = dir.path().join("remote");
+        let local_dir = dir.path().join("local");
+        let backend = LocalDirRemoteFs::new(
+            Some(remote_dir.clone()),
+            local_dir.join(EncryptedRemoteFs::STAGING_DIR),
+        );
+        let remote_fs = EncryptedRemoteFs::new(backend, local_dir.clone(), keys(&[("k", 1)]));
+
+        let temp = remote_fs.temp_upload_path("1.parquet").await.unwrap();
+        std::fs::write(&temp, "secret data").unwrap();
+        remote_fs.upload_file(&temp, "1.parquet").await.unwrap();
+        let remote = std::fs::read(remote_dir.join("1.parquet")).unwrap();
+        assert!(remote.starts_with(MAGIC));
+        assert_eq!(
+            std::fs::read_to_string(local_dir.join("1.parquet")).unwrap(),
+            "secret data"
+        );
+        assert!(!local_dir
+            .join(EncryptedRemoteFs::STAGING_DIR)
+            .join("1.parquet")
+            .exists());
+        assert_eq!(
+            remote_fs.list("").await.unwrap(),
+            vec!["1.parquet".to_string()]
+        );
+
+        std::fs::remove_file(local_dir.join("1.parquet")).unwrap();
+        let downloaded = remote_fs.download_file("1.parquet").await.unwrap();
+        assert_eq!(downloaded, remote_fs.local_file("1.parquet").await.unwrap());
+        assert_eq!(std::fs::read_to_string(&downloaded).unwrap(), "secret data");
+
+        remote_fs.delete_file("1.parquet").await.unwrap();
+        assert!(!remote_dir.join("1.parquet").exists());
+        assert!(!local_dir.join("1.parquet").exists());
+    }
+
+    #[tokio::test]
+    async fn rewrap_remote_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote_dir = dir.path().join("remote");
+        let local_dir = dir.path().join("local");
+        let remote_fs = |keys| {
+            let backend = LocalDirRemoteFs::new(
+                Some(remote_dir.clone()),
+                local_dir.join(EncryptedRemoteFs::STAGING_DIR),
+            );
+            EncryptedRemoteFs::new(backend, local_dir.clone(), keys)
+        };
+
+        // Uploaded before encryption was enabled.
+        std::fs::create_dir_all(remote_dir.join("metastore-1")).unwrap();
+        std::fs::write(remote_dir.join("1.parquet"), "plain").unwrap();
+        std::fs::write(remote_dir.join("metastore-1").join("CURRENT"), "meta").unwrap();
+        let old_fs = remote_fs(keys(&[("old", 2)]));
+        let temp = old_fs.temp_upload_path("2.parquet").await.unwrap();
+        std::fs::write(&temp, "old").unwrap();
+        old_fs.upload_file(&temp, "2.parquet").await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(old_fs.download_file("1.parquet").await.unwrap()).unwrap(),
+            "plain"
+        );
+
+        let new_fs = remote_fs(keys(&[("new", 1), ("old", 2)]));
+        let temp = new_fs.temp_upload_path("3.parquet").await.unwrap();
+        std::fs::write(&temp, "new").unwrap();
+        new_fs.upload_file(&temp, "3.parquet").await.unwrap();
+        assert_eq!(new_fs.rewrap_remote_files().await.unwrap(), 2);
+        assert_eq!(new_fs.rewrap_remote_files().await.unwrap(), 0);
+        assert_eq!(
+            std::fs::read_to_string(remote_dir.join("metastore-1").join("CURRENT")).unwrap(),
+            "meta"
+        );
+
+        std::fs::remove_dir_all(&local_dir).unwrap();
+        let new_fs = remote_fs(keys(&[("new", 1)]));
+        for (file, data) in [
+            ("1.parquet", "plain"),
+            ("2.parquet", "old"),
+            ("3.parquet", "new"),
+        ]
+        .iter()
+        {
+            assert!(std::fs::read(remote_dir.join(file))
+                .unwrap()
+                .starts_with(MAGIC));
+            let local = new_fs.download_file(file).await.unwrap();
+            assert_eq!(std::fs::read_to_string(local).unwrap(), *data);
+        }
+    }
+}
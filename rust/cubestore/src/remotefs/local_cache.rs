@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Keeps track of downloaded Parquet files in the local directory and decides which of them to
+/// remove when their total size exceeds the budget.
+///
+/// Files are evicted in the least recently used order, but ones downloaded by warmup go after all
+/// others as they are expected to be queried soon. Files used by running queries are never evicted.
+pub struct LocalFilesCache {
+    /// Zero means no limit.
+    budget: u64,
+    size: u64,
+    access_counter: u64,
+    files: HashMap<String, CachedFile>,
+    in_use: HashMap<String, usize>,
+}
+
+struct CachedFile {
+    size: u64,
+    warmed_up: bool,
+    last_access: u64,
+}
+
+impl LocalFilesCache {
+    pub fn new(budget: u64) -> LocalFilesCache {
+        LocalFilesCache {
+            budget,
+            size: 0,
+            access_counter: 0,
+            files: HashMap::new(),
+            in_use: HashMap::new(),
+        }
+    }
+
+    /// Data files and their bloom filters.
+    pub fn is_cached_file(remote_path: &str) -> bool {
+        !remote_path.contains('/')
+            && (remote_path.ends_with(".parquet") || remote_path.ends_with(".parquet.bloom"))
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Marks the file as recently used. Returns false if the file is not known to the cache.
+    pub fn touch(&mut self, remote_path: &str) -> bool {
+        self.access_counter += 1;
+        match self.files.get_mut(remote_path) {
+            Some(f) => {
+                f.last_access = self.access_counter;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, remote_path: &str, size: u64, warmed_up: bool) {
+        self.access_counter += 1;
+        let file = CachedFile {
+            size,
+            warmed_up,
+            last_access: self.access_counter,
+        };
+        if let Some(old) = self.files.insert(remote_path.to_string(), file) {
+            self.size -= old.size;
+        }
+        self.size += size;
+    }
+
+    pub fn mark_warmed_up(&mut self, remote_path: &str) {
+        if let Some(f) = self.files.get_mut(remote_path) {
+            f.warmed_up = true;
+        }
+    }
+
+    pub fn remove(&mut self, remote_path: &str) {
+        if let Some(f) = self.files.remove(remote_path) {
+            self.size -= f.size;
+        }
+    }
+
+    /// Removes files from the cache until it fits into the budget and returns them along with
+    /// their sizes. The caller is responsible for deleting returned files from the disk.
+    pub fn evict(&mut self) -> Vec<(String, u64)> {
+        if self.budget == 0 || self.size <= self.budget {
+            return Vec::new();
+        }
+        let mut candidates = self
+            .files
+            .iter()
+            .filter(|(path, _)| !self.in_use.contains_key(path.as_str()))
+            .map(|(path, f)| (f.warmed_up, f.last_access, path.clone()))
+            .collect::<Vec<_>>();
+        candidates.sort();
+
+        let mut evicted = Vec::new();
+        for (_, _, path) in candidates {
+            if self.size <= self.budget {
+                break;
+            }
+            let file = self.files.remove(&path).unwrap();
+            self.size -= file.size;
+            evicted.push((path, file.size));
+        }
+        evicted
+    }
+
+    fn acquire(&mut self, remote_paths: &[String]) {
+        for p in remote_paths {
+            *self.in_use.entry(p.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    fn release(&mut self, remote_paths: &[String]) {
+        for p in remote_paths {
+            if let Some(count) = self.in_use.get_mut(p) {
+                *count -= 1;
+                if *count == 0 {
+                    self.in_use.remove(p);
+                }
+            }
+        }
+    }
+}
+
+/// Protects files from eviction while a query or a job reads them.
+pub struct LocalFilesGuard {
+    cache: Option<Arc<Mutex<LocalFilesCache>>>,
+    remote_paths: Vec<String>,
+}
+
+impl LocalFilesGuard {
+    pub fn new(cache: Arc<Mutex<LocalFilesCache>>, remote_paths: Vec<String>) -> LocalFilesGuard {
+        cache.lock().unwrap().acquire(&remote_paths);
+        LocalFilesGuard {
+            cache: Some(cache),
+            remote_paths,
+        }
+    }
+
+    /// Guard of remote filesystems that never evict local files.
+    pub fn none() -> LocalFilesGuard {
+        LocalFilesGuard {
+            cache: None,
+            remote_paths: Vec::new(),
+        }
+    }
+}
+
+impl Drop for LocalFilesGuard {
+    fn drop(&mut self) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().release(&self.remote_paths);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evicted_names(cache: &mut LocalFilesCache) -> Vec<String> {
+        cache.evict().into_iter().map(|(p, _)| p).collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LocalFilesCache::new(100);
+        cache.insert("1.parquet", 40, false);
+        cache.insert("2.parquet", 40, false);
+        assert!(evicted_names(&mut cache).is_empty());
+
+        assert!(cache.touch("1.parquet"));
+        assert!(!cache.touch("3.parquet"));
+        cache.insert("3.parquet", 40, false);
+        assert_eq!(evicted_names(&mut cache), vec!["2.parquet".to_string()]);
+        assert_eq!(cache.size(), 80);
+
+        cache.insert("4.parquet", 90, false);
+        assert_eq!(
+            evicted_names(&mut cache),
+            vec!["1.parquet".to_string(), "3.parquet".to_string()]
+        );
+        assert_eq!(cache.size(), 90);
+    }
+
+    #[test]
+    fn keeps_warmed_up_and_used_files() {
+        let cache = Arc::new(Mutex::new(LocalFilesCache::new(100)));
+        {
+            let mut c = cache.lock().unwrap();
+            c.insert("1.parquet", 40, true);
+            c.insert("2.parquet", 40, false);
+            c.insert("3.parquet", 40, false);
+        }
+        let guard = LocalFilesGuard::new(cache.clone(), vec!["2.parquet".to_string()]);
+        assert_eq!(
+            evicted_names(&mut cache.lock().unwrap()),
+            vec!["3.parquet".to_string()]
+        );
+
+        cache.lock().unwrap().insert("4.parquet", 40, false);
+        assert_eq!(
+            evicted_names(&mut cache.lock().unwrap()),
+            vec!["4.parquet".to_string()]
+        );
+        cache.lock().unwrap().insert("5.parquet", 40, false);
+        cache.lock().unwrap().touch("1.parquet");
+        drop(guard);
+        assert_eq!(
+            evicted_names(&mut cache.lock().unwrap()),
+            vec!["2.parquet".to_string()]
+        );
+    }
+
+    #[test]
+    fn cached_files() {
+        assert!(LocalFilesCache::is_cached_file("1.parquet"));
+        assert!(LocalFilesCache::is_cached_file("1.chunk.parquet"));
+        assert!(LocalFilesCache::is_cached_file("1.parquet.bloom"));
+        assert!(LocalFilesCache::is_cached_file("1.chunk.parquet.bloom"));
+        assert!(!LocalFilesCache::is_cached_file("1.wal"));
+        assert!(!LocalFilesCache::is_cached_file("uploads/1.parquet"));
+    }
+}
@@ -1,9 +1,15 @@
+pub mod azure;
+pub mod encrypted;
 pub mod gcs;
+pub mod local_cache;
 pub mod queue;
+pub mod rest;
 pub mod s3;
+pub mod s3_compatible;
 
 use crate::config::injection::DIService;
 use crate::di_service;
+use crate::remotefs::local_cache::LocalFilesGuard;
 use crate::util::lock::acquire_lock;
 use crate::CubeError;
 use async_trait::async_trait;
@@ -23,6 +29,7 @@ use tokio::sync::{Mutex, RwLock};
 pub struct RemoteFile {
     remote_path: String,
     updated: DateTime<Utc>,
+    file_size: u64,
 }
 
 impl RemoteFile {
@@ -33,6 +40,10 @@ impl RemoteFile {
     pub fn updated(&self) -> &DateTime<Utc> {
         &self.updated
     }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
 }
 
 #[async_trait]
@@ -65,6 +76,12 @@ pub trait RemoteFs: DIService + Send + Sync + Debug {
 
     async fn download_file(&self, remote_path: &str) -> Result<String, CubeError>;
 
+    /// Prevents eviction of the local copies of `remote_paths` until the guard is dropped.
+    /// Acquire it before downloading the files and keep it until they are read.
+    fn use_local_files(&self, _remote_paths: Vec<String>) -> LocalFilesGuard {
+        LocalFilesGuard::none()
+    }
+
     async fn delete_file(&self, remote_path: &str) -> Result<(), CubeError>;
 
     async fn list(&self, remote_prefix: &str) -> Result<Vec<String>, CubeError>;
@@ -314,9 +331,11 @@ impl LocalDirRemoteFs {
                     .trim_start_matches("/")
                     .to_string();
                 if relative_name.starts_with(&remote_prefix) {
+                    let metadata = file.metadata().await?;
                     result.push(RemoteFile {
                         remote_path: relative_name.to_string(),
-                        updated: DateTime::from(file.metadata().await?.modified()?),
+                        updated: DateTime::from(metadata.modified()?),
+                        file_size: metadata.len(),
                     });
                 }
             }
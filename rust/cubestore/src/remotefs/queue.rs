@@ -1,5 +1,7 @@
+use crate::app_metrics;
 use crate::config::ConfigObj;
 use crate::di_service;
+use crate::remotefs::local_cache::{LocalFilesCache, LocalFilesGuard};
 use crate::remotefs::{RemoteFile, RemoteFs};
 use crate::util::lock::acquire_lock;
 use crate::CubeError;
@@ -13,7 +15,8 @@ use smallvec::alloc::fmt::Formatter;
 use std::collections::HashSet;
 use std::fmt::Debug;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use tokio::sync::{broadcast, watch, RwLock};
 use tokio::time::Duration;
 
@@ -25,6 +28,7 @@ pub struct QueueRemoteFs {
     // TODO not used
     deleted: RwLock<HashSet<String>>,
     downloading: RwLock<HashSet<String>>,
+    local_cache: Arc<Mutex<LocalFilesCache>>,
     _result_receiver: broadcast::Receiver<RemoteFsOpResult>,
     result_sender: broadcast::Sender<RemoteFsOpResult>,
     stopped_rx: watch::Receiver<bool>,
@@ -62,6 +66,9 @@ impl QueueRemoteFs {
     pub fn new(config: Arc<dyn ConfigObj>, remote_fs: Arc<dyn RemoteFs>) -> Arc<Self> {
         let (stopped_tx, stopped_rx) = watch::channel(false);
         let (tx, rx) = broadcast::channel(16384);
+        let local_cache = Arc::new(Mutex::new(LocalFilesCache::new(
+            config.local_files_cache_size(),
+        )));
         Arc::new(Self {
             config,
             remote_fs,
@@ -69,6 +76,7 @@ impl QueueRemoteFs {
             download_queue: unlimited::Queue::new(),
             deleted: RwLock::new(HashSet::new()),
             downloading: RwLock::new(HashSet::new()),
+            local_cache,
             result_sender: tx,
             _result_receiver: rx,
             stopped_tx,
@@ -141,6 +149,88 @@ impl QueueRemoteFs {
         Ok(self.stopped_tx.send(true)?)
     }
 
+    /// Downloads the file and makes it the last to be evicted from the local cache.
+    pub async fn warmup_download(&self, remote_path: &str) -> Result<String, CubeError> {
+        let local_path = self.download_file(remote_path).await?;
+        self.local_cache.lock().unwrap().mark_warmed_up(remote_path);
+        Ok(local_path)
+    }
+
+    /// Registers a file that appeared in the local directory and removes the files that no
+    /// longer fit into the local cache budget.
+    async fn add_to_local_cache(
+        &self,
+        remote_path: &str,
+        warmed_up: bool,
+    ) -> Result<(), CubeError> {
+        if !LocalFilesCache::is_cached_file(remote_path) {
+            return Ok(());
+        }
+        let local_path = self.local_file(remote_path).await?;
+        let size = tokio::fs::metadata(&local_path).await?.len();
+        let evicted = {
+            let mut cache = self.local_cache.lock().unwrap();
+            cache.insert(remote_path, size, warmed_up);
+            cache.evict()
+        };
+        self.remove_evicted(evicted).await;
+        Ok(())
+    }
+
+    async fn remove_evicted(&self, evicted: Vec<(String, u64)>) {
+        for (remote_path, size) in evicted {
+            log::debug!("Evicting {} from the local cache", remote_path);
+            let res = match self.local_file(&remote_path).await {
+                Ok(local_path) => tokio::fs::remove_file(local_path)
+                    .await
+                    .map_err(CubeError::from),
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(()) => {
+                    app_metrics::LOCAL_CACHE_EVICTIONS.increment();
+                    app_metrics::LOCAL_CACHE_EVICTED_BYTES.add(size as i64);
+                }
+                Err(e) => error!("Error evicting {} from the local cache: {}", remote_path, e),
+            }
+        }
+        app_metrics::LOCAL_CACHE_SIZE.report(self.local_cache.lock().unwrap().size() as i64);
+    }
+
+    /// Puts files downloaded by previous runs into the local cache, oldest first.
+    async fn index_local_files(&self) -> Result<(), CubeError> {
+        let local_dir = self.local_path().await;
+        let mut files = cube_ext::spawn_blocking(
+            move || -> Result<Vec<(SystemTime, String, u64)>, std::io::Error> {
+                let mut files = Vec::new();
+                for entry in Path::new(&local_dir).read_dir()? {
+                    let entry = entry?;
+                    let metadata = entry.metadata()?;
+                    if !metadata.is_file() {
+                        continue;
+                    }
+                    if let Ok(name) = entry.file_name().into_string() {
+                        if LocalFilesCache::is_cached_file(&name) {
+                            files.push((metadata.modified()?, name, metadata.len()));
+                        }
+                    }
+                }
+                Ok(files)
+            },
+        )
+        .await??;
+        files.sort();
+        let evicted = {
+            let mut cache = self.local_cache.lock().unwrap();
+            for (_, name, size) in files {
+                cache.insert(&name, size, false);
+            }
+            cache.evict()
+        };
+        self.remove_evicted(evicted).await;
+        Ok(())
+    }
+
     async fn upload_loop(&self, to_process: RemoteFsOp) -> Result<(), CubeError> {
         match to_process {
             RemoteFsOp::Upload {
@@ -174,6 +264,11 @@ impl QueueRemoteFs {
         match to_process {
             RemoteFsOp::Download(file) => {
                 let result = self.remote_fs.download_file(file.as_str()).await;
+                if result.is_ok() {
+                    if let Err(e) = self.add_to_local_cache(&file, false).await {
+                        error!("Error adding {} to the local cache: {}", file, e);
+                    }
+                }
                 let mut downloading =
                     acquire_lock("download loop downloading", self.downloading.write()).await?;
                 self.result_sender
@@ -195,6 +290,9 @@ impl QueueRemoteFs {
     async fn cleanup_loop(&self) -> () {
         let local_dir = self.local_path().await;
         let mut stopped_rx = self.stopped_rx.clone();
+        if let Err(e) = self.index_local_files().await {
+            log::error!("error while trying to index local files: {}", e);
+        }
         loop {
             // Do the cleanup every now and then.
             tokio::select! {
@@ -272,6 +370,13 @@ impl QueueRemoteFs {
                 log::trace!("The files being removed are {:?}", local_files);
             }
 
+            {
+                let mut cache = self.local_cache.lock().unwrap();
+                for f in local_files.iter() {
+                    cache.remove(f);
+                }
+            }
+
             let local_dir_copy = local_dir.clone();
             cube_ext::spawn_blocking(move || {
                 for f in local_files {
@@ -306,19 +411,34 @@ impl RemoteFs for QueueRemoteFs {
             let res = receiver.recv().await?;
             if let RemoteFsOpResult::Upload(file, result) = res {
                 if &file == remote_path {
-                    return result;
+                    result?;
+                    if let Err(e) = self.add_to_local_cache(remote_path, false).await {
+                        error!("Error adding {} to the local cache: {}", remote_path, e);
+                    }
+                    return Ok(());
                 }
             }
         }
     }
 
+    fn use_local_files(&self, remote_paths: Vec<String>) -> LocalFilesGuard {
+        LocalFilesGuard::new(self.local_cache.clone(), remote_paths)
+    }
+
     async fn download_file(&self, remote_path: &str) -> Result<String, CubeError> {
         // We might be lucky and the file has already been downloaded.
         if let Ok(local_path) = self.local_file(remote_path).await {
             if tokio::fs::metadata(&local_path).await.is_ok() {
+                if LocalFilesCache::is_cached_file(remote_path) {
+                    self.local_cache.lock().unwrap().touch(remote_path);
+                    app_metrics::LOCAL_CACHE_HITS.increment();
+                }
                 return Ok(local_path);
             }
         }
+        if LocalFilesCache::is_cached_file(remote_path) {
+            app_metrics::LOCAL_CACHE_MISSES.increment();
+        }
         let mut receiver = self.result_sender.subscribe();
         {
             let mut downloading =
@@ -0,0 +1,121 @@
+//! Helpers shared by remote file systems that talk to object stores over plain HTTP.
+use crate::CubeError;
+use hmac::{Hmac, Mac, NewMac};
+use regex::Regex;
+use reqwest::Response;
+use sha2::Sha256;
+
+/// Percent-encodes everything except unreserved characters. Slashes are kept when
+/// `encode_slash` is false, which is what both S3 and Azure expect in object paths.
+pub fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut res = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                res.push(b as char)
+            }
+            b'/' if !encode_slash => res.push('/'),
+            _ => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    res
+}
+
+/// Query string with parameters sorted by name, the canonical form used for request signing.
+pub fn canonical_query(query: &[(&str, String)]) -> String {
+    let mut query = query
+        .iter()
+        .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+        .collect::<Vec<_>>();
+    query.sort();
+    query
+        .into_iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CubeError> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key)
+        .map_err(|e| CubeError::internal(format!("Invalid HMAC key: {}", e)))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Turns non-success responses into errors carrying the status and the body sent by the server.
+pub async fn check_status(
+    service: &str,
+    operation: &str,
+    remote_path: &str,
+    response: Response,
+) -> Result<Response, CubeError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(CubeError::user(format!(
+        "{} {} of '{}' returned non OK status: {} {}",
+        service, operation, remote_path, status, body
+    )))
+}
+
+/// Contents of every `<tag>...</tag>` element in `xml`. Responses of the listing APIs we use are
+/// flat enough for this, so we avoid pulling in a full XML parser.
+pub fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let tag = regex::escape(tag);
+    let re = Regex::new(&format!(r"(?s)<{}>(.*?)</{}>", tag, tag)).unwrap();
+    re.captures_iter(xml)
+        .map(|c| c.get(1).unwrap().as_str())
+        .collect()
+}
+
+/// Unescaped text of the first `<tag>...</tag>` element in `xml`.
+pub fn xml_element(xml: &str, tag: &str) -> Option<String> {
+    xml_elements(xml, tag).first().map(|s| xml_unescape(s))
+}
+
+pub fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        assert_eq!(uri_encode("a b/c~d+e", false), "a%20b/c~d%2Be");
+        assert_eq!(uri_encode("a b/c", true), "a%20b%2Fc");
+        assert_eq!(
+            canonical_query(&[
+                ("prefix", "x/y".to_string()),
+                ("list-type", "2".to_string())
+            ]),
+            "list-type=2&prefix=x%2Fy"
+        );
+    }
+
+    #[test]
+    fn xml() {
+        let xml = "<R><Name>b</Name><Item><Key>a&amp;b</Key></Item>\n<Item><Key>c</Key></Item><Next /></R>";
+        let items = xml_elements(xml, "Item");
+        assert_eq!(items.len(), 2);
+        assert_eq!(xml_element(items[0], "Key"), Some("a&b".to_string()));
+        assert_eq!(xml_element(items[1], "Key"), Some("c".to_string()));
+        assert_eq!(xml_element(xml, "Next"), None);
+        assert_eq!(xml_unescape(&xml_escape("<a&'\">")), "<a&'\">");
+    }
+}
@@ -1,5 +1,7 @@
-use crate::cluster::{pick_worker_by_ids, Cluster};
+use crate::app_metrics;
+use crate::cluster::Cluster;
 use crate::config::ConfigObj;
+use crate::import::ImportServiceImpl;
 use crate::metastore::job::{Job, JobType};
 use crate::metastore::table::Table;
 use crate::metastore::{IdRow, MetaStore, MetaStoreEvent, Partition, RowKey, TableId};
@@ -12,6 +14,9 @@ use datafusion::cube_ext;
 use flatbuffers::bitflags::_core::time::Duration;
 use futures_timer::Delay;
 use log::error;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
 use std::sync::Arc;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
@@ -30,6 +35,7 @@ pub struct SchedulerImpl {
     gc_sender: UnboundedSender<GCTimedTask>,
     config: Arc<dyn ConfigObj>,
     reconcile_loop: WorkerLoop,
+    remote_gc_loop: WorkerLoop,
 }
 
 crate::di_service!(SchedulerImpl, []);
@@ -56,6 +62,7 @@ impl SchedulerImpl {
             gc_sender,
             config,
             reconcile_loop: WorkerLoop::new("Reconcile"),
+            remote_gc_loop: WorkerLoop::new("Remote GC"),
         }
     }
 
@@ -64,7 +71,8 @@ impl SchedulerImpl {
     ) -> Vec<JoinHandle<Result<(), CubeError>>> {
         let scheduler2 = scheduler.clone();
         let scheduler3 = scheduler.clone();
-        vec![
+        let scheduler4 = scheduler.clone();
+        let mut loops = vec![
             cube_ext::spawn(async move {
                 let mut gc_loop = scheduler
                     .gc_loop
@@ -85,7 +93,33 @@ impl SchedulerImpl {
                     .await;
                 Ok(())
             }),
-        ]
+        ];
+        if scheduler.config.remote_gc_interval_secs() > 0 {
+            loops.push(cube_ext::spawn(async move {
+                scheduler4
+                    .remote_gc_loop
+                    .process(
+                        scheduler4.clone(),
+                        async move |s| {
+                            Ok(
+                                Delay::new(Duration::from_secs(s.config.remote_gc_interval_secs()))
+                                    .await,
+                            )
+                        },
+                        async move |s, _| {
+                            s.remove_orphaned_remote_files(
+                                Duration::from_secs(s.config.remote_gc_grace_period_secs()),
+                                s.config.remote_gc_dry_run(),
+                            )
+                            .await?;
+                            Ok(())
+                        },
+                    )
+                    .await;
+                Ok(())
+            }));
+        }
+        loops
     }
 
     async fn run_scheduler(scheduler: Arc<SchedulerImpl>) -> Result<(), CubeError> {
@@ -188,9 +222,74 @@ impl SchedulerImpl {
         Ok(())
     }
 
+    /// Removes data files in the remote fs that are not referenced by the metastore and were not
+    /// modified during `grace_period`. Those are left behind by failed uploads, imports and
+    /// garbage collection tasks lost on restart. Returns paths of the orphaned files, which are
+    /// only reported when `dry_run` is set.
+    pub async fn remove_orphaned_remote_files(
+        &self,
+        grace_period: Duration,
+        dry_run: bool,
+    ) -> Result<Vec<String>, CubeError> {
+        // Listing goes first: files created after it can't be mistaken for orphans even if they
+        // are referenced only by metastore rows added in the meantime.
+        let remote_files = self.remote_fs.list_with_metadata("").await?;
+        let mut referenced = self
+            .meta_store
+            .get_all_remote_file_names()
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let tables = self.meta_store.get_tables_with_path().await?;
+        for table in tables.iter() {
+            if let Some(locations) = table.table.get_row().locations() {
+                for location in locations.iter() {
+                    referenced.insert(ImportServiceImpl::temp_uploads_path(location));
+                }
+            }
+        }
+
+        let data_file =
+            Regex::new(r"^(\d+(\.chunk)?\.parquet(\.bloom)?|\d+\.wal|temp-uploads/.+)$").unwrap();
+        let deadline = Utc::now() - chrono::Duration::seconds(grace_period.as_secs() as i64);
+        let mut orphaned = Vec::new();
+        for file in remote_files {
+            let path = file.remote_path();
+            if !data_file.is_match(path) || referenced.contains(path) || file.updated() > &deadline
+            {
+                continue;
+            }
+            app_metrics::REMOTE_GC_ORPHANED_FILES.increment();
+            if dry_run {
+                log::info!("Found orphaned remote file: {}", path);
+            } else {
+                log::info!("Removing orphaned remote file: {}", path);
+                self.remote_fs.delete_file(path).await?;
+                app_metrics::REMOTE_GC_REMOVED_FILES.increment();
+                app_metrics::REMOTE_GC_REMOVED_BYTES.add(file.file_size() as i64);
+            }
+            orphaned.push(path.to_string());
+        }
+        Ok(orphaned)
+    }
+
+    /// Data files are kept while queries may still read them and while metastore snapshots that
+    /// reference them can be restored.
+    fn remove_remote_file_later(&self, remote_path: String) -> Result<(), CubeError> {
+        let keep_for = self
+            .config
+            .not_used_timeout()
+            .max(self.config.metastore_snapshots_retention_secs());
+        let deadline = Instant::now() + Duration::from_secs(keep_for);
+        self.gc_sender
+            .send(GCTimedTask(deadline, GCTask::RemoveRemoteFile(remote_path)))?;
+        Ok(())
+    }
+
     pub fn stop_processing_loops(&self) -> Result<(), CubeError> {
         self.stop_sender.send(true)?;
         self.reconcile_loop.stop();
+        self.remote_gc_loop.stop();
         Ok(())
     }
 
@@ -271,16 +370,23 @@ impl SchedulerImpl {
                     .free_memory_chunk(&node_name, chunk.get_id())
                     .await?;
             } else {
-                self.remote_fs
-                    .delete_file(ChunkStore::chunk_remote_path(chunk.get_id()).as_str())
-                    .await?
+                if let Some(bloom_filter) = chunk.get_row().get_bloom_filter_name(chunk.get_id()) {
+                    self.remove_remote_file_later(bloom_filter)?;
+                }
+                self.remove_remote_file_later(ChunkStore::chunk_remote_path(chunk.get_id()))?;
             }
         }
         if let MetaStoreEvent::DeletePartition(partition) = &event {
             // remove file only if partition is active otherwise it should be removed when it's deactivated
             if partition.get_row().is_active() {
                 if let Some(file_name) = partition.get_row().get_full_name(partition.get_id()) {
-                    self.remote_fs.delete_file(file_name.as_str()).await?;
+                    self.remove_remote_file_later(file_name)?;
+                }
+                if let Some(bloom_filter) = partition
+                    .get_row()
+                    .get_bloom_filter_name(partition.get_id())
+                {
+                    self.remove_remote_file_later(bloom_filter)?;
                 }
             }
         }
@@ -290,10 +396,13 @@ impl SchedulerImpl {
                 self.schedule_repartition_if_needed(&partition).await?;
                 if partition.get_row().main_table_row_count() > 0 {
                     if let Some(file_name) = partition.get_row().get_full_name(partition.get_id()) {
-                        let deadline =
-                            Instant::now() + Duration::from_secs(self.config.not_used_timeout());
-                        self.gc_sender
-                            .send(GCTimedTask(deadline, GCTask::RemoveRemoteFile(file_name)))?;
+                        self.remove_remote_file_later(file_name)?;
+                    }
+                    if let Some(bloom_filter) = partition
+                        .get_row()
+                        .get_bloom_filter_name(partition.get_id())
+                    {
+                        self.remove_remote_file_later(bloom_filter)?;
                     }
                 }
             }
@@ -441,7 +550,10 @@ impl SchedulerImpl {
         &self,
         multi_partition_id: u64,
     ) -> Result<(), CubeError> {
-        let node = pick_worker_by_ids(self.config.as_ref(), [multi_partition_id]).to_string();
+        let node = self
+            .cluster
+            .membership()
+            .pick_worker_by_ids([multi_partition_id]);
         let job = self
             .meta_store
             .add_job(Job::new(
@@ -469,7 +581,10 @@ impl SchedulerImpl {
         {
             return Ok(());
         }
-        let node = pick_worker_by_ids(self.config.as_ref(), [multi_partition_id]).to_string();
+        let node = self
+            .cluster
+            .membership()
+            .pick_worker_by_ids([multi_partition_id]);
         let job = self
             .meta_store
             .add_job(Job::new(
@@ -514,6 +629,27 @@ impl SchedulerImpl {
 
 #[derive(Debug)]
 struct GCTimedTask(/*deadline*/ Instant, GCTask);
+
+/// Tasks with earlier deadlines go first in [BinaryHeap].
+impl Ord for GCTimedTask {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.cmp(&self.0)
+    }
+}
+
+impl PartialOrd for GCTimedTask {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for GCTimedTask {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for GCTimedTask {}
 #[derive(Debug)]
 enum GCTask {
     RemoveRemoteFile(/*remote_path*/ String),
@@ -528,6 +664,8 @@ struct DataGCLoop {
     remote_fs: Arc<dyn RemoteFs>,
     stop: watch::Receiver<bool>,
     to_delete: UnboundedReceiver<GCTimedTask>,
+    /// Tasks wait here for their deadlines, which don't come in order.
+    pending: BinaryHeap<GCTimedTask>,
 }
 
 impl DataGCLoop {
@@ -543,6 +681,7 @@ impl DataGCLoop {
                 remote_fs,
                 stop,
                 to_delete: receiver,
+                pending: BinaryHeap::new(),
             },
             sender,
         )
@@ -550,7 +689,10 @@ impl DataGCLoop {
 
     async fn run(&mut self) {
         loop {
-            let GCTimedTask(deadline, task) = tokio::select! {
+            let next_deadline = self.pending.peek().map(|t| t.0);
+            let sleep = tokio::time::sleep_until(next_deadline.unwrap_or_else(Instant::now));
+            // Wait for the nearest deadline, new tasks or cancellation.
+            tokio::select! {
                 res = self.stop.changed() => {
                     if res.is_err() || *self.stop.borrow() {
                         return;
@@ -561,25 +703,16 @@ impl DataGCLoop {
                 event = self.to_delete.recv() => {
                     match event {
                         None => return, // channel closed.
-                        Some(e) => e,
-                    }
-                }
-            };
-
-            // Sleep until the deadline or cancellation.
-            loop {
-                tokio::select! {
-                    res = self.stop.changed() => {
-                        if res.is_err() || *self.stop.borrow() {
-                            return;
-                        } else {
+                        Some(e) => {
+                            self.pending.push(e);
                             continue;
                         }
                     }
-                    () = tokio::time::sleep_until(deadline) => {break;}
                 }
+                () = sleep, if next_deadline.is_some() => {}
             }
 
+            let GCTimedTask(_, task) = self.pending.pop().unwrap();
             match task {
                 GCTask::RemoveRemoteFile(remote_path) => {
                     log::trace!("Removing deactivated data file: {}", remote_path);
@@ -43,15 +43,15 @@ use crate::metastore::job::JobType;
 use crate::metastore::multi_index::MultiIndex;
 use crate::metastore::source::SourceCredentials;
 use crate::metastore::{
-    is_valid_plain_binary_hll, table::Table, HllFlavour, IdRow, ImportFormat, Index, IndexDef,
-    MetaStoreTable, RowKey, Schema, TableId,
+    is_valid_plain_binary_hll, is_valid_tdigest, table::Table, AggregateFunction, HllFlavour,
+    IdRow, ImportFormat, Index, IndexDef, MetaStoreTable, RowKey, Schema, TableId,
 };
 use crate::queryplanner::query_executor::{batch_to_dataframe, QueryExecutor};
 use crate::queryplanner::serialized_plan::RowFilter;
 use crate::queryplanner::{QueryPlan, QueryPlanner};
 use crate::remotefs::RemoteFs;
 use crate::sql::cache::SqlResultCache;
-use crate::sql::parser::{CubeStoreParser, PartitionedIndexRef};
+use crate::sql::parser::{AggregateIndex, CubeStoreParser, PartitionedIndexRef};
 use crate::store::ChunkDataStore;
 use crate::table::{data, Row, TableValue, TimestampValue};
 use crate::util::decimal::Decimal;
@@ -59,7 +59,7 @@ use crate::util::strings::path_to_string;
 use crate::CubeError;
 use crate::{
     app_metrics,
-    metastore::{Column, ColumnType, MetaStore},
+    metastore::{Column, ColumnCodec, ColumnType, MetaStore},
     store::DataFrame,
 };
 use data::create_array_builder;
@@ -159,10 +159,14 @@ impl SqlServiceImpl {
         external: bool,
         locations: Option<Vec<String>>,
         indexes: Vec<Statement>,
+        aggregate_indexes: Vec<AggregateIndex>,
         unique_key: Option<Vec<Ident>>,
         partitioned_index: Option<PartitionedIndexRef>,
+        with_options: &[SqlOption],
     ) -> Result<IdRow<Table>, CubeError> {
-        let columns_to_set = convert_columns_type(columns)?;
+        let columns_to_set = apply_storage_options(convert_columns_type(columns)?, with_options)?;
+        let bloom_filter_columns = bloom_filter_columns(with_options)?;
+        let row_group_size = row_group_size(with_options)?;
         let mut indexes_to_create = Vec::new();
         if let Some(mut p) = partitioned_index {
             let part_index_name = match p.name.0.as_mut_slice() {
@@ -190,6 +194,7 @@ impl SqlServiceImpl {
                 name: "#mi0".to_string(),
                 columns,
                 multi_index: Some(part_index_name),
+                aggregates: None,
             });
         }
         for index in indexes.iter() {
@@ -197,6 +202,7 @@ impl SqlServiceImpl {
                 indexes_to_create.push(IndexDef {
                     name: name.to_string(),
                     multi_index: None,
+                    aggregates: None,
                     columns: columns
                         .iter()
                         .map(|c| {
@@ -213,6 +219,9 @@ impl SqlServiceImpl {
                 });
             }
         }
+        for index in aggregate_indexes {
+            indexes_to_create.push(aggregate_index_def(index)?);
+        }
 
         if !external {
             return self
@@ -226,6 +235,8 @@ impl SqlServiceImpl {
                     indexes_to_create,
                     true,
                     unique_key.map(|keys| keys.iter().map(|c| c.value.to_string()).collect()),
+                    bloom_filter_columns,
+                    row_group_size,
                 )
                 .await;
         }
@@ -242,6 +253,8 @@ impl SqlServiceImpl {
                 indexes_to_create,
                 false,
                 unique_key.map(|keys| keys.iter().map(|c| c.value.to_string()).collect()),
+                bloom_filter_columns,
+                row_group_size,
             )
             .await?;
 
@@ -332,11 +345,44 @@ impl SqlServiceImpl {
                     name,
                     multi_index: None,
                     columns: columns.iter().map(|c| c.value.to_string()).collect(),
+                    aggregates: None,
                 },
             )
             .await?)
     }
 
+    async fn create_aggregate_index(
+        &self,
+        schema_name: String,
+        table_name: String,
+        index: AggregateIndex,
+    ) -> Result<IdRow<Index>, CubeError> {
+        let (index, source) = self
+            .db
+            .create_aggregate_index(schema_name, table_name, aggregate_index_def(index)?)
+            .await?;
+        if !index.get_row().is_backfilling() {
+            return Ok(index);
+        }
+
+        // Rows inserted after the index is created go to all indexes of the table, so only the
+        // rows that existed at the moment of creation are added here. Ingestion that read the
+        // table indexes before the index was created can still miss it.
+        let new_chunks = self
+            .chunk_store
+            .backfill_index(index.clone(), source)
+            .await?;
+        let new_chunk_ids: Result<Vec<u64>, CubeError> = join_all(new_chunks)
+            .await
+            .into_iter()
+            .map(|c| Ok(c??.get_id()))
+            .collect();
+        self.db
+            .activate_chunks(index.get_row().table_id(), new_chunk_ids?)
+            .await?;
+        self.db.finish_index_backfill(index.get_id()).await
+    }
+
     async fn insert_data<'a>(
         &'a self,
         schema_name: String,
@@ -529,9 +575,11 @@ impl SqlService for SqlServiceImpl {
                         name,
                         columns,
                         external,
+                        with_options,
                         ..
                     },
                 indexes,
+                aggregate_indexes,
                 locations,
                 unique_key,
                 partitioned_index,
@@ -554,8 +602,10 @@ impl SqlService for SqlServiceImpl {
                         external,
                         locations,
                         indexes,
+                        aggregate_indexes,
                         unique_key,
                         partitioned_index,
+                        &with_options,
                     )
                     .await?;
                 Ok(Arc::new(DataFrame::from(vec![res])))
@@ -596,6 +646,43 @@ impl SqlService for SqlServiceImpl {
                     .await?;
                 Ok(Arc::new(DataFrame::from(vec![res])))
             }
+            CubeStoreStatement::CreateAggregateIndex(index) => {
+                if index.table_name.0.len() != 2 {
+                    return Err(CubeError::user(format!(
+                        "Schema's name should be present in table name but found: {}",
+                        index.table_name
+                    )));
+                }
+                let schema_name = index.table_name.0[0].value.to_string();
+                let table_name = index.table_name.0[1].value.to_string();
+                let res = self
+                    .create_aggregate_index(schema_name, table_name, index)
+                    .await?;
+                Ok(Arc::new(DataFrame::from(vec![res])))
+            }
+            CubeStoreStatement::CreateBloomFilterIndex {
+                table_name,
+                columns,
+                ..
+            } => {
+                if table_name.0.len() != 2 {
+                    return Err(CubeError::user(format!(
+                        "Schema's name should be present in table name but found: {}",
+                        table_name
+                    )));
+                }
+                let schema_name = table_name.0[0].value.to_string();
+                let table_name = table_name.0[1].value.to_string();
+                let res = self
+                    .db
+                    .add_bloom_filter_columns(
+                        schema_name,
+                        table_name,
+                        columns.into_iter().map(|c| c.value).collect(),
+                    )
+                    .await?;
+                Ok(Arc::new(DataFrame::from(vec![res])))
+            }
             CubeStoreStatement::CreateSource {
                 name,
                 source_type,
@@ -753,9 +840,16 @@ impl SqlService for SqlServiceImpl {
                                         records =
                                             executor.execute_router_plan(plan, cluster).await?.1;
                                     } else {
-                                        // Pick one of the workers to run as main for the request.
+                                        // Pick one of the workers to run as main for the request,
+                                        // others take over if it is not available.
                                         let i = thread_rng().sample(Uniform::new(0, workers.len()));
-                                        let rs = cluster.route_select(&workers[i], plan).await?.1;
+                                        let workers = workers[i..]
+                                            .iter()
+                                            .chain(workers[..i].iter())
+                                            .unique()
+                                            .cloned()
+                                            .collect_vec();
+                                        let rs = cluster.route_select(&workers, plan).await?.1;
                                         records = rs
                                             .into_iter()
                                             .map(|r| r.read())
@@ -865,6 +959,157 @@ impl SqlService for SqlServiceImpl {
     }
 }
 
+fn find_option<'a>(with_options: &'a [SqlOption], name: &str) -> Option<&'a Value> {
+    with_options
+        .iter()
+        .find(|o| o.name.value.eq_ignore_ascii_case(name))
+        .map(|o| &o.value)
+}
+
+fn aggregate_index_def(index: AggregateIndex) -> Result<IndexDef, CubeError> {
+    Ok(IndexDef {
+        name: index.name.to_string(),
+        multi_index: None,
+        columns: index.columns.into_iter().map(|c| c.value).collect(),
+        aggregates: Some(
+            index
+                .aggregates
+                .into_iter()
+                .map(|(function, column)| {
+                    Ok((AggregateFunction::from_name(&function.value)?, column.value))
+                })
+                .collect::<Result<Vec<_>, CubeError>>()?,
+        ),
+    })
+}
+
+/// Column names from `WITH (bloom_filter_columns = 'a, b')` option of CREATE TABLE.
+fn bloom_filter_columns(with_options: &[SqlOption]) -> Result<Option<Vec<String>>, CubeError> {
+    match find_option(with_options, "bloom_filter_columns") {
+        None => Ok(None),
+        Some(Value::SingleQuotedString(v)) => Ok(Some(
+            v.split(',')
+                .map(|c| c.trim().to_string())
+                .filter(|c| !c.is_empty())
+                .collect(),
+        )),
+        Some(v) => Err(CubeError::user(format!(
+            "bloom_filter_columns must be a string with comma separated column names, got {}",
+            v
+        ))),
+    }
+}
+
+/// Row group size from `WITH (row_group_size = 8192)` option of CREATE TABLE.
+fn row_group_size(with_options: &[SqlOption]) -> Result<Option<u64>, CubeError> {
+    let v = match find_option(with_options, "row_group_size") {
+        None => return Ok(None),
+        Some(Value::Number(v, _)) | Some(Value::SingleQuotedString(v)) => v,
+        Some(v) => {
+            return Err(CubeError::user(format!(
+                "row_group_size must be a positive number, got {}",
+                v
+            )))
+        }
+    };
+    match v.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(Some(n)),
+        _ => Err(CubeError::user(format!(
+            "row_group_size must be a positive number, got {}",
+            v
+        ))),
+    }
+}
+
+/// Applies codec and dictionary options of CREATE TABLE to the columns. Table-wide values are set
+/// by `WITH (codec = 'zstd', dictionary = 'off')` and can be overridden for particular columns by
+/// `WITH (column_codecs = 'a: none, b: snappy', column_dictionary = 'a: on')`.
+fn apply_storage_options(
+    columns: Vec<Column>,
+    with_options: &[SqlOption],
+) -> Result<Vec<Column>, CubeError> {
+    let codec = match find_option(with_options, "codec") {
+        None => None,
+        Some(v) => Some(ColumnCodec::from_name(&option_string("codec", v)?)?),
+    };
+    let dictionary = match find_option(with_options, "dictionary") {
+        None => None,
+        Some(v) => Some(parse_switch("dictionary", v)?),
+    };
+    let mut column_codecs = HashMap::new();
+    if let Some(v) = find_option(with_options, "column_codecs") {
+        for (c, codec) in parse_column_values("column_codecs", v)? {
+            column_codecs.insert(c, ColumnCodec::from_name(&codec)?);
+        }
+    }
+    let mut column_dictionary = HashMap::new();
+    if let Some(v) = find_option(with_options, "column_dictionary") {
+        for (c, d) in parse_column_values("column_dictionary", v)? {
+            let d = parse_switch("column_dictionary", &Value::SingleQuotedString(d))?;
+            column_dictionary.insert(c, d);
+        }
+    }
+    for c in column_codecs.keys().chain(column_dictionary.keys()) {
+        if !columns.iter().any(|column| column.get_name() == c) {
+            return Err(CubeError::user(format!(
+                "Column {} from storage options not found among column definitions",
+                c
+            )));
+        }
+    }
+
+    Ok(columns
+        .into_iter()
+        .map(|c| {
+            let codec = column_codecs.get(c.get_name()).cloned().or(codec);
+            let dictionary = column_dictionary.get(c.get_name()).cloned().or(dictionary);
+            c.with_codec(codec).with_dictionary(dictionary)
+        })
+        .collect())
+}
+
+fn option_string(name: &str, v: &Value) -> Result<String, CubeError> {
+    match v {
+        Value::SingleQuotedString(v) => Ok(v.clone()),
+        v => Err(CubeError::user(format!(
+            "{} must be a string, got {}",
+            name, v
+        ))),
+    }
+}
+
+fn parse_switch(name: &str, v: &Value) -> Result<bool, CubeError> {
+    let s = match v {
+        Value::Boolean(b) => return Ok(*b),
+        Value::SingleQuotedString(s) => s.trim().to_lowercase(),
+        v => v.to_string(),
+    };
+    match s.as_str() {
+        "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        _ => Err(CubeError::user(format!(
+            "{} must be one of: on, off, got {}",
+            name, s
+        ))),
+    }
+}
+
+/// Parses `'a: x, b: y'` into pairs of column names and values.
+fn parse_column_values(name: &str, v: &Value) -> Result<Vec<(String, String)>, CubeError> {
+    option_string(name, v)?
+        .split(',')
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.splitn(2, ':').collect_vec().as_slice() {
+            [column, value] => Ok((column.trim().to_string(), value.trim().to_string())),
+            _ => Err(CubeError::user(format!(
+                "{} must be a string of comma separated 'column: value' pairs, got '{}'",
+                name, p
+            ))),
+        })
+        .collect()
+}
+
 fn convert_columns_type(columns: &Vec<ColumnDef>) -> Result<Vec<Column>, CubeError> {
     let mut rolupdb_columns = Vec::new();
 
@@ -918,6 +1163,7 @@ fn convert_columns_type(columns: &Vec<ColumnDef>) -> Result<Vec<Column>, CubeErr
                         "hyperloglogpp" => ColumnType::HyperLogLog(HllFlavour::ZetaSketch),
                         "hll_snowflake" => ColumnType::HyperLogLog(HllFlavour::Snowflake),
                         "hll_postgres" => ColumnType::HyperLogLog(HllFlavour::Postgres),
+                        "tdigest" => ColumnType::Tdigest,
                         _ => {
                             return Err(CubeError::user(format!(
                                 "Custom type '{}' is not supported",
@@ -1193,6 +1439,24 @@ fn extract_data<'a>(
                 .unwrap()
                 .append_value(val)?;
         }
+        ColumnType::Tdigest => {
+            let builder = builder
+                .as_any_mut()
+                .downcast_mut::<BinaryBuilder>()
+                .unwrap();
+            if is_null {
+                builder.append_null()?;
+                return Ok(());
+            }
+            let val;
+            if let Expr::Value(v) = cell {
+                val = parse_binary_string(buffer, v)?
+            } else {
+                return Err(CubeError::user("Corrupted data in query.".to_string()));
+            };
+            is_valid_tdigest(val)?;
+            builder.append_value(val)?;
+        }
         ColumnType::Timestamp => {
             let builder = builder
                 .as_any_mut()
@@ -1836,6 +2100,7 @@ mod tests {
                 c.partition_split_threshold = 1000000;
                 c.compaction_chunks_count_threshold = 0;
                 c.not_used_timeout = 0;
+                c.metastore_snapshots_retention_secs = 0;
                 c
             })
             .start_test(async move |services| {
@@ -2166,6 +2431,76 @@ mod tests {
             assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(5)])]);
         }).await;
     }
+
+    #[tokio::test]
+    async fn remove_orphaned_remote_files() {
+        Config::run_test("remove_orphaned_remote_files", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service
+                .exec_query("CREATE TABLE foo.orphans (id int, name text)")
+                .await
+                .unwrap();
+            service
+                .exec_query("INSERT INTO foo.orphans (id, name) VALUES (1, 'a'), (2, 'b')")
+                .await
+                .unwrap();
+
+            let path = env::temp_dir().join("remove_orphaned_remote_files.txt");
+            fs::write(&path, "orphan").unwrap();
+            let mut orphans = vec![
+                "100500.parquet",
+                "100501.chunk.parquet",
+                "100501.chunk.parquet.bloom",
+                "100502.wal",
+                "temp-uploads/orphan.csv",
+            ];
+            for remote_path in orphans.iter().chain(["notes.txt"].iter()) {
+                services
+                    .remote_fs
+                    .upload_file(path.to_str().unwrap(), remote_path)
+                    .await
+                    .unwrap();
+            }
+            orphans.sort();
+
+            let recent = services
+                .scheduler
+                .remove_orphaned_remote_files(Duration::from_secs(3600), false)
+                .await
+                .unwrap();
+            assert_eq!(recent, Vec::<String>::new());
+
+            let mut found = services
+                .scheduler
+                .remove_orphaned_remote_files(Duration::from_secs(0), true)
+                .await
+                .unwrap();
+            found.sort();
+            assert_eq!(found, orphans);
+            let remote_files = services.remote_fs.list("").await.unwrap();
+            assert!(orphans.iter().all(|f| remote_files.iter().any(|r| r == f)));
+
+            let mut removed = services
+                .scheduler
+                .remove_orphaned_remote_files(Duration::from_secs(0), false)
+                .await
+                .unwrap();
+            removed.sort();
+            assert_eq!(removed, orphans);
+            let remote_files = services.remote_fs.list("").await.unwrap();
+            assert!(orphans.iter().all(|f| remote_files.iter().all(|r| r != f)));
+            assert!(remote_files.iter().any(|r| r == "notes.txt"));
+
+            let result = service
+                .exec_query("SELECT count(*) from foo.orphans")
+                .await
+                .unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(2)])]);
+        })
+        .await;
+    }
 }
 
 impl SqlServiceImpl {
@@ -33,6 +33,15 @@ pub struct PartitionedIndexRef {
     pub columns: Vec<Ident>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateIndex {
+    pub name: ObjectName,
+    pub table_name: ObjectName,
+    pub columns: Vec<Ident>,
+    /// Pairs of aggregate function name and column name, e.g. `SUM(amount)`.
+    pub aggregates: Vec<(Ident, Ident)>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     Statement(SQLStatement),
@@ -40,9 +49,18 @@ pub enum Statement {
         create_table: SQLStatement,
         partitioned_index: Option<PartitionedIndexRef>,
         indexes: Vec<SQLStatement>,
+        aggregate_indexes: Vec<AggregateIndex>,
         locations: Option<Vec<String>>,
         unique_key: Option<Vec<Ident>>,
     },
+    CreateAggregateIndex(AggregateIndex),
+    /// `CREATE INDEX name ON table USING bloom (columns)`. Bloom filters belong to the table
+    /// columns, so the index name is only checked for syntax.
+    CreateBloomFilterIndex {
+        name: ObjectName,
+        table_name: ObjectName,
+        columns: Vec<Ident>,
+    },
     CreateSchema {
         schema_name: ObjectName,
         if_not_exists: bool,
@@ -105,6 +123,16 @@ impl<'a> CubeStoreParser<'a> {
             || self.parser.consume_token(&Token::make_keyword("source"))
         {
             self.parse_create_source()
+        } else if self.parse_custom_keyword("AGGREGATE") {
+            self.parser.expect_keyword(Keyword::INDEX)?;
+            let name = self.parser.parse_object_name()?;
+            self.parser.expect_keyword(Keyword::ON)?;
+            let table_name = self.parser.parse_object_name()?;
+            Ok(Statement::CreateAggregateIndex(
+                self.parse_aggregate_index(name, table_name)?,
+            ))
+        } else if self.parser.parse_keyword(Keyword::INDEX) {
+            self.parse_create_index()
         } else {
             Ok(Statement::Statement(self.parser.parse_create()?))
         }
@@ -141,9 +169,18 @@ impl<'a> CubeStoreParser<'a> {
             };
 
             let mut indexes = Vec::new();
+            let mut aggregate_indexes = Vec::new();
 
-            while self.parser.parse_keyword(Keyword::INDEX) {
-                indexes.push(self.parse_with_index(name.clone())?);
+            loop {
+                if self.parser.parse_keyword(Keyword::INDEX) {
+                    indexes.push(self.parse_with_index(name.clone())?);
+                } else if self.parse_custom_keyword("AGGREGATE") {
+                    self.parser.expect_keyword(Keyword::INDEX)?;
+                    let index_name = self.parser.parse_object_name()?;
+                    aggregate_indexes.push(self.parse_aggregate_index(index_name, name.clone())?);
+                } else {
+                    break;
+                }
             }
 
             let partitioned_index = if self.parser.parse_keywords(&[
@@ -192,6 +229,7 @@ impl<'a> CubeStoreParser<'a> {
                     like,
                 },
                 indexes,
+                aggregate_indexes,
                 partitioned_index,
                 locations,
                 unique_key,
@@ -220,6 +258,80 @@ impl<'a> CubeStoreParser<'a> {
         })
     }
 
+    /// Parses `CREATE INDEX` with an optional `USING bloom` clause. Unique indexes are parsed by
+    /// the SQL parser.
+    fn parse_create_index(&mut self) -> Result<Statement, ParserError> {
+        let if_not_exists =
+            self.parser
+                .parse_keywords(&[Keyword::IF, Keyword::NOT, Keyword::EXISTS]);
+        let name = self.parser.parse_object_name()?;
+        self.parser.expect_keyword(Keyword::ON)?;
+        let table_name = self.parser.parse_object_name()?;
+        if self.parser.parse_keyword(Keyword::USING) {
+            if !self.parse_custom_keyword("BLOOM") {
+                return self.parser.expected("BLOOM", self.parser.peek_token());
+            }
+            self.parser.expect_token(&Token::LParen)?;
+            let columns = self
+                .parser
+                .parse_comma_separated(Parser::parse_identifier)?;
+            self.parser.expect_token(&Token::RParen)?;
+            return Ok(Statement::CreateBloomFilterIndex {
+                name,
+                table_name,
+                columns,
+            });
+        }
+        self.parser.expect_token(&Token::LParen)?;
+        let columns = self
+            .parser
+            .parse_comma_separated(Parser::parse_order_by_expr)?;
+        self.parser.expect_token(&Token::RParen)?;
+        Ok(Statement::Statement(SQLStatement::CreateIndex {
+            name,
+            table_name,
+            columns,
+            unique: false,
+            if_not_exists,
+        }))
+    }
+
+    /// Parses `(dimension, ...) FUNCTION(column), ...` part of the aggregate index definition.
+    fn parse_aggregate_index(
+        &mut self,
+        name: ObjectName,
+        table_name: ObjectName,
+    ) -> Result<AggregateIndex, ParserError> {
+        self.parser.expect_token(&Token::LParen)?;
+        let columns = self
+            .parser
+            .parse_comma_separated(Parser::parse_identifier)?;
+        self.parser.expect_token(&Token::RParen)?;
+        let aggregates = self.parser.parse_comma_separated(|p| {
+            let function = p.parse_identifier()?;
+            p.expect_token(&Token::LParen)?;
+            let column = p.parse_identifier()?;
+            p.expect_token(&Token::RParen)?;
+            Ok((function, column))
+        })?;
+        Ok(AggregateIndex {
+            name,
+            table_name,
+            columns,
+            aggregates,
+        })
+    }
+
+    fn parse_custom_keyword(&mut self, keyword: &str) -> bool {
+        match self.parser.peek_token() {
+            Token::Word(w) if w.quote_style.is_none() && w.value.eq_ignore_ascii_case(keyword) => {
+                self.parser.next_token();
+                true
+            }
+            _ => false,
+        }
+    }
+
     fn parse_create_schema(&mut self) -> Result<Statement, ParserError> {
         let if_not_exists =
             self.parser
@@ -2,12 +2,16 @@ use crate::config::injection::DIService;
 use crate::config::ConfigObj;
 use crate::metastore::multi_index::MultiPartition;
 use crate::metastore::partition::partition_file_name;
-use crate::metastore::{Chunk, IdRow, MetaStore, Partition, PartitionData};
+use crate::metastore::{Chunk, IdRow, Index, MetaStore, Partition, PartitionData};
 use crate::remotefs::RemoteFs;
-use crate::store::{ChunkDataStore, ChunkStore, ROW_GROUP_SIZE};
+use crate::store::{
+    aggregate_index_exec, upload_data_file, ChunkDataStore, ChunkStore, ROW_GROUP_SIZE,
+};
+use crate::table::bloom::{bloom_filter_file_name, BloomFilterSetBuilder};
 use crate::table::data::{cmp_min_rows, cmp_partition_key};
 use crate::table::parquet::{arrow_schema, ParquetTableStore};
 use crate::table::redistribute::redistribute;
+use crate::table::stats::ColumnStats;
 use crate::table::{Row, TableValue};
 use crate::CubeError;
 use arrow::array::{ArrayRef, UInt64Array};
@@ -29,7 +33,7 @@ use datafusion::physical_plan::{
     AggregateExpr, ExecutionPlan, PhysicalExpr, SendableRecordBatchStream,
 };
 use datafusion::scalar::ScalarValue;
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
 use itertools::{EitherOrBoth, Itertools};
 use num::integer::div_ceil;
 use num::Integer;
@@ -118,33 +122,6 @@ impl CompactionService for CompactionServiceImpl {
             .iter()
             .map(|c| c.get_row().get_row_count())
             .sum::<u64>();
-        // For multi-partitions, we only compact chunks and never change the main table.
-        // And we never split, multi-partitions have a different process for that.
-        let new_chunk = match &multi_part {
-            None => None,
-            Some(_) => Some(
-                self.meta_store
-                    .create_chunk(partition_id, chunks_row_count as usize, false)
-                    .await?,
-            ),
-        };
-        let mut total_rows = chunks_row_count;
-        if new_chunk.is_none() {
-            total_rows += partition.get_row().main_table_row_count();
-        }
-        let mut new_partitions = Vec::new();
-        if new_chunk.is_none() {
-            let new_partitions_count =
-                div_ceil(total_rows, self.config.partition_split_threshold()) as usize;
-            for _ in 0..new_partitions_count {
-                new_partitions.push(
-                    self.meta_store
-                        .create_partition(Partition::new_child(&partition, None))
-                        .await?,
-                );
-            }
-        }
-
         let mut data = Vec::new();
         let num_columns = index.get_row().columns().len();
         for chunk in chunks.iter() {
@@ -155,27 +132,22 @@ impl CompactionService for CompactionServiceImpl {
         }
 
         let store = ParquetTableStore::new(index.get_row().clone(), ROW_GROUP_SIZE);
-        let old_partition_remote = match &new_chunk {
+        // For multi-partitions, we only compact chunks and never change the main table.
+        // And we never split, multi-partitions have a different process for that.
+        let old_partition_remote = match &multi_part {
             Some(_) => None,
             None => partition.get_row().get_full_name(partition.get_id()),
         };
+        // The old partition file is read until the merge below finishes.
+        let _used_file = self
+            .remote_fs
+            .use_local_files(old_partition_remote.iter().cloned().collect());
         let old_partition_local = if let Some(f) = old_partition_remote {
             Some(self.remote_fs.download_file(&f).await?)
         } else {
             None
         };
-        let mut new_local_files = Vec::new();
-        if let Some(c) = &new_chunk {
-            let remote = ChunkStore::chunk_remote_path(c.get_id());
-            new_local_files.push(self.remote_fs.temp_upload_path(&remote).await?);
-        } else {
-            for p in new_partitions.iter() {
-                let new_remote_path = partition_file_name(p.get_id());
-                new_local_files.push(self.remote_fs.temp_upload_path(&new_remote_path).await?);
-            }
-        }
 
-        let new_local_files2 = new_local_files.clone();
         let key_size = index.get_row().sort_key_size() as usize;
         let (store, new) = cube_ext::spawn_blocking(move || -> Result<_, CubeError> {
             // Concat rows from all chunks.
@@ -206,7 +178,7 @@ impl CompactionService for CompactionServiceImpl {
         })
         .await??;
 
-        // Merge and write rows.
+        // Merge rows.
         let schema = Arc::new(arrow_schema(index.get_row()));
         let main_table: Arc<dyn ExecutionPlan> = match old_partition_local {
             Some(file) => Arc::new(ParquetExec::try_from_path(
@@ -225,20 +197,86 @@ impl CompactionService for CompactionServiceImpl {
             .get_table_by_id(index.get_row().table_id())
             .await?;
         let unique_key = table.get_row().unique_key_columns();
-        let records = merge_chunks(key_size, main_table, new, unique_key).await?;
+        let mut records = merge_chunks(index.get_row(), main_table, new, unique_key).await?;
+        let mut total_rows = chunks_row_count;
+        if multi_part.is_none() {
+            total_rows += partition.get_row().main_table_row_count();
+        }
+        if index.get_row().is_aggregating() {
+            // Rows with the same key are aggregated into one, the number of rows is known only
+            // after the merge. It's needed to size the new partitions.
+            let schema = records.schema();
+            let batches = records.try_collect::<Vec<_>>().await?;
+            total_rows = batches.iter().map(|b| b.num_rows() as u64).sum();
+            records = MemoryExec::try_new(&[batches], schema, None)?
+                .execute(0)
+                .await?;
+        }
+
+        let has_bloom_filter = !index.get_row().bloom_filter_columns().is_empty();
+        let new_chunk = match &multi_part {
+            None => None,
+            Some(_) => Some(
+                self.meta_store
+                    .create_chunk(
+                        partition_id,
+                        total_rows as usize,
+                        false,
+                        None,
+                        has_bloom_filter,
+                    )
+                    .await?,
+            ),
+        };
+        let mut new_partitions = Vec::new();
+        if new_chunk.is_none() {
+            let new_partitions_count =
+                div_ceil(total_rows, self.config.partition_split_threshold()) as usize;
+            for _ in 0..new_partitions_count {
+                new_partitions.push(
+                    self.meta_store
+                        .create_partition(
+                            Partition::new_child(&partition, None)
+                                .update_has_bloom_filter(has_bloom_filter),
+                        )
+                        .await?,
+                );
+            }
+        }
+
+        // Write rows.
+        let mut new_local_files = Vec::new();
+        if let Some(c) = &new_chunk {
+            let remote = ChunkStore::chunk_remote_path(c.get_id());
+            new_local_files.push(self.remote_fs.temp_upload_path(&remote).await?);
+        } else {
+            for p in new_partitions.iter() {
+                let new_remote_path = partition_file_name(p.get_id());
+                new_local_files.push(self.remote_fs.temp_upload_path(&new_remote_path).await?);
+            }
+        }
         let count_and_min =
-            write_to_files(records, total_rows as usize, store, new_local_files2).await?;
+            write_to_files(records, total_rows as usize, store, new_local_files.clone()).await?;
 
         if let Some(c) = &new_chunk {
             assert_eq!(new_local_files.len(), 1);
             let remote = ChunkStore::chunk_remote_path(c.get_id());
-            self.remote_fs
-                .upload_file(&new_local_files[0], &remote)
-                .await?;
+            upload_data_file(
+                self.remote_fs.as_ref(),
+                &new_local_files[0],
+                &remote,
+                has_bloom_filter,
+            )
+            .await?;
             let chunk_ids = chunks.iter().map(|c| c.get_id()).collect_vec();
+            let chunk_stats = count_and_min
+                .into_iter()
+                .next()
+                .map(|(_, _, stats)| stats)
+                .unwrap_or_default();
             let swapped = self
                 .meta_store
-                .swap_compacted_chunks(partition_id, chunk_ids, c.get_id())
+                .swap_compacted_chunks(partition_id, chunk_ids, c.get_id(), chunk_stats)
                 .await?;
             if !swapped {
                 log::debug!(
@@ -246,6 +284,11 @@ impl CompactionService for CompactionServiceImpl {
                     partition_id
                 );
                 self.remote_fs.delete_file(&remote).await?;
+                if has_bloom_filter {
+                    self.remote_fs
+                        .delete_file(&bloom_filter_file_name(&remote))
+                        .await?;
+                }
             }
             return Ok(());
         }
@@ -259,15 +302,23 @@ impl CompactionService for CompactionServiceImpl {
             match p {
                 EitherOrBoth::Both(p, _) => {
                     let new_remote_path = partition_file_name(p.get_id());
-                    self.remote_fs
-                        .upload_file(&new_local_files[i], new_remote_path.as_str())
-                        .await?;
+                    upload_data_file(
+                        self.remote_fs.as_ref(),
+                        &new_local_files[i],
+                        new_remote_path.as_str(),
+                        has_bloom_filter,
+                    )
+                    .await?;
                     filtered_partitions.push(p);
                 }
                 EitherOrBoth::Left(p) => {
                     self.meta_store.delete_partition(p.get_id()).await?;
                     // TODO: ensure all files get removed on errors.
                     let _ = tokio::fs::remove_file(&new_local_files[i]).await;
+                    if has_bloom_filter {
+                        let _ = tokio::fs::remove_file(bloom_filter_file_name(&new_local_files[i]))
+                            .await;
+                    }
                 }
                 EitherOrBoth::Right(_) => {
                     return Err(CubeError::internal(format!(
@@ -292,9 +343,13 @@ impl CompactionService for CompactionServiceImpl {
                     .enumerate()
                     .map(|(i, item)| -> Result<_, CubeError> {
                         match item {
-                            EitherOrBoth::Both((c, min), (_, next_min)) => {
+                            EitherOrBoth::Both((c, min, stats), (_, next_min, _)) => {
                                 if i == 0 && partition_min.is_none() {
-                                    Ok((*c as u64, (None, Some(Row::new(next_min.clone())))))
+                                    Ok((
+                                        *c as u64,
+                                        (None, Some(Row::new(next_min.clone()))),
+                                        stats.clone(),
+                                    ))
                                 } else if i < num_filtered - 1 {
                                     Ok((
                                         *c as u64,
@@ -302,6 +357,7 @@ impl CompactionService for CompactionServiceImpl {
                                             Some(Row::new(min.clone())),
                                             Some(Row::new(next_min.clone())),
                                         ),
+                                        stats.clone(),
                                     ))
                                 } else {
                                     Err(CubeError::internal(format!(
@@ -310,13 +366,18 @@ impl CompactionService for CompactionServiceImpl {
                                     )))
                                 }
                             }
-                            EitherOrBoth::Left((c, min)) => {
+                            EitherOrBoth::Left((c, min, stats)) => {
                                 if i == 0 && num_filtered == 1 {
-                                    Ok((*c as u64, (partition_min.clone(), partition_max.clone())))
+                                    Ok((
+                                        *c as u64,
+                                        (partition_min.clone(), partition_max.clone()),
+                                        stats.clone(),
+                                    ))
                                 } else if i == num_filtered - 1 {
                                     Ok((
                                         *c as u64,
                                         (Some(Row::new(min.clone())), partition_max.clone()),
+                                        stats.clone(),
                                     ))
                                 } else {
                                     Err(CubeError::internal(format!(
@@ -352,6 +413,11 @@ impl CompactionService for CompactionServiceImpl {
         let key_len = multi_index.get_row().key_columns().len();
 
         // Find key ranges for new partitions.
+        let mut remote_files = Vec::new();
+        for p in &partitions {
+            collect_remote_files(p, &mut remote_files);
+        }
+        let _used_files = self.remote_fs.use_local_files(remote_files);
         let files = download_files(&partitions, self.remote_fs.clone()).await?;
         let keys = find_partition_keys(
             keys_with_counts(&files, key_len).await?,
@@ -584,7 +650,7 @@ pub(crate) async fn write_to_files(
     num_rows: usize,
     store: ParquetTableStore,
     files: Vec<String>,
-) -> Result<Vec<(usize, Vec<TableValue>)>, CubeError> {
+) -> Result<Vec<(usize, Vec<TableValue>, Vec<ColumnStats>)>, CubeError> {
     let rows_per_file = (num_rows as usize).div_ceil(&files.len());
     let key_size = store.key_size() as usize;
     let partition_split_key_size = store.partition_split_key_size() as usize;
@@ -635,13 +701,18 @@ pub(crate) async fn write_to_files(
         };
     };
 
-    write_to_files_impl(records, store, files, pick_writer).await?;
+    let mut column_stats = write_to_files_impl(records, store, files, pick_writer)
+        .await?
+        .into_iter();
 
     let mut stats = take(stats.lock().unwrap().deref_mut());
     if stats.last().unwrap().0 == 0 {
         stats.pop();
     }
-    Ok(stats)
+    Ok(stats
+        .into_iter()
+        .map(|(num_rows, first_row)| (num_rows, first_row, column_stats.next().unwrap_or_default()))
+        .collect())
 }
 
 enum WriteBatchTo {
@@ -656,8 +727,14 @@ async fn write_to_files_impl(
     store: ParquetTableStore,
     files: Vec<String>,
     mut pick_writer: impl FnMut(&RecordBatch) -> WriteBatchTo,
-) -> Result<(), CubeError> {
+) -> Result<Vec<Vec<ColumnStats>>, CubeError> {
     let schema = Arc::new(store.arrow_schema());
+    let row_group_size = store.row_group_size();
+    let bloom_filter_columns = store.bloom_filter_columns().to_vec();
+    let bloom_filter_files = files
+        .iter()
+        .map(|f| bloom_filter_file_name(f))
+        .collect_vec();
     let mut writers = files.into_iter().map(move |f| -> Result<_, CubeError> {
         Ok(ArrowWriter::try_new(
             File::create(f)?,
@@ -668,22 +745,53 @@ async fn write_to_files_impl(
 
     let (write_tx, mut write_rx) = tokio::sync::mpsc::channel(1);
     let io_job = cube_ext::spawn_blocking(move || -> Result<_, CubeError> {
+        let new_bloom_filter = || {
+            if bloom_filter_columns.is_empty() {
+                None
+            } else {
+                Some(BloomFilterSetBuilder::new(&bloom_filter_columns))
+            }
+        };
         let mut writer = writers.next().transpose()?.unwrap();
+        let mut bloom_filter = new_bloom_filter();
         let mut current_writer_i = 0;
+        // Column statistics of every written file.
+        let mut stats: Vec<Vec<ColumnStats>> = Vec::new();
         while let Some((writer_i, batch)) = write_rx.blocking_recv() {
             debug_assert!(current_writer_i <= writer_i);
             if current_writer_i != writer_i {
                 writer.close()?;
+                if let Some(b) = bloom_filter {
+                    b.finish().write(&bloom_filter_files[current_writer_i])?;
+                }
 
                 writer = writers.next().transpose()?.unwrap();
+                bloom_filter = new_bloom_filter();
                 current_writer_i = writer_i;
             }
 
+            if let Some(b) = &mut bloom_filter {
+                b.add_columns(batch.columns());
+            }
+
+            let batch_stats = ColumnStats::from_columns(batch.columns());
+            if stats.len() <= writer_i {
+                stats.resize(writer_i + 1, Vec::new());
+            }
+            stats[writer_i] = if stats[writer_i].is_empty() {
+                batch_stats
+            } else {
+                ColumnStats::merge_all(&stats[writer_i], &batch_stats)
+            };
+
             writer.write(&batch)?;
         }
 
         writer.close()?;
-        Ok(())
+        if let Some(b) = bloom_filter {
+            b.finish().write(&bloom_filter_files[current_writer_i])?;
+        }
+        Ok(stats)
     });
 
     let mut writer_i = 0;
@@ -702,7 +810,7 @@ async fn write_to_files_impl(
             }
         }
     };
-    let err = redistribute(records, ROW_GROUP_SIZE, move |b| {
+    let err = redistribute(records, row_group_size, move |b| {
         let r = process_row_group(b);
         let write_tx = write_tx.clone();
         async move {
@@ -714,10 +822,10 @@ async fn write_to_files_impl(
     .await;
 
     // We want to report IO errors first, `err` will be unhelpful ("channel closed") when IO fails.
-    io_job.await??;
+    let stats = io_job.await??;
     err?;
 
-    Ok(())
+    Ok(stats)
 }
 
 async fn write_to_files_by_keys(
@@ -780,11 +888,12 @@ async fn write_to_files_by_keys(
 }
 
 async fn merge_chunks(
-    key_size: usize,
+    index: &Index,
     l: Arc<dyn ExecutionPlan>,
     r: Vec<ArrayRef>,
     unique_key_columns: Option<Vec<&crate::metastore::Column>>,
 ) -> Result<SendableRecordBatchStream, CubeError> {
+    let key_size = index.sort_key_size() as usize;
     let schema = l.schema();
     let r = RecordBatch::try_new(schema.clone(), r)?;
 
@@ -815,6 +924,11 @@ async fn merge_chunks(
         )?)
     }
 
+    if index.is_aggregating() {
+        // Rows with the same key can come from different chunks, merge them once again.
+        res = aggregate_index_exec(index, res, AggregateStrategy::InplaceSorted)?;
+    }
+
     Ok(res.execute(0).await?)
 }
 
@@ -849,23 +963,25 @@ mod tests {
                 vec![],
                 true,
                 None,
+                None,
+                None,
             )
             .await
             .unwrap();
         metastore.get_default_index(1).await.unwrap();
         let partition = metastore.get_partition(1).await.unwrap();
         metastore
-            .create_chunk(partition.get_id(), 10, false)
+            .create_chunk(partition.get_id(), 10, false, None, false)
             .await
             .unwrap();
         metastore.chunk_uploaded(1).await.unwrap();
         metastore
-            .create_chunk(partition.get_id(), 16, false)
+            .create_chunk(partition.get_id(), 16, false, None, false)
             .await
             .unwrap();
         metastore.chunk_uploaded(2).await.unwrap();
         metastore
-            .create_chunk(partition.get_id(), 20, false)
+            .create_chunk(partition.get_id(), 20, false, None, false)
             .await
             .unwrap();
         metastore.chunk_uploaded(3).await.unwrap();
@@ -941,7 +1057,7 @@ mod tests {
             .unwrap()
             .get_id();
         metastore
-            .create_chunk(next_partition_id, 2, false)
+            .create_chunk(next_partition_id, 2, false, None, false)
             .await
             .unwrap();
         metastore.chunk_uploaded(4).await.unwrap();
@@ -1014,9 +1130,11 @@ impl MultiSplit {
         let new_partition_rows = &mut self.new_partition_rows;
         let uploads = &mut self.uploads;
 
+        let has_bloom_filter = !p.index.get_row().bloom_filter_columns().is_empty();
         let mut children = Vec::with_capacity(mchildren.len());
         for mc in mchildren.iter() {
-            let c = Partition::new_child(&p.partition, Some(mc.get_id()));
+            let c = Partition::new_child(&p.partition, Some(mc.get_id()))
+                .update_has_bloom_filter(has_bloom_filter);
             let c = c.update_min_max_and_row_count(
                 mc.get_row().min_row().cloned(),
                 mc.get_row().max_row().cloned(),
@@ -1027,8 +1145,9 @@ impl MultiSplit {
 
         let mut in_files = Vec::new();
         collect_remote_files(&p, &mut in_files);
+        let _used_files = self.fs.use_local_files(in_files.clone());
         for f in &mut in_files {
-            *f = self.fs.local_file(f).await?;
+            *f = self.fs.download_file(f).await?;
         }
 
         let mut out_files = Vec::with_capacity(children.len());
@@ -1068,7 +1187,7 @@ impl MultiSplit {
             let local_path = take(&mut out_files[i]);
             let remote_path = take(&mut out_remote_paths[i]);
             uploads.push(cube_ext::spawn(async move {
-                fs.upload_file(&local_path, &remote_path).await
+                upload_data_file(fs.as_ref(), &local_path, &remote_path, has_bloom_filter).await
             }));
         }
         Ok(())
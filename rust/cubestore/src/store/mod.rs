@@ -1,16 +1,24 @@
 pub mod compaction;
 
 use async_trait::async_trait;
+use datafusion::physical_plan::expressions::{Max, Min, Sum};
+use datafusion::physical_plan::hash_aggregate::{
+    AggregateMode, AggregateStrategy, HashAggregateExec,
+};
 use datafusion::physical_plan::memory::MemoryExec;
-use datafusion::physical_plan::ExecutionPlan;
+use datafusion::physical_plan::udaf::create_aggregate_expr;
+use datafusion::physical_plan::{collect, AggregateExpr, ExecutionPlan, PhysicalExpr};
 use serde::{de, Deserialize, Serialize};
 extern crate bincode;
 
 use bincode::{deserialize_from, serialize_into};
 
 use crate::metastore::{
-    table::Table, Chunk, Column, ColumnType, IdRow, Index, MetaStore, Partition, WAL,
+    table::Table, AggregateFunction, Chunk, Column, ColumnType, IdRow, Index, MetaStore, Partition,
+    WAL,
 };
+use crate::queryplanner::udfs::{aggregate_udf_by_kind, CubeAggregateUDFKind};
+use crate::remotefs::local_cache::LocalFilesGuard;
 use crate::remotefs::RemoteFs;
 use crate::table::{Row, TableValue};
 use crate::CubeError;
@@ -23,8 +31,10 @@ use std::{
 
 use crate::cluster::Cluster;
 use crate::config::injection::DIService;
+use crate::table::bloom::bloom_filter_file_name;
 use crate::table::data::cmp_partition_key;
 use crate::table::parquet::{arrow_schema, ParquetTableStore};
+use crate::table::stats::ColumnStats;
 use arrow::array::{Array, ArrayRef, Int64Builder, StringBuilder, UInt64Array};
 use arrow::record_batch::RecordBatch;
 use datafusion::cube_ext;
@@ -178,6 +188,23 @@ fn load<T: de::DeserializeOwned>(path: String) -> Result<T, CubeError> {
     Ok(res)
 }
 
+/// Uploads the data file along with bloom filters written beside it by [ParquetTableStore].
+pub(crate) async fn upload_data_file(
+    fs: &dyn RemoteFs,
+    local_file: &str,
+    remote_file: &str,
+    has_bloom_filter: bool,
+) -> Result<(), CubeError> {
+    if has_bloom_filter {
+        fs.upload_file(
+            &bloom_filter_file_name(local_file),
+            &bloom_filter_file_name(remote_file),
+        )
+        .await?;
+    }
+    fs.upload_file(local_file, remote_file).await
+}
+
 #[async_trait]
 pub trait WALDataStore: DIService + Send + Sync {
     async fn add_wal(&self, table: IdRow<Table>, data: DataFrame) -> Result<IdRow<WAL>, CubeError>;
@@ -198,6 +225,13 @@ pub trait ChunkDataStore: DIService + Send + Sync {
         in_memory: bool,
     ) -> Result<Vec<ChunkUploadJob>, CubeError>;
     async fn repartition(&self, partition_id: u64) -> Result<(), CubeError>;
+    /// Adds rows of the `source` partitions and chunks of the default index to a new `index`.
+    /// Returns ids of uploaded chunks. Uploaded chunks are **not** activated.
+    async fn backfill_index(
+        &self,
+        index: IdRow<Index>,
+        source: Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>,
+    ) -> Result<Vec<ChunkUploadJob>, CubeError>;
     async fn get_chunk_columns(&self, chunk: IdRow<Chunk>) -> Result<Vec<RecordBatch>, CubeError>;
     async fn delete_remote_chunk(&self, chunk: IdRow<Chunk>) -> Result<(), CubeError>;
     async fn add_memory_chunk(&self, chunk_id: u64, batch: RecordBatch) -> Result<(), CubeError>;
@@ -257,6 +291,7 @@ impl WALDataStore for WALStore {
             )));
         }
         let remote_path = WALStore::wal_remote_path(wal_id);
+        let _used_file = self.remote_fs.use_local_files(vec![remote_path.clone()]);
         self.remote_fs.download_file(&remote_path).await?;
         let local_file = self.remote_fs.local_file(&remote_path).await?;
         Ok(
@@ -365,6 +400,64 @@ impl ChunkDataStore for ChunkStore {
         Ok(())
     }
 
+    async fn backfill_index(
+        &self,
+        index: IdRow<Index>,
+        source: Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>,
+    ) -> Result<Vec<ChunkUploadJob>, CubeError> {
+        let mut new_chunks = Vec::new();
+        for (partition, chunks) in source {
+            let source_index = self
+                .meta_store
+                .get_index(partition.get_row().get_index_id())
+                .await?
+                .into_row();
+            let mut batches = Vec::new();
+            if let Some(f) = partition.get_row().get_full_name(partition.get_id()) {
+                let _used_file = self.remote_fs.use_local_files(vec![f.clone()]);
+                let local_file = self.remote_fs.download_file(&f).await?;
+                let index = source_index.clone();
+                batches.extend(
+                    cube_ext::spawn_blocking(move || -> Result<_, CubeError> {
+                        let parquet = ParquetTableStore::new(index, ROW_GROUP_SIZE);
+                        Ok(parquet.read_columns(&local_file)?)
+                    })
+                    .await??,
+                );
+            }
+            for chunk in chunks {
+                if chunk.get_row().in_memory() {
+                    return Err(CubeError::user(format!(
+                        "Can't create '{}' index while table has in memory chunks, try again after they are compacted",
+                        index.get_row().get_name()
+                    )));
+                }
+                batches.extend(self.get_chunk_columns(chunk).await?);
+            }
+            if batches.iter().all(|b| b.num_rows() == 0) {
+                continue;
+            }
+
+            let mut columns = Vec::with_capacity(source_index.columns().len());
+            for i in 0..source_index.columns().len() {
+                columns.push(arrow::compute::concat(
+                    &batches.iter().map(|b| b.column(i).as_ref()).collect_vec(),
+                )?)
+            }
+            new_chunks.append(
+                &mut self
+                    .build_index_chunks(
+                        &[index.clone()],
+                        columns.into(),
+                        source_index.columns(),
+                        false,
+                    )
+                    .await?,
+            );
+        }
+        Ok(new_chunks)
+    }
+
     async fn get_chunk_columns(&self, chunk: IdRow<Chunk>) -> Result<Vec<RecordBatch>, CubeError> {
         if chunk.get_row().in_memory() {
             let partition = self
@@ -388,7 +481,7 @@ impl ChunkDataStore for ChunkStore {
                     arrow_schema(&index.get_row()),
                 )))])
         } else {
-            let (local_file, index) = self.download_chunk(chunk).await?;
+            let (local_file, index, _used_file) = self.download_chunk(chunk).await?;
             Ok(cube_ext::spawn_blocking(move || -> Result<_, CubeError> {
                 let parquet = ParquetTableStore::new(index, ROW_GROUP_SIZE);
                 Ok(parquet.read_columns(&local_file)?)
@@ -410,6 +503,9 @@ impl ChunkDataStore for ChunkStore {
     }
 
     async fn delete_remote_chunk(&self, chunk: IdRow<Chunk>) -> Result<(), CubeError> {
+        if let Some(bloom_filter) = chunk.get_row().get_bloom_filter_name(chunk.get_id()) {
+            self.remote_fs.delete_file(&bloom_filter).await?;
+        }
         let remote_path = ChunkStore::chunk_file_name(chunk);
         self.remote_fs.delete_file(&remote_path).await?;
         Ok(())
@@ -417,7 +513,11 @@ impl ChunkDataStore for ChunkStore {
 }
 
 impl ChunkStore {
-    async fn download_chunk(&self, chunk: IdRow<Chunk>) -> Result<(String, Index), CubeError> {
+    /// Returned guard keeps the local copy of the chunk until it's read.
+    async fn download_chunk(
+        &self,
+        chunk: IdRow<Chunk>,
+    ) -> Result<(String, Index, LocalFilesGuard), CubeError> {
         if !chunk.get_row().uploaded() {
             return Err(CubeError::internal(format!(
                 "Trying to get not uploaded chunk: {:?}",
@@ -433,10 +533,12 @@ impl ChunkStore {
             .get_index(partition.get_row().get_index_id())
             .await?;
         let remote_path = ChunkStore::chunk_file_name(chunk);
+        let used_file = self.remote_fs.use_local_files(vec![remote_path.clone()]);
         self.remote_fs.download_file(&remote_path).await?;
         Ok((
             self.remote_fs.local_file(&remote_path).await?,
             index.into_row(),
+            used_file,
         ))
     }
 }
@@ -509,6 +611,8 @@ mod tests {
                     Vec::new(),
                     true,
                     None,
+                    None,
+                    None,
                 )
                 .await
                 .unwrap();
@@ -590,6 +694,8 @@ mod tests {
                     vec![],
                     true,
                     None,
+                    None,
+                    None,
                 )
                 .await
                 .unwrap();
@@ -707,9 +813,21 @@ impl ChunkStore {
         data: Vec<ArrayRef>,
         in_memory: bool,
     ) -> Result<ChunkUploadJob, CubeError> {
+        let (data, column_stats) = cube_ext::spawn_blocking(move || {
+            let column_stats = ColumnStats::from_columns(&data);
+            (data, column_stats)
+        })
+        .await?;
+        let has_bloom_filter = !in_memory && !index.get_row().bloom_filter_columns().is_empty();
         let chunk = self
             .meta_store
-            .create_chunk(partition.get_id(), data[0].len(), in_memory)
+            .create_chunk(
+                partition.get_id(),
+                data[0].len(),
+                in_memory,
+                Some(column_stats),
+                has_bloom_filter,
+            )
             .await?;
         if in_memory {
             trace!(
@@ -739,7 +857,7 @@ impl ChunkStore {
 
             let fs = self.remote_fs.clone();
             Ok(cube_ext::spawn(async move {
-                fs.upload_file(&local_file, &remote_path).await?;
+                upload_data_file(fs.as_ref(), &local_file, &remote_path, has_bloom_filter).await?;
                 Ok(chunk)
             }))
         }
@@ -764,8 +882,11 @@ impl ChunkStore {
                 (rows, remapped)
             })
             .await?;
-            let remapped = remapped?;
+            let mut remapped = remapped?;
             rows = rows_again;
+            if index.get_row().is_aggregating() {
+                remapped = aggregate_columns(index.get_row(), remapped).await?;
+            }
             new_chunks.append(
                 &mut self
                     .partition_rows(index.get_id(), remapped, in_memory)
@@ -800,6 +921,81 @@ fn remap_columns(
     Ok(new)
 }
 
+/// Merges rows of the aggregating index that have equal sort keys. Input rows can be in any order,
+/// the order of the resulting rows is not specified.
+async fn aggregate_columns(
+    index: &Index,
+    columns: Vec<ArrayRef>,
+) -> Result<Vec<ArrayRef>, CubeError> {
+    if columns.is_empty() || columns[0].len() == 0 {
+        return Ok(columns);
+    }
+    let schema = Arc::new(arrow_schema(index));
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let input = Arc::new(MemoryExec::try_new(&[vec![batch]], schema.clone(), None)?);
+    let plan = aggregate_index_exec(index, input, AggregateStrategy::Hash)?;
+    let batches = collect(plan).await?;
+    let mut result = Vec::with_capacity(schema.fields().len());
+    for i in 0..schema.fields().len() {
+        result.push(arrow::compute::concat(
+            &batches.iter().map(|b| b.column(i).as_ref()).collect_vec(),
+        )?);
+    }
+    Ok(result)
+}
+
+/// Builds a plan that merges rows with equal sort keys using aggregate functions of the index.
+/// Output has the same columns as the index. [AggregateStrategy::InplaceSorted] requires input to
+/// be sorted by the sort key.
+pub(crate) fn aggregate_index_exec(
+    index: &Index,
+    input: Arc<dyn ExecutionPlan>,
+    strategy: AggregateStrategy,
+) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
+    let schema = input.schema();
+    let key_size = index.sort_key_size() as usize;
+    let mut group_by = Vec::<(Arc<dyn PhysicalExpr>, String)>::with_capacity(key_size);
+    for i in 0..key_size {
+        let name = schema.field(i).name();
+        let col = datafusion::physical_plan::expressions::Column::new(name, i);
+        group_by.push((Arc::new(col), name.clone()));
+    }
+    let mut aggregates = Vec::<Arc<dyn AggregateExpr>>::new();
+    for c in index.aggregate_columns() {
+        let field = schema.field(c.index() as usize);
+        let col: Arc<dyn PhysicalExpr> = Arc::new(
+            datafusion::physical_plan::expressions::Column::new(field.name(), c.index() as usize),
+        );
+        let name = field.name().clone();
+        let data_type = field.data_type().clone();
+        let aggregate: Arc<dyn AggregateExpr> = match c.function() {
+            AggregateFunction::Sum => Arc::new(Sum::new(col, name, data_type)),
+            AggregateFunction::Min => Arc::new(Min::new(col, name, data_type)),
+            AggregateFunction::Max => Arc::new(Max::new(col, name, data_type)),
+            AggregateFunction::Merge => create_aggregate_expr(
+                &aggregate_udf_by_kind(CubeAggregateUDFKind::MergeHll).descriptor(),
+                &[col],
+                schema.as_ref(),
+                name,
+            )?,
+        };
+        aggregates.push(aggregate);
+    }
+    let output_sort_order = match strategy {
+        AggregateStrategy::InplaceSorted => Some((0..key_size).collect_vec()),
+        AggregateStrategy::Hash => None,
+    };
+    Ok(Arc::new(HashAggregateExec::try_new(
+        strategy,
+        output_sort_order,
+        AggregateMode::Full,
+        group_by,
+        aggregates,
+        input,
+        schema,
+    )?))
+}
+
 /// A wrapper to workaround Rust compiler error when using Vec<ArrayRef> in function arguments.
 /// ``error[E0700]: hidden type for `impl Trait` captures lifetime that does not appear in bounds``
 pub struct VecArrayRef(Vec<ArrayRef>);
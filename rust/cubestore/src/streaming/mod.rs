@@ -2,7 +2,7 @@ use crate::config::injection::DIService;
 use crate::config::ConfigObj;
 use crate::metastore::source::SourceCredentials;
 use crate::metastore::table::Table;
-use crate::metastore::{Column, ColumnType, IdRow, MetaStore};
+use crate::metastore::{is_valid_tdigest, Column, ColumnType, IdRow, MetaStore};
 use crate::sql::timestamp_from_string;
 use crate::store::ChunkDataStore;
 use crate::table::data::{append_row, create_array_builders};
@@ -286,6 +286,19 @@ impl KSqlStreamingSource {
                                             ))),
                                         }
                                     }
+                                    ColumnType::Tdigest => {
+                                        let data = match value {
+                                            JsonValue::Short(v) => base64::decode(v.as_str())?,
+                                            JsonValue::String(v) => base64::decode(v.as_str())?,
+                                            JsonValue::Null => return Ok(TableValue::Null),
+                                            x => return Err(CubeError::internal(format!(
+                                                "ksql source returned {:?} as row value but base64 encoded TDigest expected",
+                                                x
+                                            ))),
+                                        };
+                                        is_valid_tdigest(&data)?;
+                                        Ok(TableValue::Bytes(data))
+                                    }
                                     ColumnType::Timestamp => {
                                         match value {
                                             JsonValue::Short(v) => Ok(TableValue::Timestamp(timestamp_from_string(v.as_str())?)),
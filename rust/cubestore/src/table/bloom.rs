@@ -0,0 +1,339 @@
+use crate::table::TableValue;
+use crate::CubeError;
+use arrow::array::{Array, ArrayRef};
+use datafusion::cube_ext;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::{Arc, Mutex};
+
+/// Probability of false positives for newly built bloom filters.
+pub const BLOOM_FILTER_FPP: f64 = 0.01;
+
+/// Name of the file with bloom filters of the data file `file_name`. It's kept beside the data file
+/// both locally and on the remote storage.
+pub fn bloom_filter_file_name(file_name: &str) -> String {
+    format!("{}.bloom", file_name)
+}
+
+pub fn is_bloom_filter_file(file_name: &str) -> bool {
+    file_name.ends_with(".bloom")
+}
+
+/// Bloom filter over non-null values of a single column. Hashes are computed by this module and
+/// are stable across processes, so filters can be built on one node and checked on another.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct BloomFilter {
+    num_hashes: u32,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    pub fn with_capacity(num_items: usize, fpp: f64) -> BloomFilter {
+        let num_items = num_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-num_items * fpp.ln() / (ln2 * ln2)).ceil().max(64.) as usize;
+        let num_hashes = ((num_bits as f64 / num_items) * ln2).round().max(1.) as u32;
+        BloomFilter {
+            num_hashes,
+            bits: vec![0; (num_bits + 63) / 64],
+        }
+    }
+
+    pub fn from_hashes(hashes: &[u64], fpp: f64) -> BloomFilter {
+        let mut f = BloomFilter::with_capacity(hashes.len(), fpp);
+        for h in hashes {
+            f.insert_hash(*h);
+        }
+        f
+    }
+
+    pub fn insert(&mut self, v: &TableValue) {
+        if let Some(h) = hash_value(v) {
+            self.insert_hash(h)
+        }
+    }
+
+    /// Returns false only if `v` was never inserted. Nulls are never inserted, so this always
+    /// returns true for them.
+    pub fn may_contain(&self, v: &TableValue) -> bool {
+        match hash_value(v) {
+            Some(h) => self.contains_hash(h),
+            None => true,
+        }
+    }
+
+    fn insert_hash(&mut self, h: u64) {
+        let num_bits = self.num_bits();
+        for i in bit_indices(h, self.num_hashes, num_bits) {
+            self.bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+
+    fn contains_hash(&self, h: u64) -> bool {
+        let num_bits = self.num_bits();
+        bit_indices(h, self.num_hashes, num_bits).all(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    fn num_bits(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+}
+
+/// Uses double hashing to derive `num_hashes` bit positions from a single hash.
+fn bit_indices(h: u64, num_hashes: u32, num_bits: u64) -> impl Iterator<Item = usize> {
+    let h1 = h;
+    let h2 = fmix64(h ^ 0x9e37_79b9_7f4a_7c15) | 1;
+    (0..num_hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+}
+
+/// Returns `None` for nulls.
+fn hash_value(v: &TableValue) -> Option<u64> {
+    let h = match v {
+        TableValue::Null => return None,
+        TableValue::String(s) => fnv1a(s.as_bytes()),
+        TableValue::Int(i) => fnv1a(&i.to_le_bytes()),
+        TableValue::Decimal(d) => fnv1a(&d.raw_value().to_le_bytes()),
+        TableValue::Float(f) => fnv1a(&f.0.to_bits().to_le_bytes()),
+        TableValue::Bytes(b) => fnv1a(b),
+        TableValue::Timestamp(t) => fnv1a(&t.get_time_stamp().to_le_bytes()),
+        TableValue::Boolean(b) => fnv1a(&[*b as u8]),
+    };
+    Some(fmix64(h))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h
+}
+
+/// Finalizer of MurmurHash3, improves distribution of the FNV hash bits.
+fn fmix64(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h
+}
+
+/// Bloom filters of the columns of a single data file.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct BloomFilterSet {
+    /// Positions of the columns in the index and their filters.
+    filters: Vec<(u64, BloomFilter)>,
+}
+
+impl BloomFilterSet {
+    pub fn from_columns(columns: &[ArrayRef], bloom_columns: &[u64]) -> BloomFilterSet {
+        let mut b = BloomFilterSetBuilder::new(bloom_columns);
+        b.add_columns(columns);
+        b.finish()
+    }
+
+    /// Returns `None` if there is no filter for the column.
+    pub fn get(&self, column: u64) -> Option<&BloomFilter> {
+        self.filters
+            .iter()
+            .find(|(c, _)| *c == column)
+            .map(|(_, f)| f)
+    }
+
+    pub fn read(path: &str) -> Result<BloomFilterSet, CubeError> {
+        let r = BufReader::new(File::open(path)?);
+        bincode::deserialize_from(r).map_err(|e| {
+            CubeError::internal(format!("Can't read bloom filters from {}: {}", path, e))
+        })
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), CubeError> {
+        let w = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(w, self).map_err(|e| {
+            CubeError::internal(format!("Can't write bloom filters to {}: {}", path, e))
+        })
+    }
+}
+
+/// Collects hashes of the column values, so filters can be sized by the number of distinct values
+/// once all rows of the file are seen.
+pub struct BloomFilterSetBuilder {
+    columns: Vec<u64>,
+    hashes: Vec<Vec<u64>>,
+}
+
+impl BloomFilterSetBuilder {
+    pub fn new(bloom_columns: &[u64]) -> BloomFilterSetBuilder {
+        BloomFilterSetBuilder {
+            columns: bloom_columns.to_vec(),
+            hashes: vec![Vec::new(); bloom_columns.len()],
+        }
+    }
+
+    pub fn add_columns(&mut self, columns: &[ArrayRef]) {
+        for (c, hashes) in self.columns.iter().zip(self.hashes.iter_mut()) {
+            let a = columns[*c as usize].as_ref();
+            for i in 0..a.len() {
+                if !a.is_valid(i) {
+                    continue;
+                }
+                if let Some(h) = hash_value(&TableValue::from_array(a, i)) {
+                    hashes.push(h);
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) -> BloomFilterSet {
+        BloomFilterSet {
+            filters: self
+                .columns
+                .into_iter()
+                .zip(self.hashes.into_iter())
+                .map(|(c, mut hashes)| {
+                    hashes.sort_unstable();
+                    hashes.dedup();
+                    (c, BloomFilter::from_hashes(&hashes, BLOOM_FILTER_FPP))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Keeps bloom filters recently used by queries on the worker, so point lookups do not read them
+/// from disk on every query. Bloom filter files are immutable, entries never go stale.
+pub struct BloomFilterCache {
+    cache: Mutex<lru::LruCache<String, Arc<BloomFilterSet>>>,
+}
+
+impl BloomFilterCache {
+    pub fn new(capacity: usize) -> BloomFilterCache {
+        BloomFilterCache {
+            cache: Mutex::new(lru::LruCache::new(capacity)),
+        }
+    }
+
+    /// Returns filters of the bloom filter files among the downloaded files of the query, keyed by
+    /// remote names. Files missing in the cache are read concurrently. Filters that can't be read
+    /// are skipped, so the files they belong to are scanned as usual.
+    pub async fn load(
+        &self,
+        remote_to_local_names: &HashMap<String, String>,
+    ) -> Result<HashMap<String, Arc<BloomFilterSet>>, CubeError> {
+        let mut result = HashMap::new();
+        let mut to_read = Vec::new();
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for (remote, local) in remote_to_local_names {
+                if !is_bloom_filter_file(remote) {
+                    continue;
+                }
+                match cache.get(remote) {
+                    Some(f) => {
+                        result.insert(remote.clone(), f.clone());
+                    }
+                    None => to_read.push((remote.clone(), local.clone())),
+                }
+            }
+        }
+
+        let read = join_all(to_read.into_iter().map(|(remote, local)| async move {
+            let path = local.clone();
+            let f = cube_ext::spawn_blocking(move || BloomFilterSet::read(&path)).await?;
+            Ok::<_, CubeError>((remote, local, f))
+        }))
+        .await;
+
+        let mut cache = self.cache.lock().unwrap();
+        for r in read {
+            let (remote, local, f) = r?;
+            match f {
+                Ok(f) => {
+                    let f = Arc::new(f);
+                    cache.put(remote.clone(), f.clone());
+                    result.insert(remote, f);
+                }
+                Err(e) => log::warn!("Bloom filters of {} are not used: {}", local, e),
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int64Array, StringArray};
+    use std::sync::Arc;
+
+    #[test]
+    fn bloom_filter_set() {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from((0..1000).collect::<Vec<i64>>())),
+            Arc::new(StringArray::from(
+                (0..1000)
+                    .map(|i| {
+                        if i % 10 == 0 {
+                            None
+                        } else {
+                            Some(format!("user_{}", i))
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            )),
+        ];
+        let set = BloomFilterSet::from_columns(&columns, &[1]);
+        assert!(set.get(0).is_none());
+        let f = set.get(1).unwrap();
+        for i in 1..1000 {
+            if i % 10 != 0 {
+                assert!(f.may_contain(&TableValue::String(format!("user_{}", i))));
+            }
+        }
+        assert!(f.may_contain(&TableValue::Null));
+        let false_positives = (1000..11000)
+            .filter(|i| f.may_contain(&TableValue::String(format!("user_{}", i))))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        set.write(path).unwrap();
+        assert_eq!(BloomFilterSet::read(path).unwrap(), set);
+    }
+
+    #[tokio::test]
+    async fn bloom_filter_cache() {
+        let columns: Vec<ArrayRef> = vec![Arc::new(Int64Array::from(vec![1, 2, 3]))];
+        let set = BloomFilterSet::from_columns(&columns, &[0]);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        set.write(&path).unwrap();
+
+        let mut names = HashMap::new();
+        names.insert(
+            "1.parquet".to_string(),
+            "/nonexistent/1.parquet".to_string(),
+        );
+        names.insert("1.parquet.bloom".to_string(), path.clone());
+        names.insert(
+            "2.parquet.bloom".to_string(),
+            "/nonexistent/2.parquet.bloom".to_string(),
+        );
+
+        let cache = BloomFilterCache::new(16);
+        let filters = cache.load(&names).await.unwrap();
+        assert_eq!(filters.len(), 1);
+        assert_eq!(filters["1.parquet.bloom"].as_ref(), &set);
+
+        // Cached filters are not read again.
+        std::fs::remove_file(&path).unwrap();
+        let filters = cache.load(&names).await.unwrap();
+        assert_eq!(filters["1.parquet.bloom"].as_ref(), &set);
+    }
+}
@@ -15,9 +15,11 @@ use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 
+pub mod bloom;
 pub mod data;
 pub(crate) mod parquet;
 pub mod redistribute;
+pub mod stats;
 
 #[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug, Hash)]
 pub enum TableValue {
@@ -1,11 +1,19 @@
-use crate::metastore::Index;
+use crate::metastore::{ColumnCodec, Index};
+use crate::table::bloom::{bloom_filter_file_name, BloomFilterSet};
+use crate::table::stats::ColumnStats;
+use crate::table::{TableValue, TimestampValue};
+use crate::util::decimal::Decimal;
 use crate::CubeError;
 use arrow::array::ArrayRef;
-use arrow::datatypes::Schema;
+use arrow::datatypes::{DataType, Schema, TimeUnit};
 use arrow::record_batch::RecordBatch;
 use parquet::arrow::{ArrowReader, ArrowWriter, ParquetFileArrowReader};
+use parquet::basic::Compression;
+use parquet::file::metadata::RowGroupMetaData;
 use parquet::file::properties::{WriterProperties, WriterVersion};
 use parquet::file::reader::SerializedFileReader;
+use parquet::file::statistics::Statistics;
+use parquet::schema::types::ColumnPath;
 use std::convert::TryFrom;
 use std::fs::File;
 use std::sync::Arc;
@@ -48,47 +56,163 @@ impl ParquetTableStore {
         arrow_schema(&self.table)
     }
 
+    /// Row groups are written by batches of this size, the index option takes precedence.
+    pub fn row_group_size(&self) -> usize {
+        self.table
+            .row_group_size()
+            .map(|s| s as usize)
+            .unwrap_or(self.row_group_size)
+    }
+
+    /// Row group size and per-column codec and dictionary options of the index take precedence
+    /// over the defaults.
     pub fn writer_props(&self) -> WriterProperties {
-        WriterProperties::builder()
-            .set_max_row_group_size(self.row_group_size)
-            .set_writer_version(WriterVersion::PARQUET_2_0)
-            .build()
+        let mut props = WriterProperties::builder()
+            .set_max_row_group_size(self.row_group_size())
+            .set_writer_version(WriterVersion::PARQUET_2_0);
+        for c in self.table.columns() {
+            let path = ColumnPath::from(c.get_name().as_str());
+            if let Some(codec) = c.get_codec() {
+                props = props.set_column_compression(path.clone(), compression(codec));
+            }
+            if let Some(dictionary) = c.get_dictionary() {
+                props = props.set_column_dictionary_enabled(path, dictionary);
+            }
+        }
+        props.build()
+    }
+
+    /// Positions of the columns that get bloom filters.
+    pub fn bloom_filter_columns(&self) -> &[u64] {
+        self.table.bloom_filter_columns()
+    }
+
+    pub fn has_bloom_filter(&self) -> bool {
+        !self.bloom_filter_columns().is_empty()
     }
 
+    /// Also writes bloom filters of the data into [bloom_filter_file_name] of `dest_file` when
+    /// the index has bloom filter columns.
     pub fn write_data(&self, dest_file: &str, columns: Vec<ArrayRef>) -> Result<(), CubeError> {
+        if self.has_bloom_filter() {
+            BloomFilterSet::from_columns(&columns, self.bloom_filter_columns())
+                .write(&bloom_filter_file_name(dest_file))?;
+        }
+
         let schema = Arc::new(arrow_schema(&self.table));
         let batch = RecordBatch::try_new(schema.clone(), columns.to_vec())?;
 
         let mut w =
             ArrowWriter::try_new(File::create(dest_file)?, schema, Some(self.writer_props()))?;
-        w.write(&batch)?;
+        // Each write produces a row group.
+        let row_group_size = self.row_group_size();
+        let mut offset = 0;
+        loop {
+            let len = row_group_size.min(batch.num_rows() - offset);
+            w.write(&batch.slice(offset, len))?;
+            offset += len;
+            if batch.num_rows() <= offset {
+                break;
+            }
+        }
         w.close()?;
 
         Ok(())
     }
 }
 
+fn compression(codec: ColumnCodec) -> Compression {
+    match codec {
+        ColumnCodec::Uncompressed => Compression::UNCOMPRESSED,
+        ColumnCodec::Snappy => Compression::SNAPPY,
+        ColumnCodec::Lz4 => Compression::LZ4,
+        ColumnCodec::Zstd => Compression::ZSTD,
+    }
+}
+
 pub fn arrow_schema(i: &Index) -> Schema {
     Schema::new(i.columns().iter().map(|c| c.into()).collect())
 }
 
+/// Converts statistics of the row group into [ColumnStats] in the order of `schema` fields.
+/// Returns `None` when statistics of any column are missing or can't be converted.
+pub fn row_group_column_stats(rg: &RowGroupMetaData, schema: &Schema) -> Option<Vec<ColumnStats>> {
+    if rg.num_columns() != schema.fields().len() {
+        return None;
+    }
+    schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let stats = rg.column(i).statistics()?;
+            let null_count = stats.null_count();
+            if !stats.has_min_max_set() {
+                // Min and max are not set when all values are nulls.
+                return if null_count as i64 == rg.num_rows() {
+                    Some(ColumnStats::new(
+                        TableValue::Null,
+                        TableValue::Null,
+                        null_count,
+                    ))
+                } else {
+                    None
+                };
+            }
+            let (min, max) = match (stats, f.data_type()) {
+                (Statistics::Int64(s), DataType::Int64) => {
+                    (TableValue::Int(*s.min()), TableValue::Int(*s.max()))
+                }
+                (Statistics::Int64(s), DataType::Int64Decimal(_)) => (
+                    TableValue::Decimal(Decimal::new(*s.min())),
+                    TableValue::Decimal(Decimal::new(*s.max())),
+                ),
+                (Statistics::Int64(s), DataType::Timestamp(TimeUnit::Microsecond, None)) => (
+                    TableValue::Timestamp(TimestampValue::new(s.min().checked_mul(1000)?)),
+                    TableValue::Timestamp(TimestampValue::new(s.max().checked_mul(1000)?)),
+                ),
+                (Statistics::Double(s), DataType::Float64) => (
+                    TableValue::Float((*s.min()).into()),
+                    TableValue::Float((*s.max()).into()),
+                ),
+                (Statistics::Boolean(s), DataType::Boolean) => {
+                    (TableValue::Boolean(*s.min()), TableValue::Boolean(*s.max()))
+                }
+                (Statistics::ByteArray(s), DataType::Utf8) => (
+                    TableValue::String(std::str::from_utf8(s.min().data()).ok()?.to_string()),
+                    TableValue::String(std::str::from_utf8(s.max().data()).ok()?.to_string()),
+                ),
+                (Statistics::ByteArray(s), DataType::Binary) => (
+                    TableValue::Bytes(s.min().data().to_vec()),
+                    TableValue::Bytes(s.max().data().to_vec()),
+                ),
+                _ => return None,
+            };
+            Some(ColumnStats::new(min, max, null_count))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     extern crate test;
 
     use crate::assert_eq_columns;
-    use crate::metastore::{Column, ColumnType, Index};
+    use crate::metastore::{Column, ColumnCodec, ColumnType, Index};
+    use crate::queryplanner::serialized_plan::RowRange;
     use crate::store::{compaction, ROW_GROUP_SIZE};
     use crate::table::data::{cmp_row_key_heap, concat_record_batches, rows_to_columns, to_stream};
-    use crate::table::parquet::{arrow_schema, ParquetTableStore};
+    use crate::table::parquet::{arrow_schema, row_group_column_stats, ParquetTableStore};
+    use crate::table::stats::ColumnStats;
     use crate::table::{Row, TableValue};
     use crate::util::decimal::Decimal;
     use arrow::array::{
-        ArrayRef, BooleanArray, Float64Array, Int64Array, Int64Decimal4Array, StringArray,
-        TimestampMicrosecondArray,
+        ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, Int64Decimal4Array,
+        StringArray, TimestampMicrosecondArray,
     };
     use arrow::record_batch::RecordBatch;
     use itertools::Itertools;
+    use parquet::basic::{Compression, Encoding};
     use parquet::data_type::DataType;
     use parquet::file::reader::FileReader;
     use parquet::file::reader::SerializedFileReader;
@@ -160,6 +284,7 @@ mod tests {
             ])),
         ];
         // TODO: check floats use total_cmp.
+        let expected_stats = ColumnStats::from_columns(&data);
 
         store
             .write_data(dest_file.path().to_str().unwrap(), data)
@@ -184,6 +309,74 @@ mod tests {
            \nmin: 1.1, max: 3.3\
            \nmin: false, max: true"
         );
+
+        let stats = row_group_column_stats(r.metadata().row_group(0), &store.arrow_schema());
+        assert_eq!(stats.as_ref(), Some(&expected_stats));
+        let stats = stats.unwrap();
+
+        let range = |start: Option<&str>, end: Option<&str>| RowRange {
+            start: start.map(|s| Row::new(vec![TableValue::String(s.to_string())])),
+            end: end.map(|s| Row::new(vec![TableValue::String(s.to_string())])),
+        };
+        assert!(range(Some("b"), None).can_match_column_stats(&stats[0..1]));
+        assert!(!range(Some("c"), None).can_match_column_stats(&stats[0..1]));
+        assert!(range(None, Some("b")).can_match_column_stats(&stats[0..1]));
+        // Null is the smallest value of the column.
+        assert!(range(None, Some("a")).can_match_column_stats(&stats[0..1]));
+        assert!(range(Some("a"), Some("ab")).can_match_column_stats(&stats[0..1]));
+        assert!(!range(Some("ba"), Some("c")).can_match_column_stats(&stats[0..1]));
+    }
+
+    #[test]
+    fn storage_options() {
+        let index = Index::try_new(
+            "table".to_string(),
+            1,
+            vec![
+                Column::new("id".to_string(), ColumnType::Int, 0),
+                Column::new("dim".to_string(), ColumnType::String, 1)
+                    .with_codec(Some(ColumnCodec::Zstd))
+                    .with_dictionary(Some(true)),
+                Column::new("payload".to_string(), ColumnType::Bytes, 2)
+                    .with_codec(Some(ColumnCodec::Uncompressed))
+                    .with_dictionary(Some(false)),
+            ],
+            1,
+            None,
+            None,
+        )
+        .unwrap()
+        .with_row_group_size(Some(10));
+
+        let data: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from((0..25).collect_vec())),
+            Arc::new(StringArray::from(
+                (0..25)
+                    .map(|i| if i % 2 == 0 { "a" } else { "b" })
+                    .collect_vec(),
+            )),
+            Arc::new(BinaryArray::from(
+                (0..25).map(|_| &[1u8, 2, 3][..]).collect_vec(),
+            )),
+        ];
+        let dest_file = NamedTempFile::new().unwrap();
+        let store = ParquetTableStore::new(index, ROW_GROUP_SIZE);
+        store
+            .write_data(dest_file.path().to_str().unwrap(), data)
+            .unwrap();
+
+        let r = SerializedFileReader::new(dest_file.into_file()).unwrap();
+        let metadata = r.metadata();
+        assert_eq!(metadata.num_row_groups(), 3);
+        let rg = metadata.row_group(0);
+        assert_eq!(rg.column(1).compression(), Compression::ZSTD);
+        assert!(rg.column(1).encodings().contains(&Encoding::RLE_DICTIONARY));
+        assert_eq!(rg.column(2).compression(), Compression::UNCOMPRESSED);
+        assert!(!rg.column(2).encodings().contains(&Encoding::RLE_DICTIONARY));
+        assert!(!rg
+            .column(2)
+            .encodings()
+            .contains(&Encoding::PLAIN_DICTIONARY));
     }
 
     #[tokio::test]
@@ -284,8 +477,12 @@ mod tests {
 
         assert_eq_columns!(read.columns(), &to_split_cols);
 
+        assert_eq!(count_min[1].2[0].max(), &TableValue::Int(149));
         assert_eq!(
-            count_min,
+            count_min
+                .into_iter()
+                .map(|(count, min, _)| (count, min))
+                .collect_vec(),
             vec![
                 (
                     75,
@@ -0,0 +1,248 @@
+use crate::table::{cmp_same_types, TableValue, TimestampValue};
+use arrow::array::{
+    Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, TimestampMicrosecondArray,
+};
+use arrow::compute::kernels::aggregate;
+use arrow::datatypes::{DataType, TimeUnit};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+static NULL_VALUE: TableValue = TableValue::Null;
+
+/// Statistics of a single column inside of a partition or a chunk. `min` and `max` are computed
+/// over non-null values and are `Null` when all values are null.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct ColumnStats {
+    min: TableValue,
+    max: TableValue,
+    null_count: u64,
+}
+
+impl ColumnStats {
+    pub fn new(min: TableValue, max: TableValue, null_count: u64) -> ColumnStats {
+        ColumnStats {
+            min,
+            max,
+            null_count,
+        }
+    }
+
+    pub fn from_array(a: &dyn Array) -> ColumnStats {
+        let (min, max) = match a.data_type() {
+            DataType::Int64 => {
+                let a = a.as_any().downcast_ref::<Int64Array>().unwrap();
+                (
+                    aggregate::min(a).map(TableValue::Int),
+                    aggregate::max(a).map(TableValue::Int),
+                )
+            }
+            DataType::Float64 => {
+                let a = a.as_any().downcast_ref::<Float64Array>().unwrap();
+                (
+                    aggregate::min(a).map(|v| TableValue::Float(v.into())),
+                    aggregate::max(a).map(|v| TableValue::Float(v.into())),
+                )
+            }
+            DataType::Utf8 => {
+                let a = a.as_any().downcast_ref::<StringArray>().unwrap();
+                (
+                    aggregate::min_string(a).map(|v| TableValue::String(v.to_string())),
+                    aggregate::max_string(a).map(|v| TableValue::String(v.to_string())),
+                )
+            }
+            DataType::Boolean => {
+                let a = a.as_any().downcast_ref::<BooleanArray>().unwrap();
+                (
+                    aggregate::min_boolean(a).map(TableValue::Boolean),
+                    aggregate::max_boolean(a).map(TableValue::Boolean),
+                )
+            }
+            DataType::Timestamp(TimeUnit::Microsecond, None) => {
+                let a = a
+                    .as_any()
+                    .downcast_ref::<TimestampMicrosecondArray>()
+                    .unwrap();
+                let to_value = |v: i64| TableValue::Timestamp(TimestampValue::new(1000 * v));
+                (
+                    aggregate::min(a).map(to_value),
+                    aggregate::max(a).map(to_value),
+                )
+            }
+            // There are no kernels for binary and decimal arrays.
+            _ => Self::min_max_by_rows(a),
+        };
+        ColumnStats {
+            min: min.unwrap_or(TableValue::Null),
+            max: max.unwrap_or(TableValue::Null),
+            null_count: a.null_count() as u64,
+        }
+    }
+
+    fn min_max_by_rows(a: &dyn Array) -> (Option<TableValue>, Option<TableValue>) {
+        let mut min: Option<TableValue> = None;
+        let mut max: Option<TableValue> = None;
+        for i in 0..a.len() {
+            if !a.is_valid(i) {
+                continue;
+            }
+            let v = TableValue::from_array(a, i);
+            if min.is_none() || cmp_same_types(&v, min.as_ref().unwrap()) == Ordering::Less {
+                min = Some(v.clone());
+            }
+            if max.is_none() || cmp_same_types(&v, max.as_ref().unwrap()) == Ordering::Greater {
+                max = Some(v);
+            }
+        }
+        (min, max)
+    }
+
+    pub fn from_columns(columns: &[ArrayRef]) -> Vec<ColumnStats> {
+        columns
+            .iter()
+            .map(|c| ColumnStats::from_array(c.as_ref()))
+            .collect()
+    }
+
+    pub fn merge(&self, other: &ColumnStats) -> ColumnStats {
+        let min = match (&self.min, &other.min) {
+            (TableValue::Null, v) | (v, TableValue::Null) => v.clone(),
+            (l, r) => std::cmp::min_by(l, r, |l, r| cmp_same_types(l, r)).clone(),
+        };
+        let max = match (&self.max, &other.max) {
+            (TableValue::Null, v) | (v, TableValue::Null) => v.clone(),
+            (l, r) => std::cmp::max_by(l, r, |l, r| cmp_same_types(l, r)).clone(),
+        };
+        ColumnStats {
+            min,
+            max,
+            null_count: self.null_count + other.null_count,
+        }
+    }
+
+    /// Merges statistics of every column of two sets of rows with the same schema.
+    pub fn merge_all(l: &[ColumnStats], r: &[ColumnStats]) -> Vec<ColumnStats> {
+        debug_assert_eq!(l.len(), r.len());
+        l.iter().zip(r.iter()).map(|(l, r)| l.merge(r)).collect()
+    }
+
+    pub fn min(&self) -> &TableValue {
+        &self.min
+    }
+
+    pub fn max(&self) -> &TableValue {
+        &self.max
+    }
+
+    pub fn null_count(&self) -> u64 {
+        self.null_count
+    }
+
+    /// Inclusive range of values, nulls are ordered before all other values.
+    pub fn range(&self) -> (&TableValue, &TableValue) {
+        if self.null_count != 0 {
+            (&NULL_VALUE, &self.max)
+        } else {
+            (&self.min, &self.max)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::BinaryArray;
+    use std::sync::Arc;
+
+    #[test]
+    fn stats_from_columns() {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from(vec![Some(3), None, Some(-1), Some(2)])),
+            Arc::new(StringArray::from(vec![None as Option<&str>; 4])),
+            Arc::new(StringArray::from(vec![
+                Some("b"),
+                Some("a"),
+                Some("c"),
+                Some("b"),
+            ])),
+        ];
+        let stats = ColumnStats::from_columns(&columns);
+        assert_eq!(
+            stats,
+            vec![
+                ColumnStats::new(TableValue::Int(-1), TableValue::Int(3), 1),
+                ColumnStats::new(TableValue::Null, TableValue::Null, 4),
+                ColumnStats::new(
+                    TableValue::String("a".to_string()),
+                    TableValue::String("c".to_string()),
+                    0
+                ),
+            ]
+        );
+        assert_eq!(stats[0].range(), (&TableValue::Null, &TableValue::Int(3)));
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Float64Array::from(vec![Some(1.5), Some(-2.5), None])),
+            Arc::new(BooleanArray::from(vec![Some(true), None, Some(false)])),
+            Arc::new(TimestampMicrosecondArray::from(vec![
+                Some(2000),
+                Some(1000),
+                None,
+            ])),
+            Arc::new(BinaryArray::from(vec![
+                Some(&b"b"[..]),
+                Some(&b"a"[..]),
+                None,
+            ])),
+        ];
+        assert_eq!(
+            ColumnStats::from_columns(&columns),
+            vec![
+                ColumnStats::new(
+                    TableValue::Float((-2.5).into()),
+                    TableValue::Float(1.5.into()),
+                    1
+                ),
+                ColumnStats::new(TableValue::Boolean(false), TableValue::Boolean(true), 1),
+                ColumnStats::new(
+                    TableValue::Timestamp(TimestampValue::new(1_000_000)),
+                    TableValue::Timestamp(TimestampValue::new(2_000_000)),
+                    1
+                ),
+                ColumnStats::new(
+                    TableValue::Bytes(b"a".to_vec()),
+                    TableValue::Bytes(b"b".to_vec()),
+                    1
+                ),
+            ]
+        );
+
+        let merged = ColumnStats::merge_all(
+            &stats,
+            &[
+                ColumnStats::new(TableValue::Int(5), TableValue::Int(7), 0),
+                ColumnStats::new(
+                    TableValue::String("x".to_string()),
+                    TableValue::String("y".to_string()),
+                    0,
+                ),
+                ColumnStats::new(TableValue::Null, TableValue::Null, 2),
+            ],
+        );
+        assert_eq!(
+            merged,
+            vec![
+                ColumnStats::new(TableValue::Int(-1), TableValue::Int(7), 1),
+                ColumnStats::new(
+                    TableValue::String("x".to_string()),
+                    TableValue::String("y".to_string()),
+                    4
+                ),
+                ColumnStats::new(
+                    TableValue::String("a".to_string()),
+                    TableValue::String("c".to_string()),
+                    2
+                ),
+            ]
+        );
+    }
+}
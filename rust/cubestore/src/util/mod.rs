@@ -11,6 +11,7 @@ pub mod ordfloat;
 pub mod respawn;
 pub mod strings;
 pub mod time_span;
+pub mod tls;
 
 pub use malloc_trim_loop::spawn_malloc_trim_loop;
 
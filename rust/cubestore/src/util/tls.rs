@@ -0,0 +1,492 @@
+use crate::config::ConfigObj;
+use crate::CubeError;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientConfig, NoClientAuth, PrivateKey,
+    RootCertStore, ServerCertVerified, ServerCertVerifier, ServerConfig, TLSError,
+};
+use tokio_rustls::webpki;
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> AsyncStream for T {}
+
+/// Connection that may or may not be encrypted, depending on [TlsSettings].
+pub type NetworkStream = Box<dyn AsyncStream>;
+
+/// Wraps sockets of the worker and metastore ports ("cluster" connections) in TLS when it is
+/// enabled in the config. The HTTP server ("client" connections) uses TLS of `warp` with the same
+/// certificate. The MySQL port is not encrypted: clients expect to upgrade connections during the
+/// MySQL handshake, which the MySQL server doesn't support.
+///
+/// Node certificates are used both as server and client certificates for mutual TLS, so they
+/// must be issued for host names or IP addresses of node addresses.
+pub struct TlsSettings {
+    cluster_acceptor: Option<TlsAcceptor>,
+    cluster_client: Option<Arc<ClientConfig>>,
+}
+
+crate::di_service!(TlsSettings, []);
+
+impl TlsSettings {
+    pub fn from_config(config: &dyn ConfigObj) -> Result<Arc<TlsSettings>, CubeError> {
+        let mut settings = TlsSettings {
+            cluster_acceptor: None,
+            cluster_client: None,
+        };
+        if !config.cluster_tls() && !config.client_tls() {
+            return Ok(Arc::new(settings));
+        }
+
+        let (cert_file, key_file) = match (config.tls_cert_file(), config.tls_key_file()) {
+            (Some(c), Some(k)) => (c, k),
+            _ => {
+                return Err(CubeError::user(
+                    "TLS requires both CUBESTORE_TLS_CERT_FILE and CUBESTORE_TLS_KEY_FILE"
+                        .to_string(),
+                ))
+            }
+        };
+        let cert_chain = read_certs(cert_file)?;
+        let key = read_key(key_file)?;
+
+        if config.client_tls() {
+            // The HTTP server loads the certificate by itself, it's checked here to fail on start.
+            ServerConfig::new(NoClientAuth::new())
+                .set_single_cert(cert_chain.clone(), key.clone())
+                .map_err(|e| CubeError::user(format!("Invalid TLS certificate: {}", e)))?;
+        }
+
+        if config.cluster_tls() {
+            let ca_file = config.tls_ca_file().as_ref().ok_or_else(|| {
+                CubeError::user("Cluster TLS requires CUBESTORE_TLS_CA_FILE".to_string())
+            })?;
+            let roots = read_roots(ca_file)?;
+
+            let mut server = if config.cluster_tls_mutual() {
+                ServerConfig::new(AllowAnyAuthenticatedClient::new(roots.clone()))
+            } else {
+                ServerConfig::new(NoClientAuth::new())
+            };
+            server
+                .set_single_cert(cert_chain.clone(), key.clone())
+                .map_err(|e| CubeError::user(format!("Invalid TLS certificate: {}", e)))?;
+            settings.cluster_acceptor = Some(TlsAcceptor::from(Arc::new(server)));
+
+            let mut client = ClientConfig::new();
+            client.root_store = roots;
+            if config.cluster_tls_mutual() {
+                client
+                    .set_single_client_cert(cert_chain, key)
+                    .map_err(|e| CubeError::user(format!("Invalid TLS certificate: {}", e)))?;
+            }
+            settings.cluster_client = Some(Arc::new(client));
+        }
+        Ok(Arc::new(settings))
+    }
+
+    /// Connects to the worker or metastore port of another node.
+    pub async fn connect_to_node(&self, address: &str) -> Result<NetworkStream, CubeError> {
+        let stream = TcpStream::connect(address)
+            .await
+            .map_err(|e| CubeError::internal(format!("Can't connect to {}: {}", address, e)))?;
+        let client = match &self.cluster_client {
+            Some(c) => c,
+            None => return Ok(Box::new(stream)),
+        };
+        let host = match address.rsplit_once(':') {
+            Some((host, _)) => host,
+            None => address,
+        };
+        let (client, name) = match host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+        {
+            Ok(ip) => {
+                // `webpki` only verifies host names. The name is not sent without SNI.
+                let mut client = client.as_ref().clone();
+                client.enable_sni = false;
+                client
+                    .dangerous()
+                    .set_certificate_verifier(Arc::new(IpAddressVerifier { address: ip }));
+                (Arc::new(client), IP_ADDRESS_NAME)
+            }
+            Err(_) => (client.clone(), host),
+        };
+        let name = DNSNameRef::try_from_ascii_str(name).map_err(|_| {
+            CubeError::user(format!(
+                "Cluster TLS requires host names or IP addresses in node addresses, got '{}'",
+                address
+            ))
+        })?;
+        let connector = TlsConnector::from(client);
+        let stream = connector.connect(name, stream).await.map_err(|e| {
+            CubeError::internal(format!("TLS handshake with {} failed: {}", address, e))
+        })?;
+        Ok(Box::new(stream))
+    }
+
+    /// Accepts a connection on the worker or metastore port.
+    pub async fn accept_node(&self, stream: TcpStream) -> Result<NetworkStream, CubeError> {
+        match &self.cluster_acceptor {
+            Some(acceptor) => {
+                let stream = acceptor
+                    .accept(stream)
+                    .await
+                    .map_err(|e| CubeError::internal(format!("TLS handshake failed: {}", e)))?;
+                Ok(Box::new(stream))
+            }
+            None => Ok(Box::new(stream)),
+        }
+    }
+}
+
+/// Placeholder passed to `rustls` for nodes addressed by IP, see [IpAddressVerifier].
+const IP_ADDRESS_NAME: &str = "ip-address.invalid";
+
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// Verifies certificates of nodes addressed by IP: the chain is verified as usual and the address
+/// must be one of IP subject alternative names of the certificate.
+struct IpAddressVerifier {
+    address: IpAddr,
+}
+
+impl ServerCertVerifier for IpAddressVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let (cert, intermediates) = presented_certs
+            .split_first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        let intermediates = intermediates
+            .iter()
+            .map(|c| c.0.as_slice())
+            .collect::<Vec<_>>();
+        let anchors = roots
+            .roots
+            .iter()
+            .map(|r| r.to_trust_anchor())
+            .collect::<Vec<_>>();
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        webpki::EndEntityCert::from(&cert.0)
+            .and_then(|c| {
+                c.verify_is_valid_tls_server_cert(
+                    SUPPORTED_SIG_ALGS,
+                    &webpki::TLSServerTrustAnchors(&anchors),
+                    &intermediates,
+                    webpki::Time::from_seconds_since_unix_epoch(now),
+                )
+            })
+            .map_err(TLSError::WebPKIError)?;
+        match ip_addresses(&cert.0) {
+            Some(addresses) if addresses.contains(&self.address) => {
+                Ok(ServerCertVerified::assertion())
+            }
+            _ => Err(TLSError::General(format!(
+                "Certificate is not valid for {}",
+                self.address
+            ))),
+        }
+    }
+}
+
+/// Splits the first DER element off `der` into its tag and contents.
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    let (len, rest) = if len < 0x80 {
+        (len as usize, rest)
+    } else {
+        let bytes = (len & 0x7f) as usize;
+        if bytes == 0 || bytes > 4 || rest.len() < bytes {
+            return None;
+        }
+        let len = rest[..bytes]
+            .iter()
+            .fold(0, |len, b| len << 8 | *b as usize);
+        (len, &rest[bytes..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+/// IP addresses in the subject alternative names of a DER encoded certificate, `None` if it
+/// can't be parsed.
+fn ip_addresses(cert: &[u8]) -> Option<Vec<IpAddr>> {
+    const EXTENSIONS_TAG: u8 = 0xa3;
+    const CRITICAL_TAG: u8 = 0x01;
+    const IP_ADDRESS_TAG: u8 = 0x87;
+    const SUBJECT_ALT_NAME_OID: &[u8] = &[0x55, 0x1d, 0x11];
+
+    let (_, cert, _) = der_element(cert)?;
+    let (_, mut tbs_cert, _) = der_element(cert)?;
+    let mut extensions = &[][..];
+    while !tbs_cert.is_empty() {
+        let (tag, contents, rest) = der_element(tbs_cert)?;
+        if tag == EXTENSIONS_TAG {
+            extensions = der_element(contents)?.1;
+        }
+        tbs_cert = rest;
+    }
+
+    let mut addresses = Vec::new();
+    while !extensions.is_empty() {
+        let (_, extension, rest) = der_element(extensions)?;
+        extensions = rest;
+        let (_, oid, extension) = der_element(extension)?;
+        if oid != SUBJECT_ALT_NAME_OID {
+            continue;
+        }
+        let (tag, mut value, rest) = der_element(extension)?;
+        if tag == CRITICAL_TAG {
+            value = der_element(rest)?.1;
+        }
+        let (_, mut names, _) = der_element(value)?;
+        while !names.is_empty() {
+            let (tag, name, rest) = der_element(names)?;
+            names = rest;
+            if tag != IP_ADDRESS_TAG {
+                continue;
+            }
+            match name.len() {
+                4 => {
+                    let mut octets = [0; 4];
+                    octets.copy_from_slice(name);
+                    addresses.push(IpAddr::from(octets));
+                }
+                16 => {
+                    let mut octets = [0; 16];
+                    octets.copy_from_slice(name);
+                    addresses.push(IpAddr::from(octets));
+                }
+                _ => return None,
+            }
+        }
+    }
+    Some(addresses)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, CubeError> {
+    let file = File::open(path)
+        .map_err(|e| CubeError::user(format!("Can't read {}: {}", path.display(), e)))?;
+    Ok(BufReader::new(file))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, CubeError> {
+    match certs(&mut open(path)?) {
+        Ok(c) if !c.is_empty() => Ok(c),
+        _ => Err(CubeError::user(format!(
+            "No PEM certificates found in {}",
+            path.display()
+        ))),
+    }
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey, CubeError> {
+    let mut keys = pkcs8_private_keys(&mut open(path)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(path)?).unwrap_or_default();
+    }
+    if keys.is_empty() {
+        return Err(CubeError::user(format!(
+            "No PEM private key found in {}",
+            path.display()
+        )));
+    }
+    Ok(keys.remove(0))
+}
+
+fn read_roots(path: &Path) -> Result<RootCertStore, CubeError> {
+    let mut roots = RootCertStore::empty();
+    for c in read_certs(path)? {
+        roots.add(&c).map_err(|e| {
+            CubeError::user(format!(
+                "Invalid CA certificate in {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::message::NetworkMessage;
+    use crate::config::Config;
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+
+    fn fixture(name: &str) -> Option<PathBuf> {
+        Some(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testing-fixtures/tls")
+                .join(name),
+        )
+    }
+
+    fn cluster_tls(mutual: bool) -> Arc<TlsSettings> {
+        let config = Config::test("tls_cluster").update_config(|mut c| {
+            c.cluster_tls = true;
+            c.cluster_tls_mutual = mutual;
+            c.tls_cert_file = fixture("node.pem");
+            c.tls_key_file = fixture("node.key");
+            c.tls_ca_file = fixture("ca.pem");
+            c
+        });
+        TlsSettings::from_config(config.config_obj().as_ref()).unwrap()
+    }
+
+    fn plain() -> Arc<TlsSettings> {
+        let config = Config::test("tls_plain");
+        TlsSettings::from_config(config.config_obj().as_ref()).unwrap()
+    }
+
+    async fn exchange(server: Arc<TlsSettings>, client: Arc<TlsSettings>) -> Result<(), CubeError> {
+        exchange_on("localhost", server, client).await
+    }
+
+    async fn exchange_on(
+        host: &str,
+        server: Arc<TlsSettings>,
+        client: Arc<TlsSettings>,
+    ) -> Result<(), CubeError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("{}:{}", host, listener.local_addr()?.port());
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await?;
+            let mut socket = server.accept_node(socket).await?;
+            match NetworkMessage::receive(&mut socket).await? {
+                NetworkMessage::NotifyJobListeners => {}
+                m => panic!("unexpected message: {:?}", m),
+            }
+            NetworkMessage::NotifyJobListenersSuccess
+                .send(&mut socket)
+                .await
+        });
+        let client = async move {
+            let mut socket = client.connect_to_node(&address).await?;
+            NetworkMessage::NotifyJobListeners.send(&mut socket).await?;
+            match NetworkMessage::receive(&mut socket).await? {
+                NetworkMessage::NotifyJobListenersSuccess => Ok(()),
+                m => panic!("unexpected message: {:?}", m),
+            }
+        };
+        let client_result = client.await;
+        let server_result = server.await.unwrap();
+        client_result.and(server_result)
+    }
+
+    #[tokio::test]
+    async fn cluster_connections() {
+        exchange(plain(), plain()).await.unwrap();
+        exchange(cluster_tls(false), cluster_tls(false))
+            .await
+            .unwrap();
+        exchange(cluster_tls(true), cluster_tls(true))
+            .await
+            .unwrap();
+
+        // Nodes without certificates are rejected with mutual TLS.
+        assert!(exchange(cluster_tls(true), cluster_tls(false))
+            .await
+            .is_err());
+        assert!(exchange(cluster_tls(false), plain()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ip_addresses_of_nodes() {
+        exchange_on("127.0.0.1", cluster_tls(false), cluster_tls(false))
+            .await
+            .unwrap();
+        exchange_on("127.0.0.1", cluster_tls(true), cluster_tls(true))
+            .await
+            .unwrap();
+
+        let cert = read_certs(&fixture("node.pem").unwrap()).unwrap().remove(0);
+        assert_eq!(
+            ip_addresses(&cert.0),
+            Some(vec!["127.0.0.1".parse::<IpAddr>().unwrap()])
+        );
+        let verifier = IpAddressVerifier {
+            address: "127.0.0.2".parse().unwrap(),
+        };
+        let roots = read_roots(&fixture("ca.pem").unwrap()).unwrap();
+        let name = DNSNameRef::try_from_ascii_str(IP_ADDRESS_NAME).unwrap();
+        assert!(verifier
+            .verify_server_cert(&roots, &[cert.clone()], name, &[])
+            .is_err());
+        let verifier = IpAddressVerifier {
+            address: "127.0.0.1".parse().unwrap(),
+        };
+        assert!(verifier
+            .verify_server_cert(&roots, &[cert.clone()], name, &[])
+            .is_ok());
+        assert!(verifier
+            .verify_server_cert(&RootCertStore::empty(), &[cert], name, &[])
+            .is_err());
+    }
+
+    #[test]
+    fn plain_by_default() {
+        let config = Config::test("tls_plain_by_default");
+        let tls = TlsSettings::from_config(config.config_obj().as_ref()).unwrap();
+        assert!(tls.cluster_acceptor.is_none());
+        assert!(tls.cluster_client.is_none());
+    }
+
+    #[test]
+    fn requires_certificates() {
+        let config = Config::test("tls_requires_certificates").update_config(|mut c| {
+            c.client_tls = true;
+            c
+        });
+        assert!(TlsSettings::from_config(config.config_obj().as_ref()).is_err());
+
+        let config = Config::test("tls_requires_certificates").update_config(|mut c| {
+            c.cluster_tls = true;
+            c.tls_cert_file = Some("/nonexistent/cert.pem".into());
+            c.tls_key_file = Some("/nonexistent/key.pem".into());
+            c
+        });
+        let e = TlsSettings::from_config(config.config_obj().as_ref())
+            .err()
+            .unwrap();
+        assert!(e.message.contains("/nonexistent/cert.pem"), "{}", e);
+    }
+}
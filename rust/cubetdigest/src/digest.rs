@@ -0,0 +1,464 @@
+/*
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::error::{Result, TDigestError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+use std::io::Cursor;
+
+const FORMAT_VERSION: u8 = 1;
+/// The only type of values we support, doubles.
+const VALUE_TYPE_DOUBLE: u8 = 0;
+/// Size of the header: version, value type, min, max, compression, total weight and centroid count.
+const HEADER_SIZE: usize = 2 + 4 * 8 + 4;
+
+pub const DEFAULT_COMPRESSION: f64 = 100.;
+
+/// T-Digest estimates quantiles of a distribution of values. The values are summarized by a small
+/// number of centroids, i.e. means of adjacent values with their count. Centroids near the tails
+/// of the distribution are kept smaller, so extreme quantiles are estimated more precisely.
+///
+/// Digests are mergeable: the result of `merge_with` estimates quantiles of all values added to
+/// both digests.
+#[derive(Debug, Clone)]
+pub struct TDigest {
+    compression: f64,
+    min: f64,
+    max: f64,
+    total_weight: f64,
+    /// Compressed centroids, sorted by mean.
+    means: Vec<f64>,
+    weights: Vec<f64>,
+    /// Centroids that were not compressed yet, in arbitrary order.
+    unmerged: Vec<(f64, f64)>,
+}
+
+impl TDigest {
+    /// Create a digest for an empty set of values. Larger `compression` gives more precise
+    /// estimates at the cost of more centroids, the number of centroids is at most
+    /// `2 * compression`.
+    pub fn new(compression: f64) -> Result<TDigest> {
+        if !(10. ..=1000.).contains(&compression) {
+            return Err(TDigestError::new(format!(
+                "compression must be between 10 and 1000, got {}",
+                compression
+            )));
+        }
+        return Ok(TDigest {
+            compression,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            total_weight: 0.,
+            means: Vec::new(),
+            weights: Vec::new(),
+            unmerged: Vec::new(),
+        });
+    }
+
+    pub fn compression(&self) -> f64 {
+        return self.compression;
+    }
+
+    /// Number of values added to the digest.
+    pub fn total_weight(&self) -> f64 {
+        return self.total_weight;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.total_weight == 0.;
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        return if self.is_empty() {
+            None
+        } else {
+            Some(self.min)
+        };
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        return if self.is_empty() {
+            None
+        } else {
+            Some(self.max)
+        };
+    }
+
+    pub fn add(&mut self, value: f64) -> Result<()> {
+        return self.add_weighted(value, 1.);
+    }
+
+    pub fn add_weighted(&mut self, value: f64, weight: f64) -> Result<()> {
+        if !value.is_finite() {
+            return Err(TDigestError::new(format!(
+                "cannot add {} to t-digest, value must be finite",
+                value
+            )));
+        }
+        if !(weight > 0. && weight.is_finite()) {
+            return Err(TDigestError::new(format!(
+                "weight must be positive, got {}",
+                weight
+            )));
+        }
+        self.add_centroid(value, weight);
+        return Ok(());
+    }
+
+    /// Merges values from `o` into the current digest. Digests with different compression can be
+    /// merged, the result keeps the compression of the current digest.
+    pub fn merge_with(&mut self, o: &TDigest) {
+        for (m, w) in o.means.iter().zip(o.weights.iter()) {
+            self.add_centroid(*m, *w);
+        }
+        for (m, w) in &o.unmerged {
+            self.add_centroid(*m, *w);
+        }
+        if !o.is_empty() {
+            self.min = self.min.min(o.min);
+            self.max = self.max.max(o.max);
+        }
+    }
+
+    /// Estimates the value at quantile `q`, which must be between 0 and 1.
+    /// Returns None if the digest is empty.
+    pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
+        if !(0. ..=1.).contains(&q) {
+            return Err(TDigestError::new(format!(
+                "quantile must be between 0 and 1, got {}",
+                q
+            )));
+        }
+        if self.is_empty() {
+            return Ok(None);
+        }
+        if !self.unmerged.is_empty() {
+            let mut compressed = self.clone();
+            compressed.compress();
+            return compressed.quantile(q);
+        }
+        return Ok(Some(self.compressed_quantile(q)));
+    }
+
+    pub fn read(data: &[u8]) -> Result<TDigest> {
+        if data.len() < HEADER_SIZE {
+            return Err(TDigestError::new("t-digest input data is too small"));
+        }
+        let mut c = Cursor::new(data);
+        let version = c.read_u8()?;
+        if version != FORMAT_VERSION {
+            return Err(TDigestError::new(format!(
+                "unsupported t-digest format version {}",
+                version
+            )));
+        }
+        let value_type = c.read_u8()?;
+        if value_type != VALUE_TYPE_DOUBLE {
+            return Err(TDigestError::new(format!(
+                "unsupported t-digest value type {}",
+                value_type
+            )));
+        }
+        let min = c.read_f64::<LittleEndian>()?;
+        let max = c.read_f64::<LittleEndian>()?;
+        let compression = c.read_f64::<LittleEndian>()?;
+        let total_weight = c.read_f64::<LittleEndian>()?;
+        let num_centroids = c.read_i32::<LittleEndian>()?;
+        if num_centroids < 0 || data.len() != HEADER_SIZE + 16 * num_centroids as usize {
+            return Err(TDigestError::new("invalid t-digest size"));
+        }
+        let num_centroids = num_centroids as usize;
+        let mut weights = Vec::with_capacity(num_centroids);
+        for _ in 0..num_centroids {
+            weights.push(c.read_f64::<LittleEndian>()?);
+        }
+        let mut means = Vec::with_capacity(num_centroids);
+        for _ in 0..num_centroids {
+            means.push(c.read_f64::<LittleEndian>()?);
+        }
+
+        let mut d = TDigest::new(compression)?;
+        if num_centroids == 0 {
+            return Ok(d);
+        }
+        if !(min.is_finite() && max.is_finite() && min <= max) {
+            return Err(TDigestError::new("invalid t-digest bounds"));
+        }
+        for i in 0..num_centroids {
+            if !(weights[i] > 0. && weights[i].is_finite()) {
+                return Err(TDigestError::new("invalid t-digest centroid weight"));
+            }
+            if !(min <= means[i] && means[i] <= max) {
+                return Err(TDigestError::new("invalid t-digest centroid mean"));
+            }
+            if i != 0 && means[i - 1] > means[i] {
+                return Err(TDigestError::new("t-digest centroids are not sorted"));
+            }
+        }
+        let sum: f64 = weights.iter().sum();
+        if (sum - total_weight).abs() > 1e-6 * sum {
+            return Err(TDigestError::new("invalid t-digest total weight"));
+        }
+        d.min = min;
+        d.max = max;
+        d.total_weight = total_weight;
+        d.means = means;
+        d.weights = weights;
+        return Ok(d);
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        if !self.unmerged.is_empty() {
+            let mut compressed = self.clone();
+            compressed.compress();
+            return compressed.write();
+        }
+        let mut r = Vec::with_capacity(HEADER_SIZE + 16 * self.means.len());
+        // Writes to vectors can't fail.
+        r.write_u8(FORMAT_VERSION).unwrap();
+        r.write_u8(VALUE_TYPE_DOUBLE).unwrap();
+        r.write_f64::<LittleEndian>(self.min).unwrap();
+        r.write_f64::<LittleEndian>(self.max).unwrap();
+        r.write_f64::<LittleEndian>(self.compression).unwrap();
+        r.write_f64::<LittleEndian>(self.total_weight).unwrap();
+        r.write_i32::<LittleEndian>(self.means.len() as i32)
+            .unwrap();
+        for w in &self.weights {
+            r.write_f64::<LittleEndian>(*w).unwrap();
+        }
+        for m in &self.means {
+            r.write_f64::<LittleEndian>(*m).unwrap();
+        }
+        return r;
+    }
+
+    fn add_centroid(&mut self, mean: f64, weight: f64) {
+        self.unmerged.push((mean, weight));
+        self.total_weight += weight;
+        self.min = self.min.min(mean);
+        self.max = self.max.max(mean);
+        if self.unmerged.len() >= self.buffer_size() {
+            self.compress();
+        }
+    }
+
+    fn buffer_size(&self) -> usize {
+        return 5 * self.compression.ceil() as usize;
+    }
+
+    /// Scale function that limits the size of the centroids, k1 from the T-Digest paper.
+    fn k(&self, q: f64) -> f64 {
+        return self.compression / (2. * PI) * (2. * q - 1.).asin();
+    }
+
+    /// Inverse of [k], clamps to the valid range of quantiles.
+    fn q(&self, k: f64) -> f64 {
+        let angle = (k * 2. * PI / self.compression).clamp(-PI / 2., PI / 2.);
+        return (angle.sin() + 1.) / 2.;
+    }
+
+    fn compress(&mut self) {
+        if self.unmerged.is_empty() {
+            return;
+        }
+        let mut centroids = std::mem::take(&mut self.unmerged);
+        centroids.extend(self.means.iter().cloned().zip(self.weights.iter().cloned()));
+        // Values are always finite, so there are no NaNs.
+        centroids.sort_unstable_by(|l, r| l.0.partial_cmp(&r.0).unwrap_or(Ordering::Equal));
+
+        let total = self.total_weight;
+        let mut means = Vec::new();
+        let mut weights = Vec::new();
+        let (mut mean, mut weight) = centroids[0];
+        let mut weight_so_far = 0.;
+        let mut q_limit = self.q(self.k(0.) + 1.);
+        for &(m, w) in &centroids[1..] {
+            if (weight_so_far + weight + w) / total <= q_limit {
+                weight += w;
+                mean += (m - mean) * w / weight;
+            } else {
+                weight_so_far += weight;
+                means.push(mean);
+                weights.push(weight);
+                q_limit = self.q(self.k(weight_so_far / total) + 1.);
+                mean = m;
+                weight = w;
+            }
+        }
+        means.push(mean);
+        weights.push(weight);
+
+        self.means = means;
+        self.weights = weights;
+    }
+
+    /// Interpolates between the centroids, assumes the digest is compressed and non-empty.
+    fn compressed_quantile(&self, q: f64) -> f64 {
+        let n = self.means.len();
+        let total = self.total_weight;
+        let index = q * total;
+        if index < 1. {
+            return self.min;
+        }
+        // Half of the first centroid is interpolated between the minimum and the mean.
+        if self.weights[0] > 1. && index < self.weights[0] / 2. {
+            return self.min
+                + (index - 1.) / (self.weights[0] / 2. - 1.) * (self.means[0] - self.min);
+        }
+        if index > total - 1. {
+            return self.max;
+        }
+        // Same for the last centroid and the maximum.
+        if self.weights[n - 1] > 1. && total - index <= self.weights[n - 1] / 2. {
+            return self.max
+                - (total - index - 1.) / (self.weights[n - 1] / 2. - 1.)
+                    * (self.max - self.means[n - 1]);
+        }
+
+        let mut weight_so_far = self.weights[0] / 2.;
+        for i in 0..n - 1 {
+            let dw = (self.weights[i] + self.weights[i + 1]) / 2.;
+            if weight_so_far + dw > index {
+                // Centroids of a single value are exact, do not interpolate around them.
+                let mut left_unit = 0.;
+                if self.weights[i] == 1. {
+                    if index - weight_so_far < 0.5 {
+                        return self.means[i];
+                    }
+                    left_unit = 0.5;
+                }
+                let mut right_unit = 0.;
+                if self.weights[i + 1] == 1. {
+                    if weight_so_far + dw - index <= 0.5 {
+                        return self.means[i + 1];
+                    }
+                    right_unit = 0.5;
+                }
+                let z1 = index - weight_so_far - left_unit;
+                let z2 = weight_so_far + dw - index - right_unit;
+                return weighted_average(self.means[i], z2, self.means[i + 1], z1);
+            }
+            weight_so_far += dw;
+        }
+        let z1 = index - weight_so_far;
+        let z2 = total - index;
+        return weighted_average(self.means[n - 1], z2, self.max, z1);
+    }
+}
+
+/// Weighted average of two values, clamped between them to avoid rounding errors.
+fn weighted_average(x1: f64, w1: f64, x2: f64, w2: f64) -> f64 {
+    let (lo, hi) = if x1 <= x2 { (x1, x2) } else { (x2, x1) };
+    if w1 + w2 <= 0. {
+        return lo;
+    }
+    return ((x1 * w1 + x2 * w2) / (w1 + w2)).max(lo).min(hi);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest_of(values: impl Iterator<Item = f64>) -> TDigest {
+        let mut d = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        for v in values {
+            d.add(v).unwrap();
+        }
+        d
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {} to be within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let d = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        assert_eq!(d.quantile(0.5).unwrap(), None);
+        assert_eq!(d.min(), None);
+
+        let d = TDigest::read(&d.write()).unwrap();
+        assert!(d.is_empty());
+        assert_eq!(d.quantile(0.99).unwrap(), None);
+    }
+
+    #[test]
+    fn small_sets_are_exact() {
+        let d = digest_of([3., 1., 2.].iter().cloned());
+        assert_eq!(d.quantile(0.).unwrap(), Some(1.));
+        assert_eq!(d.quantile(0.5).unwrap(), Some(2.));
+        assert_eq!(d.quantile(1.).unwrap(), Some(3.));
+    }
+
+    #[test]
+    fn uniform_distribution() {
+        let d = digest_of((0..100_000).map(|i| ((i * 7919) % 100_000) as f64));
+        assert!(d.means.len() <= 2 * DEFAULT_COMPRESSION as usize);
+        assert_eq!(d.total_weight(), 100_000.);
+        for q in &[0.01, 0.1, 0.5, 0.9, 0.95, 0.99, 0.999] {
+            assert_close(d.quantile(*q).unwrap().unwrap(), q * 100_000., 500.);
+        }
+        assert_eq!(d.quantile(0.).unwrap(), Some(0.));
+        assert_eq!(d.quantile(1.).unwrap(), Some(99_999.));
+    }
+
+    #[test]
+    fn merge() {
+        let mut l = digest_of((0..50_000).map(|i| i as f64));
+        let r = digest_of((50_000..100_000).map(|i| i as f64));
+        l.merge_with(&r);
+        assert_eq!(l.total_weight(), 100_000.);
+        assert_eq!(l.min(), Some(0.));
+        assert_eq!(l.max(), Some(99_999.));
+        for q in &[0.05, 0.5, 0.95] {
+            assert_close(l.quantile(*q).unwrap().unwrap(), q * 100_000., 500.);
+        }
+
+        let mut e = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        e.merge_with(&l);
+        assert_close(e.quantile(0.5).unwrap().unwrap(), 50_000., 500.);
+    }
+
+    #[test]
+    fn serialization() {
+        let d = digest_of((0..10_000).map(|i| (i as f64).sqrt()));
+        let data = d.write();
+        let r = TDigest::read(&data).unwrap();
+        assert_eq!(r.write(), data);
+        for q in &[0., 0.25, 0.5, 0.75, 1.] {
+            assert_eq!(r.quantile(*q).unwrap(), d.quantile(*q).unwrap());
+        }
+    }
+
+    #[test]
+    fn invalid_inputs() {
+        assert!(TDigest::read(&[]).is_err());
+        assert!(TDigest::new(0.).is_err());
+        assert!(digest_of(std::iter::empty()).add(f64::NAN).is_err());
+        assert!(digest_of(std::iter::empty()).quantile(1.5).is_err());
+
+        let data = digest_of((0..100).map(|i| i as f64)).write();
+        assert!(TDigest::read(&data[..data.len() - 1]).is_err());
+        let mut wrong_version = data.clone();
+        wrong_version[0] = 2;
+        assert!(TDigest::read(&wrong_version).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+use std::fmt::{Display, Formatter};
+
+pub type Result<T> = std::result::Result<T, TDigestError>;
+#[derive(Debug)]
+pub struct TDigestError {
+    pub message: String,
+}
+
+impl Display for TDigestError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl TDigestError {
+    pub fn new<Str: ToString>(message: Str) -> TDigestError {
+        return TDigestError {
+            message: message.to_string(),
+        };
+    }
+}
+
+impl From<std::io::Error> for TDigestError {
+    fn from(err: std::io::Error) -> Self {
+        return TDigestError::new(err);
+    }
+}
@@ -69,6 +69,9 @@ fn read_varint(data: &[u8]) -> Result<(/*result*/ u32, /*bytes read*/ usize)> {
         if 32 <= shift {
             return Err(ZetaError::new("varint too long"));
         }
+        if offset == data.len() {
+            return Err(ZetaError::new("unexpected end of varint"));
+        }
         // Get 7 bits from next byte
         let b = data[offset];
         offset += 1;
@@ -35,6 +35,16 @@ impl NormalEncoding {
          "valid index and rhoW can only be determined for precisions in the range [1, 63], but got {}", precision);
         return NormalEncoding { precision };
     }
+
+    /// Computes the HyperLogLog++ index for the given hash.
+    pub fn index(&self, hash: u64) -> usize {
+        return (hash >> (64 - self.precision)) as usize;
+    }
+
+    /// Computes the HyperLogLog++ *ρ(w)* for the given hash.
+    pub fn rho_w(&self, hash: u64) -> u8 {
+        return compute_rho_w(hash, 64 - self.precision);
+    }
 }
 
 /// An object that computes HyperLogLog++ properties for the sparse encoding at a given precision.
@@ -121,6 +131,23 @@ impl SparseEncoding {
         );
     }
 
+    /// Encodes the hash as a sparse value. See the struct docs for details on the two
+    /// representations with which sparse values are encoded.
+    pub fn encode(&self, hash: u64) -> u32 {
+        let sparse_index = (hash >> (64 - self.sparse_precision)) as i32;
+
+        // The sparse rhoW' is only encoded when the last sp-p bits of the sparse index are all
+        // zero, otherwise the normal rhoW can be determined from the sparse index.
+        let mask = (1 << (self.sparse_precision - self.normal_precision)) - 1;
+        if (sparse_index & mask) != 0 {
+            return sparse_index as u32;
+        }
+
+        let normal_index = sparse_index >> (self.sparse_precision - self.normal_precision);
+        let sparse_rho_w = compute_rho_w(hash, 64 - self.sparse_precision) as i32;
+        return (self.rho_encoded_flag | normal_index << Self::RHOW_BITS | sparse_rho_w) as u32;
+    }
+
     /// Decodes the sparse index from an encoded sparse value. See the class Javadoc for details on
     /// the two representations with which sparse values are encoded.
     fn decode_sparse_index(&self, sparse_value: i32) -> i32 {
@@ -264,3 +291,42 @@ fn compute_rho_w(value: u64, bits: i32) -> u8 {
         w.leading_zeros() as u8 + 1
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::{NormalEncoding, SparseEncoding};
+    use crate::hash::HashableValue;
+
+    #[test]
+    fn test_sparse_encode_matches_normal() {
+        for (p, sp) in &[(10, 10), (10, 15), (15, 20), (15, 25), (24, 25)] {
+            let normal = NormalEncoding::new(*p);
+            let sparse = SparseEncoding::new(*p, *sp);
+            let mut hashes: Vec<u64> = (0..10_000i64).map(|v| v.fingerprint()).collect();
+            // Hashes with zeros in the last sp-p bits of the sparse index and in the rest of bits.
+            hashes.push(0);
+            hashes.push(1 << (64 - sp));
+            hashes.push(1 << (63 - sp));
+            hashes.push(u64::MAX << (64 - p));
+            for h in hashes {
+                let v = sparse.encode(h) as i32;
+                assert_eq!(
+                    sparse.decode_normal_index(v) as usize,
+                    normal.index(h),
+                    "p={}, sp={}, hash={:x}",
+                    p,
+                    sp,
+                    h
+                );
+                assert_eq!(
+                    sparse.decode_normal_rho_w(v),
+                    normal.rho_w(h),
+                    "p={}, sp={}, hash={:x}",
+                    p,
+                    sp,
+                    h
+                );
+            }
+        }
+    }
+}
@@ -17,6 +17,7 @@ mod data;
 mod difference_encoding;
 mod encoding;
 mod error;
+mod hash;
 mod normal;
 mod sketch;
 mod sparse;
@@ -24,4 +25,5 @@ mod state;
 
 pub use error::Result;
 pub use error::ZetaError;
+pub use hash::HashableValue;
 pub use sketch::HyperLogLogPlusPlus;
@@ -157,6 +157,17 @@ impl NormalRepresentation {
         }
     }
 
+    pub fn add_hash(&mut self, state: &mut State, hash: u64) {
+        Self::ensure_data(state);
+        let data = state.data.as_mut().unwrap();
+
+        let idx = self.encoding.index(hash);
+        let rho_w = self.encoding.rho_w(hash);
+        if data[idx] < rho_w {
+            data[idx] = rho_w;
+        }
+    }
+
     pub fn add_sparse_values<I: Iterator<Item = Result<u32>>>(
         &mut self,
         state: &mut State,
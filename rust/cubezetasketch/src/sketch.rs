@@ -29,12 +29,14 @@
 ///
 /// Note that this aggregator is *not* designed to be thread safe.
 use crate::error::Result;
+use crate::hash::HashableValue;
 use crate::normal::NormalRepresentation;
 use crate::sparse::SparseRepresentation;
 use crate::state::aggregator_state_proto::AGGREGATOR_TYPE_HYPERLOGLOG_PLUS_UNIQUE;
 use crate::state::State;
 use crate::ZetaError;
 use protobuf::CodedInputStream;
+use std::borrow::Cow;
 
 #[derive(Debug, Clone)]
 pub struct HyperLogLogPlusPlus {
@@ -76,8 +78,8 @@ impl HyperLogLogPlusPlus {
     // /** The largest normal precision supported by this aggregator. */
     // pub const MAXIMUM_PRECISION : i32= NormalRepresentation::MAXIMUM_PRECISION;
     //
-    // /** The default normal precision that is used if the user does not specify a normal precision. */
-    // pub const DEFAULT_NORMAL_PRECISION :i32 = 15;
+    /** The default normal precision, same as in BigQuery. */
+    pub const DEFAULT_NORMAL_PRECISION: i32 = 15;
     //
     // /** The largest sparse precision supported by this aggregator. */
     // pub const MAXIMUM_SPARSE_PRECISION :i32 = SparseRepresentation::MAXIMUM_SPARSE_PRECISION;
@@ -95,6 +97,17 @@ impl HyperLogLogPlusPlus {
     /** The encoding version of the `AggregatorStateProto`. We only support v2. */
     const ENCODING_VERSION: i32 = 2;
 
+    /// Creates an aggregator for the empty set. Sparse precision is usually the normal precision
+    /// plus `DEFAULT_SPARSE_PRECISION_DELTA`.
+    pub fn new(precision: i32, sparse_precision: i32) -> Result<HyperLogLogPlusPlus> {
+        return Self::from_state(State {
+            encoding_version: Self::ENCODING_VERSION,
+            precision,
+            sparse_precision,
+            ..State::default()
+        });
+    }
+
     /// Creates a new HyperLogLog++ aggregator from the serialized `proto`.
     ///
     /// `proto` is a valid aggregator state of type `AggregatorType::HYPERLOGLOG_PLUS_UNIQUE`.
@@ -103,13 +116,66 @@ impl HyperLogLogPlusPlus {
     }
 
     pub fn write(&self) -> Vec<u8> {
-        return self.state.to_byte_array();
+        return self.compacted().state.to_byte_array();
     }
 
     pub fn cardinality(&self) -> u64 {
-        match &self.representation {
-            Representation::Sparse(r) => return r.cardinality(&self.state),
-            Representation::Normal(r) => return r.cardinality(&self.state),
+        let s = self.compacted();
+        match &s.representation {
+            Representation::Sparse(r) => return r.cardinality(&s.state),
+            Representation::Normal(r) => return r.cardinality(&s.state),
+        }
+    }
+
+    /// Adds `value` to the set. Hashes are compatible with ZetaSketch, see `HashableValue`.
+    /// All values added to the same aggregator must have the same type.
+    pub fn add<T: HashableValue + ?Sized>(&mut self, value: &T) -> Result<()> {
+        if self.state.value_type == 0 {
+            self.state.value_type = T::VALUE_TYPE;
+        } else if self.state.value_type != T::VALUE_TYPE {
+            return Err(ZetaError::new(format!(
+                "Expected values of type {} but got {}",
+                self.state.value_type,
+                T::VALUE_TYPE
+            )));
+        }
+
+        let hash = value.fingerprint();
+        match &mut self.representation {
+            Representation::Sparse(r) => {
+                if let Some(n) = r.add_hash(&mut self.state, hash)? {
+                    self.representation = Representation::Normal(n);
+                }
+            }
+            Representation::Normal(r) => r.add_hash(&mut self.state, hash),
+        }
+        self.state.num_values += 1;
+        return Ok(());
+    }
+
+    /// The normal precision, i.e. the sketch has `2^precision` registers once it is not sparse.
+    pub fn precision(&self) -> i32 {
+        return self.state.precision;
+    }
+
+    /// Returns the values of all `2^precision` registers of the normal representation, converting
+    /// from the sparse one if necessary. The value of a register is the number of leading zeros
+    /// in the hash bits after the register index plus one, or zero for an empty register.
+    pub fn normal_registers(&self) -> Result<Vec<u8>> {
+        let s = self.compacted();
+        match &s.representation {
+            Representation::Normal(_) => {
+                return Ok(s
+                    .state
+                    .data
+                    .clone()
+                    .expect("normal representation has no data"))
+            }
+            Representation::Sparse(r) => {
+                let mut state = s.state.clone();
+                r.clone().normalize(&mut state)?;
+                return Ok(state.data.expect("normal representation has no data"));
+            }
         }
     }
 
@@ -127,6 +193,8 @@ impl HyperLogLogPlusPlus {
       other.state.sparse_precision, other
                                             .state.precision)));
         }
+        self.compact()?;
+        let other = other.compacted();
         self.state.num_values += other.state.num_values;
 
         let new_repr: Option<NormalRepresentation>;
@@ -153,6 +221,28 @@ impl HyperLogLogPlusPlus {
         return Ok(());
     }
 
+    /// Returns the sketch with values buffered by `add()` merged into the state.
+    fn compacted(&self) -> Cow<HyperLogLogPlusPlus> {
+        match &self.representation {
+            Representation::Sparse(r) if r.has_buffered_values() => {
+                let mut s = self.clone();
+                // Sparse data was validated on read, so merging can not fail.
+                s.compact().expect("failed to merge buffered values");
+                return Cow::Owned(s);
+            }
+            _ => return Cow::Borrowed(self),
+        }
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        if let Representation::Sparse(r) = &mut self.representation {
+            if let Some(n) = r.compact(&mut self.state)? {
+                self.representation = Representation::Normal(n);
+            }
+        }
+        return Ok(());
+    }
+
     fn for_coded_input(proto: CodedInputStream) -> Result<HyperLogLogPlusPlus> {
         return Self::from_state(State::parse_stream(proto)?);
     }
@@ -174,9 +264,121 @@ impl HyperLogLogPlusPlus {
         // TODO: implement or remove.
         // allowedTypes = Type.extractAndNormalize(state);
         let representation = Representation::from_state(&state)?;
+        // Make sure the sparse data can be decoded, we rely on this when adding values.
+        for v in SparseRepresentation::sorted_iterator(state.sparse_data.as_deref()) {
+            v?;
+        }
         return Ok(HyperLogLogPlusPlus {
             state,
             representation,
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::NormalEncoding;
+    use crate::sketch::{HyperLogLogPlusPlus, Representation};
+    use crate::HashableValue;
+
+    fn sketch_of(values: impl Iterator<Item = i64>) -> HyperLogLogPlusPlus {
+        let mut s = HyperLogLogPlusPlus::new(15, 20).unwrap();
+        for v in values {
+            s.add(&v).unwrap();
+        }
+        return s;
+    }
+
+    #[test]
+    fn test_add_sparse() {
+        let s = sketch_of(0..100);
+        assert!(matches!(s.representation, Representation::Sparse(_)));
+        assert_eq!(s.cardinality(), 100);
+
+        let r = HyperLogLogPlusPlus::read(&s.write()).unwrap();
+        assert_eq!(r.cardinality(), 100);
+        assert_eq!(r.write(), s.write());
+        assert_eq!(r.state.num_values, 100);
+        assert_eq!(r.state.value_type, 4);
+    }
+
+    #[test]
+    fn test_add_normal() {
+        let s = sketch_of(0..100_000);
+        assert!(matches!(s.representation, Representation::Normal(_)));
+        let c = s.cardinality();
+        assert!(98_000 <= c && c <= 102_000, "cardinality is {}", c);
+
+        let r = HyperLogLogPlusPlus::read(&s.write()).unwrap();
+        assert_eq!(r.cardinality(), c);
+    }
+
+    #[test]
+    fn test_add_duplicates() {
+        let s = sketch_of((0..1000).chain(0..1000));
+        assert_eq!(s.cardinality(), 1000);
+        assert_eq!(s.state.num_values, 2000);
+        assert_eq!(
+            HyperLogLogPlusPlus::read(&s.write())
+                .unwrap()
+                .state
+                .sparse_data,
+            HyperLogLogPlusPlus::read(&sketch_of(0..1000).write())
+                .unwrap()
+                .state
+                .sparse_data
+        );
+    }
+
+    #[test]
+    fn test_add_and_merge() {
+        for (l, r) in &[
+            (0..100, 50..150),
+            (0..5_000, 2_000..30_000),
+            (0..100, 0..50_000),
+        ] {
+            let mut merged = sketch_of(l.clone());
+            merged.merge_with(&sketch_of(r.clone())).unwrap();
+            let single = sketch_of(l.clone().chain(r.clone()));
+            assert_eq!(merged.cardinality(), single.cardinality());
+            assert_eq!(merged.state.data, single.compacted().state.data);
+        }
+    }
+
+    #[test]
+    fn test_normal_registers() {
+        for (values, is_sparse) in &[(0..100, true), (0..100_000, false)] {
+            let s = sketch_of(values.clone());
+            assert_eq!(
+                matches!(s.compacted().representation, Representation::Sparse(_)),
+                *is_sparse
+            );
+            // Same as adding all hashes directly to the normal representation.
+            let encoding = NormalEncoding::new(s.precision());
+            let mut expected = vec![0; 1 << s.precision()];
+            for v in values.clone() {
+                let hash = v.fingerprint();
+                let r = &mut expected[encoding.index(hash)];
+                *r = (*r).max(encoding.rho_w(hash));
+            }
+            assert_eq!(s.normal_registers().unwrap(), expected);
+        }
+        assert_eq!(
+            sketch_of(0..0).normal_registers().unwrap(),
+            vec![0; 1 << 15]
+        );
+    }
+
+    #[test]
+    fn test_value_types() {
+        let mut s = HyperLogLogPlusPlus::new(15, 20).unwrap();
+        s.add("foo").unwrap();
+        s.add(&b"bar"[..]).unwrap();
+        s.add(&1i64).unwrap_err();
+        assert_eq!(s.cardinality(), 2);
+        assert_eq!(s.state.value_type, 11);
+
+        HyperLogLogPlusPlus::new(9, 20).unwrap_err();
+        HyperLogLogPlusPlus::new(15, 10).unwrap_err();
+    }
+}
@@ -31,6 +31,13 @@ pub struct SparseRepresentation {
     max_sparse_data_bytes: u32,
     /** Helper object for encoding and decoding individual sparse values. */
     encoding: SparseEncoding,
+    /**
+     * Sparse values added with `add_hash` that were not merged into `State::sparse_data` yet.
+     * Unsorted and may contain duplicates.
+     */
+    buffer: Vec<u32>,
+    /** The maximum number of values in `buffer` before they are merged into the sparse data. */
+    max_buffer_elements: usize,
 }
 
 impl SparseRepresentation {
@@ -47,6 +54,11 @@ impl SparseRepresentation {
      * independently (e.g. improving runtime performance while trading off for peak memory usage).
      */
     const MAXIMUM_SPARSE_DATA_FRACTION: f32 = 0.75;
+    /**
+     * The maximum amount of memory used by the temporary `buffer`, relative to the normal
+     * representation size. Each buffered value takes 4 bytes.
+     */
+    const MAXIMUM_BUFFER_FRACTION: f32 = 1. - Self::MAXIMUM_SPARSE_DATA_FRACTION;
 
     pub fn new(state: &State) -> Result<SparseRepresentation> {
         Self::check_precision(state.precision, state.sparse_precision)?;
@@ -71,9 +83,12 @@ impl SparseRepresentation {
         }
         // We have no good way of checking whether the data actually contains the given number of
         // elements without decoding the data, which would be inefficient here.
+        let max_buffer_elements = (m as f32 * Self::MAXIMUM_BUFFER_FRACTION / 4.) as usize;
         return Ok(SparseRepresentation {
             max_sparse_data_bytes,
             encoding,
+            buffer: Vec::new(),
+            max_buffer_elements,
         });
     }
 
@@ -105,6 +120,40 @@ impl SparseRepresentation {
         return estimate.round() as u64;
     }
 
+    pub fn has_buffered_values(&self) -> bool {
+        return !self.buffer.is_empty();
+    }
+
+    /// Returns a new normal representation if this sparse representation has outgrown itself.
+    pub fn add_hash(
+        &mut self,
+        state: &mut State,
+        hash: u64,
+    ) -> Result<Option<NormalRepresentation>> {
+        self.buffer.push(self.encoding.encode(hash));
+        if self.buffer.len() <= self.max_buffer_elements {
+            return Ok(None);
+        }
+        return self.compact(state);
+    }
+
+    /// Merges the buffered values into `State::sparse_data`. Must be called before reading the
+    /// state, e.g. to serialize it or estimate the cardinality.
+    /// Returns a new normal representation if this sparse representation has outgrown itself.
+    pub fn compact(&mut self, state: &mut State) -> Result<Option<NormalRepresentation>> {
+        self.flush_buffer(state)?;
+        return self.update_representation(state);
+    }
+
+    fn flush_buffer(&mut self, state: &mut State) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.sort_unstable();
+        return self.merge_sorted(state, buffer.into_iter().map(Ok));
+    }
+
     /// `self` may end up be in the invalid state on error and must not be used further.
     pub fn merge_with_sparse(
         &mut self,
@@ -140,9 +189,20 @@ impl SparseRepresentation {
         sparse_values: Iter,
     ) -> Result<Option<NormalRepresentation>> {
         self.encoding.assert_compatible(encoding);
+        self.flush_buffer(state)?;
 
-        // Special case when encodings are the same. Then we can profit from the fact that sparse_values
-        // are sorted (as defined in the add_sparse_values contract) and do a merge-join.
+        // TODO: Merge without risking to grow this representation above its maximum size.
+        self.merge_sorted(state, sparse_values)?;
+        return Ok(self.update_representation(state)?);
+    }
+
+    /// Special case when encodings are the same. Then we can profit from the fact that sparse_values
+    /// are sorted (as defined in the add_sparse_values contract) and do a merge-join.
+    fn merge_sorted<Iter: Iterator<Item = Result<u32>>>(
+        &self,
+        state: &mut State,
+        sparse_values: Iter,
+    ) -> Result<()> {
         let self_data = state.sparse_data.take();
         let iter =
             Self::sorted_iterator(self_data.as_deref()).merge_by(sparse_values, |l, r| {
@@ -152,10 +212,7 @@ impl SparseRepresentation {
                     (Ok(l), Ok(r)) => l <= r,
                 }
             });
-
-        // TODO: Merge without risking to grow this representation above its maximum size.
-        Self::set(state, self.encoding.dedupe(iter))?;
-        return Ok(self.update_representation(state)?);
+        return Self::set(state, self.encoding.dedupe(iter));
     }
 
     fn set<Iter: Iterator<Item = Result<u32>>>(state: &mut State, mut iter: Iter) -> Result<()> {
@@ -207,7 +264,8 @@ impl SparseRepresentation {
 
     /// Convert to `NormalRepresentation`.
     #[must_use]
-    fn normalize(&mut self, state: &mut State) -> Result<NormalRepresentation> {
+    pub fn normalize(&mut self, state: &mut State) -> Result<NormalRepresentation> {
+        self.flush_buffer(state)?;
         let mut representation = NormalRepresentation::new(state).expect("programming error");
         let sparse_data = state.sparse_data.take();
         state.sparse_size = 0;